@@ -7,6 +7,7 @@ use aes_gcm::{
     Aes256Gcm, Nonce,
 };
 use rand::{rngs::OsRng, RngCore};
+use super::workspace::DEFAULT_WORKSPACE;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SslMode {
@@ -32,6 +33,13 @@ pub struct DatabaseConfig {
     pub password_encrypted: String,
     pub ssl_mode: SslMode,
     pub enabled: bool,
+    // 当前工作区，多个团队共用一个数据库时按工作区隔离数据
+    #[serde(default = "default_workspace")]
+    pub workspace: String,
+}
+
+fn default_workspace() -> String {
+    DEFAULT_WORKSPACE.to_string()
 }
 
 impl Default for DatabaseConfig {
@@ -45,6 +53,7 @@ impl Default for DatabaseConfig {
             password_encrypted: String::new(),
             ssl_mode: SslMode::default(),
             enabled: false,
+            workspace: default_workspace(),
         }
     }
 }
@@ -60,6 +69,7 @@ impl DatabaseConfig {
             password_encrypted: String::new(),
             ssl_mode: SslMode::default(),
             enabled: true,
+            workspace: default_workspace(),
         };
 
         if let Ok(encrypted) = encrypt_password(&password) {
@@ -79,6 +89,7 @@ impl DatabaseConfig {
             password_encrypted: String::new(),
             ssl_mode,
             enabled: true,
+            workspace: default_workspace(),
         };
 
         if let Ok(encrypted) = encrypt_password(&password) {
@@ -88,6 +99,11 @@ impl DatabaseConfig {
         config
     }
 
+    pub fn with_workspace(mut self, workspace: String) -> Self {
+        self.workspace = workspace;
+        self
+    }
+
     pub fn connection_string(&self) -> String {
         let ssl_mode_str = match self.ssl_mode {
             SslMode::Disable => "disable",
@@ -230,5 +246,13 @@ mod tests {
         assert_eq!(config.password, "password");
         assert!(!config.password_encrypted.is_empty());
         assert!(config.enabled);
+        assert_eq!(config.workspace, DEFAULT_WORKSPACE);
+    }
+
+    #[test]
+    fn test_config_without_workspace_uses_default() {
+        let json = r#"{"host":"localhost","port":5432,"database":"db","username":"user","password_encrypted":"","ssl_mode":"Prefer","enabled":true}"#;
+        let config: DatabaseConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.workspace, DEFAULT_WORKSPACE);
     }
 }
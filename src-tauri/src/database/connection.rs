@@ -1,9 +1,14 @@
 use deadpool_postgres::{Config, Pool, Runtime};
-use tokio_postgres::{NoTls, Error as PgError};
+use tokio_postgres::{AsyncMessage, Client, Connection, NoTls};
 use tokio_postgres_rustls::MakeRustlsConnect;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
 use rustls::{ClientConfig, RootCertStore};
 use std::sync::Arc;
 use super::config::{DatabaseConfig, SslMode};
+use super::migrations::{self, SchemaVersion};
+use super::workspace::{self, WorkspaceInfo, validate_workspace_id, workspace_connection_options};
+use crate::storage::{StorageError, StorageResult};
 
 pub type DbPool = Pool;
 
@@ -21,7 +26,7 @@ impl DatabaseManager {
         }
     }
 
-    pub async fn initialize(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn initialize(&mut self) -> StorageResult<()> {
         if !self.config.enabled {
             return Ok(());
         }
@@ -32,28 +37,16 @@ impl DatabaseManager {
         cfg.dbname = Some(self.config.database.clone());
         cfg.user = Some(self.config.username.clone());
         cfg.password = Some(self.config.password.clone());
+        // 每条连接建立时设置当前工作区，行级安全策略据此过滤
+        validate_workspace_id(&self.config.workspace)?;
+        cfg.options = Some(workspace_connection_options(&self.config.workspace));
 
         let pool = match self.config.ssl_mode {
             SslMode::Disable => {
                 cfg.create_pool(Some(Runtime::Tokio1), NoTls)?
             },
             SslMode::Prefer | SslMode::Require => {
-                let mut root_store = RootCertStore::empty();
-                root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
-                    rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                        ta.subject,
-                        ta.spki,
-                        ta.name_constraints,
-                    )
-                }));
-
-                let tls_config = ClientConfig::builder()
-                    .with_safe_defaults()
-                    .with_root_certificates(root_store)
-                    .with_no_client_auth();
-
-                let tls = MakeRustlsConnect::new(tls_config);
-                cfg.create_pool(Some(Runtime::Tokio1), tls)?
+                cfg.create_pool(Some(Runtime::Tokio1), tls_connector())?
             }
         };
 
@@ -73,25 +66,166 @@ impl DatabaseManager {
         self.pool.is_some()
     }
 
-    pub async fn test_connection(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// 当前连接使用的工作区
+    pub fn workspace(&self) -> &str {
+        &self.config.workspace
+    }
+
+    pub async fn test_connection(&self) -> StorageResult<()> {
         if let Some(pool) = &self.pool {
             let client = pool.get().await?;
             client.simple_query("SELECT 1").await?;
             Ok(())
         } else {
-            Err("Database not connected".into())
+            Err(StorageError::Unavailable("Database not connected".to_string()))
         }
     }
 
+    /// 执行未应用的结构迁移，返回迁移后的版本
+    pub async fn migrate(&self) -> StorageResult<i64> {
+        let pool = self.pool.as_ref()
+            .ok_or_else(|| StorageError::Unavailable("Database not connected".to_string()))?;
+        let mut client = pool.get().await?;
+        migrations::run_migrations(&mut client).await
+    }
+
+    pub async fn schema_version(&self) -> StorageResult<SchemaVersion> {
+        let pool = self.pool.as_ref()
+            .ok_or_else(|| StorageError::Unavailable("Database not connected".to_string()))?;
+        let client = pool.get().await?;
+        migrations::schema_version(&client).await
+    }
+
+    /// 数据库中登记的全部工作区
+    pub async fn list_workspaces(&self) -> StorageResult<Vec<WorkspaceInfo>> {
+        let client = self.client().await?;
+        workspace::list_workspaces(&client).await
+    }
+
+    /// 登记当前工作区，已存在时不做修改
+    pub async fn ensure_workspace(&self, name: Option<&str>) -> StorageResult<()> {
+        let client = self.client().await?;
+        workspace::ensure_workspace(&client, &self.config.workspace, name).await
+    }
+
+    pub async fn row_level_security_enabled(&self) -> StorageResult<bool> {
+        let client = self.client().await?;
+        workspace::row_level_security_enabled(&client).await
+    }
+
+    /// 见 [`workspace::set_row_level_security`]：只防止误操作，不能阻止客户端访问其他工作区
+    pub async fn set_row_level_security(&self, enabled: bool) -> StorageResult<()> {
+        let mut client = self.client().await?;
+        workspace::set_row_level_security(&mut client, enabled).await
+    }
+
+    async fn client(&self) -> StorageResult<deadpool_postgres::Object> {
+        let pool = self.pool.as_ref()
+            .ok_or_else(|| StorageError::Unavailable("Database not connected".to_string()))?;
+        Ok(pool.get().await?)
+    }
+
     pub async fn close(&mut self) {
         if let Some(pool) = self.pool.take() {
             // deadpool会自动处理连接的关闭
             drop(pool);
         }
     }
+
+    /// 在一条独立于连接池的连接上执行 LISTEN，收到的通知内容通过返回的通道发送
+    ///
+    /// 接收端被丢弃时关闭连接；连接断开时通道随之关闭，调用方可据此重连。
+    pub async fn listen(&self, channel: &str) -> StorageResult<mpsc::UnboundedReceiver<String>> {
+        if !self.config.enabled {
+            return Err(StorageError::Unavailable("Database not enabled".to_string()));
+        }
+
+        let mut pg_config = tokio_postgres::Config::new();
+        pg_config
+            .host(&self.config.host)
+            .port(self.config.port)
+            .dbname(&self.config.database)
+            .user(&self.config.username)
+            .password(&self.config.password)
+            .options(workspace_connection_options(&self.config.workspace).as_str());
+
+        match self.config.ssl_mode {
+            SslMode::Disable => {
+                let (client, connection) = pg_config.connect(NoTls).await?;
+                start_listener(client, connection, channel).await
+            }
+            SslMode::Prefer | SslMode::Require => {
+                let (client, connection) = pg_config.connect(tls_connector()).await?;
+                start_listener(client, connection, channel).await
+            }
+        }
+    }
+}
+
+fn tls_connector() -> MakeRustlsConnect {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+
+    let tls_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+
+    MakeRustlsConnect::new(tls_config)
+}
+
+async fn start_listener<S, T>(
+    client: Client,
+    mut connection: Connection<S, T>,
+    channel: &str,
+) -> StorageResult<mpsc::UnboundedReceiver<String>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    // 通知只有在连接被轮询时才会送达，LISTEN 本身也依赖轮询才能完成
+    let notification_sender = sender.clone();
+    let poller = tokio::spawn(async move {
+        while let Some(message) = std::future::poll_fn(|cx| connection.poll_message(cx)).await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    if notification_sender.send(notification.payload().to_string()).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Database listener connection error: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    // 频道名不能作为参数绑定，需要加引号防止注入
+    client.batch_execute(&format!("LISTEN \"{}\"", channel.replace('"', "\"\""))).await?;
+
+    // 持有 client 直到接收端被丢弃或连接断开，之后所有发送端被释放，接收端收到 None
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sender.closed() => {}
+            _ = poller => {}
+        }
+        drop(client);
+    });
+
+    Ok(receiver)
 }
 
-pub async fn test_database_connection(config: &DatabaseConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+pub async fn test_database_connection(config: &DatabaseConfig) -> StorageResult<()> {
     let mut cfg = Config::new();
     cfg.host = Some(config.host.clone());
     cfg.port = Some(config.port);
@@ -104,22 +238,7 @@ pub async fn test_database_connection(config: &DatabaseConfig) -> Result<(), Box
             cfg.create_pool(Some(Runtime::Tokio1), NoTls)?
         },
         SslMode::Prefer | SslMode::Require => {
-            let mut root_store = RootCertStore::empty();
-            root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
-                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                    ta.subject,
-                    ta.spki,
-                    ta.name_constraints,
-                )
-            }));
-
-            let tls_config = ClientConfig::builder()
-                .with_safe_defaults()
-                .with_root_certificates(root_store)
-                .with_no_client_auth();
-
-            let tls = MakeRustlsConnect::new(tls_config);
-            cfg.create_pool(Some(Runtime::Tokio1), tls)?
+            cfg.create_pool(Some(Runtime::Tokio1), tls_connector())?
         }
     };
 
@@ -139,5 +258,6 @@ mod tests {
         let manager = DatabaseManager::new(config);
         assert!(!manager.is_connected());
         assert!(manager.get_pool().is_none());
+        assert_eq!(manager.workspace(), crate::database::DEFAULT_WORKSPACE);
     }
 }
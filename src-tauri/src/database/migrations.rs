@@ -1,6 +1,11 @@
-use tokio_postgres::Client;
+use serde::Serialize;
+use tokio_postgres::{Client, GenericClient};
+use crate::storage::{StorageError, StorageResult};
 
-pub async fn check_tables_exist(client: &Client) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+/// tokens 表变更时发送 NOTIFY 的频道
+pub const TOKENS_CHANGED_CHANNEL: &str = "tokens_changed";
+
+pub async fn check_tables_exist(client: &Client) -> StorageResult<bool> {
     // 检查tokens表是否存在
     let rows = client.query(
         r#"
@@ -21,202 +26,580 @@ pub async fn check_tables_exist(client: &Client) -> Result<bool, Box<dyn std::er
     }
 }
 
-pub async fn create_tables(client: &Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // 创建tokens表
-    client.execute(
-        r#"
-        CREATE TABLE IF NOT EXISTS tokens (
-            id VARCHAR(255) PRIMARY KEY,
-            tenant_url TEXT NOT NULL,
-            access_token TEXT NOT NULL,
-            created_at TIMESTAMP WITH TIME ZONE NOT NULL,
-            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
-            portal_url TEXT,
-            email_note TEXT,
-            ban_status JSONB,
-            portal_info JSONB,
-            auth_session TEXT,
-            suspensions JSONB
-        )
+/// 一个编号的结构迁移，up/down 在同一个事务中执行
+///
+/// 引入版本表之前创建的数据库没有迁移记录，会从第一个迁移开始执行，
+/// 所以前几个迁移必须可以在已有表上重复执行（IF NOT EXISTS）。
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+/// 按版本号递增排列的全部迁移，只能在末尾追加
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        up: r#"
+            CREATE TABLE IF NOT EXISTS tokens (
+                id VARCHAR(255) PRIMARY KEY,
+                tenant_url TEXT NOT NULL,
+                access_token TEXT NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                portal_url TEXT,
+                email_note TEXT,
+                ban_status JSONB,
+                portal_info JSONB,
+                auth_session TEXT,
+                suspensions JSONB,
+                balance_color_mode TEXT,
+                skip_check BOOLEAN
+            );
+
+            -- 旧版本创建的 tokens 表可能缺少后来加入的字段
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS auth_session TEXT;
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS suspensions JSONB;
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS balance_color_mode TEXT;
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS skip_check BOOLEAN;
+
+            CREATE INDEX IF NOT EXISTS idx_tokens_created_at ON tokens(created_at);
+            CREATE INDEX IF NOT EXISTS idx_tokens_updated_at ON tokens(updated_at);
+
+            CREATE TABLE IF NOT EXISTS sync_status (
+                id SERIAL PRIMARY KEY,
+                last_sync_at TIMESTAMP WITH TIME ZONE,
+                sync_direction VARCHAR(50),
+                status VARCHAR(50),
+                error_message TEXT,
+                tokens_synced INTEGER DEFAULT 0,
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+            );
+
+            CREATE OR REPLACE FUNCTION update_updated_at_column()
+            RETURNS TRIGGER AS $$
+            BEGIN
+                NEW.updated_at = NOW();
+                RETURN NEW;
+            END;
+            $$ language 'plpgsql';
+
+            DROP TRIGGER IF EXISTS update_tokens_updated_at ON tokens;
+            CREATE TRIGGER update_tokens_updated_at
+                BEFORE UPDATE ON tokens
+                FOR EACH ROW
+                EXECUTE FUNCTION update_updated_at_column();
         "#,
-        &[],
-    ).await?;
+        down: r#"
+            DROP TABLE IF EXISTS sync_status;
+            DROP TABLE IF EXISTS tokens;
+            DROP FUNCTION IF EXISTS update_updated_at_column();
+        "#,
+    },
+    Migration {
+        version: 2,
+        name: "sync_status_conflicts",
+        up: "ALTER TABLE sync_status ADD COLUMN IF NOT EXISTS conflicts JSONB;",
+        down: "ALTER TABLE sync_status DROP COLUMN IF EXISTS conflicts;",
+    },
+    // 删除墓碑表：记录被删除的token，防止双向同步时被其他设备重新写回
+    Migration {
+        version: 3,
+        name: "token_tombstones",
+        up: r#"
+            CREATE TABLE IF NOT EXISTS token_tombstones (
+                token_id VARCHAR(255) PRIMARY KEY,
+                deleted_at TIMESTAMP WITH TIME ZONE NOT NULL,
+                origin VARCHAR(255) NOT NULL
+            );
 
-    // 创建索引
-    client.execute(
-        "CREATE INDEX IF NOT EXISTS idx_tokens_created_at ON tokens(created_at)",
-        &[],
-    ).await?;
+            CREATE INDEX IF NOT EXISTS idx_token_tombstones_deleted_at ON token_tombstones(deleted_at);
+        "#,
+        down: "DROP TABLE IF EXISTS token_tombstones;",
+    },
+    // 变更跟踪：每次插入或更新token时分配单调递增的 change_seq，客户端按水位线只拉取变更
+    Migration {
+        version: 4,
+        name: "token_change_seq",
+        up: r#"
+            CREATE SEQUENCE IF NOT EXISTS tokens_change_seq;
 
-    client.execute(
-        "CREATE INDEX IF NOT EXISTS idx_tokens_updated_at ON tokens(updated_at)",
-        &[],
-    ).await?;
+            -- 带易变默认值的 ADD COLUMN 会为已有行逐行分配序号，且不会触发 updated_at 触发器
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS change_seq BIGINT NOT NULL DEFAULT nextval('tokens_change_seq');
+            CREATE INDEX IF NOT EXISTS idx_tokens_change_seq ON tokens(change_seq);
 
-    // 创建sync_status表
-    client.execute(
-        r#"
-        CREATE TABLE IF NOT EXISTS sync_status (
-            id SERIAL PRIMARY KEY,
-            last_sync_at TIMESTAMP WITH TIME ZONE,
-            sync_direction VARCHAR(50),
-            status VARCHAR(50),
-            error_message TEXT,
-            tokens_synced INTEGER DEFAULT 0,
-            created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
-        )
+            CREATE OR REPLACE FUNCTION bump_token_change_seq()
+            RETURNS TRIGGER AS $$
+            BEGIN
+                NEW.change_seq = nextval('tokens_change_seq');
+                RETURN NEW;
+            END;
+            $$ language 'plpgsql';
+
+            DROP TRIGGER IF EXISTS bump_tokens_change_seq ON tokens;
+            CREATE TRIGGER bump_tokens_change_seq
+                BEFORE UPDATE ON tokens
+                FOR EACH ROW
+                EXECUTE FUNCTION bump_token_change_seq();
+
+            -- 数据库标识，客户端据此判断本地水位线是否属于当前数据库
+            CREATE TABLE IF NOT EXISTS sync_metadata (
+                key VARCHAR(255) PRIMARY KEY,
+                value TEXT NOT NULL
+            );
         "#,
-        &[],
-    ).await?;
+        down: r#"
+            DROP TABLE IF EXISTS sync_metadata;
+            DROP TRIGGER IF EXISTS bump_tokens_change_seq ON tokens;
+            DROP FUNCTION IF EXISTS bump_token_change_seq();
+            ALTER TABLE tokens DROP COLUMN IF EXISTS change_seq;
+            DROP SEQUENCE IF EXISTS tokens_change_seq;
+        "#,
+    },
+    // 每行变更后发送 NOTIFY，在线客户端可以实时拉取
+    // payload 为 {"op": "INSERT|UPDATE|DELETE", "id": "..."}，频道名与 TOKENS_CHANGED_CHANNEL 一致
+    Migration {
+        version: 5,
+        name: "token_change_notifications",
+        up: r#"
+            CREATE OR REPLACE FUNCTION notify_tokens_changed()
+            RETURNS TRIGGER AS $$
+            DECLARE
+                token_id TEXT;
+            BEGIN
+                IF TG_OP = 'DELETE' THEN
+                    token_id := OLD.id;
+                ELSE
+                    token_id := NEW.id;
+                END IF;
+                PERFORM pg_notify('tokens_changed', json_build_object('op', TG_OP, 'id', token_id)::text);
+                RETURN NULL;
+            END;
+            $$ language 'plpgsql';
 
-    // 创建updated_at触发器函数
-    client.execute(
-        r#"
-        CREATE OR REPLACE FUNCTION update_updated_at_column()
-        RETURNS TRIGGER AS $$
-        BEGIN
-            NEW.updated_at = NOW();
-            RETURN NEW;
-        END;
-        $$ language 'plpgsql'
+            DROP TRIGGER IF EXISTS notify_tokens_changed ON tokens;
+            CREATE TRIGGER notify_tokens_changed
+                AFTER INSERT OR UPDATE OR DELETE ON tokens
+                FOR EACH ROW
+                EXECUTE FUNCTION notify_tokens_changed();
         "#,
-        &[],
-    ).await?;
+        down: r#"
+            DROP TRIGGER IF EXISTS notify_tokens_changed ON tokens;
+            DROP FUNCTION IF EXISTS notify_tokens_changed();
+        "#,
+    },
+    Migration {
+        version: 6,
+        name: "token_tags_and_group",
+        up: r#"
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS group_name TEXT;
+            CREATE INDEX IF NOT EXISTS idx_tokens_tags ON tokens USING GIN (tags);
+            CREATE INDEX IF NOT EXISTS idx_tokens_group_name ON tokens(group_name);
+        "#,
+        down: r#"
+            DROP INDEX IF EXISTS idx_tokens_group_name;
+            DROP INDEX IF EXISTS idx_tokens_tags;
+            ALTER TABLE tokens DROP COLUMN IF EXISTS group_name;
+            ALTER TABLE tokens DROP COLUMN IF EXISTS tags;
+        "#,
+    },
+    Migration {
+        version: 7,
+        name: "token_events",
+        up: r#"
+            CREATE TABLE IF NOT EXISTS token_events (
+                id VARCHAR(64) PRIMARY KEY,
+                token_id VARCHAR(255) NOT NULL,
+                kind VARCHAR(16) NOT NULL,
+                changes JSONB NOT NULL DEFAULT '[]',
+                device_id VARCHAR(255) NOT NULL,
+                occurred_at TIMESTAMP WITH TIME ZONE NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_token_events_token_time ON token_events(token_id, occurred_at);
+        "#,
+        down: r#"
+            DROP TABLE IF EXISTS token_events;
+        "#,
+    },
+    // 行版本号：每次更新加一，客户端按读到的版本做比较并交换更新
+    Migration {
+        version: 8,
+        name: "token_row_version",
+        up: r#"
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
 
-    // 删除现有触发器（如果存在）
-    client.execute(
-        "DROP TRIGGER IF EXISTS update_tokens_updated_at ON tokens",
-        &[],
-    ).await?;
+            CREATE OR REPLACE FUNCTION bump_token_version()
+            RETURNS TRIGGER AS $$
+            BEGIN
+                NEW.version := OLD.version + 1;
+                RETURN NEW;
+            END;
+            $$ language 'plpgsql';
 
-    // 为tokens表创建触发器
-    client.execute(
-        r#"
-        CREATE TRIGGER update_tokens_updated_at
-            BEFORE UPDATE ON tokens
-            FOR EACH ROW
-            EXECUTE FUNCTION update_updated_at_column()
+            DROP TRIGGER IF EXISTS bump_tokens_version ON tokens;
+            CREATE TRIGGER bump_tokens_version
+                BEFORE UPDATE ON tokens
+                FOR EACH ROW
+                EXECUTE FUNCTION bump_token_version();
         "#,
-        &[],
-    ).await?;
+        down: r#"
+            DROP TRIGGER IF EXISTS bump_tokens_version ON tokens;
+            DROP FUNCTION IF EXISTS bump_token_version();
+            ALTER TABLE tokens DROP COLUMN IF EXISTS version;
+        "#,
+    },
+    Migration {
+        version: 9,
+        name: "token_secret_encryption",
+        up: r#"
+            -- 团队密钥的派生参数和校验值，密钥本身不保存在数据库中
+            CREATE TABLE IF NOT EXISTS secret_keys (
+                key_id TEXT PRIMARY KEY,
+                kdf JSONB NOT NULL,
+                check_value TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                retired_at TIMESTAMPTZ
+            );
 
-    Ok(())
-}
+            -- access_token 加密后无法直接比较，按带密钥的哈希查找重复token
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS access_token_hash TEXT;
+            CREATE INDEX IF NOT EXISTS idx_tokens_access_token_hash ON tokens(tenant_url, access_token_hash);
+        "#,
+        down: r#"
+            DROP INDEX IF EXISTS idx_tokens_access_token_hash;
+            ALTER TABLE tokens DROP COLUMN IF EXISTS access_token_hash;
+            DROP TABLE IF EXISTS secret_keys;
+        "#,
+    },
+    // 工作区：多个团队共用一个数据库，每行属于一个工作区，已有数据归入 default
+    // 行级安全策略按连接参数 app.workspace_id 过滤，建立后默认不生效，需要单独启用；
+    // 该参数由客户端设置，策略只防止误操作，不是访问控制
+    Migration {
+        version: 10,
+        name: "workspaces",
+        up: r#"
+            CREATE TABLE IF NOT EXISTS workspaces (
+                id VARCHAR(64) PRIMARY KEY,
+                name TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+            INSERT INTO workspaces (id, name) VALUES ('default', 'Default') ON CONFLICT (id) DO NOTHING;
 
-pub async fn drop_tables(client: &Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    client.execute("DROP TABLE IF EXISTS sync_status CASCADE", &[]).await?;
-    client.execute("DROP TABLE IF EXISTS tokens CASCADE", &[]).await?;
-    client.execute("DROP FUNCTION IF EXISTS update_updated_at_column() CASCADE", &[]).await?;
-    Ok(())
-}
+            -- 不同工作区可以有相同的token ID，主键改为 (workspace_id, id)
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS workspace_id VARCHAR(64) NOT NULL DEFAULT 'default';
+            ALTER TABLE tokens DROP CONSTRAINT IF EXISTS tokens_pkey;
+            ALTER TABLE tokens ADD PRIMARY KEY (workspace_id, id);
 
-// 添加新字段的迁移函数
-pub async fn add_new_fields_if_not_exist(client: &Client) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // 检查 auth_session 字段是否存在
-    let auth_session_exists = client.query(
-        r#"
-        SELECT EXISTS (
-            SELECT FROM information_schema.columns
-            WHERE table_schema = 'public'
-            AND table_name = 'tokens'
-            AND column_name = 'auth_session'
-        )
+            ALTER TABLE token_tombstones ADD COLUMN IF NOT EXISTS workspace_id VARCHAR(64) NOT NULL DEFAULT 'default';
+            ALTER TABLE token_tombstones DROP CONSTRAINT IF EXISTS token_tombstones_pkey;
+            ALTER TABLE token_tombstones ADD PRIMARY KEY (workspace_id, token_id);
+
+            ALTER TABLE token_events ADD COLUMN IF NOT EXISTS workspace_id VARCHAR(64) NOT NULL DEFAULT 'default';
+            DROP INDEX IF EXISTS idx_token_events_token_time;
+            CREATE INDEX IF NOT EXISTS idx_token_events_workspace_token_time ON token_events(workspace_id, token_id, occurred_at);
+
+            ALTER TABLE sync_status ADD COLUMN IF NOT EXISTS workspace_id VARCHAR(64) NOT NULL DEFAULT 'default';
+            CREATE INDEX IF NOT EXISTS idx_sync_status_workspace_created_at ON sync_status(workspace_id, created_at);
+
+            ALTER TABLE secret_keys ADD COLUMN IF NOT EXISTS workspace_id VARCHAR(64) NOT NULL DEFAULT 'default';
+            CREATE INDEX IF NOT EXISTS idx_secret_keys_workspace ON secret_keys(workspace_id);
+
+            -- 通知中带上工作区，客户端只处理自己工作区的变更
+            CREATE OR REPLACE FUNCTION notify_tokens_changed()
+            RETURNS TRIGGER AS $$
+            DECLARE
+                changed RECORD;
+            BEGIN
+                IF TG_OP = 'DELETE' THEN
+                    changed := OLD;
+                ELSE
+                    changed := NEW;
+                END IF;
+                PERFORM pg_notify('tokens_changed', json_build_object('op', TG_OP, 'id', changed.id, 'workspace', changed.workspace_id)::text);
+                RETURN NULL;
+            END;
+            $$ language 'plpgsql';
+
+            DROP POLICY IF EXISTS workspace_isolation ON tokens;
+            CREATE POLICY workspace_isolation ON tokens
+                USING (workspace_id = current_setting('app.workspace_id', true));
+            DROP POLICY IF EXISTS workspace_isolation ON token_tombstones;
+            CREATE POLICY workspace_isolation ON token_tombstones
+                USING (workspace_id = current_setting('app.workspace_id', true));
+            DROP POLICY IF EXISTS workspace_isolation ON token_events;
+            CREATE POLICY workspace_isolation ON token_events
+                USING (workspace_id = current_setting('app.workspace_id', true));
+            DROP POLICY IF EXISTS workspace_isolation ON sync_status;
+            CREATE POLICY workspace_isolation ON sync_status
+                USING (workspace_id = current_setting('app.workspace_id', true));
+            DROP POLICY IF EXISTS workspace_isolation ON secret_keys;
+            CREATE POLICY workspace_isolation ON secret_keys
+                USING (workspace_id = current_setting('app.workspace_id', true));
         "#,
-        &[],
-    ).await?;
+        // 多个工作区中有相同ID时无法恢复单列主键，回滚会失败并保持原样
+        down: r#"
+            DROP POLICY IF EXISTS workspace_isolation ON secret_keys;
+            DROP POLICY IF EXISTS workspace_isolation ON sync_status;
+            DROP POLICY IF EXISTS workspace_isolation ON token_events;
+            DROP POLICY IF EXISTS workspace_isolation ON token_tombstones;
+            DROP POLICY IF EXISTS workspace_isolation ON tokens;
+            ALTER TABLE secret_keys DISABLE ROW LEVEL SECURITY;
+            ALTER TABLE sync_status DISABLE ROW LEVEL SECURITY;
+            ALTER TABLE token_events DISABLE ROW LEVEL SECURITY;
+            ALTER TABLE token_tombstones DISABLE ROW LEVEL SECURITY;
+            ALTER TABLE tokens DISABLE ROW LEVEL SECURITY;
 
-    if let Some(row) = auth_session_exists.first() {
-        let exists: bool = row.get(0);
-        if !exists {
-            client.execute(
-                "ALTER TABLE tokens ADD COLUMN auth_session TEXT",
-                &[],
-            ).await?;
-            println!("Added auth_session column to tokens table");
-        }
-    }
+            CREATE OR REPLACE FUNCTION notify_tokens_changed()
+            RETURNS TRIGGER AS $$
+            DECLARE
+                token_id TEXT;
+            BEGIN
+                IF TG_OP = 'DELETE' THEN
+                    token_id := OLD.id;
+                ELSE
+                    token_id := NEW.id;
+                END IF;
+                PERFORM pg_notify('tokens_changed', json_build_object('op', TG_OP, 'id', token_id)::text);
+                RETURN NULL;
+            END;
+            $$ language 'plpgsql';
 
-    // 检查 suspensions 字段是否存在
-    let suspensions_exists = client.query(
-        r#"
-        SELECT EXISTS (
-            SELECT FROM information_schema.columns
-            WHERE table_schema = 'public'
-            AND table_name = 'tokens'
-            AND column_name = 'suspensions'
-        )
+            DROP INDEX IF EXISTS idx_secret_keys_workspace;
+            ALTER TABLE secret_keys DROP COLUMN IF EXISTS workspace_id;
+            DROP INDEX IF EXISTS idx_sync_status_workspace_created_at;
+            ALTER TABLE sync_status DROP COLUMN IF EXISTS workspace_id;
+            DROP INDEX IF EXISTS idx_token_events_workspace_token_time;
+            ALTER TABLE token_events DROP COLUMN IF EXISTS workspace_id;
+            CREATE INDEX IF NOT EXISTS idx_token_events_token_time ON token_events(token_id, occurred_at);
+
+            ALTER TABLE token_tombstones DROP CONSTRAINT IF EXISTS token_tombstones_pkey;
+            ALTER TABLE token_tombstones DROP COLUMN IF EXISTS workspace_id;
+            ALTER TABLE token_tombstones ADD PRIMARY KEY (token_id);
+
+            ALTER TABLE tokens DROP CONSTRAINT IF EXISTS tokens_pkey;
+            ALTER TABLE tokens DROP COLUMN IF EXISTS workspace_id;
+            ALTER TABLE tokens ADD PRIMARY KEY (id);
+
+            DROP TABLE IF EXISTS workspaces;
+        "#,
+    },
+    // 同步错误按token逐行记录，旧记录的 error_message 迁移为一行不带token的错误
+    Migration {
+        version: 11,
+        name: "sync_errors",
+        up: r#"
+            CREATE TABLE IF NOT EXISTS sync_errors (
+                id BIGSERIAL PRIMARY KEY,
+                sync_id INTEGER NOT NULL REFERENCES sync_status(id) ON DELETE CASCADE,
+                workspace_id VARCHAR(64) NOT NULL DEFAULT 'default',
+                token_id VARCHAR(255),
+                code VARCHAR(32) NOT NULL,
+                message TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_sync_errors_sync_id ON sync_errors(sync_id);
+
+            INSERT INTO sync_errors (sync_id, workspace_id, code, message)
+            SELECT id, workspace_id, 'backend', error_message
+            FROM sync_status
+            WHERE error_message IS NOT NULL AND error_message <> '';
+
+            DROP POLICY IF EXISTS workspace_isolation ON sync_errors;
+            CREATE POLICY workspace_isolation ON sync_errors
+                USING (workspace_id = current_setting('app.workspace_id', true));
+
+            -- 已经启用行级安全时新表同样启用
+            DO $$
+            BEGIN
+                IF (SELECT relrowsecurity FROM pg_class WHERE oid = 'sync_status'::regclass) THEN
+                    ALTER TABLE sync_errors ENABLE ROW LEVEL SECURITY;
+                    ALTER TABLE sync_errors FORCE ROW LEVEL SECURITY;
+                END IF;
+            END
+            $$;
+        "#,
+        down: r#"
+            DROP TABLE IF EXISTS sync_errors;
         "#,
+    },
+    // change_seq 在写入时分配而不是在提交时，晚提交的事务可能持有比已读到的更小的序号；
+    // 记录写入每行的事务ID，增量同步从上次读取时仍未提交的最早事务开始重新读取
+    Migration {
+        version: 12,
+        name: "token_change_xid",
+        up: r#"
+            ALTER TABLE tokens ADD COLUMN IF NOT EXISTS change_xid xid8 NOT NULL DEFAULT pg_current_xact_id();
+            CREATE INDEX IF NOT EXISTS idx_tokens_change_xid ON tokens(change_xid);
+
+            CREATE OR REPLACE FUNCTION bump_token_change_seq()
+            RETURNS TRIGGER AS $$
+            BEGIN
+                NEW.change_seq = nextval('tokens_change_seq');
+                NEW.change_xid = pg_current_xact_id();
+                RETURN NEW;
+            END;
+            $$ language 'plpgsql';
+        "#,
+        down: r#"
+            CREATE OR REPLACE FUNCTION bump_token_change_seq()
+            RETURNS TRIGGER AS $$
+            BEGIN
+                NEW.change_seq = nextval('tokens_change_seq');
+                RETURN NEW;
+            END;
+            $$ language 'plpgsql';
+
+            DROP INDEX IF EXISTS idx_tokens_change_xid;
+            ALTER TABLE tokens DROP COLUMN IF EXISTS change_xid;
+        "#,
+    },
+];
+
+// 迁移期间持有的事务级咨询锁，防止多个客户端同时迁移同一个数据库
+const MIGRATION_LOCK_ID: i64 = 7_421_783_112;
+
+/// 当前程序支持的最新结构版本
+pub fn latest_schema_version() -> i64 {
+    MIGRATIONS.last().map(|migration| migration.version).unwrap_or(0)
+}
+
+/// 数据库结构版本信息
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaVersion {
+    /// 数据库当前的结构版本，0 表示尚未迁移
+    pub current: i64,
+    /// 当前程序支持的最新版本
+    pub latest: i64,
+}
+
+pub async fn schema_version(client: &Client) -> StorageResult<SchemaVersion> {
+    Ok(SchemaVersion {
+        current: current_schema_version(client).await?,
+        latest: latest_schema_version(),
+    })
+}
+
+/// 读取数据库已应用的最高迁移版本，没有版本表时返回 0
+pub async fn current_schema_version(client: &Client) -> StorageResult<i64> {
+    let row = client.query_one(
+        "SELECT to_regclass('public.schema_migrations') IS NOT NULL",
         &[],
     ).await?;
+    if !row.get::<_, bool>(0) {
+        return Ok(0);
+    }
 
-    if let Some(row) = suspensions_exists.first() {
-        let exists: bool = row.get(0);
-        if !exists {
-            client.execute(
-                "ALTER TABLE tokens ADD COLUMN suspensions JSONB",
-                &[],
-            ).await?;
-            println!("Added suspensions column to tokens table");
-        }
+    applied_version(client).await
+}
+
+async fn applied_version(client: &impl GenericClient) -> StorageResult<i64> {
+    let row = client.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[]).await?;
+    Ok(row.get(0))
+}
+
+// 数据库结构比程序新时拒绝操作，避免旧程序按旧结构读写
+fn ensure_supported(version: i64) -> StorageResult<()> {
+    let latest = latest_schema_version();
+    if version > latest {
+        return Err(StorageError::Incompatible(format!(
+            "Database schema version {} is newer than the latest version {} supported by this app, please upgrade the app",
+            version, latest
+        )));
     }
+    Ok(())
+}
 
-    // 检查 balance_color_mode 字段是否存在
-    let balance_color_mode_exists = client.query(
+/// 依次执行所有未应用的迁移，每个迁移一个事务，返回迁移后的版本
+pub async fn run_migrations(client: &mut Client) -> StorageResult<i64> {
+    client.batch_execute(
         r#"
-        SELECT EXISTS (
-            SELECT FROM information_schema.columns
-            WHERE table_schema = 'public'
-            AND table_name = 'tokens'
-            AND column_name = 'balance_color_mode'
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
         )
         "#,
-        &[],
     ).await?;
 
-    if let Some(row) = balance_color_mode_exists.first() {
-        let exists: bool = row.get(0);
-        if !exists {
-            client.execute(
-                "ALTER TABLE tokens ADD COLUMN balance_color_mode TEXT",
-                &[],
-            ).await?;
-            println!("Added balance_color_mode column to tokens table");
+    let start_version = applied_version(client).await?;
+    ensure_supported(start_version)?;
+
+    let mut version = start_version;
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > start_version) {
+        let tx = client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID]).await?;
+
+        // 等锁期间其他客户端可能已经执行了这个迁移
+        let applied = applied_version(&tx).await?;
+        ensure_supported(applied)?;
+        if applied >= migration.version {
+            tx.commit().await?;
+            version = applied;
+            continue;
         }
+
+        tx.batch_execute(migration.up).await?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+            &[&migration.version, &migration.name],
+        ).await?;
+        tx.commit().await?;
+
+        println!("Applied database migration {} ({})", migration.version, migration.name);
+        version = migration.version;
     }
 
-    // 检查 skip_check 字段是否存在
-    let skip_check_exists = client.query(
-        r#"
-        SELECT EXISTS (
-            SELECT FROM information_schema.columns
-            WHERE table_schema = 'public'
-            AND table_name = 'tokens'
-            AND column_name = 'skip_check'
-        )
-        "#,
-        &[],
-    ).await?;
+    Ok(version)
+}
 
-    if let Some(row) = skip_check_exists.first() {
-        let exists: bool = row.get(0);
-        if !exists {
-            client.execute(
-                "ALTER TABLE tokens ADD COLUMN skip_check BOOLEAN",
-                &[],
-            ).await?;
-            println!("Added skip_check column to tokens table");
+/// 按相反顺序回滚迁移，直到版本不高于 `target`，返回回滚后的版本
+pub async fn rollback_migrations(client: &mut Client, target: i64) -> StorageResult<i64> {
+    let start_version = current_schema_version(client).await?;
+    ensure_supported(start_version)?;
+
+    let mut version = start_version;
+    for migration in MIGRATIONS.iter().rev().filter(|migration| migration.version > target && migration.version <= start_version) {
+        let tx = client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID]).await?;
+
+        let deleted = tx.execute(
+            "DELETE FROM schema_migrations WHERE version = $1",
+            &[&migration.version],
+        ).await?;
+        if deleted > 0 {
+            tx.batch_execute(migration.down).await?;
         }
+        tx.commit().await?;
+
+        println!("Rolled back database migration {} ({})", migration.version, migration.name);
+        version = migration.version - 1;
     }
 
+    Ok(version)
+}
+
+/// 执行迁移前检查tokens表是否已存在，返回值表示是否有需要同步的已有数据
+pub async fn prepare_schema(client: &mut Client) -> StorageResult<bool> {
+    let tables_existed = check_tables_exist(client).await?;
+    run_migrations(client).await?;
+    Ok(tables_existed)
+}
+
+pub async fn drop_tables(client: &mut Client) -> StorageResult<()> {
+    rollback_migrations(client, 0).await?;
+    client.execute("DROP TABLE IF EXISTS schema_migrations", &[]).await?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::database::WORKSPACE_SETTING;
     use tokio_postgres::{NoTls, Config};
 
-    async fn get_test_client() -> Result<Client, Box<dyn std::error::Error + Send + Sync>> {
+    async fn get_test_client() -> StorageResult<Client> {
         // 这里需要一个测试数据库连接
         // 在实际测试中，你需要设置一个测试数据库
         let mut config = Config::new();
@@ -237,20 +620,49 @@ mod tests {
         Ok(client)
     }
 
+    #[test]
+    fn test_migrations_are_ordered() {
+        let versions: Vec<i64> = MIGRATIONS.iter().map(|migration| migration.version).collect();
+        let expected: Vec<i64> = (1..=MIGRATIONS.len() as i64).collect();
+        assert_eq!(versions, expected);
+        assert_eq!(latest_schema_version(), MIGRATIONS.len() as i64);
+    }
+
+    #[test]
+    fn test_notify_migration_uses_channel() {
+        let migration = MIGRATIONS.iter().find(|migration| migration.name == "token_change_notifications").unwrap();
+        assert!(migration.up.contains(&format!("pg_notify('{}'", TOKENS_CHANGED_CHANNEL)));
+
+        let migration = MIGRATIONS.iter().find(|migration| migration.name == "workspaces").unwrap();
+        assert!(migration.up.contains(&format!("pg_notify('{}'", TOKENS_CHANGED_CHANNEL)));
+        assert!(migration.up.contains(&format!("current_setting('{}', true)", WORKSPACE_SETTING)));
+    }
+
+    #[test]
+    fn test_newer_schema_is_refused() {
+        assert!(ensure_supported(latest_schema_version()).is_ok());
+        let error = ensure_supported(latest_schema_version() + 1).unwrap_err();
+        assert_eq!(error.code(), "incompatible");
+    }
+
     #[tokio::test]
     #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
-    async fn test_create_tables() {
-        let client = get_test_client().await.unwrap();
-        let result = create_tables(&client).await;
-        assert!(result.is_ok());
+    async fn test_run_migrations() {
+        let mut client = get_test_client().await.unwrap();
+        let version = run_migrations(&mut client).await.unwrap();
+        assert_eq!(version, latest_schema_version());
+
+        // 重复执行不会再应用任何迁移
+        assert_eq!(run_migrations(&mut client).await.unwrap(), version);
     }
 
     #[tokio::test]
     #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
     async fn test_drop_tables() {
-        let client = get_test_client().await.unwrap();
-        let _ = create_tables(&client).await;
-        let result = drop_tables(&client).await;
+        let mut client = get_test_client().await.unwrap();
+        let _ = run_migrations(&mut client).await;
+        let result = drop_tables(&mut client).await;
         assert!(result.is_ok());
+        assert_eq!(current_schema_version(&client).await.unwrap(), 0);
     }
 }
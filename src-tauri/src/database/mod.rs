@@ -1,7 +1,9 @@
 pub mod config;
 pub mod connection;
 pub mod migrations;
+pub mod workspace;
 
 pub use config::*;
 pub use connection::*;
 pub use migrations::*;
+pub use workspace::*;
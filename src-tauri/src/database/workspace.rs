@@ -0,0 +1,113 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio_postgres::Client;
+use crate::storage::{StorageError, StorageResult};
+
+/// 未配置工作区时使用的工作区，升级前的数据都归入这里
+pub const DEFAULT_WORKSPACE: &str = "default";
+
+/// 连接级设置项，行级安全策略按它过滤当前工作区的行；客户端可以随意修改，不能用于访问控制
+pub const WORKSPACE_SETTING: &str = "app.workspace_id";
+
+// 按工作区隔离的表，行级安全策略建立在这些表上
+const WORKSPACE_TABLES: &[&str] = &["tokens", "token_tombstones", "token_events", "sync_status", "sync_errors", "secret_keys"];
+
+const MAX_WORKSPACE_ID_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkspaceInfo {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 工作区ID只允许字母、数字、`-` 和 `_`，它会出现在连接参数中
+pub fn validate_workspace_id(workspace_id: &str) -> StorageResult<()> {
+    if workspace_id.is_empty() || workspace_id.len() > MAX_WORKSPACE_ID_LEN {
+        return Err(StorageError::InvalidInput(format!(
+            "Workspace id must be 1 to {} characters long", MAX_WORKSPACE_ID_LEN
+        )));
+    }
+    if !workspace_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(StorageError::InvalidInput(format!(
+            "Workspace id {:?} may only contain letters, digits, '-' and '_'", workspace_id
+        )));
+    }
+    Ok(())
+}
+
+/// 建立连接时设置当前工作区的启动参数
+pub fn workspace_connection_options(workspace_id: &str) -> String {
+    format!("-c {}={}", WORKSPACE_SETTING, workspace_id)
+}
+
+pub async fn list_workspaces(client: &Client) -> StorageResult<Vec<WorkspaceInfo>> {
+    let rows = client.query("SELECT id, name, created_at FROM workspaces ORDER BY created_at, id", &[]).await?;
+
+    Ok(rows.iter()
+        .map(|row| WorkspaceInfo {
+            id: row.get(0),
+            name: row.get(1),
+            created_at: row.get(2),
+        })
+        .collect())
+}
+
+/// 登记工作区，已存在时保持原名称
+pub async fn ensure_workspace(client: &Client, workspace_id: &str, name: Option<&str>) -> StorageResult<()> {
+    validate_workspace_id(workspace_id)?;
+    client.execute(
+        "INSERT INTO workspaces (id, name) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING",
+        &[&workspace_id, &name.unwrap_or(workspace_id)],
+    ).await?;
+    Ok(())
+}
+
+/// 启用或关闭按工作区隔离的行级安全策略
+///
+/// 启用后表的所有者也受策略约束（FORCE），但超级用户和带 BYPASSRLS 的角色仍然可以看到全部行。
+///
+/// **这不是安全边界。** 策略按 `app.workspace_id` 过滤，而这个设置由客户端自己提供，
+/// 任何能连接数据库的客户端都可以执行 `SET app.workspace_id = '...'` 读写其他工作区。
+/// 它只防止应用程序的查询遗漏工作区条件时误读误写其他工作区的数据。
+/// 需要真正隔离的团队应使用各自的数据库，或为每个工作区建立单独的数据库角色并按 `current_user` 授权。
+pub async fn set_row_level_security(client: &mut Client, enabled: bool) -> StorageResult<()> {
+    let action = if enabled { "ENABLE" } else { "DISABLE" };
+    let force = if enabled { "FORCE" } else { "NO FORCE" };
+
+    let tx = client.transaction().await?;
+    for table in WORKSPACE_TABLES {
+        tx.batch_execute(&format!(
+            "ALTER TABLE {0} {1} ROW LEVEL SECURITY; ALTER TABLE {0} {2} ROW LEVEL SECURITY;",
+            table, action, force
+        )).await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// 行级安全策略是否已在全部工作区表上启用
+pub async fn row_level_security_enabled(client: &Client) -> StorageResult<bool> {
+    let tables: Vec<&str> = WORKSPACE_TABLES.to_vec();
+    let row = client.query_one(
+        "SELECT COUNT(*) FROM pg_class WHERE relname = ANY($1) AND relnamespace = 'public'::regnamespace AND relrowsecurity",
+        &[&tables],
+    ).await?;
+    Ok(row.get::<_, i64>(0) == WORKSPACE_TABLES.len() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_workspace_id() {
+        assert!(validate_workspace_id("default").is_ok());
+        assert!(validate_workspace_id("team-a_2").is_ok());
+        assert_eq!(validate_workspace_id("").unwrap_err().code(), "invalid_input");
+        assert_eq!(validate_workspace_id("team a").unwrap_err().code(), "invalid_input");
+        assert_eq!(validate_workspace_id("a'; DROP TABLE tokens; --").unwrap_err().code(), "invalid_input");
+        assert_eq!(validate_workspace_id(&"a".repeat(65)).unwrap_err().code(), "invalid_input");
+    }
+}
@@ -16,7 +16,7 @@ use augment_user_info::{get_user_info, get_user_info_with_app_session, CompleteU
 use bookmarks::{BookmarkManager, Bookmark};
 use http_server::HttpServer;
 use database::{DatabaseConfig, DatabaseConfigManager, DatabaseManager};
-use storage::{DualStorage, LocalFileStorage, PostgreSQLStorage, TokenStorage, SyncManager};
+use storage::{DualStorage, LocalFileStorage, SqliteStorage, PostgreSQLStorage, TokenStorage, SyncManager, TokenCipher, SecretCipher, Outbox, TokenEventLog, SnapshotManager, SyncJournal, LocalStorageBackend, run_change_feed, run_snapshot_schedule, run_sync_scheduler, run_file_watcher, StorageConfig, StorageConfigManager, StorageError, StorageResult};
 use std::sync::{Arc, Mutex};
 use std::path::PathBuf;
 use std::collections::HashMap;
@@ -63,6 +63,18 @@ struct AppState {
     http_server: Mutex<Option<HttpServer>>,
     storage_manager: Arc<Mutex<Option<Arc<DualStorage>>>>,
     database_manager: Arc<Mutex<Option<Arc<DatabaseManager>>>>,
+    // 数据库变更监听任务
+    change_feed: Mutex<Option<tauri::async_runtime::JoinHandle<()>>>,
+    // 定时快照任务
+    snapshot_schedule: Mutex<Option<tauri::async_runtime::JoinHandle<()>>>,
+    // 自动同步任务
+    sync_scheduler: Mutex<Option<tauri::async_runtime::JoinHandle<()>>>,
+    // tokens.json 外部修改监视任务，仅 JSON 文件后端启用
+    file_watcher: Mutex<Option<tauri::async_runtime::JoinHandle<()>>>,
+    // tokens.json 的加密口令（仅保存在内存中）
+    token_cipher: Mutex<Option<Arc<TokenCipher>>>,
+    // PostgreSQL 中凭据的团队密钥（仅保存在内存中）
+    database_secrets: Mutex<Option<Arc<SecretCipher>>>,
     // App session 缓存: key 为 auth_session, value 为缓存的 app_session
     app_session_cache: Arc<Mutex<HashMap<String, AppSessionCache>>>,
     // 邮箱助手相关状态
@@ -430,114 +442,65 @@ async fn open_url(app: tauri::AppHandle, url: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-async fn save_tokens_json(json_string: String, app: tauri::AppHandle) -> Result<(), String> {
-    use std::fs;
-    use std::io::Write;
-
-    // 获取应用数据目录
-    let app_data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
-
-    // 确保目录存在
-    fs::create_dir_all(&app_data_dir)
-        .map_err(|e| format!("Failed to create app data directory: {}", e))?;
-
-    let storage_path = app_data_dir.join("tokens.json");
-    let temp_path = storage_path.with_extension("tmp");
-
-    // 基本的 JSON 格式验证
-    serde_json::from_str::<serde_json::Value>(&json_string)
-        .map_err(|e| format!("Invalid JSON format: {}", e))?;
+async fn save_tokens_json(
+    json_string: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    // 整组替换，有任一条目无效时不写入，错误中逐条列出无效条目
+    let value = serde_json::from_str::<serde_json::Value>(&json_string)?;
+    let entries = value.as_array()
+        .ok_or_else(|| StorageError::InvalidInput("Expected a JSON array of tokens".to_string()))?;
+    let tokens = storage::convert_legacy_tokens_strict(entries)?;
 
-    // 原子性写入：先写临时文件，再重命名
-    {
-        let mut temp_file = fs::File::create(&temp_path)
-            .map_err(|e| format!("Failed to create temp file: {}", e))?;
+    // 通过存储管理器写入，记录修改历史并把修改排入发件箱
+    let storage_manager = ensure_storage_manager(&app, &state).await?;
+    storage_manager.replace_tokens(&tokens).await
+}
 
-        temp_file.write_all(json_string.as_bytes())
-            .map_err(|e| format!("Failed to write temp file: {}", e))?;
 
-        temp_file.sync_all()
-            .map_err(|e| format!("Failed to sync temp file: {}", e))?;
+#[tauri::command]
+async fn load_tokens_json(app: tauri::AppHandle, state: State<'_, AppState>) -> Result<String, StorageError> {
+    // JSON 文件后端：新目录中还没有 tokens.json 时从旧目录迁移
+    if load_local_backend(&app) == LocalStorageBackend::JsonFile {
+        migrate_old_tokens_file(&app)?;
     }
 
-    // 原子性重命名
-    fs::rename(&temp_path, &storage_path)
-        .map_err(|e| format!("Failed to rename temp file: {}", e))?;
+    let storage_manager = ensure_storage_manager(&app, &state).await?;
+    let tokens = storage_manager.load_local_tokens().await?;
 
-    Ok(())
+    let legacy_tokens: Vec<serde_json::Value> = tokens.iter()
+        .map(storage::convert_to_legacy_format)
+        .collect();
+    Ok(serde_json::to_string_pretty(&legacy_tokens)?)
 }
 
-
-#[tauri::command]
-async fn load_tokens_json(app: tauri::AppHandle) -> Result<String, String> {
+// 把旧应用数据目录中的 tokens.json 复制到新目录，新目录已有文件时不做任何事
+fn migrate_old_tokens_file(app: &tauri::AppHandle) -> StorageResult<()> {
     use std::fs;
 
-    // 获取新的应用数据目录
-    let new_app_data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
-
-    let new_storage_path = new_app_data_dir.join("tokens.json");
-
-    println!("尝试读取新文件路径: {:?}", new_storage_path);
-
-    // 首先尝试从新目录读取
+    let new_storage_path = app.path().app_data_dir()?.join("tokens.json");
     if new_storage_path.exists() {
-        let content = fs::read_to_string(&new_storage_path)
-            .map_err(|e| format!("Failed to read tokens file: {}", e))?;
-
-        println!("从新目录读取到的文件内容: {}", content);
-
-        // 如果文件为空，返回空数组的 JSON
-        if content.trim().is_empty() {
-            return Ok("[]".to_string());
-        }
-
-        return process_token_content(content);
+        return Ok(());
     }
 
-    // 如果新目录没有文件，尝试从旧目录读取
-    println!("新目录中没有文件，尝试从旧目录读取...");
-
     // 构造旧的应用数据目录路径
-    let old_app_data_dir = get_old_app_data_dir()?;
+    let old_app_data_dir = get_old_app_data_dir().map_err(StorageError::Unavailable)?;
     let old_storage_path = old_app_data_dir.join("tokens.json");
+    if !old_storage_path.exists() {
+        return Ok(());
+    }
 
-    println!("尝试读取旧文件路径: {:?}", old_storage_path);
-
-    if old_storage_path.exists() {
-        let content = fs::read_to_string(&old_storage_path)
-            .map_err(|e| format!("Failed to read old tokens file: {}", e))?;
-
-        println!("从旧目录读取到的文件内容: {}", content);
-
-        // 如果文件为空，返回空数组的 JSON
-        if content.trim().is_empty() {
-            return Ok("[]".to_string());
-        }
-
-        // 创建新目录（如果不存在）
-        if let Some(parent) = new_storage_path.parent() {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create new app data directory: {}", e))?;
-        }
-
-        // 将文件迁移到新目录
-        fs::copy(&old_storage_path, &new_storage_path)
-            .map_err(|e| format!("Failed to migrate tokens file: {}", e))?;
-
-        println!("文件已迁移到新目录: {:?}", new_storage_path);
-
-        return process_token_content(content);
+    // 创建新目录（如果不存在）
+    if let Some(parent) = new_storage_path.parent() {
+        fs::create_dir_all(parent)?;
     }
 
-    // 两个目录都没有文件
-    println!("新旧目录都没有找到 tokens.json 文件");
-    Ok("[]".to_string())
+    // 将文件迁移到新目录
+    fs::copy(&old_storage_path, &new_storage_path)?;
+
+    println!("文件已迁移到新目录: {:?}", new_storage_path);
+    Ok(())
 }
 
 // 获取旧的应用数据目录
@@ -572,49 +535,6 @@ fn get_old_app_data_dir() -> Result<PathBuf, String> {
     Ok(old_path)
 }
 
-// 处理 token 内容的通用函数
-fn process_token_content(content: String) -> Result<String, String> {
-    // 尝试解析 JSON 内容
-    match serde_json::from_str::<serde_json::Value>(&content) {
-        Ok(value) => {
-            // 如果解析成功，检查是否需要转换格式
-            match value {
-                serde_json::Value::Array(_) => {
-                    // 如果已经是数组格式，直接返回原内容
-                    Ok(content)
-                }
-                serde_json::Value::Object(ref obj) => {
-                    // 检查是否是旧格式 {tokens: [...]}
-                    if let Some(tokens_array) = obj.get("tokens") {
-                        if tokens_array.is_array() {
-                            // 旧格式，提取 tokens 数组
-                            Ok(serde_json::to_string_pretty(tokens_array)
-                                .map_err(|e| format!("Failed to serialize tokens: {}", e))?)
-                        } else {
-                            Ok("[]".to_string())
-                        }
-                    } else {
-                        // 如果是单个对象格式，包装成数组
-                        let array = serde_json::Value::Array(vec![value]);
-                        Ok(serde_json::to_string_pretty(&array)
-                            .map_err(|e| format!("Failed to serialize tokens: {}", e))?)
-                    }
-                }
-                _ => {
-                    // 其他格式，返回空数组
-                    Ok("[]".to_string())
-                }
-            }
-        }
-        Err(_) => {
-            // 如果 JSON 解析失败，可能是其他格式的旧数据，返回空数组
-            Ok("[]".to_string())
-        }
-    }
-}
-
-
-
 // Bookmark management commands
 #[tauri::command]
 async fn add_bookmark(
@@ -2187,11 +2107,12 @@ async fn save_database_config(
     username: String,
     password: String,
     ssl_mode: Option<String>,
+    workspace: Option<String>,
     app: tauri::AppHandle,
     state: State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<(), StorageError> {
     let config_manager = DatabaseConfigManager::new(&app)
-        .map_err(|e| format!("Failed to create config manager: {}", e))?;
+        .map_err(StorageError::Backend)?;
 
     let ssl_mode = match ssl_mode.as_deref() {
         Some("disable") => database::SslMode::Disable,
@@ -2199,91 +2120,79 @@ async fn save_database_config(
         _ => database::SslMode::Prefer,
     };
 
-    let config = DatabaseConfig::new_with_ssl(host, port, database, username, password, ssl_mode);
+    // 未指定工作区时沿用之前保存的工作区
+    let workspace = match workspace {
+        Some(workspace) => workspace,
+        None => config_manager.load_config()
+            .map(|config| config.workspace)
+            .unwrap_or_else(|_| database::DEFAULT_WORKSPACE.to_string()),
+    };
+    database::validate_workspace_id(&workspace)?;
+
+    let config = DatabaseConfig::new_with_ssl(host, port, database, username, password, ssl_mode)
+        .with_workspace(workspace);
 
     config_manager.save_config(&config)
-        .map_err(|e| format!("Failed to save config: {}", e))?;
+        .map_err(StorageError::Backend)?;
 
     // 尝试初始化数据库连接
     let mut db_manager = DatabaseManager::new(config);
-    match db_manager.initialize().await {
-        Ok(_) => {
-            // 检查数据库表是否已存在
-            if let Some(pool) = db_manager.get_pool() {
-                let client = pool.get().await
-                    .map_err(|e| format!("Failed to get database client: {}", e))?;
-
-                let tables_exist = database::check_tables_exist(&client).await
-                    .map_err(|e| format!("Failed to check tables: {}", e))?;
-
-                if !tables_exist {
-                    // 表不存在，创建表
-                    database::create_tables(&client).await
-                        .map_err(|e| format!("Failed to create tables: {}", e))?;
-                } else {
-                    // 表已存在，检查并添加新字段
-                    println!("Database tables already exist, checking for new fields");
-                    database::add_new_fields_if_not_exist(&client).await
-                        .map_err(|e| format!("Failed to add new fields: {}", e))?;
-                }
-            }
+    db_manager.initialize().await?;
 
-            // 更新应用状态
-            *state.database_manager.lock().unwrap() = Some(Arc::new(db_manager));
+    // 创建表或执行未应用的结构迁移
+    db_manager.migrate().await?;
+    db_manager.ensure_workspace(None).await?;
 
-            // 重新初始化存储管理器
-            initialize_storage_manager(&app, &state).await
-                .map_err(|e| format!("Failed to initialize storage: {}", e))?;
+    // 更新应用状态
+    *state.database_manager.lock().unwrap() = Some(Arc::new(db_manager));
 
-            // 如果表已存在，执行初始同步
-            let pool_option = {
-                let db_guard = state.database_manager.lock().unwrap();
-                db_guard.as_ref().and_then(|db| db.get_pool())
-            };
+    // 重新初始化存储管理器
+    initialize_storage_manager(&app, &state).await?;
 
-            if let Some(pool) = pool_option {
-                let client = pool.get().await
-                    .map_err(|e| format!("Failed to get database client for sync check: {}", e))?;
+    // 如果表已存在，执行初始同步
+    let pool_option = {
+        let db_guard = state.database_manager.lock().unwrap();
+        db_guard.as_ref().and_then(|db| db.get_pool())
+    };
 
-                let tables_exist = database::check_tables_exist(&client).await
-                    .map_err(|e| format!("Failed to check tables for sync: {}", e))?;
+    if let Some(pool) = pool_option {
+        let client = pool.get().await?;
 
-                if tables_exist {
-                    // 执行初始双向同步
-                    let storage_manager = {
-                        let storage_guard = state.storage_manager.lock().unwrap();
-                        storage_guard.as_ref().cloned()
-                    };
+        let tables_exist = database::check_tables_exist(&client).await?;
 
-                    if let Some(storage_manager) = storage_manager {
-                        match storage_manager.bidirectional_sync().await {
-                            Ok(sync_result) => {
-                                println!("Initial sync completed: {} tokens synced", sync_result.tokens_synced);
-                            }
-                            Err(e) => {
-                                eprintln!("Initial sync failed: {}", e);
-                                // 同步失败不影响配置保存
-                            }
-                        }
+        if tables_exist {
+            // 执行初始双向同步
+            let storage_manager = {
+                let storage_guard = state.storage_manager.lock().unwrap();
+                storage_guard.as_ref().cloned()
+            };
+
+            if let Some(storage_manager) = storage_manager {
+                match storage_manager.bidirectional_sync().await {
+                    Ok(sync_result) => {
+                        println!("Initial sync completed: {} tokens synced", sync_result.tokens_synced);
+                    }
+                    Err(e) => {
+                        eprintln!("Initial sync failed: {}", e);
+                        // 同步失败不影响配置保存
                     }
                 }
             }
-
-            Ok(())
         }
-        Err(e) => Err(format!("Failed to connect to database: {}", e))
     }
+
+    Ok(())
 }
 
 #[tauri::command]
 async fn load_database_config(
     app: tauri::AppHandle,
-) -> Result<DatabaseConfig, String> {
+) -> Result<DatabaseConfig, StorageError> {
     let config_manager = DatabaseConfigManager::new(&app)
-        .map_err(|e| format!("Failed to create config manager: {}", e))?;
+        .map_err(StorageError::Backend)?;
 
     config_manager.load_config()
-        .map_err(|e| format!("Failed to load config: {}", e))
+        .map_err(StorageError::Backend)
 }
 
 #[tauri::command]
@@ -2294,7 +2203,7 @@ async fn test_database_connection(
     username: String,
     password: String,
     ssl_mode: Option<String>,
-) -> Result<(), String> {
+) -> Result<(), StorageError> {
     let ssl_mode = match ssl_mode.as_deref() {
         Some("disable") => database::SslMode::Disable,
         Some("require") => database::SslMode::Require,
@@ -2304,107 +2213,113 @@ async fn test_database_connection(
     let config = DatabaseConfig::new_with_ssl(host, port, database, username, password, ssl_mode);
 
     database::test_database_connection(&config).await
-        .map_err(|e| format!("Connection test failed: {}", e))
 }
 
 #[tauri::command]
 async fn delete_database_config(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<(), StorageError> {
     let config_manager = DatabaseConfigManager::new(&app)
-        .map_err(|e| format!("Failed to create config manager: {}", e))?;
+        .map_err(StorageError::Backend)?;
 
     config_manager.delete_config()
-        .map_err(|e| format!("Failed to delete config: {}", e))?;
+        .map_err(StorageError::Backend)?;
 
     // 清除应用状态中的数据库管理器
     *state.database_manager.lock().unwrap() = None;
 
     // 重新初始化存储管理器（仅本地存储）
     initialize_storage_manager(&app, &state).await
-        .map_err(|e| format!("Failed to reinitialize storage: {}", e))?;
-
-    Ok(())
 }
 
 // 同步相关命令
 #[tauri::command]
 async fn sync_tokens_to_database(
     state: State<'_, AppState>,
-) -> Result<storage::SyncStatus, String> {
-    let storage_manager = {
-        let guard = state.storage_manager.lock().unwrap();
-        guard.clone().ok_or("Storage manager not initialized")?
-    };
+) -> Result<storage::SyncStatus, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
 
     storage_manager.sync_local_to_remote().await
-        .map_err(|e| format!("Sync failed: {}", e))
 }
 
 #[tauri::command]
 async fn sync_tokens_from_database(
     state: State<'_, AppState>,
-) -> Result<storage::SyncStatus, String> {
-    let storage_manager = {
-        let guard = state.storage_manager.lock().unwrap();
-        guard.clone().ok_or("Storage manager not initialized")?
-    };
+) -> Result<storage::SyncStatus, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
 
     storage_manager.sync_remote_to_local().await
-        .map_err(|e| format!("Sync failed: {}", e))
 }
 
 #[tauri::command]
 async fn delete_token(
     token_id: String,
     state: State<'_, AppState>,
-) -> Result<bool, String> {
-    let storage_manager = {
-        let guard = state.storage_manager.lock().unwrap();
-        guard.clone().ok_or("Storage manager not initialized")?
-    };
+) -> Result<bool, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
 
     storage_manager.delete_token(&token_id).await
-        .map_err(|e| format!("Delete failed: {}", e))
+}
+
+#[tauri::command]
+async fn update_token(
+    token: storage::TokenData,
+    state: State<'_, AppState>,
+) -> Result<storage::TokenData, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+
+    // 其他客户端已修改过时返回 conflict，前端需要重新加载后再修改
+    storage_manager.update_token(&token).await?;
+    storage_manager.get_token(&token.id).await?
+        .ok_or_else(|| StorageError::NotFound(format!("Token {} not found after update", token.id)))
 }
 
 #[tauri::command]
 async fn bidirectional_sync_tokens(
     state: State<'_, AppState>,
-) -> Result<storage::SyncStatus, String> {
-    let storage_manager = {
-        let guard = state.storage_manager.lock().unwrap();
-        guard.clone().ok_or("Storage manager not initialized")?
-    };
+) -> Result<storage::SyncStatus, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
 
     storage_manager.bidirectional_sync().await
-        .map_err(|e| format!("Sync failed: {}", e))
 }
 
 #[tauri::command]
 async fn bidirectional_sync_tokens_with_data(
     tokens_json: String,
     state: State<'_, AppState>,
-) -> Result<storage::SyncStatus, String> {
-    let storage_manager = {
-        let guard = state.storage_manager.lock().unwrap();
-        guard.clone().ok_or("Storage manager not initialized")?
-    };
+) -> Result<storage::SyncStatus, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
 
     // 解析前端传入的 tokens JSON
-    let tokens: Vec<storage::TokenData> = serde_json::from_str(&tokens_json)
-        .map_err(|e| format!("Failed to parse tokens JSON: {}", e))?;
+    let tokens: Vec<storage::TokenData> = serde_json::from_str(&tokens_json)?;
 
     storage_manager.bidirectional_sync_with_tokens(tokens).await
-        .map_err(|e| format!("Sync failed: {}", e))
+}
+
+#[tauri::command]
+async fn plan_sync(
+    direction: storage::SyncDirection,
+    state: State<'_, AppState>,
+) -> Result<storage::SyncPlan, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.plan_sync(direction).await
+}
+
+#[tauri::command]
+async fn apply_sync_plan(
+    plan: storage::SyncPlan,
+    state: State<'_, AppState>,
+) -> Result<storage::SyncStatus, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.apply_plan(&plan).await
 }
 
 #[tauri::command]
 async fn get_storage_status(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
-) -> Result<serde_json::Value, String> {
+) -> Result<serde_json::Value, StorageError> {
     // 首先尝试重新加载数据库配置（如果还没有的话）
     let db_manager_exists = {
         let guard = state.database_manager.lock().unwrap();
@@ -2420,7 +2335,11 @@ async fn get_storage_status(
                         if config.enabled {
                             let mut db_manager = database::DatabaseManager::new(config);
                             if db_manager.initialize().await.is_ok() {
-                                *state.database_manager.lock().unwrap() = Some(Arc::new(db_manager));
+                                // 结构迁移失败或数据库结构比程序新时不使用数据库
+                                match db_manager.migrate().await {
+                                    Ok(_) => *state.database_manager.lock().unwrap() = Some(Arc::new(db_manager)),
+                                    Err(e) => eprintln!("Failed to migrate database schema: {}", e),
+                                }
                             }
                         }
                     }
@@ -2446,12 +2365,9 @@ async fn get_storage_status(
             manager
         } else {
             // 尝试初始化存储管理器
-            if let Err(e) = initialize_storage_manager(&app, &state).await {
-                return Err(format!("Failed to initialize storage manager: {}", e));
-            }
+            initialize_storage_manager(&app, &state).await?;
             // 重新获取存储管理器
-            let guard = state.storage_manager.lock().unwrap();
-            guard.clone().ok_or("Storage manager still not initialized after initialization attempt")?
+            current_storage_manager(&state)?
         }
     };
 
@@ -2459,18 +2375,213 @@ async fn get_storage_status(
     let storage_type = storage_manager.storage_type();
     let is_database_available = storage_manager.is_database_available();
 
+    let local_storage_type = storage_manager.local_storage_type();
+    let pending_outbox = storage_manager.pending_outbox_count().await?;
+
     Ok(serde_json::json!({
         "is_available": is_available,
         "storage_type": storage_type,
-        "is_database_available": is_database_available
+        "local_storage_type": local_storage_type,
+        "is_database_available": is_database_available,
+        "pending_outbox": pending_outbox
     }))
 }
 
+#[tauri::command]
+async fn query_tokens(
+    filter: Option<storage::TokenFilter>,
+    sort: Option<storage::TokenSort>,
+    page: Option<storage::PageRequest>,
+    state: State<'_, AppState>,
+) -> Result<storage::TokenPage, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+
+    storage_manager.query_tokens(
+        &filter.unwrap_or_default(),
+        &sort.unwrap_or_default(),
+        &page.unwrap_or_default(),
+    ).await
+}
+
+#[tauri::command]
+async fn delete_matching_tokens(
+    filter: storage::TokenFilter,
+    state: State<'_, AppState>,
+) -> Result<usize, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.delete_matching(&filter).await
+}
+
+#[tauri::command]
+async fn update_matching_tokens(
+    filter: storage::TokenFilter,
+    update: storage::TokenBulkUpdate,
+    state: State<'_, AppState>,
+) -> Result<usize, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.update_matching(&filter, &update).await
+}
+
+#[tauri::command]
+async fn find_duplicate_tokens(
+    state: State<'_, AppState>,
+) -> Result<Vec<storage::DuplicateGroup>, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.find_duplicates().await
+}
+
+#[tauri::command]
+async fn merge_duplicate_tokens(
+    token_ids: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<storage::TokenData, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.merge_duplicate_tokens(&token_ids).await
+}
+
+#[tauri::command]
+async fn export_tokens(
+    options: storage::ExportOptions,
+    filter: Option<storage::TokenFilter>,
+    state: State<'_, AppState>,
+) -> Result<String, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.export_tokens(filter.as_ref(), &options).await
+}
+
+#[tauri::command]
+async fn import_tokens(
+    content: String,
+    options: storage::ImportOptions,
+    state: State<'_, AppState>,
+) -> Result<storage::ImportReport, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.import_tokens(&content, &options).await
+}
+
+#[tauri::command]
+async fn get_token_history(
+    token_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<storage::TokenEvent>, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.token_history(&token_id).await
+}
+
+#[tauri::command]
+async fn list_snapshots(
+    state: State<'_, AppState>,
+) -> Result<Vec<storage::SnapshotInfo>, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.list_snapshots().await
+}
+
+#[tauri::command]
+async fn create_snapshot(
+    state: State<'_, AppState>,
+) -> Result<Option<storage::SnapshotInfo>, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.create_snapshot(storage::SnapshotReason::Manual).await
+}
+
+#[tauri::command]
+async fn diff_snapshot(
+    snapshot_id: String,
+    state: State<'_, AppState>,
+) -> Result<storage::SnapshotDiff, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.diff_snapshot(&snapshot_id).await
+}
+
+#[tauri::command]
+async fn restore_snapshot(
+    snapshot_id: String,
+    state: State<'_, AppState>,
+) -> Result<storage::SnapshotRestore, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.restore_snapshot(&snapshot_id).await
+}
+
+#[tauri::command]
+async fn get_database_schema_version(
+    state: State<'_, AppState>,
+) -> Result<database::SchemaVersion, StorageError> {
+    let db_manager = state.database_manager.lock().unwrap().clone()
+        .ok_or_else(|| StorageError::Unavailable("Database not configured".to_string()))?;
+    db_manager.schema_version().await
+}
+
+// 工作区相关命令
+#[tauri::command]
+async fn list_workspaces(
+    state: State<'_, AppState>,
+) -> Result<serde_json::Value, StorageError> {
+    let db_manager = state.database_manager.lock().unwrap().clone()
+        .ok_or_else(|| StorageError::Unavailable("Database not configured".to_string()))?;
+
+    Ok(serde_json::json!({
+        "current": db_manager.workspace(),
+        "workspaces": db_manager.list_workspaces().await?,
+        "rowLevelSecurity": db_manager.row_level_security_enabled().await?
+    }))
+}
+
+/// 切换到另一个工作区（不存在时创建），返回从新工作区载入的token数
+#[tauri::command]
+async fn switch_workspace(
+    workspace_id: String,
+    name: Option<String>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<usize, StorageError> {
+    database::validate_workspace_id(&workspace_id)?;
+    let current_db = state.database_manager.lock().unwrap().clone()
+        .ok_or_else(|| StorageError::Unavailable("Database not configured".to_string()))?;
+    if current_db.workspace() == workspace_id {
+        return Ok(0);
+    }
+
+    // 积压的修改属于当前工作区，必须先写入
+    current_storage_manager(&state)?.flush_outbox().await?;
+
+    let config_manager = DatabaseConfigManager::new(&app)
+        .map_err(|e| StorageError::backend(format!("Failed to create config manager: {}", e)))?;
+    let config = config_manager.load_config()
+        .map_err(|e| StorageError::backend(format!("Failed to load config: {}", e)))?
+        .with_workspace(workspace_id);
+
+    let mut db_manager = DatabaseManager::new(config.clone());
+    db_manager.initialize().await?;
+    db_manager.migrate().await?;
+    db_manager.ensure_workspace(name.as_deref()).await?;
+
+    config_manager.save_config(&config)
+        .map_err(|e| StorageError::backend(format!("Failed to save config: {}", e)))?;
+
+    // 团队密钥按工作区区分，需要在新工作区重新解锁
+    *state.database_secrets.lock().unwrap() = None;
+    *state.database_manager.lock().unwrap() = Some(Arc::new(db_manager));
+    initialize_storage_manager(&app, &state).await?;
+
+    current_storage_manager(&state)?.load_workspace().await
+}
+
+/// 启用后只防止误读误写其他工作区，客户端仍可修改 app.workspace_id，不能代替数据库权限
+#[tauri::command]
+async fn set_workspace_row_level_security(
+    enabled: bool,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    let db_manager = state.database_manager.lock().unwrap().clone()
+        .ok_or_else(|| StorageError::Unavailable("Database not configured".to_string()))?;
+    db_manager.set_row_level_security(enabled).await
+}
+
 #[tauri::command]
 async fn get_sync_status(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
-) -> Result<Option<storage::SyncStatus>, String> {
+) -> Result<storage::SyncOverview, StorageError> {
     // 检查存储管理器是否已初始化，如果没有则尝试初始化
     let storage_manager = {
         let manager_option = {
@@ -2482,17 +2593,233 @@ async fn get_sync_status(
             manager
         } else {
             // 尝试初始化存储管理器
-            if let Err(e) = initialize_storage_manager(&app, &state).await {
-                return Err(format!("Failed to initialize storage manager: {}", e));
-            }
+            initialize_storage_manager(&app, &state).await?;
             // 重新获取存储管理器
-            let guard = state.storage_manager.lock().unwrap();
-            guard.clone().ok_or("Storage manager still not initialized after initialization attempt")?
+            current_storage_manager(&state)?
         }
     };
 
-    storage_manager.get_sync_status().await
-        .map_err(|e| format!("Failed to get sync status: {}", e))
+    storage_manager.sync_overview().await
+}
+
+// 自动同步的暂停状态和设置保存到配置中，重新初始化存储管理器或重启后保持
+fn current_sync_scheduler(state: &State<'_, AppState>) -> Result<Arc<storage::SyncScheduler>, StorageError> {
+    current_storage_manager(state)?.sync_scheduler()
+        .ok_or_else(|| StorageError::Unavailable("Database storage not available".to_string()))
+}
+
+fn save_sync_schedule(app: &tauri::AppHandle, schedule: storage::SyncSchedule) -> Result<(), StorageError> {
+    let config_manager = StorageConfigManager::new(app)?;
+    let mut config = config_manager.load_config()?;
+    config.sync_schedule = schedule;
+    config_manager.save_config(&config)
+}
+
+#[tauri::command]
+async fn pause_auto_sync(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<storage::SyncSchedulerState, StorageError> {
+    let scheduler = current_sync_scheduler(&state)?;
+    scheduler.pause();
+    save_sync_schedule(&app, scheduler.schedule())?;
+    Ok(scheduler.state())
+}
+
+#[tauri::command]
+async fn resume_auto_sync(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<storage::SyncSchedulerState, StorageError> {
+    let scheduler = current_sync_scheduler(&state)?;
+    scheduler.resume();
+    save_sync_schedule(&app, scheduler.schedule())?;
+    Ok(scheduler.state())
+}
+
+#[tauri::command]
+async fn set_auto_sync_schedule(
+    schedule: storage::SyncSchedule,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<storage::SyncSchedulerState, StorageError> {
+    save_sync_schedule(&app, schedule)?;
+    // 未配置数据库时只保存设置
+    match current_storage_manager(&state)?.sync_scheduler() {
+        Some(scheduler) => {
+            scheduler.set_schedule(schedule);
+            Ok(scheduler.state())
+        }
+        None => Ok(storage::SyncSchedulerState { paused: schedule.paused, ..Default::default() }),
+    }
+}
+
+#[tauri::command]
+async fn get_sync_history(
+    page: Option<storage::PageRequest>,
+    state: State<'_, AppState>,
+) -> Result<storage::SyncHistoryPage, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.sync_history(&page.unwrap_or_default()).await
+}
+
+#[tauri::command]
+async fn get_sync_stats(
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    state: State<'_, AppState>,
+) -> Result<Vec<storage::SyncDirectionStats>, StorageError> {
+    let storage_manager = current_storage_manager(&state)?;
+    storage_manager.sync_stats(since).await
+}
+
+// 本地存储后端配置命令
+#[tauri::command]
+async fn load_storage_config(app: tauri::AppHandle) -> Result<StorageConfig, StorageError> {
+    StorageConfigManager::new(&app)?.load_config()
+}
+
+#[tauri::command]
+async fn save_storage_config(
+    local_backend: LocalStorageBackend,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    let config_manager = StorageConfigManager::new(&app)?;
+
+    let previous_backend = load_local_backend(&app);
+
+    // SQLite 后端不加密，已加密的 tokens 不能迁移过去
+    if local_backend == LocalStorageBackend::Sqlite
+        && (config_manager.load_config()?.encrypt_local_tokens || LocalFileStorage::new(&app)?.is_file_encrypted())
+    {
+        return Err(StorageError::Conflict(
+            "The token store is encrypted and SQLite storage does not support encryption".to_string()
+        ));
+    }
+
+    if previous_backend != local_backend {
+        // 切换后端时，如果新后端为空，则把现有 tokens 迁移过去
+        let previous_storage = create_local_backend(&app, &state, previous_backend)?;
+        let next_storage = create_local_backend(&app, &state, local_backend)?;
+
+        if next_storage.load_tokens().await?.is_empty() {
+            let tokens = previous_storage.load_tokens().await?;
+            next_storage.save_tokens(&tokens).await?;
+            println!("Migrated {} tokens from {} to {}", tokens.len(), previous_storage.storage_type(), next_storage.storage_type());
+        }
+    }
+
+    // 只修改后端，保留设备ID等其他配置
+    let mut config = config_manager.load_config()?;
+    config.local_backend = local_backend;
+    config_manager.save_config(&config)?;
+
+    initialize_storage_manager(&app, &state).await
+}
+
+// 本地存储加密相关命令
+#[tauri::command]
+async fn unlock_token_store(
+    passphrase: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    // SQLite 后端不加密，不能在其上启用加密
+    if load_local_backend(&app) == LocalStorageBackend::Sqlite {
+        return Err(StorageError::Conflict("Encryption is only supported by the JSON file storage".to_string()));
+    }
+
+    let cipher = Arc::new(TokenCipher::new(passphrase)?);
+
+    // 用新口令读取一次：加密文件会被校验，明文文件会被迁移为加密格式
+    let local_storage = LocalFileStorage::new(&app)?.with_cipher(cipher.clone());
+    local_storage.read_file_content().await?;
+
+    // 记住已启用加密，之后未解锁时不会以明文写入
+    let config_manager = StorageConfigManager::new(&app)?;
+    let mut config = config_manager.load_config()?;
+    if !config.encrypt_local_tokens {
+        config.encrypt_local_tokens = true;
+        config_manager.save_config(&config)?;
+    }
+
+    *state.token_cipher.lock().unwrap() = Some(cipher);
+
+    initialize_storage_manager(&app, &state).await
+}
+
+#[tauri::command]
+async fn get_token_store_encryption_status(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<serde_json::Value, StorageError> {
+    let local_storage = LocalFileStorage::new(&app)?;
+    let config = StorageConfigManager::new(&app)?.load_config()?;
+    let unlocked = state.token_cipher.lock().unwrap().is_some();
+
+    Ok(serde_json::json!({
+        "enabled": config.encrypt_local_tokens || local_storage.is_file_encrypted(),
+        "encrypted": local_storage.is_file_encrypted(),
+        "unlocked": unlocked,
+        "supported": config.local_backend == LocalStorageBackend::JsonFile
+    }))
+}
+
+// 数据库凭据加密相关命令
+#[tauri::command]
+async fn get_database_encryption_status(
+    state: State<'_, AppState>,
+) -> Result<serde_json::Value, StorageError> {
+    let postgres_storage = current_postgres_storage(&state)?;
+    let active_key = postgres_storage.active_secret_key().await?;
+    let unlocked = state.database_secrets.lock().unwrap().as_ref()
+        .zip(active_key.as_ref())
+        .is_some_and(|(secrets, key)| secrets.key_id() == key.key_id);
+
+    Ok(serde_json::json!({
+        "enabled": active_key.is_some(),
+        "unlocked": unlocked,
+        "keyId": active_key.map(|key| key.key_id)
+    }))
+}
+
+#[tauri::command]
+async fn enable_database_encryption(
+    passphrase: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    let postgres_storage = current_postgres_storage(&state)?;
+    let secrets = postgres_storage.enable_secret_encryption(&passphrase).await?;
+    *state.database_secrets.lock().unwrap() = Some(Arc::new(secrets));
+
+    initialize_storage_manager(&app, &state).await
+}
+
+#[tauri::command]
+async fn unlock_database_secrets(
+    passphrase: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    let postgres_storage = current_postgres_storage(&state)?;
+    let secrets = postgres_storage.unlock_secrets(&passphrase).await?;
+    *state.database_secrets.lock().unwrap() = Some(Arc::new(secrets));
+
+    initialize_storage_manager(&app, &state).await
+}
+
+#[tauri::command]
+async fn rotate_database_key(
+    new_passphrase: String,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), StorageError> {
+    let postgres_storage = current_postgres_storage(&state)?;
+    let secrets = postgres_storage.rotate_secret_key(&new_passphrase).await?;
+    *state.database_secrets.lock().unwrap() = Some(Arc::new(secrets));
+
+    initialize_storage_manager(&app, &state).await
 }
 
 // 代理配置相关命令
@@ -2580,30 +2907,174 @@ async fn proxy_config_exists(app: tauri::AppHandle) -> Result<bool, String> {
         .map_err(|e| format!("Failed to check proxy config: {}", e))
 }
 
+// 辅助函数：获取已初始化的存储管理器
+fn current_storage_manager(state: &State<'_, AppState>) -> StorageResult<Arc<DualStorage>> {
+    state.storage_manager.lock().unwrap().clone()
+        .ok_or_else(|| StorageError::Unavailable("Storage manager not initialized".to_string()))
+}
+
+// 辅助函数：获取存储管理器，尚未初始化时（例如启动时本地存储尚未解锁）先初始化
+async fn ensure_storage_manager(
+    app: &tauri::AppHandle,
+    state: &State<'_, AppState>,
+) -> StorageResult<Arc<DualStorage>> {
+    if let Ok(storage_manager) = current_storage_manager(state) {
+        return Ok(storage_manager);
+    }
+
+    initialize_storage_manager(app, state).await?;
+    current_storage_manager(state)
+}
+
+// 辅助函数：创建数据库存储，已解锁团队密钥时启用凭据加密
+fn create_postgres_storage(state: &State<'_, AppState>, db_manager: Arc<DatabaseManager>) -> PostgreSQLStorage {
+    let postgres_storage = PostgreSQLStorage::new(db_manager);
+    match state.database_secrets.lock().unwrap().clone() {
+        Some(secrets) => postgres_storage.with_secret_cipher(secrets),
+        None => postgres_storage,
+    }
+}
+
+// 辅助函数：获取已连接数据库的存储
+fn current_postgres_storage(state: &State<'_, AppState>) -> StorageResult<PostgreSQLStorage> {
+    let db_manager = state.database_manager.lock().unwrap().clone()
+        .ok_or_else(|| StorageError::Unavailable("Database not configured".to_string()))?;
+    Ok(create_postgres_storage(state, db_manager))
+}
+
+// 辅助函数：创建本地存储，已解锁时启用加密；已启用加密但未解锁时返回 Locked
+fn create_local_storage(
+    app: &tauri::AppHandle,
+    state: &State<'_, AppState>,
+) -> StorageResult<LocalFileStorage> {
+    let local_storage = LocalFileStorage::new(app)?;
+    let cipher = state.token_cipher.lock().unwrap().clone();
+
+    match cipher {
+        Some(cipher) => Ok(local_storage.with_cipher(cipher)),
+        None if StorageConfigManager::new(app)?.load_config()?.encrypt_local_tokens => {
+            Err(StorageError::Locked("Token store is encrypted, passphrase required".to_string()))
+        }
+        None => Ok(local_storage),
+    }
+}
+
+// 辅助函数：读取本地存储后端配置，读取失败时使用 JSON 文件
+fn load_local_backend(app: &tauri::AppHandle) -> LocalStorageBackend {
+    StorageConfigManager::new(app)
+        .and_then(|manager| manager.load_config())
+        .map(|config| config.local_backend)
+        .unwrap_or_default()
+}
+
+// 辅助函数：按配置创建本地存储后端
+fn create_local_backend(
+    app: &tauri::AppHandle,
+    state: &State<'_, AppState>,
+    backend: LocalStorageBackend,
+) -> StorageResult<Arc<dyn TokenStorage>> {
+    let local_storage: Arc<dyn TokenStorage> = match backend {
+        LocalStorageBackend::JsonFile => Arc::new(create_local_storage(app, state)?),
+        LocalStorageBackend::Sqlite => Arc::new(SqliteStorage::new(app)?),
+    };
+
+    Ok(local_storage)
+}
+
 // 辅助函数：初始化存储管理器
 async fn initialize_storage_manager(
     app: &tauri::AppHandle,
     state: &State<'_, AppState>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // 创建本地存储
-    let local_storage = Arc::new(LocalFileStorage::new(app)?);
+) -> StorageResult<()> {
+    let config_manager = StorageConfigManager::new(app)?;
+    let device_id = config_manager.ensure_device_id()?;
+    let storage_config = config_manager.load_config()?;
+
+    // 创建本地存储，JSON 文件后端同时保留文件存储用于监视外部修改
+    let (local_storage, watched_file): (Arc<dyn TokenStorage>, Option<Arc<LocalFileStorage>>) = match storage_config.local_backend {
+        LocalStorageBackend::JsonFile => {
+            let file_storage = Arc::new(create_local_storage(app, state)?);
+            (file_storage.clone(), Some(file_storage))
+        }
+        LocalStorageBackend::Sqlite => (create_local_backend(app, state, storage_config.local_backend)?, None),
+    };
 
     // 尝试加载数据库配置并创建数据库存储
-    let postgres_storage = {
-        let db_manager_guard = state.database_manager.lock().unwrap();
-        if let Some(db_manager) = db_manager_guard.as_ref() {
-            Some(Arc::new(PostgreSQLStorage::new(db_manager.clone())))
-        } else {
-            None
-        }
+    let db_manager = state.database_manager.lock().unwrap().clone();
+    let postgres_storage = db_manager.as_ref()
+        .map(|db_manager| Arc::new(create_postgres_storage(state, db_manager.clone())));
+
+    // 数据库写入的发件箱、修改历史和同步日志，与 tokens.json 使用同一口令加密
+    let cipher = state.token_cipher.lock().unwrap().clone();
+    let (outbox, event_log, snapshots, sync_journal) = match cipher {
+        Some(cipher) => (
+            Outbox::new(app)?.with_cipher(cipher.clone()),
+            TokenEventLog::new(app)?.with_cipher(cipher.clone()),
+            SnapshotManager::new(app)?.with_cipher(cipher.clone()),
+            SyncJournal::new(app)?.with_cipher(cipher),
+        ),
+        None => (Outbox::new(app)?, TokenEventLog::new(app)?, SnapshotManager::new(app)?, SyncJournal::new(app)?),
     };
 
     // 创建双重存储管理器
-    let dual_storage = Arc::new(DualStorage::new(local_storage, postgres_storage));
+    let dual_storage = Arc::new(
+        DualStorage::new(local_storage, postgres_storage)
+            .with_device_id(device_id)
+            .with_tombstone_retention_days(storage_config.tombstone_retention_days)
+            .with_sync_history_retention_days(storage_config.sync_history_retention_days)
+            .with_outbox(outbox)
+            .with_event_log(event_log)
+            .with_snapshots(snapshots.with_retention(storage_config.snapshot_retention))
+            .with_sync_journal(sync_journal)
+            .with_sync_schedule(storage_config.sync_schedule)
+    );
+
+    // 上次同步被中断时先补完或放弃，失败时（例如口令未解锁）留到下次同步前再恢复
+    match dual_storage.recover_interrupted_sync().await {
+        Ok(Some(recovery)) => println!(
+            "Recovered interrupted {} sync started at {} (completed: {})",
+            recovery.direction, recovery.started_at, recovery.completed
+        ),
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to recover interrupted sync [{}]: {}", e.code(), e),
+    }
 
     // 更新应用状态
     *state.storage_manager.lock().unwrap() = Some(dual_storage);
 
+    // 重新启动数据库变更监听
+    let change_feed = db_manager.map(|db_manager| {
+        tauri::async_runtime::spawn(run_change_feed(app.clone(), db_manager, state.storage_manager.clone()))
+    });
+    if let Some(old) = std::mem::replace(&mut *state.change_feed.lock().unwrap(), change_feed) {
+        old.abort();
+    }
+
+    // 重新启动定时快照
+    let snapshot_schedule = (storage_config.snapshot_interval_hours > 0).then(|| {
+        let interval = std::time::Duration::from_secs(storage_config.snapshot_interval_hours as u64 * 3600);
+        tauri::async_runtime::spawn(run_snapshot_schedule(state.storage_manager.clone(), interval))
+    });
+    if let Some(old) = std::mem::replace(&mut *state.snapshot_schedule.lock().unwrap(), snapshot_schedule) {
+        old.abort();
+    }
+
+    // 重新启动自动同步，未配置数据库时不需要
+    let sync_scheduler = state.storage_manager.lock().unwrap().clone()
+        .filter(|storage_manager| storage_manager.sync_scheduler().is_some())
+        .map(|storage_manager| tauri::async_runtime::spawn(run_sync_scheduler(Arc::downgrade(&storage_manager))));
+    if let Some(old) = std::mem::replace(&mut *state.sync_scheduler.lock().unwrap(), sync_scheduler) {
+        old.abort();
+    }
+
+    // 重新启动 tokens.json 监视
+    let file_watcher = watched_file.map(|file_storage| {
+        tauri::async_runtime::spawn(run_file_watcher(app.clone(), file_storage))
+    });
+    if let Some(old) = std::mem::replace(&mut *state.file_watcher.lock().unwrap(), file_watcher) {
+        old.abort();
+    }
+
     Ok(())
 }
 
@@ -2621,6 +3092,12 @@ fn main() {
                 http_server: Mutex::new(None),
                 storage_manager: Arc::new(Mutex::new(None)),
                 database_manager: Arc::new(Mutex::new(None)),
+                change_feed: Mutex::new(None),
+                snapshot_schedule: Mutex::new(None),
+                sync_scheduler: Mutex::new(None),
+                file_watcher: Mutex::new(None),
+                token_cipher: Mutex::new(None),
+                database_secrets: Mutex::new(None),
                 app_session_cache: Arc::new(Mutex::new(HashMap::new())),
                 monitoring_email: Mutex::new(None),
                 verification_code: Mutex::new(None),
@@ -2646,42 +3123,32 @@ fn main() {
                                 if config.enabled {
                                     let mut db_manager = DatabaseManager::new(config);
                                     if db_manager.initialize().await.is_ok() {
-                                        // 检查表是否存在
-                                        should_sync = if let Some(pool) = db_manager.get_pool() {
-                                            match pool.get().await {
-                                                Ok(client) => {
-                                                    match database::check_tables_exist(&client).await {
-                                                        Ok(exists) => {
-                                                            if !exists {
-                                                                // 创建表
-                                                                if let Err(e) = database::create_tables(&client).await {
-                                                                    eprintln!("Failed to create tables on startup: {}", e);
-                                                                }
-                                                                false // 新创建的表不需要同步
-                                                            } else {
-                                                                // 表已存在，检查并添加新字段
-                                                                if let Err(e) = database::add_new_fields_if_not_exist(&client).await {
-                                                                    eprintln!("Failed to add new fields on startup: {}", e);
-                                                                }
-                                                                true // 表已存在，需要同步
-                                                            }
-                                                        }
-                                                        Err(e) => {
-                                                            eprintln!("Failed to check tables on startup: {}", e);
-                                                            false
-                                                        }
+                                        // 执行结构迁移；新创建的表不需要同步，已有的表需要同步
+                                        // 只有迁移和版本检查确实执行成功后才使用数据库，否则可能写入比程序新或迁移了一半的结构
+                                        let schema_ready = match db_manager.get_pool() {
+                                            Some(pool) => match pool.get().await {
+                                                Ok(mut client) => match database::prepare_schema(&mut client).await {
+                                                    Ok(tables_existed) => {
+                                                        should_sync = tables_existed;
+                                                        true
                                                     }
-                                                }
+                                                    Err(e) => {
+                                                        // 迁移失败或数据库结构比程序新时不使用数据库
+                                                        eprintln!("Failed to migrate database schema on startup: {}", e);
+                                                        false
+                                                    }
+                                                },
                                                 Err(e) => {
                                                     eprintln!("Failed to get database client on startup: {}", e);
                                                     false
                                                 }
-                                            }
-                                        } else {
-                                            false
+                                            },
+                                            None => false,
                                         };
 
-                                        *state.database_manager.lock().unwrap() = Some(Arc::new(db_manager));
+                                        if schema_ready {
+                                            *state.database_manager.lock().unwrap() = Some(Arc::new(db_manager));
+                                        }
 
                                         // 如果需要同步，在存储管理器初始化后执行
                                         if should_sync {
@@ -2722,6 +3189,16 @@ fn main() {
                         eprintln!("Failed to initialize storage manager: {}", e);
                     }
                 }
+
+                // 清理过期的删除墓碑
+                let storage_manager = state.storage_manager.lock().unwrap().clone();
+                if let Some(storage_manager) = storage_manager {
+                    match storage_manager.purge_expired_tombstones().await {
+                        Ok(purged) if purged > 0 => println!("Purged {} expired tombstones", purged),
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Failed to purge expired tombstones: {}", e),
+                    }
+                }
             });
 
             Ok(())
@@ -2783,10 +3260,44 @@ fn main() {
             sync_tokens_from_database,
             // 删除命令
             delete_token,
+            update_token,
             bidirectional_sync_tokens,
             bidirectional_sync_tokens_with_data,
+            plan_sync,
+            apply_sync_plan,
             get_storage_status,
             get_sync_status,
+            pause_auto_sync,
+            resume_auto_sync,
+            set_auto_sync_schedule,
+            get_sync_history,
+            get_sync_stats,
+            list_workspaces,
+            switch_workspace,
+            set_workspace_row_level_security,
+            get_database_schema_version,
+            query_tokens,
+            delete_matching_tokens,
+            update_matching_tokens,
+            find_duplicate_tokens,
+            merge_duplicate_tokens,
+            export_tokens,
+            import_tokens,
+            get_token_history,
+            list_snapshots,
+            create_snapshot,
+            diff_snapshot,
+            restore_snapshot,
+            // 本地存储后端命令
+            load_storage_config,
+            save_storage_config,
+            // 本地存储加密命令
+            unlock_token_store,
+            get_token_store_encryption_status,
+            get_database_encryption_status,
+            enable_database_encryption,
+            unlock_database_secrets,
+            rotate_database_key,
 
             open_internal_browser,
             close_window,
@@ -0,0 +1,163 @@
+use super::dual_storage::DualStorage;
+use super::traits::SyncManager;
+use crate::database::{DatabaseManager, TOKENS_CHANGED_CHANNEL};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::Emitter;
+use tokio::sync::mpsc;
+
+/// 推送给前端的事件名
+pub const TOKENS_CHANGED_EVENT: &str = "tokens-changed";
+
+// 同一批修改通常会连续触发多条通知，等待片刻后合并处理
+const DEBOUNCE: Duration = Duration::from_millis(300);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// 数据库触发器发出的通知内容
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TokenChange {
+    pub op: String,
+    pub id: String,
+    /// 变更所属的工作区，旧版本触发器发出的通知没有这个字段
+    #[serde(default)]
+    pub workspace: Option<String>,
+}
+
+impl TokenChange {
+    pub fn parse(payload: &str) -> Option<Self> {
+        serde_json::from_str(payload).ok()
+    }
+
+    pub fn belongs_to(&self, workspace: &str) -> bool {
+        self.workspace.as_deref().is_none_or(|changed| changed == workspace)
+    }
+}
+
+/// 发给前端的变更事件，token_ids 为空表示可能有任意token变化
+#[derive(Debug, Clone, Serialize)]
+pub struct TokensChangedEvent {
+    pub token_ids: Vec<String>,
+}
+
+/// 监听数据库的token变更通知，把变化拉取到本地并通知前端
+///
+/// 每次连接成功后重放发件箱；连接断开后按指数退避重连，重连成功后做一次增量同步，补上断线期间漏掉的通知。
+pub async fn run_change_feed(
+    app: tauri::AppHandle,
+    db_manager: Arc<DatabaseManager>,
+    storage_manager: Arc<Mutex<Option<Arc<DualStorage>>>>,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut reconnecting = false;
+
+    loop {
+        match db_manager.listen(TOKENS_CHANGED_CHANNEL).await {
+            Ok(mut receiver) => {
+                backoff = INITIAL_BACKOFF;
+
+                if let Some(storage) = current_storage(&storage_manager) {
+                    // 数据库恢复可用后先写入离线期间积压的修改
+                    match storage.replay_outbox().await {
+                        Ok(replayed) if replayed > 0 => println!("Replayed {} queued changes to database", replayed),
+                        Ok(_) => {}
+                        Err(e) => eprintln!("Failed to replay outbox [{}]: {}", e.code(), e),
+                    }
+
+                    if reconnecting {
+                        match storage.bidirectional_sync().await {
+                            Ok(status) if status.tokens_synced > 0 => emit_changes(&app, Vec::new()),
+                            Ok(_) => {}
+                            Err(e) => eprintln!("Failed to sync after change feed reconnect [{}]: {}", e.code(), e),
+                        }
+                    }
+                }
+
+                while let Some(payload) = receiver.recv().await {
+                    let token_ids = collect_batch(&mut receiver, &payload, db_manager.workspace()).await;
+                    if token_ids.is_empty() {
+                        continue;
+                    }
+
+                    let Some(storage) = current_storage(&storage_manager) else {
+                        continue;
+                    };
+                    match storage.pull_remote_changes(&token_ids).await {
+                        Ok(0) => {}
+                        Ok(_) => emit_changes(&app, token_ids),
+                        Err(e) => eprintln!("Failed to pull remote token changes [{}]: {}", e.code(), e),
+                    }
+                }
+
+                eprintln!("Token change feed disconnected, reconnecting");
+            }
+            Err(e) => eprintln!("Failed to listen for token changes [{}]: {}", e.code(), e),
+        }
+
+        reconnecting = true;
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+fn current_storage(storage_manager: &Mutex<Option<Arc<DualStorage>>>) -> Option<Arc<DualStorage>> {
+    storage_manager.lock().ok().and_then(|guard| guard.clone())
+}
+
+fn emit_changes(app: &tauri::AppHandle, token_ids: Vec<String>) {
+    if let Err(e) = app.emit(TOKENS_CHANGED_EVENT, TokensChangedEvent { token_ids }) {
+        eprintln!("Failed to emit token change event: {}", e);
+    }
+}
+
+/// 收集一段时间内连续到达的通知，返回当前工作区中去重后的token ID
+async fn collect_batch(receiver: &mut mpsc::UnboundedReceiver<String>, first: &str, workspace: &str) -> Vec<String> {
+    let parse = |payload: &str| TokenChange::parse(payload)
+        .filter(|change| change.belongs_to(workspace))
+        .map(|change| change.id);
+
+    let mut token_ids = BTreeSet::new();
+    token_ids.extend(parse(first));
+
+    while let Ok(Some(payload)) = tokio::time::timeout(DEBOUNCE, receiver.recv()).await {
+        token_ids.extend(parse(&payload));
+    }
+
+    token_ids.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_token_change() {
+        let change = TokenChange::parse(r#"{"op":"UPDATE","id":"abc"}"#).unwrap();
+        assert_eq!(change, TokenChange { op: "UPDATE".to_string(), id: "abc".to_string(), workspace: None });
+        assert!(TokenChange::parse("not json").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_collect_batch_deduplicates_ids() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        sender.send(r#"{"op":"UPDATE","id":"b"}"#.to_string()).unwrap();
+        sender.send(r#"{"op":"DELETE","id":"a"}"#.to_string()).unwrap();
+        drop(sender);
+
+        let ids = collect_batch(&mut receiver, r#"{"op":"INSERT","id":"a"}"#, "default").await;
+        assert_eq!(ids, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_collect_batch_ignores_other_workspaces() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        sender.send(r#"{"op":"UPDATE","id":"b","workspace":"team-b"}"#.to_string()).unwrap();
+        sender.send(r#"{"op":"UPDATE","id":"c","workspace":"team-a"}"#.to_string()).unwrap();
+        drop(sender);
+
+        let ids = collect_batch(&mut receiver, r#"{"op":"INSERT","id":"a","workspace":"team-a"}"#, "team-a").await;
+        assert_eq!(ids, vec!["a".to_string(), "c".to_string()]);
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::fs;
+use tauri::Manager;
+use super::error::StorageResult;
+use super::snapshot::SnapshotRetention;
+use super::sync_scheduler::SyncSchedule;
+
+/// 本地存储使用的后端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalStorageBackend {
+    #[default]
+    JsonFile,
+    Sqlite,
+}
+
+/// 删除墓碑的默认保留天数
+pub const DEFAULT_TOMBSTONE_RETENTION_DAYS: u32 = 90;
+/// 删除墓碑的最短保留天数，更短的设置按此处理，避免刚删除的token被其他设备重新写回
+pub const MIN_TOMBSTONE_RETENTION_DAYS: u32 = 7;
+
+fn default_tombstone_retention_days() -> u32 {
+    DEFAULT_TOMBSTONE_RETENTION_DAYS
+}
+
+/// 数据库中同步记录的默认保留天数
+pub const DEFAULT_SYNC_HISTORY_RETENTION_DAYS: u32 = 90;
+
+fn default_sync_history_retention_days() -> u32 {
+    DEFAULT_SYNC_HISTORY_RETENTION_DAYS
+}
+
+/// 定时快照的默认间隔（小时）
+pub const DEFAULT_SNAPSHOT_INTERVAL_HOURS: u32 = 24;
+
+fn default_snapshot_interval_hours() -> u32 {
+    DEFAULT_SNAPSHOT_INTERVAL_HOURS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub local_backend: LocalStorageBackend,
+    /// 本机的设备ID，记录在删除墓碑的 origin 中
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// 墓碑保留天数，超过后由清理任务删除
+    #[serde(default = "default_tombstone_retention_days")]
+    pub tombstone_retention_days: u32,
+    /// 定时快照间隔（小时），0 表示关闭定时快照
+    #[serde(default = "default_snapshot_interval_hours")]
+    pub snapshot_interval_hours: u32,
+    #[serde(default)]
+    pub snapshot_retention: SnapshotRetention,
+    /// 数据库中同步记录的保留天数，0 表示一直保留
+    #[serde(default = "default_sync_history_retention_days")]
+    pub sync_history_retention_days: u32,
+    #[serde(default)]
+    pub sync_schedule: SyncSchedule,
+    /// tokens.json 已启用加密，未解锁时不读写本地存储
+    #[serde(default)]
+    pub encrypt_local_tokens: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            local_backend: LocalStorageBackend::default(),
+            device_id: None,
+            tombstone_retention_days: DEFAULT_TOMBSTONE_RETENTION_DAYS,
+            snapshot_interval_hours: DEFAULT_SNAPSHOT_INTERVAL_HOURS,
+            snapshot_retention: SnapshotRetention::default(),
+            sync_history_retention_days: DEFAULT_SYNC_HISTORY_RETENTION_DAYS,
+            sync_schedule: SyncSchedule::default(),
+            encrypt_local_tokens: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageConfigManager {
+    config_path: PathBuf,
+}
+
+impl StorageConfigManager {
+    pub fn new(app_handle: &tauri::AppHandle) -> StorageResult<Self> {
+        let app_data_dir = app_handle.path().app_data_dir()?;
+        fs::create_dir_all(&app_data_dir)?;
+
+        let config_path = app_data_dir.join("storage_config.json");
+
+        Ok(Self { config_path })
+    }
+
+    pub fn load_config(&self) -> StorageResult<StorageConfig> {
+        if !self.config_path.exists() {
+            return Ok(StorageConfig::default());
+        }
+
+        let content = fs::read_to_string(&self.config_path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save_config(&self, config: &StorageConfig) -> StorageResult<()> {
+        let json = serde_json::to_string_pretty(config)?;
+        fs::write(&self.config_path, json)?;
+        Ok(())
+    }
+
+    /// 返回本机设备ID，首次调用时生成并保存
+    pub fn ensure_device_id(&self) -> StorageResult<String> {
+        let mut config = self.load_config()?;
+        if let Some(device_id) = &config.device_id {
+            return Ok(device_id.clone());
+        }
+
+        let device_id = uuid::Uuid::new_v4().to_string();
+        config.device_id = Some(device_id.clone());
+        self.save_config(&config)?;
+
+        Ok(device_id)
+    }
+}
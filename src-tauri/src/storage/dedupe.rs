@@ -0,0 +1,217 @@
+use super::traits::{TokenData, normalize_tags};
+use super::query::tenant_host;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// 判定为重复的依据
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateReason {
+    /// access_token 完全相同
+    AccessToken,
+    /// auth_session 完全相同
+    AuthSession,
+    /// 同一租户下邮箱备注相同（不区分大小写）
+    TenantEmail,
+}
+
+/// 一组可能重复的tokens及建议的合并结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub reasons: Vec<DuplicateReason>,
+    /// 第一个为保留的token（创建最早），其余合并后删除
+    pub tokens: Vec<TokenData>,
+    pub merged: TokenData,
+}
+
+impl DuplicateGroup {
+    pub fn token_ids(&self) -> Vec<String> {
+        self.tokens.iter().map(|token| token.id.clone()).collect()
+    }
+}
+
+/// 找出所有可能重复的token组，满足任一依据即归为一组，依据可以传递
+pub fn find_duplicates(tokens: &[TokenData]) -> Vec<DuplicateGroup> {
+    let mut parents: Vec<usize> = (0..tokens.len()).collect();
+    let mut reasons: Vec<BTreeSet<DuplicateReason>> = vec![BTreeSet::new(); tokens.len()];
+    let mut first_seen: HashMap<(DuplicateReason, String), usize> = HashMap::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        for (reason, key) in duplicate_keys(token) {
+            match first_seen.get(&(reason, key.clone())) {
+                Some(&other) => {
+                    let root = union(&mut parents, other, index);
+                    reasons[root].insert(reason);
+                }
+                None => {
+                    first_seen.insert((reason, key), index);
+                }
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..tokens.len() {
+        members.entry(find(&mut parents, index)).or_default().push(index);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = members.into_values()
+        .filter(|indexes| indexes.len() > 1)
+        .map(|indexes| {
+            let group_tokens: Vec<TokenData> = indexes.iter().map(|&index| tokens[index].clone()).collect();
+            let group_reasons: BTreeSet<DuplicateReason> = indexes.iter().flat_map(|&index| reasons[index].iter().copied()).collect();
+            let merged = merge_duplicates(&group_tokens);
+
+            let mut group_tokens = group_tokens;
+            group_tokens.sort_by(|a, b| (a.id != merged.id).cmp(&(b.id != merged.id)).then_with(|| oldest_first(a, b)));
+            DuplicateGroup {
+                reasons: group_reasons.into_iter().collect(),
+                tokens: group_tokens,
+                merged,
+            }
+        })
+        .collect();
+
+    groups.sort_by(|a, b| a.merged.id.cmp(&b.merged.id));
+    groups
+}
+
+fn duplicate_keys(token: &TokenData) -> Vec<(DuplicateReason, String)> {
+    let mut keys = Vec::new();
+    if !token.access_token.trim().is_empty() {
+        keys.push((DuplicateReason::AccessToken, token.access_token.clone()));
+    }
+    if let Some(auth_session) = token.auth_session.as_ref().filter(|session| !session.trim().is_empty()) {
+        keys.push((DuplicateReason::AuthSession, auth_session.clone()));
+    }
+    if let Some(email) = token.email_note.as_ref().map(|email| email.trim().to_lowercase()).filter(|email| !email.is_empty())
+        && let Some(host) = tenant_host(&token.tenant_url)
+    {
+        keys.push((DuplicateReason::TenantEmail, format!("{}\n{}", host, email)));
+    }
+    keys
+}
+
+fn find(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    // 路径压缩
+    let mut current = index;
+    while parents[current] != root {
+        let next = parents[current];
+        parents[current] = root;
+        current = next;
+    }
+    root
+}
+
+// 合并两个集合，返回合并后的根
+fn union(parents: &mut [usize], a: usize, b: usize) -> usize {
+    let (root_a, root_b) = (find(parents, a), find(parents, b));
+    let (root, child) = (root_a.min(root_b), root_a.max(root_b));
+    parents[child] = root;
+    root
+}
+
+fn oldest_first(a: &TokenData, b: &TokenData) -> std::cmp::Ordering {
+    a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id))
+}
+
+/// 把一组重复的tokens合并为一条记录
+///
+/// 保留创建最早的token的ID和创建时间；凭据取最近更新的token，其余字段取最近更新且不为空的值，标签取并集。
+pub fn merge_duplicates(tokens: &[TokenData]) -> TokenData {
+    let primary = tokens.iter().min_by(|a, b| oldest_first(a, b)).expect("merge_duplicates requires at least one token");
+
+    let mut newest_first: Vec<&TokenData> = tokens.iter().collect();
+    newest_first.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then_with(|| a.id.cmp(&b.id)));
+    let newest = newest_first[0];
+
+    macro_rules! richest {
+        ($field:ident, $is_empty:expr) => {
+            newest_first.iter()
+                .filter_map(|token| token.$field.clone())
+                .find(|value| !$is_empty(value))
+        };
+    }
+    let blank_string = |value: &String| value.trim().is_empty();
+    let blank_json = |value: &serde_json::Value| value.is_null();
+
+    TokenData {
+        id: primary.id.clone(),
+        tenant_url: newest.tenant_url.clone(),
+        access_token: newest.access_token.clone(),
+        created_at: primary.created_at,
+        updated_at: newest.updated_at,
+        portal_url: richest!(portal_url, blank_string),
+        email_note: richest!(email_note, blank_string),
+        ban_status: richest!(ban_status, blank_json),
+        portal_info: richest!(portal_info, blank_json),
+        auth_session: richest!(auth_session, blank_string),
+        suspensions: richest!(suspensions, blank_json),
+        balance_color_mode: richest!(balance_color_mode, blank_string),
+        skip_check: newest_first.iter().find_map(|token| token.skip_check),
+        // 按更新时间从早到晚合并，较早的标签排在前面
+        tags: normalize_tags(newest_first.iter().rev().flat_map(|token| token.tags.iter())),
+        group: richest!(group, blank_string),
+        version: primary.version,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    fn token(id: &str, tenant_url: &str, access_token: &str, email: Option<&str>, age_days: i64) -> TokenData {
+        let mut token = TokenData::new(id.to_string(), tenant_url.to_string(), access_token.to_string(), None, email.map(str::to_string));
+        token.created_at = Utc::now() - Duration::days(age_days);
+        token.updated_at = token.created_at;
+        token
+    }
+
+    #[test]
+    fn test_find_duplicates_groups_transitively() {
+        let mut session_twin = token("c", "https://d2.example.com/", "t3", None, 1);
+        session_twin.auth_session = Some("session-1".to_string());
+        let mut tokens = vec![
+            token("a", "https://d1.example.com/", "t1", Some("Alice@example.com"), 5),
+            token("b", "https://D1.example.com", "t2", Some("alice@example.com "), 3),
+            session_twin,
+            token("d", "https://d2.example.com/", "t1", None, 4),
+            token("e", "https://d3.example.com/", "t9", Some("alice@example.com"), 2),
+            token("f", "https://d2.example.com/", "t4", None, 2),
+        ];
+        tokens[1].auth_session = Some("session-1".to_string());
+
+        let groups = find_duplicates(&tokens);
+        assert_eq!(groups.len(), 1);
+        // a-b 同租户同邮箱，a-d 同 access_token，b-c 同 auth_session；e 在其他租户
+        assert_eq!(groups[0].token_ids(), vec!["a", "d", "b", "c"]);
+        assert_eq!(groups[0].reasons, vec![DuplicateReason::AccessToken, DuplicateReason::AuthSession, DuplicateReason::TenantEmail]);
+        assert_eq!(groups[0].merged.id, "a");
+    }
+
+    #[test]
+    fn test_merge_keeps_richest_fields() {
+        let mut old = token("a", "https://d1.example.com/", "old-token", Some("alice@example.com"), 10);
+        old.portal_url = Some("https://portal.example.com/a".to_string());
+        old.tags = vec!["team-a".to_string()];
+        old.version = 3;
+        let mut new = token("b", "https://d1.example.com/", "new-token", Some(""), 1);
+        new.ban_status = Some(serde_json::json!("ACTIVE"));
+        new.tags = vec!["team-b".to_string()];
+
+        let merged = merge_duplicates(&[new.clone(), old.clone()]);
+        assert_eq!(merged.id, "a");
+        assert_eq!(merged.created_at, old.created_at);
+        assert_eq!(merged.version, 3);
+        assert_eq!(merged.access_token, "new-token");
+        assert_eq!(merged.email_note, Some("alice@example.com".to_string()));
+        assert_eq!(merged.portal_url, old.portal_url);
+        assert_eq!(merged.ban_status, Some(serde_json::json!("ACTIVE")));
+        assert_eq!(merged.tags, vec!["team-a", "team-b"]);
+    }
+}
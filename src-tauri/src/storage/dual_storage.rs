@@ -1,26 +1,134 @@
-use super::traits::{TokenStorage, TokenData, SyncManager, SyncStatus};
-use super::{LocalFileStorage, PostgreSQLStorage};
+use super::traits::{TokenStorage, TokenData, Tombstone, SyncManager, SyncStatus, SyncConflict, SyncError, SyncHistoryPage, SyncDirectionStats, SyncWatermark, apply_tombstones, merge_tombstones};
+use super::merge::{merge_token_sets, same_content};
+use super::error::{StorageError, StorageResult};
+use super::config::{DEFAULT_TOMBSTONE_RETENTION_DAYS, MIN_TOMBSTONE_RETENTION_DAYS, DEFAULT_SYNC_HISTORY_RETENTION_DAYS};
+use super::outbox::{Outbox, OutboxOp};
+use super::history::{TokenEvent, TokenEventLog, sort_events};
+use super::snapshot::{SnapshotManager, SnapshotReason, SnapshotInfo, SnapshotDiff, SnapshotRestore};
+use super::query::{TokenFilter, TokenSort, PageRequest, TokenPage, TokenBulkUpdate};
+use super::postgres_storage::{record_sync_status, get_latest_sync_status, load_sync_history, load_sync_stats, prune_sync_history};
+use super::dedupe::{DuplicateGroup, find_duplicates, merge_duplicates};
+use super::transfer::{ExportOptions, ImportOptions, ImportReport, export_tokens, plan_import};
+use super::sync_plan::{SyncDirection, SyncPlan, StoreChanges, plan_fingerprint};
+use super::sync_journal::{SyncJournal, SyncJournalEntry, SyncJournalPhase, SyncRecovery};
+use super::sync_scheduler::{SyncSchedule, SyncScheduler, SyncOverview};
+use super::PostgreSQLStorage;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
+
+// 9999-12-31T23:59:59Z，清除早于它的墓碑即清除全部墓碑
+const ALL_TOMBSTONES_BEFORE: DateTime<Utc> = DateTime::from_timestamp(253_402_300_799, 0).unwrap();
 
 pub struct DualStorage {
-    local_storage: Arc<LocalFileStorage>,
+    // 本地存储可以是JSON文件或SQLite
+    local_storage: Arc<dyn TokenStorage>,
     postgres_storage: Option<Arc<PostgreSQLStorage>>,
     prefer_database: bool,
+    // 写入墓碑 origin 的设备ID
+    device_id: String,
+    tombstone_retention: Duration,
+    // 数据库不可用时暂存待写入的修改
+    outbox: Option<Arc<Outbox>>,
+    replay_lock: tokio::sync::Mutex<()>,
+    // 本设备做出的修改历史
+    event_log: Option<Arc<TokenEventLog>>,
+    // 本地tokens的快照，破坏性操作之前自动创建
+    snapshots: Option<Arc<SnapshotManager>>,
+    // 数据库中同步记录的保留期限，为空时不清理
+    sync_history_retention: Option<Duration>,
+    // 同步写入之前记下本地要写入的内容，中断后用于恢复
+    sync_journal: Option<Arc<SyncJournal>>,
+    // 同一时间只允许一次同步
+    sync_lock: tokio::sync::Mutex<()>,
+    // 自动同步计划，由 run_sync_scheduler 执行
+    sync_scheduler: Option<Arc<SyncScheduler>>,
 }
 
 impl DualStorage {
     pub fn new(
-        local_storage: Arc<LocalFileStorage>,
+        local_storage: Arc<dyn TokenStorage>,
         postgres_storage: Option<Arc<PostgreSQLStorage>>,
     ) -> Self {
         Self {
             local_storage,
             postgres_storage,
             prefer_database: true,
+            device_id: "unknown".to_string(),
+            tombstone_retention: Duration::days(DEFAULT_TOMBSTONE_RETENTION_DAYS as i64),
+            outbox: None,
+            replay_lock: tokio::sync::Mutex::new(()),
+            event_log: None,
+            snapshots: None,
+            sync_history_retention: Some(Duration::days(DEFAULT_SYNC_HISTORY_RETENTION_DAYS as i64)),
+            sync_journal: None,
+            sync_lock: tokio::sync::Mutex::new(()),
+            sync_scheduler: None,
         }
     }
 
+    pub fn with_device_id(mut self, device_id: String) -> Self {
+        self.device_id = device_id;
+        self
+    }
+
+    /// 设置墓碑保留天数。离线时间超过该期限的设备再次同步时，已删除的token可能会被重新写回
+    ///
+    /// 少于 `MIN_TOMBSTONE_RETENTION_DAYS`（包括 0）时按最短保留天数处理。
+    pub fn with_tombstone_retention_days(mut self, days: u32) -> Self {
+        self.tombstone_retention = Duration::days(days.max(MIN_TOMBSTONE_RETENTION_DAYS) as i64);
+        self
+    }
+
+    /// 设置数据库中同步记录的保留天数，每次同步后删除更早的记录；0 表示一直保留
+    pub fn with_sync_history_retention_days(mut self, days: u32) -> Self {
+        self.sync_history_retention = (days > 0).then(|| Duration::days(days as i64));
+        self
+    }
+
+    /// 数据库写入先进入发件箱，数据库恢复可用后按顺序重放
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = Some(Arc::new(outbox));
+        self
+    }
+
+    /// 记录每次新增、修改和删除的字段差异，数据库可用时同时写入数据库
+    pub fn with_event_log(mut self, event_log: TokenEventLog) -> Self {
+        self.event_log = Some(Arc::new(event_log));
+        self
+    }
+
+    /// 清空、批量删除、同步和恢复之前先为本地tokens创建快照
+    pub fn with_snapshots(mut self, snapshots: SnapshotManager) -> Self {
+        self.snapshots = Some(Arc::new(snapshots));
+        self
+    }
+
+    /// 同步先写日志再写两端，中断的同步在下次启动或同步时恢复
+    pub fn with_sync_journal(mut self, journal: SyncJournal) -> Self {
+        self.sync_journal = Some(Arc::new(journal));
+        self
+    }
+
+    /// 配置了数据库时按计划自动同步，本地修改后也会延迟触发同步
+    pub fn with_sync_schedule(mut self, schedule: SyncSchedule) -> Self {
+        self.sync_scheduler = Some(Arc::new(SyncScheduler::new(schedule)));
+        self
+    }
+
+    /// 自动同步计划，未配置数据库时为空
+    pub fn sync_scheduler(&self) -> Option<Arc<SyncScheduler>> {
+        self.postgres_storage.as_ref().and(self.sync_scheduler.clone())
+    }
+
+    /// 最近一次同步记录和自动同步的状态
+    pub async fn sync_overview(&self) -> StorageResult<SyncOverview> {
+        Ok(SyncOverview {
+            last_sync: self.get_sync_status().await?,
+            scheduler: self.sync_scheduler().map(|scheduler| scheduler.state()),
+        })
+    }
+
     pub fn set_prefer_database(&mut self, prefer: bool) {
         self.prefer_database = prefer;
     }
@@ -29,563 +137,1258 @@ impl DualStorage {
         self.postgres_storage.is_some()
     }
 
-    async fn sync_to_both_storages(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // 总是保存到本地存储
-        if let Err(e) = self.local_storage.save_token(token).await {
-            eprintln!("Failed to save to local storage: {}", e);
-        }
+    pub fn local_storage_type(&self) -> &'static str {
+        self.local_storage.storage_type()
+    }
 
-        // 如果数据库可用，也保存到数据库
-        if let Some(postgres) = &self.postgres_storage {
-            if postgres.is_available().await {
-                if let Err(e) = postgres.save_token(token).await {
-                    eprintln!("Failed to save to database: {}", e);
-                    // 数据库保存失败不应该影响整体操作
-                }
-            }
-        }
+    async fn sync_to_both_storages(&self, token: &TokenData) -> StorageResult<()> {
+        let previous = if self.records_history() {
+            self.local_storage.get_token(&token.id).await?
+        } else {
+            None
+        };
+
+        // 总是保存到本地存储，本地失败直接返回错误
+        self.local_storage.save_token(token).await?;
+
+        let mut ops = vec![OutboxOp::Upsert { token: Box::new(token.clone()) }];
+        ops.extend(self.record_events(TokenEvent::between(previous.as_ref(), Some(token), &self.device_id).into_iter().collect()).await);
+
+        // 本地已保存成功，数据库写入失败不影响整体操作
+        self.push_to_database(ops).await;
 
         Ok(())
     }
 
-    async fn delete_from_both_storages(&self, token_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        let mut local_deleted = false;
-        let mut db_deleted = false;
+    fn records_history(&self) -> bool {
+        self.event_log.is_some() || self.postgres_storage.is_some()
+    }
 
-        // 从本地存储删除
-        if let Ok(deleted) = self.local_storage.delete_token(token_id).await {
-            local_deleted = deleted;
+    /// 把修改历史追加到本地日志，返回需要写入数据库的事件
+    ///
+    /// 历史记录失败不影响token本身的写入。
+    async fn record_events(&self, events: Vec<TokenEvent>) -> Vec<OutboxOp> {
+        if events.is_empty() {
+            return Vec::new();
         }
 
-        // 从数据库删除
-        if let Some(postgres) = &self.postgres_storage {
-            if postgres.is_available().await {
-                if let Ok(deleted) = postgres.delete_token(token_id).await {
-                    db_deleted = deleted;
+        if let Some(event_log) = &self.event_log
+            && let Err(e) = event_log.append(&events).await
+        {
+            eprintln!("Failed to record token history [{}]: {}", e.code(), e);
+        }
+
+        events.into_iter().map(|event| OutboxOp::Event { event: Box::new(event) }).collect()
+    }
+
+    /// 按时间返回一个token的修改历史，合并数据库中所有设备的记录和本地尚未写入数据库的记录
+    pub async fn token_history(&self, token_id: &str) -> StorageResult<Vec<TokenEvent>> {
+        let mut events = match &self.event_log {
+            Some(event_log) => event_log.events_for(token_id).await?,
+            None => Vec::new(),
+        };
+
+        if let Some(postgres) = &self.postgres_storage
+            && postgres.is_available().await
+        {
+            match postgres.load_events(token_id).await {
+                Ok(remote_events) => {
+                    let known: HashSet<String> = events.iter().map(|event| event.id.clone()).collect();
+                    events.extend(remote_events.into_iter().filter(|event| !known.contains(&event.id)));
                 }
+                Err(e) => eprintln!("Failed to load token history from database [{}]: {}", e.code(), e),
             }
         }
 
-        Ok(local_deleted || db_deleted)
+        sort_events(&mut events);
+        Ok(events)
     }
 
-    /// 删除token及其在数据库中的重复项
-    async fn delete_token_and_duplicates(&self, token_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        eprintln!("Starting delete_token_and_duplicates for token_id: {}", token_id);
+    /// 把修改写入数据库，返回数据库中删除的行数
+    ///
+    /// 配置了发件箱时先持久化再写入，数据库不可用或写入失败时修改保留在发件箱中等待重放。
+    async fn push_to_database(&self, ops: Vec<OutboxOp>) -> usize {
+        let Some(postgres) = &self.postgres_storage else {
+            return 0;
+        };
+        if let Some(scheduler) = &self.sync_scheduler {
+            scheduler.local_changed(Utc::now());
+        }
 
-        // 首先获取要删除的token信息，用于查找重复项
-        let token_info = if let Some(postgres) = &self.postgres_storage {
-            if postgres.is_available().await {
-                eprintln!("Database is available, trying to get token from database");
-                match postgres.get_token(token_id).await {
-                    Ok(Some(token)) => {
-                        eprintln!("Found token in database: tenant_url={}, access_token={}", token.tenant_url, token.access_token);
-                        Some(token)
-                    },
-                    Ok(None) => {
-                        eprintln!("Token not found in database, trying local storage");
-                        // 如果数据库中没有，尝试从本地存储获取
-                        match self.local_storage.get_token(token_id).await {
-                            Ok(token) => {
-                                if let Some(token) = token {
-                                    eprintln!("Found token in local storage: tenant_url={}, access_token={}", token.tenant_url, token.access_token);
-                                    Some(token)
-                                } else {
-                                    eprintln!("Token not found in local storage either");
-                                    None
-                                }
-                            },
-                            Err(e) => {
-                                eprintln!("Error getting token from local storage: {}", e);
-                                None
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        eprintln!("Error getting token from database: {}, trying local storage", e);
-                        // 数据库查询失败，尝试从本地存储获取
-                        match self.local_storage.get_token(token_id).await {
-                            Ok(token) => {
-                                if let Some(token) = token {
-                                    eprintln!("Found token in local storage: tenant_url={}, access_token={}", token.tenant_url, token.access_token);
-                                    Some(token)
-                                } else {
-                                    eprintln!("Token not found in local storage either");
-                                    None
-                                }
-                            },
-                            Err(e) => {
-                                eprintln!("Error getting token from local storage: {}", e);
-                                None
-                            }
-                        }
+        if let Some(outbox) = &self.outbox {
+            match outbox.enqueue(ops.clone()).await {
+                Ok(()) => {
+                    if !postgres.is_available().await {
+                        return 0;
                     }
-                }
-            } else {
-                eprintln!("Database not available, trying local storage");
-                // 数据库不可用，从本地存储获取
-                match self.local_storage.get_token(token_id).await {
-                    Ok(token) => {
-                        if let Some(token) = token {
-                            eprintln!("Found token in local storage: tenant_url={}, access_token={}", token.tenant_url, token.access_token);
-                            Some(token)
-                        } else {
-                            eprintln!("Token not found in local storage");
-                            None
+                    return match self.replay_outbox_to(postgres).await {
+                        Ok((_, deleted)) => deleted,
+                        Err(e) => {
+                            eprintln!("Failed to replay outbox [{}]: {}", e.code(), e);
+                            0
                         }
-                    },
-                    Err(e) => {
-                        eprintln!("Error getting token from local storage: {}", e);
-                        None
-                    }
+                    };
                 }
+                // 发件箱不可写时退回直接写入数据库
+                Err(e) => eprintln!("Failed to queue changes in outbox [{}]: {}", e.code(), e),
             }
-        } else {
-            eprintln!("No database storage configured, trying local storage");
-            // 没有数据库存储，从本地存储获取
-            match self.local_storage.get_token(token_id).await {
-                Ok(token) => {
-                    if let Some(token) = token {
-                        eprintln!("Found token in local storage: tenant_url={}, access_token={}", token.tenant_url, token.access_token);
-                        Some(token)
-                    } else {
-                        eprintln!("Token not found in local storage");
-                        None
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Error getting token from local storage: {}", e);
-                    None
-                }
+        }
+
+        if !postgres.is_available().await {
+            return 0;
+        }
+
+        let (tokens, tombstones, events) = split_outbox_ops(ops);
+        let result = async {
+            let deleted = postgres.apply_changes(&tokens, &tombstones).await?;
+            postgres.append_events(&events).await?;
+            Ok::<_, StorageError>(deleted)
+        }.await;
+        match result {
+            Ok(deleted) => deleted,
+            Err(e) => {
+                eprintln!("Failed to write to database [{}]: {}", e.code(), e);
+                0
             }
+        }
+    }
+
+    /// 把发件箱中积压的修改写入数据库，返回写入的条目数
+    pub async fn replay_outbox(&self) -> StorageResult<usize> {
+        let postgres = self.require_database().await?;
+        Ok(self.replay_outbox_to(postgres).await?.0)
+    }
+
+    /// 发件箱中等待写入数据库的修改数量
+    pub async fn pending_outbox_count(&self) -> StorageResult<usize> {
+        match &self.outbox {
+            Some(outbox) => outbox.len().await,
+            None => Ok(0),
+        }
+    }
+
+    /// 把发件箱中的修改全部写入数据库，仍有未写入的修改时返回 Unavailable
+    ///
+    /// 切换工作区前调用，避免积压的修改在切换后被写进新的工作区。
+    pub async fn flush_outbox(&self) -> StorageResult<()> {
+        self.replay_outbox().await?;
+        let pending = self.pending_outbox_count().await?;
+        if pending > 0 {
+            return Err(StorageError::Unavailable(format!(
+                "{} queued changes could not be written to the current workspace", pending
+            )));
+        }
+        Ok(())
+    }
+
+    // 返回 (写入的条目数, 数据库中删除的行数)
+    async fn replay_outbox_to(&self, postgres: &PostgreSQLStorage) -> StorageResult<(usize, usize)> {
+        let Some(outbox) = &self.outbox else {
+            return Ok((0, 0));
         };
 
-        // 执行原有的删除逻辑
-        eprintln!("Executing main delete operation");
-        let main_deleted = self.delete_from_both_storages(token_id).await?;
-        eprintln!("Main delete result: {}", main_deleted);
+        // 同一时间只允许一次重放，避免同一批条目被重复写入
+        let _guard = self.replay_lock.lock().await;
 
-        // 如果是双重存储且数据库可用，查找并删除重复的token
-        if let Some(postgres) = &self.postgres_storage {
-            if postgres.is_available().await {
-                if let Some(token) = token_info {
-                    eprintln!("Looking for duplicate tokens with tenant_url={}, access_token={}", token.tenant_url, token.access_token);
-                    // 查找数据库中的重复token
-                    match postgres.find_duplicate_tokens(&token.tenant_url, &token.access_token, token_id).await {
-                        Ok(duplicate_tokens) => {
-                            eprintln!("Found {} duplicate tokens", duplicate_tokens.len());
-                            let mut duplicate_deleted_count = 0;
-                            for duplicate_token in duplicate_tokens {
-                                eprintln!("Attempting to delete duplicate token with ID: {}", duplicate_token.id);
-                                // 删除每个重复的token
-                                if let Ok(deleted) = postgres.delete_token(&duplicate_token.id).await {
-                                    if deleted {
-                                        duplicate_deleted_count += 1;
-                                        eprintln!("Successfully deleted duplicate token with ID: {}", duplicate_token.id);
-                                    } else {
-                                        eprintln!("Failed to delete duplicate token with ID: {} (not found)", duplicate_token.id);
-                                    }
-                                } else {
-                                    eprintln!("Error deleting duplicate token with ID: {}", duplicate_token.id);
-                                }
-                            }
-                            if duplicate_deleted_count > 0 {
-                                eprintln!("Deleted {} duplicate tokens", duplicate_deleted_count);
-                            } else {
-                                eprintln!("No duplicate tokens were deleted");
-                            }
-                        },
-                        Err(e) => {
-                            eprintln!("Failed to find duplicate tokens: {}", e);
-                        }
-                    }
-                } else {
-                    eprintln!("No token info available for finding duplicates");
-                }
-            } else {
-                eprintln!("Database not available for duplicate deletion");
-            }
-        } else {
-            eprintln!("No database storage for duplicate deletion");
+        let entries = outbox.entries().await?;
+        if entries.is_empty() {
+            return Ok((0, 0));
         }
 
-        Ok(main_deleted)
+        // 每个token只有一个条目，全部条目可以在一个事务中写入；历史事件写入是幂等的，失败后可以重放
+        let (tokens, tombstones, events) = split_outbox_ops(entries.iter().map(|entry| entry.op.clone()));
+        let deleted = postgres.apply_changes(&tokens, &tombstones).await?;
+        postgres.append_events(&events).await?;
+        outbox.acknowledge(&entries).await?;
+
+        Ok((entries.len(), deleted))
     }
 
-    async fn load_from_preferred_storage(&self) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
-        if self.prefer_database {
-            if let Some(postgres) = &self.postgres_storage {
-                if postgres.is_available().await {
-                    match postgres.load_tokens().await {
-                        Ok(tokens) => return Ok(tokens),
-                        Err(e) => {
-                            eprintln!("Failed to load from database, falling back to local: {}", e);
-                        }
-                    }
+    /// 删除tokens并在两端记录墓碑，返回删除的数量
+    async fn delete_from_both_storages(&self, token_ids: &[String]) -> StorageResult<usize> {
+        let tombstones: Vec<Tombstone> = token_ids.iter()
+            .map(|id| Tombstone::new(id.clone(), self.device_id.clone()))
+            .collect();
+
+        let previous: Vec<TokenData> = if self.records_history() {
+            self.local_storage.load_tokens().await?
+                .into_iter()
+                .filter(|token| token_ids.contains(&token.id))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // 本地删除并记录墓碑；数据库不可用时墓碑留在发件箱中，恢复后再写入数据库
+        let local_deleted = self.local_storage.apply_changes(&[], &tombstones).await?;
+
+        let mut ops: Vec<OutboxOp> = tombstones.into_iter().map(|tombstone| OutboxOp::Delete { tombstone }).collect();
+        ops.extend(self.record_events(
+            previous.iter().filter_map(|token| TokenEvent::between(Some(token), None, &self.device_id)).collect()
+        ).await);
+        let db_deleted = self.push_to_database(ops).await;
+
+        Ok(local_deleted.max(db_deleted))
+    }
+
+    /// 为本地tokens创建快照，未配置快照或没有tokens时返回 None
+    pub async fn create_snapshot(&self, reason: SnapshotReason) -> StorageResult<Option<SnapshotInfo>> {
+        let Some(snapshots) = &self.snapshots else {
+            return Ok(None);
+        };
+        snapshots.create(&self.local_storage.load_tokens().await?, reason).await
+    }
+
+    pub async fn list_snapshots(&self) -> StorageResult<Vec<SnapshotInfo>> {
+        self.require_snapshots()?.list().await
+    }
+
+    /// 比较快照与当前本地tokens
+    pub async fn diff_snapshot(&self, snapshot_id: &str) -> StorageResult<SnapshotDiff> {
+        let snapshot = self.require_snapshots()?.load(snapshot_id).await?;
+        let current = self.local_storage.load_tokens().await?;
+        Ok(SnapshotDiff::between(&snapshot, &current))
+    }
+
+    /// 把tokens恢复为快照中的内容，恢复前先为当前内容创建快照
+    ///
+    /// 快照之后新增的tokens按删除处理（记录墓碑），写回的tokens使用当前时间作为更新时间，
+    /// 这样同步时不会被其他设备的旧版本或删除墓碑覆盖。
+    pub async fn restore_snapshot(&self, snapshot_id: &str) -> StorageResult<SnapshotRestore> {
+        let snapshots = self.require_snapshots()?;
+        let snapshot_tokens = snapshots.load(snapshot_id).await?;
+
+        let current = self.local_storage.load_tokens().await?;
+        let backup = snapshots.create(&current, SnapshotReason::BeforeRestore).await?;
+
+        let current_by_id: HashMap<&str, &TokenData> = current.iter().map(|token| (token.id.as_str(), token)).collect();
+        let restored: Vec<TokenData> = snapshot_tokens.iter()
+            .filter(|token| !current_by_id.get(token.id.as_str()).is_some_and(|existing| same_content(existing, token)))
+            .map(|token| {
+                let mut token = token.clone();
+                token.update_timestamp();
+                // 版本号以数据库中的当前行为准
+                if let Some(existing) = current_by_id.get(token.id.as_str()) {
+                    token.version = existing.version;
                 }
-            }
+                token
+            })
+            .collect();
+
+        let removed_ids: Vec<String> = current.iter()
+            .filter(|token| !snapshot_tokens.iter().any(|snapshot_token| snapshot_token.id == token.id))
+            .map(|token| token.id.clone())
+            .collect();
+        let removed = if removed_ids.is_empty() {
+            0
+        } else {
+            self.delete_from_both_storages(&removed_ids).await?
+        };
+
+        if !restored.is_empty() {
+            self.save_tokens(&restored).await?;
         }
 
-        // 回退到本地存储
-        self.local_storage.load_tokens().await
+        Ok(SnapshotRestore {
+            restored: restored.len(),
+            removed,
+            backup_id: backup.map(|info| info.id),
+        })
     }
-}
 
-#[async_trait::async_trait]
-impl TokenStorage for DualStorage {
-    async fn save_token(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.sync_to_both_storages(token).await
+    fn require_snapshots(&self) -> StorageResult<&Arc<SnapshotManager>> {
+        self.snapshots.as_ref()
+            .ok_or_else(|| StorageError::Unavailable("Snapshots not configured".to_string()))
     }
 
-    async fn load_tokens(&self) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
-        self.load_from_preferred_storage().await
+    /// 删除所有满足条件的tokens，返回删除的数量
+    pub async fn delete_matching(&self, filter: &TokenFilter) -> StorageResult<usize> {
+        let matched = self.query_tokens(filter, &TokenSort::default(), &PageRequest::default()).await?;
+        let token_ids: Vec<String> = matched.tokens.into_iter().map(|token| token.id).collect();
+        if token_ids.is_empty() {
+            return Ok(0);
+        }
+
+        self.create_snapshot(SnapshotReason::BeforeBulkDelete).await?;
+
+        self.delete_from_both_storages(&token_ids).await
     }
 
-    async fn update_token(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut updated_token = token.clone();
-        updated_token.update_timestamp();
-        self.sync_to_both_storages(&updated_token).await
+    /// 批量修改所有满足条件的tokens的标签和分组，返回实际修改的数量
+    pub async fn update_matching(&self, filter: &TokenFilter, update: &TokenBulkUpdate) -> StorageResult<usize> {
+        let matched = self.query_tokens(filter, &TokenSort::default(), &PageRequest::default()).await?;
+        let changed: Vec<TokenData> = matched.tokens.into_iter()
+            .filter_map(|mut token| {
+                if !update.apply(&mut token) {
+                    return None;
+                }
+                token.update_timestamp();
+                Some(token)
+            })
+            .collect();
+
+        if !changed.is_empty() {
+            self.save_tokens(&changed).await?;
+        }
+        Ok(changed.len())
     }
 
-    async fn delete_token(&self, token_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        self.delete_token_and_duplicates(token_id).await
+    /// 读取本地存储中的全部tokens，不读取数据库
+    pub async fn load_local_tokens(&self) -> StorageResult<Vec<TokenData>> {
+        self.local_storage.load_tokens().await
     }
 
-    async fn get_token(&self, token_id: &str) -> Result<Option<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
-        // 优先从首选存储获取
-        if self.prefer_database {
-            if let Some(postgres) = &self.postgres_storage {
-                if postgres.is_available().await {
-                    match postgres.get_token(token_id).await {
-                        Ok(Some(token)) => return Ok(Some(token)),
-                        Ok(None) => {}, // 继续尝试本地存储
-                        Err(e) => {
-                            eprintln!("Failed to get token from database: {}", e);
-                        }
-                    }
-                }
+    /// 用前端保存的完整列表替换本地tokens
+    ///
+    /// 只写入内容有变化的tokens；列表中没有的tokens先创建快照，再按删除处理（记录墓碑）。
+    /// 数据库可用时已有的tokens按版本号更新，被其他客户端修改过的返回 Conflict，其余修改照常写入。
+    pub async fn replace_tokens(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        let current = self.local_storage.load_tokens().await?;
+        let current_by_id: HashMap<&str, &TokenData> = current.iter().map(|token| (token.id.as_str(), token)).collect();
+        let incoming_ids: HashSet<&str> = tokens.iter().map(|token| token.id.as_str()).collect();
+
+        let removed_ids: Vec<String> = current.iter()
+            .filter(|token| !incoming_ids.contains(token.id.as_str()))
+            .map(|token| token.id.clone())
+            .collect();
+        if !removed_ids.is_empty() {
+            self.create_snapshot(SnapshotReason::BeforeReplace).await?;
+            self.delete_from_both_storages(&removed_ids).await?;
+        }
+
+        let (mut updated, mut writes): (Vec<TokenData>, Vec<TokenData>) = tokens.iter()
+            .filter(|token| !current_by_id.get(token.id.as_str()).is_some_and(|existing| same_content(existing, token)))
+            .cloned()
+            .partition(|token| current_by_id.contains_key(token.id.as_str()));
+
+        let mut conflict = None;
+        if let Some(postgres) = &self.postgres_storage
+            && postgres.is_available().await
+            && !updated.is_empty()
+        {
+            match self.compare_and_swap_updates(postgres, std::mem::take(&mut updated), &current_by_id).await {
+                Ok(()) => {}
+                Err(e @ StorageError::Conflict(_)) => conflict = Some(e),
+                Err(e) => return Err(e),
             }
         }
 
-        // 从本地存储获取
-        self.local_storage.get_token(token_id).await
+        writes.append(&mut updated);
+        if !writes.is_empty() {
+            self.save_tokens(&writes).await?;
+        }
+
+        match conflict {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
 
-    async fn clear_all_tokens(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // 清空本地存储
-        if let Err(e) = self.local_storage.clear_all_tokens().await {
-            eprintln!("Failed to clear local storage: {}", e);
+    // 在一个数据库事务中按版本号写入全部修改过的tokens，成功后再写入本地
+    //
+    // 有token已被其他客户端修改时整批不写入，把这些tokens的最新版本拉取到本地后返回 Conflict。
+    async fn compare_and_swap_updates(
+        &self,
+        postgres: &PostgreSQLStorage,
+        mut updated: Vec<TokenData>,
+        current_by_id: &HashMap<&str, &TokenData>,
+    ) -> StorageResult<()> {
+        // 先写入积压的修改，避免稍后重放时用旧内容覆盖这次更新
+        self.replay_outbox_to(postgres).await?;
+
+        for token in &mut updated {
+            token.update_timestamp();
         }
 
-        // 清空数据库
-        if let Some(postgres) = &self.postgres_storage {
-            if postgres.is_available().await {
-                if let Err(e) = postgres.clear_all_tokens().await {
-                    eprintln!("Failed to clear database: {}", e);
+        let stored = match postgres.compare_and_swap_many(&updated).await {
+            Ok(stored) => stored,
+            Err(e @ StorageError::Conflict(_)) => {
+                // 用户重新加载后可以在最新版本上重试
+                let token_ids: Vec<String> = updated.iter().map(|token| token.id.clone()).collect();
+                if let Err(pull_error) = self.pull_remote_changes(&token_ids).await {
+                    eprintln!("Failed to pull conflicting tokens [{}]: {}", pull_error.code(), pull_error);
                 }
+                return Err(e);
             }
-        }
+            Err(e) => return Err(e),
+        };
 
+        self.local_storage.save_tokens(&stored).await?;
+        let events = stored.iter()
+            .filter_map(|token| TokenEvent::between(current_by_id.get(token.id.as_str()).copied(), Some(token), &self.device_id))
+            .collect();
+        let ops = self.record_events(events).await;
+        self.push_to_database(ops).await;
         Ok(())
     }
 
-    fn storage_type(&self) -> &'static str {
-        if self.is_database_available() {
-            "dual_storage"
-        } else {
-            "local_only"
+    /// 切换工作区后用数据库中当前工作区的tokens和墓碑替换本地数据，返回载入的token数
+    ///
+    /// 替换前创建快照；本地原有的数据属于上一个工作区，不会被合并进新的工作区。
+    pub async fn load_workspace(&self) -> StorageResult<usize> {
+        let postgres = self.require_database().await?;
+        let remote_tokens = postgres.load_tokens().await?;
+        let remote_tombstones = postgres.load_tombstones().await?;
+
+        self.create_snapshot(SnapshotReason::BeforeWorkspaceSwitch).await?;
+        self.local_storage.purge_tombstones(ALL_TOMBSTONES_BEFORE).await?;
+        self.local_storage.replace_all(&remote_tokens).await?;
+        self.local_storage.save_tombstones(&remote_tombstones).await?;
+        // 本地与数据库此时一致，作为下次双向同步的合并基准
+        self.local_storage.save_sync_base(&remote_tokens).await?;
+
+        Ok(remote_tokens.len())
+    }
+
+    /// 找出所有可能重复的tokens及建议的合并结果
+    pub async fn find_duplicates(&self) -> StorageResult<Vec<DuplicateGroup>> {
+        Ok(find_duplicates(&self.load_tokens().await?))
+    }
+
+    /// 把一组重复的tokens合并为一条，其余的删除并记录墓碑，返回合并后的token
+    ///
+    /// 本地和数据库各自在一次写入中完成，不会出现只删除了重复项而合并结果没有保存的情况。
+    pub async fn merge_duplicate_tokens(&self, token_ids: &[String]) -> StorageResult<TokenData> {
+        let tokens: Vec<TokenData> = self.load_tokens().await?
+            .into_iter()
+            .filter(|token| token_ids.contains(&token.id))
+            .collect();
+        if let Some(missing) = token_ids.iter().find(|id| !tokens.iter().any(|token| &token.id == *id)) {
+            return Err(StorageError::NotFound(format!("Token not found: {}", missing)));
+        }
+        if tokens.len() < 2 {
+            return Err(StorageError::InvalidInput("At least two tokens are required to merge".to_string()));
         }
+
+        self.create_snapshot(SnapshotReason::BeforeMerge).await?;
+
+        let mut merged = merge_duplicates(&tokens);
+        merged.update_timestamp();
+        let (primary, removed): (Vec<&TokenData>, Vec<&TokenData>) = tokens.iter().partition(|token| token.id == merged.id);
+        let tombstones: Vec<Tombstone> = removed.iter()
+            .map(|token| Tombstone::new(token.id.clone(), self.device_id.clone()))
+            .collect();
+
+        self.local_storage.apply_changes(std::slice::from_ref(&merged), &tombstones).await?;
+
+        let mut events: Vec<TokenEvent> = TokenEvent::between(primary.first().copied(), Some(&merged), &self.device_id).into_iter().collect();
+        events.extend(removed.iter().filter_map(|token| TokenEvent::between(Some(token), None, &self.device_id)));
+
+        // 合并结果和墓碑在同一批写入数据库
+        let mut ops = vec![OutboxOp::Upsert { token: Box::new(merged.clone()) }];
+        ops.extend(tombstones.into_iter().map(|tombstone| OutboxOp::Delete { tombstone }));
+        ops.extend(self.record_events(events).await);
+        self.push_to_database(ops).await;
+
+        Ok(merged)
     }
 
-    async fn is_available(&self) -> bool {
-        // 至少本地存储应该可用
-        self.local_storage.is_available().await
+    /// 按指定格式导出tokens，不传过滤条件时导出全部
+    pub async fn export_tokens(&self, filter: Option<&TokenFilter>, options: &ExportOptions) -> StorageResult<String> {
+        let filter = filter.cloned().unwrap_or_default();
+        let matched = self.query_tokens(&filter, &TokenSort::default(), &PageRequest::default()).await?;
+        export_tokens(&matched.tokens, options)
     }
-}
 
-#[async_trait::async_trait]
-impl SyncManager for DualStorage {
-    async fn sync_local_to_remote(&self) -> Result<SyncStatus, Box<dyn std::error::Error + Send + Sync>> {
-        let postgres = self.postgres_storage.as_ref()
-            .ok_or("Database storage not available")?;
+    /// 导入tokens并返回逐行报告，dry_run 时只生成报告
+    pub async fn import_tokens(&self, content: &str, options: &ImportOptions) -> StorageResult<ImportReport> {
+        let existing = self.load_tokens().await?;
+        let (report, tokens) = plan_import(content, options, &existing)?;
 
-        if !postgres.is_available().await {
-            return Err("Database not available".into());
+        if !options.dry_run && !tokens.is_empty() {
+            self.save_tokens(&tokens).await?;
         }
+        Ok(report)
+    }
 
-        let local_tokens = self.local_storage.load_tokens().await?;
-        let mut synced_count = 0;
-        let mut errors = Vec::new();
+    /// 合并本地和远程的墓碑
+    async fn load_all_tombstones(&self, postgres: Option<&PostgreSQLStorage>) -> StorageResult<Vec<Tombstone>> {
+        let local_tombstones = self.local_storage.load_tombstones().await?;
 
-        for token in local_tokens {
-            match postgres.save_token(&token).await {
-                Ok(_) => synced_count += 1,
-                Err(e) => errors.push(format!("Token {}: {}", token.id, e)),
-            }
+        match postgres {
+            Some(postgres) => Ok(merge_tombstones(local_tombstones, postgres.load_tombstones().await?)),
+            None => Ok(local_tombstones),
         }
+    }
 
-        let status = if errors.is_empty() { "success" } else { "partial_success" };
-        let error_message = if errors.is_empty() {
-            None
-        } else {
-            Some(errors.join("; "))
-        };
-
-        let sync_status = SyncStatus {
-            last_sync_at: Some(Utc::now()),
-            sync_direction: "local_to_remote".to_string(),
-            status: status.to_string(),
-            error_message: error_message.clone(),
-            tokens_synced: synced_count,
-        };
+    /// 增量合并本地和远程tokens并写回两端
+    ///
+    /// `memory_tokens` 为前端内存中的tokens，为空时使用本地存储中的tokens。
+    async fn bidirectional_sync_inner(&self, memory_tokens: Option<Vec<TokenData>>, direction: &str) -> StorageResult<SyncStatus> {
+        let postgres = self.require_database().await?;
+        let _guard = self.lock_sync().await?;
+        // 先写入积压的修改，避免同步后再用旧版本覆盖数据库
+        self.replay_outbox_to(postgres).await?;
+
+        let prepared = self.prepare_sync(postgres, SyncDirection::Bidirectional, memory_tokens).await?;
+        self.execute_sync(postgres, prepared, direction).await
+    }
 
-        // 记录同步状态到数据库
-        if let Some(pool) = postgres.db_manager.get_pool() {
-            let _ = super::postgres_storage::record_sync_status(
-                &pool,
-                &sync_status.sync_direction,
-                &sync_status.status,
-                error_message.as_deref(),
-                sync_status.tokens_synced,
-            ).await;
+    /// 预演一次同步，返回两端将发生的变化，不修改任何一端
+    ///
+    /// 发件箱中还有未写入数据库的修改时返回 Conflict，这些修改写入后再预演。
+    pub async fn plan_sync(&self, direction: SyncDirection) -> StorageResult<SyncPlan> {
+        let postgres = self.require_database().await?;
+        self.ensure_outbox_empty().await?;
+        if let Some(journal) = &self.sync_journal
+            && let Some(entry) = journal.pending().await?
+        {
+            return Err(StorageError::Conflict(format!(
+                "Sync started at {} has not been recovered yet", entry.started_at
+            )));
         }
 
-        Ok(sync_status)
+        let prepared = self.prepare_sync(postgres, direction, None).await?;
+        self.describe_sync(postgres, &prepared).await
     }
 
-    async fn sync_remote_to_local(&self) -> Result<SyncStatus, Box<dyn std::error::Error + Send + Sync>> {
-        let postgres = self.postgres_storage.as_ref()
-            .ok_or("Database storage not available")?;
-
-        if !postgres.is_available().await {
-            return Err("Database not available".into());
+    /// 执行预演得到的同步计划
+    ///
+    /// 执行前重新计算计划，两端在预演之后有变化（指纹不同）时返回 Conflict，不做任何修改。
+    pub async fn apply_plan(&self, plan: &SyncPlan) -> StorageResult<SyncStatus> {
+        let postgres = self.require_database().await?;
+        let _guard = self.lock_sync().await?;
+        self.ensure_outbox_empty().await?;
+
+        let prepared = self.prepare_sync(postgres, plan.direction, None).await?;
+        let current = self.describe_sync(postgres, &prepared).await?;
+        if current.fingerprint != plan.fingerprint {
+            return Err(StorageError::Conflict(
+                "Tokens changed since the sync was planned, plan the sync again".to_string()
+            ));
         }
 
-        let remote_tokens = postgres.load_tokens().await?;
-        let mut synced_count = 0;
-        let mut errors = Vec::new();
+        self.execute_sync(postgres, prepared, plan.direction.as_str()).await
+    }
 
-        for token in remote_tokens {
-            match self.local_storage.save_token(&token).await {
-                Ok(_) => synced_count += 1,
-                Err(e) => errors.push(format!("Token {}: {}", token.id, e)),
+    // 预演时不写数据库，积压的修改会让执行结果与计划不一致
+    async fn ensure_outbox_empty(&self) -> StorageResult<()> {
+        let pending = self.pending_outbox_count().await?;
+        if pending > 0 {
+            return Err(StorageError::Conflict(format!(
+                "{} queued changes have not been written to the database yet", pending
+            )));
+        }
+        Ok(())
+    }
+
+    /// 读取两端并计算同步需要写入的内容，不修改任何一端
+    ///
+    /// 双向同步时远程只拉取水位线之后变更的行，本地只推送相对同步基准有变化的token；
+    /// 没有水位线（首次同步或切换了数据库）时退化为全量同步。`memory_tokens` 只用于双向同步。
+    async fn prepare_sync(
+        &self,
+        postgres: &PostgreSQLStorage,
+        direction: SyncDirection,
+        memory_tokens: Option<Vec<TokenData>>,
+    ) -> StorageResult<PreparedSync> {
+        let stored_tokens = self.local_storage.load_tokens().await?;
+
+        let mut prepared = match direction {
+            SyncDirection::LocalToRemote => {
+                let local_tombstones = self.local_storage.load_tombstones().await?;
+                let tombstones = merge_tombstones(local_tombstones.clone(), postgres.load_tombstones().await?);
+                // 其他设备已删除的token不再推送
+                let to_remote = apply_tombstones(stored_tokens.clone(), &tombstones);
+
+                PreparedSync {
+                    direction,
+                    token_count: to_remote.len(),
+                    to_remote,
+                    remote_tombstones: local_tombstones,
+                    to_local: Vec::new(),
+                    local_tombstones: Vec::new(),
+                    conflicts: Vec::new(),
+                    errors: Vec::new(),
+                    watermark: None,
+                    stored_tokens,
+                    remote_versions: HashMap::new(),
+                }
             }
+            SyncDirection::RemoteToLocal => {
+                let remote_tombstones = postgres.load_tombstones().await?;
+                let tombstones = merge_tombstones(self.local_storage.load_tombstones().await?, remote_tombstones.clone());
+                // 本机已删除的token不再拉回
+                let to_local = apply_tombstones(postgres.load_tokens().await?, &tombstones);
+
+                PreparedSync {
+                    direction,
+                    token_count: to_local.len(),
+                    to_remote: Vec::new(),
+                    remote_tombstones: Vec::new(),
+                    to_local,
+                    local_tombstones: remote_tombstones,
+                    conflicts: Vec::new(),
+                    errors: Vec::new(),
+                    watermark: None,
+                    stored_tokens,
+                    remote_versions: HashMap::new(),
+                }
+            }
+            SyncDirection::Bidirectional => {
+                let database_id = postgres.database_id().await?;
+                let watermark = self.local_storage.load_sync_watermark().await?
+                    .filter(|watermark| watermark.database_id == database_id);
+
+                let changes = postgres.load_tokens_changed_since(watermark.as_ref()).await?;
+                let errors = changes.errors;
+                let remote_tokens = changes.tokens;
+                // 合并基于的远程版本，写入时据此检查其间是否有其他客户端修改
+                let remote_versions = remote_tokens.iter().map(|token| (token.id.clone(), token.version)).collect();
+                // 有行读取失败时水位线不前移，下次同步重新读取这些行
+                let (remote_change_seq, remote_xmin) = if errors.is_empty() {
+                    (changes.change_seq, Some(changes.xmin))
+                } else {
+                    watermark.as_ref().map_or((0, None), |watermark| (watermark.remote_change_seq, watermark.remote_xmin))
+                };
+                let local_tokens = memory_tokens.unwrap_or_else(|| stored_tokens.clone());
+                let tombstones = self.load_all_tombstones(Some(postgres)).await?;
+                let base_tokens = self.local_storage.load_sync_base().await?;
+
+                let plan = plan_merge(local_tokens, &stored_tokens, remote_tokens, &base_tokens, &tombstones);
+
+                PreparedSync {
+                    direction,
+                    token_count: plan.changed_count(),
+                    to_remote: plan.to_remote,
+                    remote_tombstones: plan.tombstones.clone(),
+                    to_local: plan.to_local,
+                    local_tombstones: plan.tombstones,
+                    conflicts: plan.conflicts,
+                    errors,
+                    watermark: Some(SyncWatermark {
+                        database_id,
+                        remote_change_seq,
+                        remote_xmin,
+                    }),
+                    stored_tokens,
+                    remote_versions,
+                }
+            }
+        };
+
+        // 没有读到的行以现在的版本为准，不存在的token只能新建
+        let unknown_ids: Vec<String> = prepared.to_remote.iter()
+            .filter(|token| !prepared.remote_versions.contains_key(&token.id))
+            .map(|token| token.id.clone())
+            .collect();
+        prepared.remote_versions.extend(postgres.load_token_versions(&unknown_ids).await?);
+
+        // 固定顺序，相同的数据总是得到相同的计划指纹
+        prepared.to_remote.sort_by(|a, b| a.id.cmp(&b.id));
+        prepared.to_local.sort_by(|a, b| a.id.cmp(&b.id));
+        prepared.remote_tombstones.sort_by(|a, b| a.token_id.cmp(&b.token_id));
+        prepared.local_tombstones.sort_by(|a, b| a.token_id.cmp(&b.token_id));
+        prepared.conflicts.sort_by(|a, b| (&a.token_id, &a.field).cmp(&(&b.token_id, &b.field)));
+
+        Ok(prepared)
+    }
+
+    /// 生成展示给用户的计划，数据库只读取受影响的tokens
+    async fn describe_sync(&self, postgres: &PostgreSQLStorage, prepared: &PreparedSync) -> StorageResult<SyncPlan> {
+        let affected_ids: Vec<String> = prepared.to_remote.iter().map(|token| token.id.clone())
+            .chain(prepared.remote_tombstones.iter().map(|tombstone| tombstone.token_id.clone()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let remote_tokens = postgres.load_tokens_by_ids(&affected_ids).await?;
+
+        let mut plan = SyncPlan {
+            direction: prepared.direction,
+            local: StoreChanges::between(&prepared.stored_tokens, &prepared.to_local, &prepared.local_tombstones),
+            remote: StoreChanges::between(&remote_tokens, &prepared.to_remote, &prepared.remote_tombstones),
+            conflicts: prepared.conflicts.clone(),
+            errors: prepared.errors.clone(),
+            fingerprint: String::new(),
+        };
+        plan.fingerprint = plan_fingerprint(
+            &plan,
+            &[
+                (&prepared.to_remote, &prepared.remote_tombstones),
+                (&prepared.to_local, &prepared.local_tombstones),
+            ],
+            prepared.watermark.as_ref().map(|watermark| watermark.remote_change_seq),
+        )?;
+
+        Ok(plan)
+    }
+
+    /// 把准备好的内容写入两端并记录同步结果
+    ///
+    /// 先写同步日志，再在一个事务中写入数据库，最后在一次写入中替换本地的tokens、同步基准和水位线。
+    /// 数据库写入失败时两端都保持原样；本地写入失败时日志保留，之后由恢复流程补完。
+    async fn execute_sync(&self, postgres: &PostgreSQLStorage, prepared: PreparedSync, direction: &str) -> StorageResult<SyncStatus> {
+        let PreparedSync {
+            direction: sync_direction,
+            to_remote,
+            remote_tombstones,
+            to_local,
+            local_tombstones,
+            conflicts,
+            errors,
+            watermark,
+            token_count,
+            remote_versions,
+            ..
+        } = prepared;
+
+        if sync_direction != SyncDirection::LocalToRemote {
+            self.create_snapshot(SnapshotReason::BeforeSync).await?;
         }
 
-        let status = if errors.is_empty() { "success" } else { "partial_success" };
-        let error_message = if errors.is_empty() {
-            None
+        let entry = SyncJournalEntry::new(direction, to_local, local_tombstones, watermark);
+        if let Some(journal) = &self.sync_journal {
+            journal.begin(&entry).await?;
+        }
+
+        let remote_result = if to_remote.is_empty() && remote_tombstones.is_empty() {
+            Ok(0)
         } else {
-            Some(errors.join("; "))
+            postgres.apply_sync_changes(&to_remote, &remote_versions, &remote_tombstones).await
         };
 
-        let sync_status = SyncStatus {
-            last_sync_at: Some(Utc::now()),
-            sync_direction: "remote_to_local".to_string(),
-            status: status.to_string(),
-            error_message: error_message.clone(),
-            tokens_synced: synced_count,
+        let result = match remote_result {
+            Ok(_) => self.commit_local(&entry).await,
+            Err(e) => {
+                // 数据库没有提交，放弃这次同步
+                if let Some(journal) = &self.sync_journal
+                    && let Err(e) = journal.finish(&entry.id).await
+                {
+                    eprintln!("Failed to discard sync journal [{}]: {}", e.code(), e);
+                }
+                Err(e)
+            }
         };
 
-        // 记录同步状态到数据库
-        if let Some(pool) = postgres.db_manager.get_pool() {
-            let _ = super::postgres_storage::record_sync_status(
-                &pool,
-                &sync_status.sync_direction,
-                &sync_status.status,
-                error_message.as_deref(),
-                sync_status.tokens_synced,
-            ).await;
+        if result.is_ok() && entry.watermark.is_some() && let Err(e) = self.purge_expired_tombstones().await {
+            eprintln!("Failed to purge expired tombstones [{}]: {}", e.code(), e);
         }
 
-        Ok(sync_status)
+        self.finish_sync(postgres, direction, token_count, conflicts, errors, result).await
     }
 
-    async fn bidirectional_sync(&self) -> Result<SyncStatus, Box<dyn std::error::Error + Send + Sync>> {
-        let postgres = self.postgres_storage.as_ref()
-            .ok_or("Database storage not available")?;
+    // 数据库已提交后写入本地存储，完成后删除日志
+    async fn commit_local(&self, entry: &SyncJournalEntry) -> StorageResult<()> {
+        if let Some(journal) = &self.sync_journal {
+            journal.mark_remote_committed(&entry.id).await?;
+        }
 
-        if !postgres.is_available().await {
-            return Err("Database not available".into());
+        if !entry.tokens.is_empty() || !entry.tombstones.is_empty() || entry.watermark.is_some() {
+            self.local_storage.commit_sync(&entry.tokens, &entry.tombstones, entry.watermark.as_ref()).await?;
         }
 
-        let local_tokens = self.local_storage.load_tokens().await?;
-        let remote_tokens = postgres.load_tokens().await?;
+        if let Some(journal) = &self.sync_journal {
+            journal.finish(&entry.id).await?;
+        }
+        Ok(())
+    }
+
+    /// 恢复上次中断的同步：数据库已提交的补完本地写入，否则放弃这次同步
+    ///
+    /// 本地存储只在数据库提交之后写入，放弃时本地仍是同步前的状态；
+    /// 数据库可能已经提交的修改会在下次同步时合并回来。没有中断的同步时返回 None。
+    pub async fn recover_interrupted_sync(&self) -> StorageResult<Option<SyncRecovery>> {
+        let _guard = self.sync_lock.lock().await;
+        self.recover_journal().await
+    }
 
-        let resolved_tokens = self.resolve_conflicts(local_tokens.clone(), remote_tokens.clone()).await?;
+    // 获取同步锁，并先恢复上次中断的同步
+    async fn lock_sync(&self) -> StorageResult<tokio::sync::MutexGuard<'_, ()>> {
+        let guard = self.sync_lock.lock().await;
+        if let Some(recovery) = self.recover_journal().await? {
+            println!("Recovered interrupted {} sync (completed: {})", recovery.direction, recovery.completed);
+        }
+        Ok(guard)
+    }
 
-        let mut synced_count = 0;
-        let mut errors = Vec::new();
+    // 调用方需持有 sync_lock
+    async fn recover_journal(&self) -> StorageResult<Option<SyncRecovery>> {
+        let Some(journal) = &self.sync_journal else {
+            return Ok(None);
+        };
+        let Some(entry) = journal.pending().await? else {
+            return Ok(None);
+        };
 
-        // 先清空本地存储，然后重新写入解决后的tokens
-        if let Err(e) = self.local_storage.clear_all_tokens().await {
-            errors.push(format!("Failed to clear local storage: {}", e));
+        let completed = entry.phase == SyncJournalPhase::RemoteCommitted;
+        if completed {
+            self.commit_local(&entry).await?;
+        } else {
+            journal.finish(&entry.id).await?;
         }
 
-        // 同步解决后的tokens到两个存储
-        for token in resolved_tokens {
-            let mut local_ok = false;
-            let mut remote_ok = false;
+        Ok(Some(SyncRecovery {
+            direction: entry.direction,
+            started_at: entry.started_at,
+            completed,
+        }))
+    }
 
-            if let Ok(_) = self.local_storage.save_token(&token).await {
-                local_ok = true;
-            }
+    /// 把数据库中指定tokens的最新状态拉取到本地存储（用于实时变更通知）
+    ///
+    /// 本地未修改的token直接采用远程版本，本地也修改过的做三方合并，
+    /// 已被删除的token按墓碑从本地删除。不写数据库，本地修改在下次同步时推送。
+    pub async fn pull_remote_changes(&self, token_ids: &[String]) -> StorageResult<usize> {
+        let postgres = self.require_database().await?;
+
+        let remote_tokens = postgres.load_tokens_by_ids(token_ids).await?;
+        let tombstones: Vec<Tombstone> = postgres.load_tombstones().await?
+            .into_iter()
+            .filter(|tombstone| token_ids.contains(&tombstone.token_id))
+            .collect();
+        let stored_tokens: Vec<TokenData> = self.local_storage.load_tokens().await?
+            .into_iter()
+            .filter(|token| token_ids.contains(&token.id))
+            .collect();
+        let base_tokens = self.local_storage.load_sync_base().await?;
+
+        let plan = plan_merge(stored_tokens.clone(), &stored_tokens, remote_tokens, &base_tokens, &tombstones);
+        if plan.to_local.is_empty() && plan.tombstones.is_empty() {
+            return Ok(0);
+        }
 
-            if let Ok(_) = postgres.save_token(&token).await {
-                remote_ok = true;
-            }
+        let deleted = self.local_storage.apply_changes(&plan.to_local, &plan.tombstones).await?;
+        Ok(plan.to_local.len() + deleted)
+    }
+
+    /// 分页读取当前工作区的同步历史，按时间倒序
+    pub async fn sync_history(&self, page: &PageRequest) -> StorageResult<SyncHistoryPage> {
+        let postgres = self.require_database().await?;
+        let pool = postgres.db_manager.get_pool()
+            .ok_or_else(|| StorageError::Unavailable("Database not connected".to_string()))?;
+        load_sync_history(&pool, postgres.workspace(), page).await
+    }
+
+    /// 按同步方向统计同步次数、失败率和平均写入的token数，`since` 为空时统计全部保留的记录
+    pub async fn sync_stats(&self, since: Option<DateTime<Utc>>) -> StorageResult<Vec<SyncDirectionStats>> {
+        let postgres = self.require_database().await?;
+        let pool = postgres.db_manager.get_pool()
+            .ok_or_else(|| StorageError::Unavailable("Database not connected".to_string()))?;
+        load_sync_stats(&pool, postgres.workspace(), since).await
+    }
 
-            if local_ok || remote_ok {
-                synced_count += 1;
-            } else {
-                errors.push(format!("Failed to sync token {}", token.id));
+    /// 清理两端超过保留期限的墓碑，返回清理的数量
+    pub async fn purge_expired_tombstones(&self) -> StorageResult<usize> {
+        let cutoff = Utc::now() - self.tombstone_retention;
+
+        let mut purged = self.local_storage.purge_tombstones(cutoff).await?;
+
+        if let Some(postgres) = &self.postgres_storage {
+            if postgres.is_available().await {
+                purged += postgres.purge_tombstones(cutoff).await?;
             }
         }
 
-        let status = if errors.is_empty() { "success" } else { "partial_success" };
-        let error_message = if errors.is_empty() {
-            None
-        } else {
-            Some(errors.join("; "))
+        Ok(purged)
+    }
+
+    /// 生成同步结果并记录到数据库，`errors` 为未能同步的token
+    async fn finish_sync(
+        &self,
+        postgres: &PostgreSQLStorage,
+        direction: &str,
+        token_count: usize,
+        conflicts: Vec<SyncConflict>,
+        errors: Vec<SyncError>,
+        result: StorageResult<()>,
+    ) -> StorageResult<SyncStatus> {
+        let (status, tokens_synced, conflicts, errors) = match &result {
+            Ok(_) if errors.is_empty() => ("success", token_count as i32, conflicts, errors),
+            Ok(_) => ("partial", token_count as i32, conflicts, errors),
+            Err(e) => ("failed", 0, Vec::new(), vec![SyncError::new(None, e)]),
+        };
+        let error_message = match errors.as_slice() {
+            [] => None,
+            [error] => Some(error.message.clone()),
+            errors => Some(format!("{} tokens could not be synced", errors.len())),
         };
 
-        let sync_status = SyncStatus {
+        let mut sync_status = SyncStatus {
+            id: None,
             last_sync_at: Some(Utc::now()),
-            sync_direction: "bidirectional".to_string(),
+            sync_direction: direction.to_string(),
             status: status.to_string(),
-            error_message: error_message.clone(),
-            tokens_synced: synced_count,
+            error_message,
+            tokens_synced,
+            conflicts,
+            errors,
         };
 
-        // 记录同步状态到数据库
+        // 记录同步状态到数据库，并清理超过保留期限的记录
         if let Some(pool) = postgres.db_manager.get_pool() {
-            let _ = super::postgres_storage::record_sync_status(
-                &pool,
-                &sync_status.sync_direction,
-                &sync_status.status,
-                error_message.as_deref(),
-                sync_status.tokens_synced,
-            ).await;
+            match record_sync_status(&pool, postgres.workspace(), &sync_status).await {
+                Ok(id) => sync_status.id = Some(id),
+                Err(e) => eprintln!("Failed to record sync status [{}]: {}", e.code(), e),
+            }
+            if let Some(retention) = self.sync_history_retention
+                && let Err(e) = prune_sync_history(&pool, postgres.workspace(), Utc::now() - retention).await
+            {
+                eprintln!("Failed to prune sync history [{}]: {}", e.code(), e);
+            }
         }
 
-        Ok(sync_status)
+        result.map(|_| sync_status)
     }
 
-    async fn bidirectional_sync_with_tokens(&self, local_tokens: Vec<TokenData>) -> Result<SyncStatus, Box<dyn std::error::Error + Send + Sync>> {
+    /// 获取数据库连接，数据库未配置或不可达时返回 Unavailable
+    async fn require_database(&self) -> StorageResult<&Arc<PostgreSQLStorage>> {
         let postgres = self.postgres_storage.as_ref()
-            .ok_or("Database storage not available")?;
+            .ok_or_else(|| StorageError::Unavailable("Database storage not available".to_string()))?;
 
         if !postgres.is_available().await {
-            return Err("Database not available".into());
+            return Err(StorageError::Unavailable("Database not available".to_string()));
         }
 
-        // 使用传入的 local_tokens 而不是从文件读取
-        let remote_tokens = postgres.load_tokens().await?;
+        Ok(postgres)
+    }
 
-        let resolved_tokens = self.resolve_conflicts(local_tokens.clone(), remote_tokens.clone()).await?;
+    /// 删除token及其在数据库中的重复项
+    async fn delete_token_and_duplicates(&self, token_id: &str) -> StorageResult<bool> {
+        // 首先获取要删除的token信息，用于查找重复项
+        let token_info = self.get_token(token_id).await?;
 
-        let mut synced_count = 0;
-        let mut errors = Vec::new();
+        let mut token_ids = vec![token_id.to_string()];
 
-        // 先清空本地存储，然后重新写入解决后的tokens
-        if let Err(e) = self.local_storage.clear_all_tokens().await {
-            errors.push(format!("Failed to clear local storage: {}", e));
+        // 如果是双重存储且数据库可用，查找重复的token一并删除
+        if let (Some(postgres), Some(token)) = (&self.postgres_storage, token_info) {
+            if postgres.is_available().await {
+                let duplicate_tokens = postgres.find_duplicate_tokens(&token.tenant_url, &token.access_token, token_id).await?;
+                for duplicate_token in duplicate_tokens {
+                    eprintln!("Deleting duplicate token with ID: {}", duplicate_token.id);
+                    token_ids.push(duplicate_token.id);
+                }
+            }
         }
 
-        // 同步解决后的tokens到两个存储
-        for token in resolved_tokens {
-            let mut local_ok = false;
-            let mut remote_ok = false;
+        Ok(self.delete_from_both_storages(&token_ids).await? > 0)
+    }
 
-            if let Ok(_) = self.local_storage.save_token(&token).await {
-                local_ok = true;
+    async fn load_from_preferred_storage(&self) -> StorageResult<Vec<TokenData>> {
+        if self.prefer_database {
+            if let Some(postgres) = &self.postgres_storage {
+                if postgres.is_available().await {
+                    match postgres.load_tokens().await {
+                        Ok(tokens) => return Ok(tokens),
+                        Err(e) => {
+                            eprintln!("Failed to load from database [{}], falling back to local: {}", e.code(), e);
+                        }
+                    }
+                }
             }
+        }
+
+        // 回退到本地存储
+        self.local_storage.load_tokens().await
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenStorage for DualStorage {
+    async fn save_token(&self, token: &TokenData) -> StorageResult<()> {
+        self.sync_to_both_storages(token).await
+    }
+
+    async fn load_tokens(&self) -> StorageResult<Vec<TokenData>> {
+        self.load_from_preferred_storage().await
+    }
+
+    /// 数据库可用时按版本号更新，其他客户端已修改过同一token时返回 Conflict 且不写入本地；
+    /// 数据库不可用时照常写入本地和发件箱
+    async fn update_token(&self, token: &TokenData) -> StorageResult<()> {
+        let mut updated_token = token.clone();
+        updated_token.update_timestamp();
 
-            if let Ok(_) = postgres.save_token(&token).await {
-                remote_ok = true;
+        if let Some(postgres) = &self.postgres_storage
+            && postgres.is_available().await
+        {
+            // 先写入积压的修改，避免稍后重放时用旧内容覆盖这次更新
+            self.replay_outbox_to(postgres).await?;
+
+            let previous = self.local_storage.get_token(&updated_token.id).await?;
+            match postgres.compare_and_swap(&updated_token).await {
+                Ok(stored) => {
+                    self.local_storage.save_token(&stored).await?;
+                    let ops = self.record_events(
+                        TokenEvent::between(previous.as_ref(), Some(&stored), &self.device_id).into_iter().collect()
+                    ).await;
+                    self.push_to_database(ops).await;
+                    return Ok(());
+                }
+                // 数据库中还没有这个token，按新增写入
+                Err(StorageError::NotFound(_)) => {}
+                Err(e @ StorageError::Conflict(_)) => {
+                    // 把其他客户端的修改拉取到本地，用户重新加载后可以在最新版本上重试
+                    if let Err(pull_error) = self.pull_remote_changes(std::slice::from_ref(&updated_token.id)).await {
+                        eprintln!("Failed to pull conflicting token [{}]: {}", pull_error.code(), pull_error);
+                    }
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
             }
+        }
 
-            if local_ok || remote_ok {
-                synced_count += 1;
-            } else {
-                errors.push(format!("Failed to sync token {}", token.id));
+        self.sync_to_both_storages(&updated_token).await
+    }
+
+    async fn delete_token(&self, token_id: &str) -> StorageResult<bool> {
+        self.delete_token_and_duplicates(token_id).await
+    }
+
+    async fn get_token(&self, token_id: &str) -> StorageResult<Option<TokenData>> {
+        // 优先从首选存储获取
+        if self.prefer_database {
+            if let Some(postgres) = &self.postgres_storage {
+                if postgres.is_available().await {
+                    match postgres.get_token(token_id).await {
+                        Ok(Some(token)) => return Ok(Some(token)),
+                        Ok(None) => {}, // 继续尝试本地存储
+                        Err(e) => {
+                            eprintln!("Failed to get token from database [{}], falling back to local: {}", e.code(), e);
+                        }
+                    }
+                }
             }
         }
 
-        let status = if errors.is_empty() { "success" } else { "partial_success" };
-        let error_message = if errors.is_empty() {
-            None
+        // 从本地存储获取
+        self.local_storage.get_token(token_id).await
+    }
+
+    async fn save_tokens(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        let previous: HashMap<String, TokenData> = if self.records_history() {
+            self.local_storage.load_tokens().await?
+                .into_iter()
+                .map(|token| (token.id.clone(), token))
+                .collect()
         } else {
-            Some(errors.join("; "))
+            HashMap::new()
         };
 
-        let sync_status = SyncStatus {
-            last_sync_at: Some(Utc::now()),
-            sync_direction: "bidirectional_with_memory".to_string(),
-            status: status.to_string(),
-            error_message: error_message.clone(),
-            tokens_synced: synced_count,
-        };
+        self.local_storage.save_tokens(tokens).await?;
 
-        // 记录同步状态到数据库
-        if let Some(pool) = postgres.db_manager.get_pool() {
-            let _ = super::postgres_storage::record_sync_status(
-                &pool,
-                &sync_status.sync_direction,
-                &sync_status.status,
-                error_message.as_deref(),
-                sync_status.tokens_synced,
-            ).await;
+        let mut ops: Vec<OutboxOp> = tokens.iter().map(|token| OutboxOp::Upsert { token: Box::new(token.clone()) }).collect();
+        ops.extend(self.record_events(
+            tokens.iter().filter_map(|token| TokenEvent::between(previous.get(&token.id), Some(token), &self.device_id)).collect()
+        ).await);
+        self.push_to_database(ops).await;
+
+        Ok(())
+    }
+
+    async fn delete_tokens(&self, token_ids: &[String]) -> StorageResult<usize> {
+        self.delete_from_both_storages(token_ids).await
+    }
+
+    async fn query_tokens(&self, filter: &TokenFilter, sort: &TokenSort, page: &PageRequest) -> StorageResult<TokenPage> {
+        if self.prefer_database
+            && let Some(postgres) = &self.postgres_storage
+            && postgres.is_available().await
+        {
+            match postgres.query_tokens(filter, sort, page).await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    eprintln!("Failed to query database [{}], falling back to local: {}", e.code(), e);
+                }
+            }
         }
 
-        Ok(sync_status)
+        self.local_storage.query_tokens(filter, sort, page).await
     }
 
-    async fn get_sync_status(&self) -> Result<Option<SyncStatus>, Box<dyn std::error::Error + Send + Sync>> {
+    async fn clear_all_tokens(&self) -> StorageResult<()> {
+        self.create_snapshot(SnapshotReason::BeforeClear).await?;
+
+        // 清空本地存储
+        self.local_storage.clear_all_tokens().await?;
+
+        // 清空数据库，积压的修改不再需要写入
         if let Some(postgres) = &self.postgres_storage {
             if postgres.is_available().await {
-                if let Some(pool) = postgres.db_manager.get_pool() {
-                    return super::postgres_storage::get_latest_sync_status(&pool).await;
+                postgres.clear_all_tokens().await?;
+                if let Some(outbox) = &self.outbox {
+                    outbox.clear().await?;
                 }
             }
         }
-        // 如果数据库不可用，返回None
-        Ok(None)
+
+        Ok(())
+    }
+
+    fn storage_type(&self) -> &'static str {
+        if self.is_database_available() {
+            "dual_storage"
+        } else {
+            "local_only"
+        }
     }
 
-    async fn resolve_conflicts(&self, local_tokens: Vec<TokenData>, remote_tokens: Vec<TokenData>) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
-        use std::collections::HashMap;
+    async fn is_available(&self) -> bool {
+        // 至少本地存储应该可用
+        self.local_storage.is_available().await
+    }
+}
 
-        let mut resolved = HashMap::new();
+#[async_trait::async_trait]
+impl SyncManager for DualStorage {
+    async fn sync_local_to_remote(&self) -> StorageResult<SyncStatus> {
+        let postgres = self.require_database().await?;
+        let _guard = self.lock_sync().await?;
+        self.replay_outbox_to(postgres).await?;
+
+        // 一次事务写入全部tokens和本地墓碑，失败时数据库保持原样
+        let prepared = self.prepare_sync(postgres, SyncDirection::LocalToRemote, None).await?;
+        self.execute_sync(postgres, prepared, SyncDirection::LocalToRemote.as_str()).await
+    }
 
-        // 合并策略：保留所有tokens，以最新更新时间为准
+    async fn sync_remote_to_local(&self) -> StorageResult<SyncStatus> {
+        let postgres = self.require_database().await?;
+        let _guard = self.lock_sync().await?;
+        self.replay_outbox_to(postgres).await?;
 
-        // 首先添加所有远程tokens
-        for remote_token in remote_tokens {
-            resolved.insert(remote_token.id.clone(), remote_token);
-        }
+        let prepared = self.prepare_sync(postgres, SyncDirection::RemoteToLocal, None).await?;
+        self.execute_sync(postgres, prepared, SyncDirection::RemoteToLocal.as_str()).await
+    }
 
-        // 然后处理本地tokens
-        for local_token in local_tokens {
-            if let Some(remote_token) = resolved.get(&local_token.id) {
-                // 远程也存在此token，比较更新时间，使用更新的版本
-                if local_token.updated_at > remote_token.updated_at {
-                    resolved.insert(local_token.id.clone(), local_token);
+    async fn bidirectional_sync(&self) -> StorageResult<SyncStatus> {
+        self.bidirectional_sync_inner(None, SyncDirection::Bidirectional.as_str()).await
+    }
+
+    async fn bidirectional_sync_with_tokens(&self, local_tokens: Vec<TokenData>) -> StorageResult<SyncStatus> {
+        // 使用传入的 local_tokens 而不是从文件读取
+        self.bidirectional_sync_inner(Some(local_tokens), "bidirectional_with_memory").await
+    }
+
+    async fn get_sync_status(&self) -> StorageResult<Option<SyncStatus>> {
+        if let Some(postgres) = &self.postgres_storage {
+            if postgres.is_available().await {
+                if let Some(pool) = postgres.db_manager.get_pool() {
+                    return get_latest_sync_status(&pool, postgres.workspace()).await;
                 }
-                // 否则保持远程版本
-            } else {
-                // 远程不存在此token，添加本地token（新增的token）
-                resolved.insert(local_token.id.clone(), local_token);
             }
         }
+        // 如果数据库不可用，返回None
+        Ok(None)
+    }
+
+    async fn resolve_conflicts(&self, local_tokens: Vec<TokenData>, remote_tokens: Vec<TokenData>) -> StorageResult<Vec<TokenData>> {
+        // 数据库不可用时只使用本地墓碑
+        let postgres = self.require_database().await.ok().map(|postgres| &**postgres);
+        let tombstones = self.load_all_tombstones(postgres).await?;
+        let base_tokens = self.local_storage.load_sync_base().await?;
 
-        Ok(resolved.into_values().collect())
+        let (merged_tokens, _) = merge_token_sets(local_tokens, remote_tokens, &base_tokens);
+        Ok(apply_tombstones(merged_tokens, &tombstones))
+    }
+}
+
+fn split_outbox_ops(ops: impl IntoIterator<Item = OutboxOp>) -> (Vec<TokenData>, Vec<Tombstone>, Vec<TokenEvent>) {
+    let mut tokens = Vec::new();
+    let mut tombstones = Vec::new();
+    let mut events = Vec::new();
+    for op in ops {
+        match op {
+            OutboxOp::Upsert { token } => tokens.push(*token),
+            OutboxOp::Delete { tombstone } => tombstones.push(tombstone),
+            OutboxOp::Event { event } => events.push(*event),
+        }
+    }
+    (tokens, tombstones, events)
+}
+
+/// 一次同步需要写入两端的全部内容，预演和执行共用
+struct PreparedSync {
+    direction: SyncDirection,
+    to_remote: Vec<TokenData>,
+    remote_tombstones: Vec<Tombstone>,
+    to_local: Vec<TokenData>,
+    local_tombstones: Vec<Tombstone>,
+    conflicts: Vec<SyncConflict>,
+    errors: Vec<SyncError>,
+    // 双向同步成功后保存的水位线
+    watermark: Option<SyncWatermark>,
+    // 写入前本地存储中的tokens
+    stored_tokens: Vec<TokenData>,
+    // 写入数据库时预期的行版本，计划之后被其他客户端修改的行不会被覆盖
+    remote_versions: HashMap<String, i64>,
+    // 记入同步状态的token数
+    token_count: usize,
+}
+
+/// 一次合并需要写入两端的变更
+struct MergePlan {
+    /// 需要写入数据库的tokens
+    to_remote: Vec<TokenData>,
+    /// 需要写入本地存储的tokens
+    to_local: Vec<TokenData>,
+    /// 需要在两端生效的墓碑（不含删除后又更新过的token）
+    tombstones: Vec<Tombstone>,
+    conflicts: Vec<SyncConflict>,
+}
+
+impl MergePlan {
+    fn changed_count(&self) -> usize {
+        self.to_remote.iter().chain(&self.to_local)
+            .map(|token| token.id.as_str())
+            .collect::<HashSet<_>>()
+            .len()
+    }
+}
+
+/// 根据同步基准合并本地和远程的变更，计算两端各自需要写入的内容
+///
+/// `local_tokens` 为参与合并的本地tokens（可能来自前端内存），`stored_tokens` 为本地存储中的实际内容。
+fn plan_merge(
+    local_tokens: Vec<TokenData>,
+    stored_tokens: &[TokenData],
+    remote_tokens: Vec<TokenData>,
+    base_tokens: &[TokenData],
+    tombstones: &[Tombstone],
+) -> MergePlan {
+    // 与基准相同的本地token自上次同步后没有修改，不参与合并
+    let base_by_id: HashMap<&str, &TokenData> = base_tokens.iter().map(|t| (t.id.as_str(), t)).collect();
+    let local_changed: Vec<TokenData> = local_tokens.into_iter()
+        .filter(|token| !base_by_id.get(token.id.as_str()).is_some_and(|base| same_content(base, token)))
+        .collect();
+
+    let remote_by_id: HashMap<String, TokenData> = remote_tokens.iter().map(|t| (t.id.clone(), t.clone())).collect();
+    let (merged_tokens, conflicts) = merge_token_sets(local_changed, remote_tokens, base_tokens);
+    let resolved_tokens = apply_tombstones(merged_tokens, tombstones);
+
+    // 只写入内容确实不同的一端，避免自己推送的行在下次同步时又被写回
+    let stored_by_id: HashMap<&str, &TokenData> = stored_tokens.iter().map(|t| (t.id.as_str(), t)).collect();
+    let differs = |token: &TokenData, existing: Option<&TokenData>| !existing.is_some_and(|existing| same_content(existing, token));
+    let to_remote: Vec<TokenData> = resolved_tokens.iter()
+        .filter(|token| differs(token, remote_by_id.get(&token.id)))
+        .cloned()
+        .collect();
+    // 本地还要记下数据库的最新版本号，否则下次按版本更新时会误报冲突
+    let to_local: Vec<TokenData> = resolved_tokens.iter()
+        .filter(|token| {
+            let stored = stored_by_id.get(token.id.as_str()).copied();
+            differs(token, stored) || stored.is_some_and(|stored| stored.version != token.version)
+        })
+        .cloned()
+        .collect();
+
+    // 删除后又更新过的token保留，对应的墓碑不再生效
+    let resolved_ids: HashSet<&str> = resolved_tokens.iter().map(|t| t.id.as_str()).collect();
+    let tombstones = tombstones.iter()
+        .filter(|tombstone| !resolved_ids.contains(tombstone.token_id.as_str()))
+        .cloned()
+        .collect();
+
+    MergePlan {
+        to_remote,
+        to_local,
+        tombstones,
+        conflicts,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::{LocalFileStorage, SqliteStorage};
     use tempfile::tempdir;
 
     #[tokio::test]
@@ -613,4 +1416,418 @@ mod tests {
         let loaded_tokens = dual_storage.load_tokens().await.unwrap();
         assert_eq!(loaded_tokens.len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_dual_storage_with_sqlite_local() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(SqliteStorage::new_with_path(temp_dir.path().join("tokens.db")).unwrap());
+
+        let dual_storage = DualStorage::new(local_storage, None);
+        assert_eq!(dual_storage.local_storage_type(), "sqlite");
+
+        let token = TokenData::new(
+            "test_id".to_string(),
+            "https://example.com".to_string(),
+            "test_token".to_string(),
+            None,
+            None,
+        );
+
+        assert!(dual_storage.save_token(&token).await.is_ok());
+        assert!(dual_storage.get_token("test_id").await.unwrap().is_some());
+        assert!(dual_storage.delete_token("test_id").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_deleted_token_not_resurrected_by_merge() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let dual_storage = DualStorage::new(local_storage.clone(), None)
+            .with_device_id("device_1".to_string());
+
+        let token = TokenData::new("dead".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        let other = TokenData::new("alive".to_string(), "https://a.com".to_string(), "b".to_string(), None, None);
+        dual_storage.save_tokens(&[token.clone(), other.clone()]).await.unwrap();
+
+        assert!(dual_storage.delete_token("dead").await.unwrap());
+        let tombstones = local_storage.load_tombstones().await.unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].origin, "device_1");
+
+        // 另一台设备仍然持有已删除的token
+        let resolved = dual_storage.resolve_conflicts(vec![other], vec![token]).await.unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].id, "alive");
+
+        // 过期墓碑会被清理，保留期内的墓碑保留
+        let mut expired = Tombstone::new("expired".to_string(), "device_1".to_string());
+        expired.deleted_at = Utc::now() - Duration::days(DEFAULT_TOMBSTONE_RETENTION_DAYS as i64 + 1);
+        local_storage.save_tombstones(&[expired]).await.unwrap();
+        assert_eq!(dual_storage.purge_expired_tombstones().await.unwrap(), 1);
+        assert_eq!(local_storage.load_tombstones().await.unwrap()[0].token_id, "dead");
+
+        // 保留天数为 0 时按最短保留天数处理，不会清除刚写入的墓碑
+        let dual_storage = dual_storage.with_tombstone_retention_days(0);
+        assert_eq!(dual_storage.purge_expired_tombstones().await.unwrap(), 0);
+        assert_eq!(local_storage.load_tombstones().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_writes_queued_while_database_unavailable() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        // 未初始化的数据库管理器始终不可用
+        let db_manager = Arc::new(crate::database::DatabaseManager::new(crate::database::DatabaseConfig::new(
+            "localhost".to_string(), 5432, "test".to_string(), "postgres".to_string(), "password".to_string(),
+        )));
+        let postgres = Arc::new(PostgreSQLStorage::new(db_manager));
+        let dual_storage = DualStorage::new(local_storage, Some(postgres))
+            .with_outbox(Outbox::new_with_path(temp_dir.path().join("outbox.json")));
+
+        let token = TokenData::new("a".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        let other = TokenData::new("b".to_string(), "https://a.com".to_string(), "b".to_string(), None, None);
+        dual_storage.save_token(&token).await.unwrap();
+        dual_storage.save_token(&other).await.unwrap();
+        dual_storage.update_token(&token).await.unwrap();
+        // 两个token各一条写入，加上两条新增历史；没有内容变化的更新不产生历史
+        assert_eq!(dual_storage.pending_outbox_count().await.unwrap(), 4);
+
+        // 删除替换同一token之前的写入
+        dual_storage.delete_tokens(&["a".to_string()]).await.unwrap();
+        let outbox = Outbox::new_with_path(temp_dir.path().join("outbox.json"));
+        let entries: Vec<OutboxOp> = outbox.entries().await.unwrap()
+            .into_iter()
+            .map(|entry| entry.op)
+            .filter(|op| !matches!(op, OutboxOp::Event { .. }))
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(&entries[1], OutboxOp::Delete { tombstone } if tombstone.token_id == "a"));
+
+        assert!(dual_storage.replay_outbox().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recover_interrupted_sync() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let journal_path = temp_dir.path().join("sync_journal.json");
+        let dual_storage = DualStorage::new(local_storage.clone(), None)
+            .with_sync_journal(SyncJournal::new_with_path(journal_path.clone()));
+        assert!(dual_storage.recover_interrupted_sync().await.unwrap().is_none());
+
+        let token = TokenData::new("synced".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        let watermark = SyncWatermark { database_id: "db".to_string(), remote_change_seq: 3, remote_xmin: None };
+
+        // 数据库未确认提交时放弃这次同步，本地保持原样
+        let journal = SyncJournal::new_with_path(journal_path.clone());
+        let entry = SyncJournalEntry::new("bidirectional", vec![token.clone()], Vec::new(), Some(watermark.clone()));
+        journal.begin(&entry).await.unwrap();
+        let recovery = dual_storage.recover_interrupted_sync().await.unwrap().unwrap();
+        assert!(!recovery.completed);
+        assert!(local_storage.load_tokens().await.unwrap().is_empty());
+        assert!(journal.pending().await.unwrap().is_none());
+
+        // 数据库已提交时补完本地写入，同步基准和水位线一起写入
+        let entry = SyncJournalEntry::new("bidirectional", vec![token], Vec::new(), Some(watermark.clone()));
+        journal.begin(&entry).await.unwrap();
+        journal.mark_remote_committed(&entry.id).await.unwrap();
+        let recovery = dual_storage.recover_interrupted_sync().await.unwrap().unwrap();
+        assert!(recovery.completed);
+        assert_eq!(local_storage.load_tokens().await.unwrap().len(), 1);
+        assert_eq!(local_storage.load_sync_base().await.unwrap().len(), 1);
+        assert_eq!(local_storage.load_sync_watermark().await.unwrap(), Some(watermark));
+        assert!(journal.pending().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_plan_sync_requires_database() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let dual_storage = DualStorage::new(local_storage, None);
+
+        let error = dual_storage.plan_sync(SyncDirection::Bidirectional).await.unwrap_err();
+        assert_eq!(error.code(), "unavailable");
+    }
+
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_apply_plan_rejects_stale_plan() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let mut db_manager = crate::database::DatabaseManager::new(crate::database::DatabaseConfig::new(
+            "localhost".to_string(), 5432, "test_augment_tokens".to_string(), "postgres".to_string(), "password".to_string(),
+        ).with_workspace("sync-plan-test".to_string()));
+        if db_manager.initialize().await.is_err() {
+            return;
+        }
+        let postgres = Arc::new(PostgreSQLStorage::new(Arc::new(db_manager)));
+        postgres.clear_all_tokens().await.unwrap();
+        let dual_storage = DualStorage::new(local_storage.clone(), Some(postgres.clone()));
+
+        let token = TokenData::new("planned".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        local_storage.save_token(&token).await.unwrap();
+
+        // 预演不修改任何一端
+        let plan = dual_storage.plan_sync(SyncDirection::LocalToRemote).await.unwrap();
+        assert_eq!(plan.remote.to_add.len(), 1);
+        assert!(plan.local.is_empty());
+        assert!(postgres.get_token("planned").await.unwrap().is_none());
+
+        // 预演之后本地有变化时拒绝执行旧计划
+        let other = TokenData::new("late".to_string(), "https://a.com".to_string(), "b".to_string(), None, None);
+        local_storage.save_token(&other).await.unwrap();
+        assert_eq!(dual_storage.apply_plan(&plan).await.unwrap_err().code(), "conflict");
+
+        let plan = dual_storage.plan_sync(SyncDirection::LocalToRemote).await.unwrap();
+        let status = dual_storage.apply_plan(&plan).await.unwrap();
+        assert_eq!(status.sync_direction, "local_to_remote");
+        assert!(postgres.get_token("planned").await.unwrap().is_some());
+        assert!(postgres.get_token("late").await.unwrap().is_some());
+
+        postgres.clear_all_tokens().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_sync_does_not_overwrite_concurrent_remote_write() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let mut db_manager = crate::database::DatabaseManager::new(crate::database::DatabaseConfig::new(
+            "localhost".to_string(), 5432, "test_augment_tokens".to_string(), "postgres".to_string(), "password".to_string(),
+        ).with_workspace("sync-version-test".to_string()));
+        if db_manager.initialize().await.is_err() {
+            return;
+        }
+        let postgres = Arc::new(PostgreSQLStorage::new(Arc::new(db_manager)));
+        postgres.clear_all_tokens().await.unwrap();
+        let dual_storage = DualStorage::new(local_storage.clone(), Some(postgres.clone()));
+
+        let mut token = TokenData::new("contended".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        postgres.save_token(&token).await.unwrap();
+        token.email_note = Some("local".to_string());
+        local_storage.save_token(&token).await.unwrap();
+
+        // 计划之后、写入之前另一个客户端修改了这一行
+        let prepared = dual_storage.prepare_sync(&postgres, SyncDirection::LocalToRemote, None).await.unwrap();
+        let mut concurrent = postgres.get_token("contended").await.unwrap().unwrap();
+        concurrent.email_note = Some("concurrent".to_string());
+        postgres.compare_and_swap(&concurrent).await.unwrap();
+
+        let error = dual_storage.execute_sync(&postgres, prepared, "local_to_remote").await.unwrap_err();
+        assert_eq!(error.code(), "conflict");
+        assert_eq!(postgres.get_token("contended").await.unwrap().unwrap().email_note.as_deref(), Some("concurrent"));
+
+        // 重新计划后基于最新版本写入
+        let prepared = dual_storage.prepare_sync(&postgres, SyncDirection::LocalToRemote, None).await.unwrap();
+        dual_storage.execute_sync(&postgres, prepared, "local_to_remote").await.unwrap();
+        assert_eq!(postgres.get_token("contended").await.unwrap().unwrap().email_note.as_deref(), Some("local"));
+
+        postgres.clear_all_tokens().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_replace_tokens_updates_atomically() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let mut db_manager = crate::database::DatabaseManager::new(crate::database::DatabaseConfig::new(
+            "localhost".to_string(), 5432, "test_augment_tokens".to_string(), "postgres".to_string(), "password".to_string(),
+        ).with_workspace("replace-atomic-test".to_string()));
+        if db_manager.initialize().await.is_err() {
+            return;
+        }
+        let postgres = Arc::new(PostgreSQLStorage::new(Arc::new(db_manager)));
+        postgres.clear_all_tokens().await.unwrap();
+        let dual_storage = DualStorage::new(local_storage.clone(), Some(postgres.clone()));
+
+        let a = TokenData::new("a".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        let b = TokenData::new("b".to_string(), "https://b.com".to_string(), "b".to_string(), None, None);
+        dual_storage.save_tokens(&[a, b]).await.unwrap();
+        let mut loaded = local_storage.load_tokens().await.unwrap();
+        loaded.sort_by(|x, y| x.id.cmp(&y.id));
+
+        // 另一个客户端先修改了 b
+        let mut concurrent = postgres.get_token("b").await.unwrap().unwrap();
+        concurrent.email_note = Some("concurrent".to_string());
+        postgres.compare_and_swap(&concurrent).await.unwrap();
+
+        let edited: Vec<TokenData> = loaded.iter().cloned().map(|mut token| {
+            token.email_note = Some("local".to_string());
+            token
+        }).collect();
+        assert_eq!(dual_storage.replace_tokens(&edited).await.unwrap_err().code(), "conflict");
+
+        // 整批回滚，a 也没有写入
+        assert_eq!(postgres.get_token("a").await.unwrap().unwrap().email_note, None);
+        assert_eq!(postgres.get_token("b").await.unwrap().unwrap().email_note.as_deref(), Some("concurrent"));
+
+        // 拉取最新版本后重试成功
+        let mut latest = local_storage.load_tokens().await.unwrap();
+        for token in &mut latest {
+            token.email_note = Some("local".to_string());
+        }
+        dual_storage.replace_tokens(&latest).await.unwrap();
+        for id in ["a", "b"] {
+            assert_eq!(postgres.get_token(id).await.unwrap().unwrap().email_note.as_deref(), Some("local"));
+        }
+
+        postgres.clear_all_tokens().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_token_history_records_changes() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let dual_storage = DualStorage::new(local_storage, None)
+            .with_device_id("device_1".to_string())
+            .with_event_log(TokenEventLog::new_with_path(temp_dir.path().join("token_events.jsonl")));
+
+        let mut token = TokenData::new("a".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        dual_storage.save_token(&token).await.unwrap();
+        token.ban_status = Some(serde_json::json!("SUSPENDED"));
+        dual_storage.update_token(&token).await.unwrap();
+        dual_storage.update_token(&token).await.unwrap();
+        dual_storage.delete_token("a").await.unwrap();
+
+        let history = dual_storage.token_history("a").await.unwrap();
+        let kinds: Vec<&str> = history.iter().map(|event| event.kind.as_str()).collect();
+        assert_eq!(kinds, vec!["created", "updated", "deleted"]);
+        assert_eq!(history[1].changes.len(), 1);
+        assert_eq!(history[1].changes[0].field, "ban_status");
+        assert!(history.iter().all(|event| event.device_id == "device_1"));
+    }
+
+    #[tokio::test]
+    async fn test_restore_snapshot_after_clear() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let dual_storage = DualStorage::new(local_storage, None)
+            .with_snapshots(SnapshotManager::new_with_dir(temp_dir.path().join("snapshots")));
+
+        let a = TokenData::new("a".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        let b = TokenData::new("b".to_string(), "https://a.com".to_string(), "b".to_string(), None, None);
+        dual_storage.save_tokens(&[a, b]).await.unwrap();
+
+        dual_storage.clear_all_tokens().await.unwrap();
+        let c = TokenData::new("c".to_string(), "https://a.com".to_string(), "c".to_string(), None, None);
+        dual_storage.save_token(&c).await.unwrap();
+
+        let snapshots = dual_storage.list_snapshots().await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].reason, SnapshotReason::BeforeClear);
+
+        let diff = dual_storage.diff_snapshot(&snapshots[0].id).await.unwrap();
+        assert_eq!(diff.removed.len(), 2);
+        assert_eq!(diff.added.len(), 1);
+
+        let result = dual_storage.restore_snapshot(&snapshots[0].id).await.unwrap();
+        assert_eq!(result.restored, 2);
+        assert_eq!(result.removed, 1);
+        assert!(result.backup_id.is_some());
+
+        let mut ids: Vec<String> = dual_storage.load_tokens().await.unwrap().into_iter().map(|token| token.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_replace_tokens_writes_only_changes() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let dual_storage = DualStorage::new(local_storage.clone(), None)
+            .with_device_id("device_1".to_string())
+            .with_snapshots(SnapshotManager::new_with_dir(temp_dir.path().join("snapshots")));
+
+        let a = TokenData::new("a".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        let b = TokenData::new("b".to_string(), "https://a.com".to_string(), "b".to_string(), None, None);
+        dual_storage.save_tokens(&[a.clone(), b]).await.unwrap();
+
+        let mut updated = a.clone();
+        updated.email_note = Some("a@example.com".to_string());
+        let c = TokenData::new("c".to_string(), "https://a.com".to_string(), "c".to_string(), None, None);
+        dual_storage.replace_tokens(&[updated, c]).await.unwrap();
+
+        let mut tokens = dual_storage.load_local_tokens().await.unwrap();
+        tokens.sort_by(|x, y| x.id.cmp(&y.id));
+        assert_eq!(tokens.iter().map(|token| token.id.as_str()).collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(tokens[0].email_note.as_deref(), Some("a@example.com"));
+
+        // 列表中没有的token记录墓碑，替换前创建快照
+        let tombstones = local_storage.load_tombstones().await.unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].token_id, "b");
+        let snapshots = dual_storage.list_snapshots().await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].reason, SnapshotReason::BeforeReplace);
+    }
+
+    #[test]
+    fn test_plan_merge_refreshes_local_version() {
+        let mut stored = TokenData::new("a".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        stored.version = 1;
+        // 自己推送后数据库把版本加一，内容不变
+        let mut remote = stored.clone();
+        remote.version = 2;
+
+        let plan = plan_merge(vec![stored.clone()], std::slice::from_ref(&stored), vec![remote], std::slice::from_ref(&stored), &[]);
+        assert!(plan.to_remote.is_empty());
+        assert_eq!(plan.to_local.len(), 1);
+        assert_eq!(plan.to_local[0].version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_operations_by_tag() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let dual_storage = DualStorage::new(local_storage, None);
+
+        let mut a = TokenData::new("a".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        a.tags = vec!["project-x".to_string()];
+        let mut b = TokenData::new("b".to_string(), "https://a.com".to_string(), "b".to_string(), None, None);
+        b.tags = vec!["project-y".to_string()];
+        dual_storage.save_tokens(&[a, b]).await.unwrap();
+
+        let filter = TokenFilter { tags: vec!["project-x".to_string()], ..Default::default() };
+        let update = TokenBulkUpdate { set_group: Some("owner-alice".to_string()), ..Default::default() };
+        assert_eq!(dual_storage.update_matching(&filter, &update).await.unwrap(), 1);
+        assert_eq!(dual_storage.get_token("a").await.unwrap().unwrap().group, Some("owner-alice".to_string()));
+
+        let filter = TokenFilter { group: Some("owner-alice".to_string()), ..Default::default() };
+        assert_eq!(dual_storage.delete_matching(&filter).await.unwrap(), 1);
+        let remaining = dual_storage.load_tokens().await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, "b");
+    }
+
+    #[tokio::test]
+    async fn test_merge_duplicate_tokens() {
+        let temp_dir = tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let dual_storage = DualStorage::new(local_storage.clone(), None);
+
+        let mut a = TokenData::new("a".to_string(), "https://d1.example.com".to_string(), "old".to_string(), None, Some("alice@example.com".to_string()));
+        a.created_at = Utc::now() - Duration::days(1);
+        let mut b = TokenData::new("b".to_string(), "https://d1.example.com".to_string(), "new".to_string(), Some("https://portal.example.com".to_string()), Some("Alice@example.com".to_string()));
+        b.tags = vec!["team".to_string()];
+        let c = TokenData::new("c".to_string(), "https://d2.example.com".to_string(), "other".to_string(), None, None);
+        dual_storage.save_tokens(&[a, b, c]).await.unwrap();
+
+        let groups = dual_storage.find_duplicates().await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].token_ids(), vec!["a", "b"]);
+
+        let merged = dual_storage.merge_duplicate_tokens(&groups[0].token_ids()).await.unwrap();
+        assert_eq!(merged.id, "a");
+        assert_eq!(merged.access_token, "new");
+
+        let tokens = dual_storage.load_tokens().await.unwrap();
+        assert_eq!(tokens.len(), 2);
+        let stored = tokens.iter().find(|token| token.id == "a").unwrap();
+        assert_eq!(stored.portal_url, Some("https://portal.example.com".to_string()));
+        assert_eq!(stored.tags, vec!["team"]);
+        assert!(local_storage.load_tombstones().await.unwrap().iter().any(|tombstone| tombstone.token_id == "b"));
+
+        let error = dual_storage.merge_duplicate_tokens(&["a".to_string(), "b".to_string()]).await.unwrap_err();
+        assert_eq!(error.code(), "not_found");
+    }
 }
@@ -0,0 +1,305 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use super::error::{StorageError, StorageResult};
+
+/// 加密文件的格式标识，用于和明文JSON区分
+pub const ENVELOPE_FORMAT: &str = "atm-encrypted-tokens";
+/// 当前支持的加密信封版本
+///
+/// 版本2起信封头部（格式、版本、密钥派生参数）作为附加认证数据参与加密，
+/// 版本1的文件仍可读取，下次保存时升级。
+pub const ENVELOPE_VERSION: u32 = 2;
+const LEGACY_ENVELOPE_VERSION: u32 = 1;
+
+const KDF_ALGORITHM: &str = "argon2id";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+// 密钥派生参数来自文件，超出上限的按损坏处理，避免被篡改的文件耗尽内存或CPU
+const MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_ITERATIONS: u32 = 16;
+const MAX_PARALLELISM: u32 = 16;
+
+/// 密钥派生参数，随密文一起保存，解密时按文件中的参数重新派生
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub salt: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    pub(crate) fn generate() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        Self {
+            algorithm: KDF_ALGORITHM.to_string(),
+            salt: hex::encode(salt),
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    pub(crate) fn derive_key(&self, passphrase: &str) -> StorageResult<[u8; KEY_LEN]> {
+        if self.algorithm != KDF_ALGORITHM {
+            return Err(StorageError::Corrupt(format!("Unsupported key derivation algorithm: {}", self.algorithm)));
+        }
+
+        if self.memory_kib > MAX_MEMORY_KIB || self.iterations > MAX_ITERATIONS || self.parallelism > MAX_PARALLELISM {
+            return Err(StorageError::Corrupt(format!(
+                "Key derivation parameters exceed limits: memory {} KiB, iterations {}, parallelism {}",
+                self.memory_kib, self.iterations, self.parallelism
+            )));
+        }
+
+        let salt = hex::decode(&self.salt)?;
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(KEY_LEN))
+            .map_err(|e| StorageError::Corrupt(format!("Invalid key derivation parameters: {}", e)))?;
+
+        let mut key = [0u8; KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| StorageError::backend(format!("Failed to derive encryption key: {}", e)))?;
+
+        Ok(key)
+    }
+}
+
+/// tokens.json 的加密信封格式
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedEnvelope {
+    pub format: String,
+    pub version: u32,
+    pub kdf: KdfParams,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// 参与认证的信封头部，字段顺序固定，序列化结果即为附加认证数据
+#[derive(Serialize)]
+struct EnvelopeHeader<'a> {
+    format: &'a str,
+    version: u32,
+    kdf: &'a KdfParams,
+}
+
+fn header_aad(format: &str, version: u32, kdf: &KdfParams) -> StorageResult<Vec<u8>> {
+    Ok(serde_json::to_vec(&EnvelopeHeader { format, version, kdf })?)
+}
+
+/// 判断文件内容是否为加密信封
+pub fn is_encrypted_content(content: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(content)
+        .ok()
+        .and_then(|value| value.get("format").and_then(|v| v.as_str()).map(|s| s == ENVELOPE_FORMAT))
+        .unwrap_or(false)
+}
+
+/// 基于口令的token文件加解密器
+///
+/// 派生出的密钥按盐值缓存，避免每次读写都重新执行Argon2。
+pub struct TokenCipher {
+    passphrase: String,
+    cached_key: Mutex<Option<(KdfParams, [u8; KEY_LEN])>>,
+}
+
+impl TokenCipher {
+    pub fn new(passphrase: String) -> StorageResult<Self> {
+        if passphrase.is_empty() {
+            return Err(StorageError::InvalidInput("Passphrase must not be empty".to_string()));
+        }
+
+        Ok(Self {
+            passphrase,
+            cached_key: Mutex::new(None),
+        })
+    }
+
+    fn key_for(&self, kdf: &KdfParams) -> StorageResult<[u8; KEY_LEN]> {
+        let mut cached = self.cached_key.lock().unwrap();
+        if let Some((params, key)) = cached.as_ref()
+            && params == kdf
+        {
+            return Ok(*key);
+        }
+
+        let key = kdf.derive_key(&self.passphrase)?;
+        *cached = Some((kdf.clone(), key));
+        Ok(key)
+    }
+
+    fn current_kdf(&self) -> KdfParams {
+        let cached = self.cached_key.lock().unwrap();
+        cached.as_ref()
+            .map(|(params, _)| params.clone())
+            .unwrap_or_else(KdfParams::generate)
+    }
+
+    /// 加密明文JSON，返回序列化后的信封
+    pub fn encrypt(&self, plaintext: &str) -> StorageResult<String> {
+        // 复用已有的盐值，这样同一文件的后续写入不需要重新派生密钥
+        let kdf = self.current_kdf();
+        let key = self.key_for(&kdf)?;
+
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|e| StorageError::backend(format!("Failed to create encryption key: {}", e)))?;
+
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let nonce = Nonce::from_slice(&nonce_bytes);
+
+        let aad = header_aad(ENVELOPE_FORMAT, ENVELOPE_VERSION, &kdf)?;
+        let ciphertext = cipher.encrypt(nonce, Payload { msg: plaintext.as_bytes(), aad: &aad })
+            .map_err(|e| StorageError::backend(format!("Failed to encrypt tokens: {}", e)))?;
+
+        let envelope = EncryptedEnvelope {
+            format: ENVELOPE_FORMAT.to_string(),
+            version: ENVELOPE_VERSION,
+            kdf,
+            nonce: hex::encode(nonce_bytes),
+            ciphertext: hex::encode(ciphertext),
+        };
+
+        Ok(serde_json::to_string_pretty(&envelope)?)
+    }
+
+    /// 解密信封，返回明文JSON
+    pub fn decrypt(&self, content: &str) -> StorageResult<String> {
+        let envelope: EncryptedEnvelope = serde_json::from_str(content)?;
+
+        if envelope.format != ENVELOPE_FORMAT {
+            return Err(StorageError::Corrupt(format!("Unknown token file format: {}", envelope.format)));
+        }
+        if envelope.version > ENVELOPE_VERSION {
+            return Err(StorageError::Incompatible(format!(
+                "Token file version {} is newer than supported version {}",
+                envelope.version, ENVELOPE_VERSION
+            )));
+        }
+
+        let nonce_bytes = hex::decode(&envelope.nonce)?;
+        if nonce_bytes.len() != NONCE_LEN {
+            return Err(StorageError::Corrupt("Invalid nonce length".to_string()));
+        }
+        let ciphertext = hex::decode(&envelope.ciphertext)?;
+
+        let key = self.key_for(&envelope.kdf)?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|e| StorageError::backend(format!("Failed to create decryption key: {}", e)))?;
+
+        // 版本1的信封没有附加认证数据
+        let aad = if envelope.version <= LEGACY_ENVELOPE_VERSION {
+            Vec::new()
+        } else {
+            header_aad(&envelope.format, envelope.version, &envelope.kdf)?
+        };
+
+        // AES-GCM 的认证标签校验失败意味着口令错误或文件被篡改
+        let plaintext = cipher.decrypt(Nonce::from_slice(&nonce_bytes), Payload { msg: ciphertext.as_ref(), aad: &aad })
+            .map_err(|_| StorageError::Locked("Failed to decrypt tokens: wrong passphrase or corrupted file".to_string()))?;
+
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let cipher = TokenCipher::new("correct horse".to_string()).unwrap();
+        let plaintext = r#"[{"id":"a","access_token":"secret"}]"#;
+
+        let encrypted = cipher.encrypt(plaintext).unwrap();
+        assert!(is_encrypted_content(&encrypted));
+        assert!(!encrypted.contains("secret"));
+
+        let decrypted = cipher.decrypt(&encrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_wrong_passphrase_rejected() {
+        let cipher = TokenCipher::new("correct horse".to_string()).unwrap();
+        let encrypted = cipher.encrypt("[]").unwrap();
+
+        let other = TokenCipher::new("battery staple".to_string()).unwrap();
+        assert_eq!(other.decrypt(&encrypted).unwrap_err().code(), "locked");
+    }
+
+    #[test]
+    fn test_newer_version_rejected() {
+        let cipher = TokenCipher::new("correct horse".to_string()).unwrap();
+        let encrypted = cipher.encrypt("[]").unwrap();
+
+        let mut envelope: EncryptedEnvelope = serde_json::from_str(&encrypted).unwrap();
+        envelope.version = ENVELOPE_VERSION + 1;
+        let tampered = serde_json::to_string(&envelope).unwrap();
+
+        assert_eq!(cipher.decrypt(&tampered).unwrap_err().code(), "incompatible");
+    }
+
+    #[test]
+    fn test_header_is_authenticated() {
+        let cipher = TokenCipher::new("correct horse".to_string()).unwrap();
+        let encrypted = cipher.encrypt("[]").unwrap();
+
+        // 降级为版本1会去掉附加认证数据，认证标签随之失效
+        let mut envelope: EncryptedEnvelope = serde_json::from_str(&encrypted).unwrap();
+        envelope.version = LEGACY_ENVELOPE_VERSION;
+        let downgraded = serde_json::to_string(&envelope).unwrap();
+        assert_eq!(cipher.decrypt(&downgraded).unwrap_err().code(), "locked");
+    }
+
+    #[test]
+    fn test_legacy_envelope_still_readable() {
+        let cipher = TokenCipher::new("correct horse".to_string()).unwrap();
+        let kdf = cipher.current_kdf();
+        let key = cipher.key_for(&kdf).unwrap();
+
+        let nonce_bytes = [7u8; NONCE_LEN];
+        let ciphertext = Aes256Gcm::new_from_slice(&key).unwrap()
+            .encrypt(Nonce::from_slice(&nonce_bytes), b"[]".as_ref())
+            .unwrap();
+        let legacy = EncryptedEnvelope {
+            format: ENVELOPE_FORMAT.to_string(),
+            version: LEGACY_ENVELOPE_VERSION,
+            kdf,
+            nonce: hex::encode(nonce_bytes),
+            ciphertext: hex::encode(ciphertext),
+        };
+
+        assert_eq!(cipher.decrypt(&serde_json::to_string(&legacy).unwrap()).unwrap(), "[]");
+    }
+
+    #[test]
+    fn test_excessive_kdf_params_rejected() {
+        let cipher = TokenCipher::new("correct horse".to_string()).unwrap();
+        let encrypted = cipher.encrypt("[]").unwrap();
+
+        let mut envelope: EncryptedEnvelope = serde_json::from_str(&encrypted).unwrap();
+        envelope.kdf.memory_kib = u32::MAX;
+        let tampered = serde_json::to_string(&envelope).unwrap();
+
+        assert_eq!(cipher.decrypt(&tampered).unwrap_err().code(), "corrupt");
+    }
+
+    #[test]
+    fn test_plaintext_is_not_encrypted_content() {
+        assert!(!is_encrypted_content("[]"));
+        assert!(!is_encrypted_content(r#"{"tokens": []}"#));
+        assert!(!is_encrypted_content("not json"));
+    }
+}
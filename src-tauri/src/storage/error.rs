@@ -0,0 +1,197 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+
+/// 存储层和数据库层统一使用的错误类型
+///
+/// 序列化为 `{ code, message }`，前端可以按 `code` 区分“数据库不可用”和“token不存在”等情况。
+#[derive(Debug)]
+pub enum StorageError {
+    /// 请求的记录不存在
+    NotFound(String),
+    /// 写入与已有数据冲突
+    Conflict(String),
+    /// 存储后端当前不可用（未配置、未连接或连接已断开）
+    Unavailable(String),
+    /// 存储已加密，需要正确的口令
+    Locked(String),
+    /// 存储中的数据已损坏或格式无法识别
+    Corrupt(String),
+    /// 调用方传入的参数无效
+    InvalidInput(String),
+    /// 数据格式或结构版本比当前程序新，无法安全读写
+    Incompatible(String),
+    /// JSON序列化/反序列化失败
+    Serialization(serde_json::Error),
+    /// 底层后端（文件系统、SQLite、PostgreSQL）返回的其他错误
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+impl StorageError {
+    /// 稳定的机器可读错误码
+    pub fn code(&self) -> &'static str {
+        match self {
+            StorageError::NotFound(_) => "not_found",
+            StorageError::Conflict(_) => "conflict",
+            StorageError::Unavailable(_) => "unavailable",
+            StorageError::Locked(_) => "locked",
+            StorageError::Corrupt(_) => "corrupt",
+            StorageError::InvalidInput(_) => "invalid_input",
+            StorageError::Incompatible(_) => "incompatible",
+            StorageError::Serialization(_) => "serialization",
+            StorageError::Backend(_) => "backend",
+        }
+    }
+
+    pub fn backend(message: impl Into<String>) -> Self {
+        StorageError::Backend(message.into().into())
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::NotFound(msg)
+            | StorageError::Conflict(msg)
+            | StorageError::Unavailable(msg)
+            | StorageError::Locked(msg)
+            | StorageError::Corrupt(msg)
+            | StorageError::InvalidInput(msg)
+            | StorageError::Incompatible(msg) => write!(f, "{}", msg),
+            StorageError::Serialization(e) => write!(f, "Serialization error: {}", e),
+            StorageError::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Serialization(e) => Some(e),
+            StorageError::Backend(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for StorageError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("StorageError", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(e: serde_json::Error) -> Self {
+        StorageError::Serialization(e)
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => StorageError::NotFound(e.to_string()),
+            _ => StorageError::Backend(Box::new(e)),
+        }
+    }
+}
+
+impl From<tauri::Error> for StorageError {
+    fn from(e: tauri::Error) -> Self {
+        StorageError::Backend(Box::new(e))
+    }
+}
+
+impl From<tokio::task::JoinError> for StorageError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        StorageError::Backend(Box::new(e))
+    }
+}
+
+impl From<hex::FromHexError> for StorageError {
+    fn from(e: hex::FromHexError) -> Self {
+        StorageError::Corrupt(format!("Invalid hex data: {}", e))
+    }
+}
+
+impl From<std::string::FromUtf8Error> for StorageError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        StorageError::Corrupt(format!("Invalid UTF-8 data: {}", e))
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => StorageError::NotFound(e.to_string()),
+            rusqlite::Error::SqliteFailure(ref err, _) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+                StorageError::Conflict(e.to_string())
+            }
+            rusqlite::Error::SqliteFailure(ref err, _) if err.code == rusqlite::ErrorCode::DatabaseBusy
+                || err.code == rusqlite::ErrorCode::DatabaseLocked => {
+                StorageError::Unavailable(e.to_string())
+            }
+            rusqlite::Error::SqliteFailure(ref err, _) if err.code == rusqlite::ErrorCode::DatabaseCorrupt
+                || err.code == rusqlite::ErrorCode::NotADatabase => {
+                StorageError::Corrupt(e.to_string())
+            }
+            _ => StorageError::Backend(Box::new(e)),
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for StorageError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        use tokio_postgres::error::SqlState;
+
+        if e.is_closed() {
+            return StorageError::Unavailable(e.to_string());
+        }
+
+        match e.code() {
+            Some(code) if *code == SqlState::UNIQUE_VIOLATION || *code == SqlState::T_R_SERIALIZATION_FAILURE => {
+                StorageError::Conflict(e.to_string())
+            }
+            _ => StorageError::Backend(Box::new(e)),
+        }
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for StorageError {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        // 无法从连接池获取连接，通常意味着数据库不可达
+        StorageError::Unavailable(format!("Database connection unavailable: {}", e))
+    }
+}
+
+impl From<deadpool_postgres::CreatePoolError> for StorageError {
+    fn from(e: deadpool_postgres::CreatePoolError) -> Self {
+        StorageError::Backend(Box::new(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_serializes_with_code() {
+        let error = StorageError::NotFound("Token not found".to_string());
+        let value = serde_json::to_value(&error).unwrap();
+
+        assert_eq!(value["code"], "not_found");
+        assert_eq!(value["message"], "Token not found");
+    }
+
+    #[test]
+    fn test_io_not_found_maps_to_not_found() {
+        let error: StorageError = std::io::Error::new(std::io::ErrorKind::NotFound, "missing").into();
+        assert_eq!(error.code(), "not_found");
+
+        let error: StorageError = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied").into();
+        assert_eq!(error.code(), "backend");
+    }
+}
@@ -0,0 +1,163 @@
+use super::local_storage::{LocalFileStorage, TokenFileChange};
+use super::error::StorageError;
+use notify::{Config, Event, PollWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Emitter;
+use tokio::sync::mpsc;
+
+/// tokens.json 被外部修改并已加载时推送给前端的事件名
+pub const TOKENS_FILE_CHANGED_EVENT: &str = "tokens-file-changed";
+/// tokens.json 被外部改成无效内容时推送给前端的事件名
+pub const TOKENS_FILE_REJECTED_EVENT: &str = "tokens-file-rejected";
+
+// 网络盘上的修改不会触发本机的文件系统通知，只能按固定间隔检查修改时间
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// 收到通知后等文件在这段时间内不再变化再读取，避免读到写了一半的文件
+const SETTLE_DELAY: Duration = Duration::from_millis(300);
+// 这些文件系统上的文件可能被其他机器修改，本机收不到通知
+const NETWORK_FILESYSTEMS: &[&str] = &["nfs", "nfs4", "cifs", "smb3", "smbfs", "afs", "9p", "fuse.sshfs"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenFileRejectedEvent {
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// 监视本地 tokens.json，发现外部修改后校验新内容并通知前端
+///
+/// 使用系统的文件通知监视所在目录（文件会被改名替换）；网络盘或无法创建通知时退回按间隔检查。
+/// 本应用自己的写入（先写 `.tmp` 再改名）按内容指纹忽略。
+pub async fn run_file_watcher(app: tauri::AppHandle, storage: Arc<LocalFileStorage>) {
+    // 以当前内容为基准，启动前的内容不算外部修改
+    if let Err(e) = storage.check_external_change().await {
+        report_error(&app, &storage, e);
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // 监视器在本函数返回前必须保持存活
+    let _watcher = match start_watcher(storage.path(), tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("Failed to watch {:?}: {}", storage.path(), e);
+            return;
+        }
+    };
+
+    let mut checked = storage.file_stamp().await;
+    while rx.recv().await.is_some() {
+        // 合并连续的通知，直到文件一段时间内不再变化
+        loop {
+            match tokio::time::timeout(SETTLE_DELAY, rx.recv()).await {
+                Ok(Some(())) => continue,
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+
+        let stamp = storage.file_stamp().await;
+        // 文件被替换时可能短暂不存在，等它重新出现
+        if stamp.is_none() || stamp == checked {
+            continue;
+        }
+
+        checked = stamp;
+        match storage.check_external_change().await {
+            Ok(Some(change)) if !change.is_empty() => emit_changed(&app, change),
+            Ok(_) => {}
+            Err(e) => report_error(&app, &storage, e),
+        }
+    }
+}
+
+/// 监视文件所在目录，与该文件有关的通知转发到 `tx`
+fn start_watcher(path: &Path, tx: mpsc::UnboundedSender<()>) -> notify::Result<Box<dyn Watcher + Send>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let handler = move |result: notify::Result<Event>| match result {
+        // 没有路径的通知（例如需要重新扫描）也要检查一次
+        Ok(event) if event.paths.is_empty()
+            || event.paths.iter().any(|p| p.file_name() == file_name.as_deref()) => {
+            let _ = tx.send(());
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Token file watcher error: {}", e),
+    };
+
+    if is_network_path(&dir) {
+        println!("Token file is on a network share, polling {:?} for changes", dir);
+        return start_poll_watcher(&dir, handler);
+    }
+
+    let native = notify::recommended_watcher(handler.clone())
+        .and_then(|mut watcher| watcher.watch(&dir, RecursiveMode::NonRecursive).map(|_| watcher));
+    match native {
+        Ok(watcher) => Ok(Box::new(watcher)),
+        Err(e) => {
+            eprintln!("Native file watcher unavailable ({}), polling {:?} for changes", e, dir);
+            start_poll_watcher(&dir, handler)
+        }
+    }
+}
+
+fn start_poll_watcher<F>(dir: &Path, handler: F) -> notify::Result<Box<dyn Watcher + Send>>
+where
+    F: notify::EventHandler,
+{
+    let mut watcher = PollWatcher::new(handler, Config::default().with_poll_interval(POLL_INTERVAL))?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok(Box::new(watcher))
+}
+
+/// 判断目录是否在网络盘上
+fn is_network_path(dir: &Path) -> bool {
+    if cfg!(windows) {
+        // UNC 路径（\\server\share）
+        return dir.to_string_lossy().starts_with(r"\\");
+    }
+
+    // 找到包含该目录的最长挂载点，按文件系统类型判断
+    let Ok(mounts) = std::fs::read_to_string("/proc/mounts") else {
+        return false;
+    };
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    mounts.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?;
+            let fs_type = fields.next()?;
+            Some((Path::new(mount_point), fs_type))
+        })
+        .filter(|(mount_point, _)| dir.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())
+        .is_some_and(|(_, fs_type)| NETWORK_FILESYSTEMS.contains(&fs_type))
+}
+
+fn emit_changed(app: &tauri::AppHandle, change: TokenFileChange) {
+    println!(
+        "Token file changed externally: {} added, {} removed, {} modified",
+        change.added.len(), change.removed.len(), change.modified.len()
+    );
+    if let Err(e) = app.emit(TOKENS_FILE_CHANGED_EVENT, change) {
+        eprintln!("Failed to emit token file change event: {}", e);
+    }
+}
+
+fn report_error(app: &tauri::AppHandle, storage: &LocalFileStorage, error: StorageError) {
+    match error {
+        // 文件已加密而本应用还没有口令，不是损坏，解锁后会重新读取
+        StorageError::Locked(_) => eprintln!("Skipped external change to {:?}: {}", storage.path(), error),
+        _ => emit_rejected(app, storage, error),
+    }
+}
+
+fn emit_rejected(app: &tauri::AppHandle, storage: &LocalFileStorage, error: StorageError) {
+    eprintln!("Rejected external change to {:?} [{}]: {}", storage.path(), error.code(), error);
+    let event = TokenFileRejectedEvent { code: error.code(), message: error.to_string() };
+    if let Err(e) = app.emit(TOKENS_FILE_REJECTED_EVENT, event) {
+        eprintln!("Failed to emit token file rejected event: {}", e);
+    }
+}
@@ -0,0 +1,344 @@
+use super::traits::TokenData;
+use super::encryption::{TokenCipher, is_encrypted_content};
+use super::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use tauri::Manager;
+
+// 这些字段只记录指纹，历史中不保存明文凭据
+const SECRET_FIELDS: &[&str] = &["access_token", "auth_session"];
+// 时间戳和版本号每次修改都会变化，不计入差异
+const IGNORED_FIELDS: &[&str] = &["id", "created_at", "updated_at", "version"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl TokenEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenEventKind::Created => "created",
+            TokenEventKind::Updated => "updated",
+            TokenEventKind::Deleted => "deleted",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "created" => Some(TokenEventKind::Created),
+            "updated" => Some(TokenEventKind::Updated),
+            "deleted" => Some(TokenEventKind::Deleted),
+            _ => None,
+        }
+    }
+}
+
+/// 单个字段的变化，新增时 old 为 null，删除时 new 为 null
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+/// token的一次修改记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenEvent {
+    pub id: String,
+    pub token_id: String,
+    pub kind: TokenEventKind,
+    pub changes: Vec<FieldChange>,
+    /// 做出修改的设备
+    pub device_id: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl TokenEvent {
+    /// 根据修改前后的token生成事件，内容没有变化时返回 None
+    pub fn between(old: Option<&TokenData>, new: Option<&TokenData>, device_id: &str) -> Option<Self> {
+        let (kind, token_id) = match (old, new) {
+            (None, Some(new)) => (TokenEventKind::Created, &new.id),
+            (Some(old), Some(_)) => (TokenEventKind::Updated, &old.id),
+            (Some(old), None) => (TokenEventKind::Deleted, &old.id),
+            (None, None) => return None,
+        };
+
+        let changes = diff_tokens(old, new);
+        if kind == TokenEventKind::Updated && changes.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            token_id: token_id.clone(),
+            kind,
+            changes,
+            device_id: device_id.to_string(),
+            occurred_at: Utc::now(),
+        })
+    }
+}
+
+/// 逐字段比较两个版本的token，凭据字段只比较指纹
+pub fn diff_tokens(old: Option<&TokenData>, new: Option<&TokenData>) -> Vec<FieldChange> {
+    let old = old.map(token_fields).unwrap_or_default();
+    let new = new.map(token_fields).unwrap_or_default();
+
+    let mut fields: Vec<&String> = old.keys().chain(new.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields.into_iter()
+        .filter(|field| !IGNORED_FIELDS.contains(&field.as_str()))
+        .filter_map(|field| {
+            let old_value = old.get(field).cloned().unwrap_or_default();
+            let new_value = new.get(field).cloned().unwrap_or_default();
+            if old_value == new_value {
+                return None;
+            }
+            Some(FieldChange { field: field.clone(), old: old_value, new: new_value })
+        })
+        .collect()
+}
+
+fn token_fields(token: &TokenData) -> serde_json::Map<String, serde_json::Value> {
+    let mut fields = match serde_json::to_value(token) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+
+    for field in SECRET_FIELDS {
+        if let Some(value) = fields.get_mut(*field) {
+            *value = fingerprint(value);
+        }
+    }
+    // 空值和缺失字段视为相同
+    fields.retain(|_, value| !value.is_null());
+    fields
+}
+
+fn fingerprint(value: &serde_json::Value) -> serde_json::Value {
+    if value.is_null() {
+        return serde_json::Value::Null;
+    }
+    let digest = Sha256::digest(value.to_string().as_bytes());
+    serde_json::Value::String(format!("sha256:{}", &hex::encode(digest)[..12]))
+}
+
+/// 本地只追加的token修改历史，每行一个事件
+#[derive(Clone)]
+pub struct TokenEventLog {
+    path: PathBuf,
+    // 设置后每行以加密信封格式保存，与 tokens.json 使用同一口令
+    cipher: Option<Arc<TokenCipher>>,
+    // 克隆之间共享，保证追加和读取互斥
+    lock: Arc<Mutex<()>>,
+}
+
+impl TokenEventLog {
+    pub fn new(app_handle: &tauri::AppHandle) -> StorageResult<Self> {
+        let app_data_dir = app_handle.path().app_data_dir()?;
+        fs::create_dir_all(&app_data_dir)?;
+
+        Ok(Self::new_with_path(app_data_dir.join("token_events.jsonl")))
+    }
+
+    pub fn new_with_path(path: PathBuf) -> Self {
+        Self {
+            path,
+            cipher: None,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn with_cipher(mut self, cipher: Arc<TokenCipher>) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    pub async fn append(&self, events: &[TokenEvent]) -> StorageResult<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let mut lines = String::new();
+        for event in events {
+            let json = serde_json::to_string(event)?;
+            let line = match &self.cipher {
+                // 信封是多行JSON，压缩成一行
+                Some(cipher) => serde_json::to_string(&serde_json::from_str::<serde_json::Value>(&cipher.encrypt(&json)?)?)?,
+                None => json,
+            };
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+
+        self.with_lock(move |log| {
+            if let Some(parent) = log.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            log.truncate_torn_line()?;
+            let mut file = OpenOptions::new().create(true).append(true).open(&log.path)?;
+            file.write_all(lines.as_bytes())?;
+            file.sync_all()?;
+            Ok(())
+        }).await
+    }
+
+    /// 按发生时间返回一个token的全部事件
+    ///
+    /// 只跳过写入中途退出留下的不完整末行；其他无法读取的行返回 Corrupt，不会被静默忽略。
+    pub async fn events_for(&self, token_id: &str) -> StorageResult<Vec<TokenEvent>> {
+        let token_id = token_id.to_string();
+        self.with_lock(move |log| {
+            if !log.path.exists() {
+                return Ok(Vec::new());
+            }
+
+            let content = fs::read_to_string(&log.path)?;
+            let mut events = Vec::new();
+            for (index, line) in complete_lines(&content).enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let json = if is_encrypted_content(line) {
+                    let cipher = log.cipher.as_ref()
+                        .ok_or_else(|| StorageError::Locked("Token history is encrypted, passphrase required".to_string()))?;
+                    cipher.decrypt(line)?
+                } else {
+                    line.to_string()
+                };
+
+                let event = serde_json::from_str::<TokenEvent>(&json).map_err(|e| {
+                    StorageError::Corrupt(format!("Token history line {} is unreadable: {}", index + 1, e))
+                })?;
+                if event.token_id == token_id {
+                    events.push(event);
+                }
+            }
+
+            sort_events(&mut events);
+            Ok(events)
+        }).await
+    }
+
+    /// 持有锁在阻塞线程池中执行文件读写，不阻塞异步运行时
+    async fn with_lock<F, T>(&self, f: F) -> StorageResult<T>
+    where
+        F: FnOnce(&TokenEventLog) -> StorageResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let log = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = log.lock.lock().unwrap();
+            f(&log)
+        }).await?
+    }
+
+    // 进程在追加中途退出时末行没有换行符，追加前截掉，否则新事件会接在半行后面；调用方需持有 lock
+    fn truncate_torn_line(&self) -> StorageResult<()> {
+        let Ok(content) = fs::read(&self.path) else {
+            return Ok(());
+        };
+        if content.last().is_none_or(|byte| *byte == b'\n') {
+            return Ok(());
+        }
+        let complete_len = content.iter().rposition(|byte| *byte == b'\n').map_or(0, |index| index + 1);
+        eprintln!("Discarding incomplete token history entry ({} bytes)", content.len() - complete_len);
+        OpenOptions::new().write(true).open(&self.path)?.set_len(complete_len as u64)?;
+        Ok(())
+    }
+}
+
+// 以换行符结尾的行，不含写入中途退出留下的不完整末行
+fn complete_lines(content: &str) -> impl Iterator<Item = &str> {
+    let complete_len = content.rfind('\n').map_or(0, |index| index + 1);
+    content[..complete_len].lines()
+}
+
+/// 按发生时间排序，同一时间按ID排序
+pub fn sort_events(events: &mut [TokenEvent]) {
+    events.sort_by(|a, b| a.occurred_at.cmp(&b.occurred_at).then_with(|| a.id.cmp(&b.id)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn token() -> TokenData {
+        TokenData::new("a".to_string(), "https://example.com".to_string(), "secret-token".to_string(), None, Some("note".to_string()))
+    }
+
+    #[test]
+    fn test_diff_hides_secrets() {
+        let old = token();
+        let mut new = old.clone();
+        new.access_token = "refreshed-token".to_string();
+        new.ban_status = Some(serde_json::json!("SUSPENDED"));
+        new.update_timestamp();
+
+        let event = TokenEvent::between(Some(&old), Some(&new), "device_1").unwrap();
+        assert_eq!(event.kind, TokenEventKind::Updated);
+        let fields: Vec<&str> = event.changes.iter().map(|change| change.field.as_str()).collect();
+        assert_eq!(fields, vec!["access_token", "ban_status"]);
+        assert!(!event.changes[0].new.to_string().contains("refreshed-token"));
+        assert_ne!(event.changes[0].old, event.changes[0].new);
+        assert_eq!(event.changes[1].new, serde_json::json!("SUSPENDED"));
+
+        // 只有时间戳变化不算修改
+        assert!(TokenEvent::between(Some(&old), Some(&old), "device_1").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_event_log_round_trip() {
+        let dir = tempdir().unwrap();
+        let cipher = Arc::new(TokenCipher::new("passphrase".to_string()).unwrap());
+        let log = TokenEventLog::new_with_path(dir.path().join("token_events.jsonl")).with_cipher(cipher);
+
+        let created = TokenEvent::between(None, Some(&token()), "device_1").unwrap();
+        let other = TokenEvent::between(None, Some(&TokenData::new("b".to_string(), "https://b.com".to_string(), "b".to_string(), None, None)), "device_1").unwrap();
+        let deleted = TokenEvent::between(Some(&token()), None, "device_2").unwrap();
+        log.append(&[created, other]).await.unwrap();
+        log.append(&[deleted]).await.unwrap();
+
+        let events = log.events_for("a").await.unwrap();
+        let kinds: Vec<TokenEventKind> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![TokenEventKind::Created, TokenEventKind::Deleted]);
+        assert_eq!(events[1].device_id, "device_2");
+
+        let content = fs::read_to_string(dir.path().join("token_events.jsonl")).unwrap();
+        assert!(!content.contains("secret-token"));
+        assert_eq!(content.lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_event_log_reports_corruption() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("token_events.jsonl");
+        let log = TokenEventLog::new_with_path(path.clone());
+        log.append(&[TokenEvent::between(None, Some(&token()), "device_1").unwrap()]).await.unwrap();
+
+        // 写入中途退出留下的半行被跳过，下次追加前截掉
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"id": "torn"#).unwrap();
+        assert_eq!(log.events_for("a").await.unwrap().len(), 1);
+        log.append(&[TokenEvent::between(Some(&token()), None, "device_1").unwrap()]).await.unwrap();
+        assert_eq!(log.events_for("a").await.unwrap().len(), 2);
+        assert!(!fs::read_to_string(&path).unwrap().contains("torn"));
+
+        // 完整但无法解析的行说明文件已损坏，不能静默跳过
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"not json\n").unwrap();
+        assert_eq!(log.events_for("a").await.unwrap_err().code(), "corrupt");
+    }
+}
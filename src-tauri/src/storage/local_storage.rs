@@ -1,17 +1,81 @@
-use super::traits::{TokenStorage, TokenData, convert_legacy_token, convert_to_legacy_format};
-use std::path::PathBuf;
-use std::fs;
-use std::sync::Mutex;
+use super::traits::{TokenStorage, TokenData, Tombstone, SyncWatermark, convert_legacy_token, convert_to_legacy_format, merge_tombstones};
+use super::encryption::{TokenCipher, is_encrypted_content};
+use super::error::{StorageError, StorageResult};
+use super::merge::same_content;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+use chrono::{DateTime, Utc};
 use tauri::Manager;
 
+// 同一个文件会被多个 LocalFileStorage 实例读写，按路径记录本应用最近一次写入或接受的内容
+static FILE_STATES: OnceLock<Mutex<HashMap<PathBuf, FileState>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Default)]
+struct FileState {
+    // 磁盘上内容的指纹，与之相同说明文件没有被外部修改
+    fingerprint: Option<String>,
+    // 最近一次正常的（解密后的）内容
+    last_good: Option<String>,
+    // 被拒绝的外部修改的指纹
+    rejected: Option<String>,
+}
+
+fn with_file_state<R>(path: &Path, f: impl FnOnce(&mut FileState) -> R) -> R {
+    let states = FILE_STATES.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut states = states.lock().unwrap();
+    f(states.entry(path.to_path_buf()).or_default())
+}
+
+fn fingerprint(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+/// tokens.json 被外部修改时的变化摘要（token ID）
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TokenFileChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl TokenFileChange {
+    fn between(old: &[TokenData], new: &[TokenData]) -> Self {
+        let mut change = Self::default();
+        for token in new {
+            match old.iter().find(|existing| existing.id == token.id) {
+                None => change.added.push(token.id.clone()),
+                Some(existing) if !same_content(existing, token) => change.modified.push(token.id.clone()),
+                Some(_) => {}
+            }
+        }
+        change.removed = old.iter()
+            .filter(|token| !new.iter().any(|existing| existing.id == token.id))
+            .map(|token| token.id.clone())
+            .collect();
+        change
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// 所有文件操作都在阻塞线程池中执行，并持有跨进程的建议锁（`tokens.json.lock`）
+#[derive(Clone)]
 pub struct LocalFileStorage {
     storage_path: PathBuf,
-    // 使用Mutex来确保文件操作的线程安全
-    _lock: Mutex<()>,
+    // 设置后文件以加密信封格式保存
+    cipher: Option<Arc<TokenCipher>>,
 }
 
 impl LocalFileStorage {
-    pub fn new(app_handle: &tauri::AppHandle) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn new(app_handle: &tauri::AppHandle) -> StorageResult<Self> {
         let app_data_dir = app_handle.path().app_data_dir()?;
         fs::create_dir_all(&app_data_dir)?;
         
@@ -19,35 +83,134 @@ impl LocalFileStorage {
         
         Ok(Self {
             storage_path,
-            _lock: Mutex::new(()),
+            cipher: None,
         })
     }
 
     pub fn new_with_path(storage_path: PathBuf) -> Self {
         Self {
             storage_path,
-            _lock: Mutex::new(()),
+            cipher: None,
         }
     }
 
-    async fn read_file_content(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let _guard = self._lock.lock().unwrap();
-        
+    /// 启用静态加密，已有的明文文件会在下一次读取时迁移为加密格式
+    pub fn with_cipher(mut self, cipher: Arc<TokenCipher>) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// 检查磁盘上的文件是否已经是加密格式
+    pub fn is_file_encrypted(&self) -> bool {
+        fs::read_to_string(&self.storage_path)
+            .map(|content| is_encrypted_content(&content))
+            .unwrap_or(false)
+    }
+
+    /// 读取解密后的JSON内容
+    pub async fn read_file_content(&self) -> StorageResult<String> {
+        self.with_file_lock(|storage| storage.read_content_locked()).await
+    }
+
+    /// 写入JSON内容，启用加密时先加密再写入
+    pub async fn write_file_content(&self, content: &str) -> StorageResult<()> {
+        let content = content.to_string();
+        self.with_file_lock(move |storage| storage.write_content_locked(&content)).await
+    }
+
+    /// 在阻塞线程池中持有文件锁执行文件操作
+    ///
+    /// 锁对其他进程（第二个应用实例、命令行工具）和同一进程中的其他实例同样有效。
+    async fn with_file_lock<F, T>(&self, f: F) -> StorageResult<T>
+    where
+        F: FnOnce(&LocalFileStorage) -> StorageResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let storage = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _lock = storage.lock_file()?;
+            f(&storage)
+        }).await?
+    }
+
+    // 锁文件与 tokens.json 分开，tokens.json 每次写入都会被改名替换；返回的文件关闭时释放锁
+    fn lock_file(&self) -> StorageResult<fs::File> {
+        if let Some(parent) = self.storage_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.storage_path.with_extension("json.lock"))?;
+        lock_file.lock()?;
+        Ok(lock_file)
+    }
+
+    // 调用方需持有文件锁
+    fn read_content_locked(&self) -> StorageResult<String> {
         if !self.storage_path.exists() {
             return Ok("[]".to_string());
         }
 
         let content = fs::read_to_string(&self.storage_path)?;
+
+        // 被拒绝的外部修改不加载，继续使用上一次正常的内容；下一次写入会覆盖它
+        let rejected_fallback = with_file_state(&self.storage_path, |state| {
+            (state.rejected == Some(fingerprint(&content))).then(|| state.last_good.clone()).flatten()
+        });
+        if let Some(last_good) = rejected_fallback {
+            return Ok(last_good);
+        }
+
+        self.decode_content(&content)
+    }
+
+    // 解密磁盘上的内容，调用方需持有文件锁
+    fn decode_content(&self, content: &str) -> StorageResult<String> {
         if content.trim().is_empty() {
             return Ok("[]".to_string());
         }
 
-        Ok(content)
+        if is_encrypted_content(content) {
+            let cipher = self.cipher.as_ref()
+                .ok_or_else(|| StorageError::Locked("Token file is encrypted, passphrase required".to_string()))?;
+            return cipher.decrypt(content);
+        }
+
+        // 一次性迁移：明文文件在启用加密后第一次读取时改写为加密格式
+        if let Some(cipher) = &self.cipher {
+            serde_json::from_str::<serde_json::Value>(content)?;
+            self.write_atomic(&cipher.encrypt(content)?, content)?;
+        }
+
+        Ok(content.to_string())
     }
 
-    async fn write_file_content(&self, content: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let _guard = self._lock.lock().unwrap();
-        
+    // 调用方需持有文件锁
+    fn write_content_locked(&self, content: &str) -> StorageResult<()> {
+        // 验证JSON格式
+        serde_json::from_str::<serde_json::Value>(content)?;
+
+        match &self.cipher {
+            Some(cipher) => self.write_atomic(&cipher.encrypt(content)?, content),
+            None => {
+                // 未解锁时不能用明文覆盖已加密的文件
+                if self.is_file_encrypted() {
+                    return Err(StorageError::Locked("Token file is encrypted, passphrase required".to_string()));
+                }
+                self.write_atomic(content, content)
+            }
+        }
+    }
+
+    // `content` 为写入磁盘的内容，`json` 为其解密后的内容；调用方需持有文件锁
+    fn write_atomic(&self, content: &str, json: &str) -> StorageResult<()> {
         // 确保父目录存在
         if let Some(parent) = self.storage_path.parent() {
             fs::create_dir_all(parent)?;
@@ -55,52 +218,153 @@ impl LocalFileStorage {
 
         let temp_path = self.storage_path.with_extension("tmp");
 
-        // 验证JSON格式
-        serde_json::from_str::<serde_json::Value>(content)?;
-
         // 原子性写入
-        fs::write(&temp_path, content)?;
+        {
+            let mut temp_file = fs::File::create(&temp_path)?;
+            temp_file.write_all(content.as_bytes())?;
+            temp_file.sync_all()?;
+        }
         fs::rename(&temp_path, &self.storage_path)?;
 
+        // 记下本应用写入的内容，文件监视不会把它当作外部修改
+        with_file_state(&self.storage_path, |state| {
+            state.fingerprint = Some(fingerprint(content));
+            state.last_good = Some(json.to_string());
+            state.rejected = None;
+        });
+
         Ok(())
     }
 
-    async fn parse_tokens_from_content(&self, content: &str) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn path(&self) -> &Path {
+        &self.storage_path
+    }
+
+    /// 文件的修改时间和大小，文件不存在时返回 None
+    pub async fn file_stamp(&self) -> Option<(SystemTime, u64)> {
+        let metadata = tokio::fs::metadata(&self.storage_path).await.ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    /// 检查文件是否被本应用以外的程序修改过，返回变化摘要
+    ///
+    /// 新内容通过校验后成为新的基准；不是合法JSON或含有无效token时返回错误，
+    /// 文件被复制为 `.rejected` 备份，读取继续使用上一次正常的内容。
+    /// 文件已加密而未提供口令时返回 `Locked`，不视为损坏。
+    pub async fn check_external_change(&self) -> StorageResult<Option<TokenFileChange>> {
+        self.with_file_lock(|storage| storage.check_external_change_locked()).await
+    }
+
+    fn check_external_change_locked(&self) -> StorageResult<Option<TokenFileChange>> {
+        if !self.storage_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&self.storage_path)?;
+        let current = fingerprint(&content);
+        let state = with_file_state(&self.storage_path, |state| state.clone());
+        if state.fingerprint.as_ref() == Some(&current) || state.rejected.as_ref() == Some(&current) {
+            return Ok(None);
+        }
+
+        let parsed = self.decode_content(&content)
+            .and_then(|json| TokenDocument::parse_strict(&json).map(|document| (json, document)));
+        let (json, document) = match parsed {
+            Ok(parsed) => parsed,
+            // 只有内容本身无效才算损坏；缺少口令等情况不备份也不记为已拒绝，解锁后再检查
+            Err(e @ (StorageError::Corrupt(_) | StorageError::Serialization(_))) => {
+                let backup_path = self.storage_path.with_extension("json.rejected");
+                if let Err(copy_error) = fs::copy(&self.storage_path, &backup_path) {
+                    eprintln!("Failed to back up rejected token file: {}", copy_error);
+                }
+                with_file_state(&self.storage_path, |state| state.rejected = Some(current));
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let previous = state.last_good.as_deref()
+            .and_then(|last_good| TokenDocument::parse(last_good).ok())
+            .map(|previous| previous.token_data())
+            .unwrap_or_default();
+        let change = TokenFileChange::between(&previous, &document.token_data());
+
+        // 启用加密时明文文件在读取时已被改写为加密格式，记录改写后的指纹
+        let current = fs::read_to_string(&self.storage_path)
+            .map(|content| fingerprint(&content))
+            .unwrap_or(current);
+        with_file_state(&self.storage_path, |state| {
+            state.fingerprint = Some(current);
+            state.last_good = Some(json);
+            state.rejected = None;
+        });
+
+        Ok(Some(change))
+    }
+
+    async fn read_document(&self) -> StorageResult<TokenDocument> {
+        self.with_file_lock(|storage| TokenDocument::parse(&storage.read_content_locked()?)).await
+    }
+
+    /// 在同一把文件锁内完成读取-修改-写入，避免并发写入（包括其他进程）互相覆盖
+    async fn update_document<F, R>(&self, f: F) -> StorageResult<R>
+    where
+        F: FnOnce(&mut TokenDocument) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.with_file_lock(move |storage| {
+            let mut document = TokenDocument::parse(&storage.read_content_locked()?)?;
+            let result = f(&mut document);
+            storage.write_content_locked(&document.to_json()?)?;
+            Ok(result)
+        }).await
+    }
+}
+
+/// tokens.json 的内容
+///
+/// 没有同步相关数据时保存为tokens数组（兼容旧版本），否则保存为
+/// `{tokens, tombstones, sync_base, sync_watermark}` 对象。
+/// tokens 保留原始JSON，未修改的token中前端额外写入的字段不会丢失。
+struct TokenDocument {
+    tokens: Vec<serde_json::Value>,
+    tombstones: Vec<Tombstone>,
+    sync_base: Vec<TokenData>,
+    sync_watermark: Option<SyncWatermark>,
+}
+
+impl TokenDocument {
+    fn parse(content: &str) -> StorageResult<Self> {
         let json_value: serde_json::Value = serde_json::from_str(content)?;
-        let mut tokens = Vec::new();
+        let mut document = Self {
+            tokens: Vec::new(),
+            tombstones: Vec::new(),
+            sync_base: Vec::new(),
+            sync_watermark: None,
+        };
 
         match json_value {
             serde_json::Value::Array(array) => {
-                for item in array {
-                    match convert_legacy_token(&item) {
-                        Ok(token) => tokens.push(token),
-                        Err(e) => {
-                            eprintln!("Failed to convert token: {}", e);
-                            continue;
-                        }
-                    }
-                }
+                document.tokens = array;
             }
             serde_json::Value::Object(ref obj) => {
-                // 检查是否是旧格式 {tokens: [...]}
+                // 检查是否是对象格式 {tokens: [...], tombstones: [...], ...}
                 if let Some(tokens_array) = obj.get("tokens") {
                     if let serde_json::Value::Array(array) = tokens_array {
-                        for item in array {
-                            match convert_legacy_token(item) {
-                                Ok(token) => tokens.push(token),
-                                Err(e) => {
-                                    eprintln!("Failed to convert token: {}", e);
-                                    continue;
-                                }
-                            }
-                        }
+                        document.tokens = array.clone();
+                    }
+                    if let Some(tombstones) = obj.get("tombstones") {
+                        document.tombstones = serde_json::from_value(tombstones.clone())?;
+                    }
+                    if let Some(sync_base) = obj.get("sync_base") {
+                        document.sync_base = serde_json::from_value(sync_base.clone())?;
+                    }
+                    if let Some(sync_watermark) = obj.get("sync_watermark") {
+                        document.sync_watermark = serde_json::from_value(sync_watermark.clone())?;
                     }
                 } else {
                     // 单个对象格式
-                    match convert_legacy_token(&json_value) {
-                        Ok(token) => tokens.push(token),
-                        Err(e) => eprintln!("Failed to convert single token: {}", e),
-                    }
+                    document.tokens.push(json_value);
                 }
             }
             _ => {
@@ -108,71 +372,212 @@ impl LocalFileStorage {
             }
         }
 
-        Ok(tokens)
+        Ok(document)
     }
-}
 
-#[async_trait::async_trait]
-impl TokenStorage for LocalFileStorage {
-    async fn save_token(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut tokens = self.load_tokens().await?;
-        
-        // 检查是否已存在相同ID的token
-        if let Some(existing_index) = tokens.iter().position(|t| t.id == token.id) {
-            tokens[existing_index] = token.clone();
+    /// 解析并校验外部写入的内容，任何一个token无效都视为整个文件无效
+    fn parse_strict(content: &str) -> StorageResult<Self> {
+        let json_value: serde_json::Value = serde_json::from_str(content)
+            .map_err(|e| StorageError::Corrupt(format!("Token file is not valid JSON: {}", e)))?;
+        if !json_value.is_array() && !json_value.is_object() {
+            return Err(StorageError::Corrupt("Token file must contain a JSON array or object".to_string()));
+        }
+
+        let document = Self::parse(content)
+            .map_err(|e| StorageError::Corrupt(format!("Invalid token file: {}", e)))?;
+        for (index, item) in document.tokens.iter().enumerate() {
+            convert_legacy_token(item)
+                .map_err(|e| StorageError::Corrupt(format!("Token #{} is invalid: {}", index + 1, e)))?;
+        }
+
+        Ok(document)
+    }
+
+    fn to_json(&self) -> StorageResult<String> {
+        if self.tombstones.is_empty() && self.sync_base.is_empty() && self.sync_watermark.is_none() {
+            Ok(serde_json::to_string_pretty(&self.tokens)?)
         } else {
-            tokens.push(token.clone());
+            Ok(serde_json::to_string_pretty(&serde_json::json!({
+                "tokens": self.tokens,
+                "tombstones": self.tombstones,
+                "sync_base": self.sync_base,
+                "sync_watermark": self.sync_watermark,
+            }))?)
         }
+    }
 
-        // 转换为旧格式并保存
-        let legacy_tokens: Vec<serde_json::Value> = tokens.iter()
-            .map(convert_to_legacy_format)
+    /// 可以解析的tokens；无法解析的条目跳过，但仍保留在文件中，见 `replace_tokens`
+    fn token_data(&self) -> Vec<TokenData> {
+        let mut tokens = Vec::new();
+        for (index, item) in self.tokens.iter().enumerate() {
+            match convert_legacy_token(item) {
+                Ok(token) => tokens.push(token),
+                Err(e) => eprintln!("Skipping unreadable token entry {} (kept in file): {}", index, e),
+            }
+        }
+        tokens
+    }
+
+    /// 整体替换tokens，无法解析的原始条目原样保留，除非新的tokens中有相同ID的条目
+    fn replace_tokens(&mut self, tokens: Vec<serde_json::Value>) {
+        let new_ids: HashSet<String> = tokens.iter()
+            .filter_map(|t| t.get("id").and_then(|id| id.as_str()).map(str::to_string))
+            .collect();
+        let unreadable: Vec<serde_json::Value> = std::mem::take(&mut self.tokens)
+            .into_iter()
+            .filter(|t| convert_legacy_token(t).is_err())
+            .filter(|t| !t.get("id").and_then(|id| id.as_str()).is_some_and(|id| new_ids.contains(id)))
             .collect();
+        self.tokens = tokens;
+        self.tokens.extend(unreadable);
+    }
+
+    fn upsert(&mut self, token: &TokenData) {
+        let value = convert_to_legacy_format(token);
+        match self.tokens.iter().position(|t| t.get("id").and_then(|id| id.as_str()) == Some(token.id.as_str())) {
+            Some(existing_index) => self.tokens[existing_index] = value,
+            None => self.tokens.push(value),
+        }
+    }
 
-        let json_content = serde_json::to_string_pretty(&legacy_tokens)?;
-        self.write_file_content(&json_content).await?;
+    fn remove(&mut self, token_ids: &[String]) -> usize {
+        let initial_len = self.tokens.len();
+        self.tokens.retain(|t| {
+            !t.get("id")
+                .and_then(|id| id.as_str())
+                .is_some_and(|id| token_ids.iter().any(|token_id| token_id == id))
+        });
+        initial_len - self.tokens.len()
+    }
 
-        Ok(())
+    /// 写入墓碑、删除被墓碑覆盖的tokens并写入给定的tokens，返回删除的数量
+    fn apply_changes(&mut self, tokens: &[TokenData], tombstones: Vec<Tombstone>) -> usize {
+        let deleted_ids: Vec<String> = tombstones.iter()
+            .filter(|t| !tokens.iter().any(|token| token.id == t.token_id))
+            .map(|t| t.token_id.clone())
+            .collect();
+
+        let existing = std::mem::take(&mut self.tombstones);
+        self.tombstones = merge_tombstones(existing, tombstones);
+        let deleted = self.remove(&deleted_ids);
+
+        for token in tokens {
+            self.upsert(token);
+        }
+        deleted
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenStorage for LocalFileStorage {
+    async fn save_token(&self, token: &TokenData) -> StorageResult<()> {
+        self.save_tokens(std::slice::from_ref(token)).await
     }
 
-    async fn load_tokens(&self) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
-        let content = self.read_file_content().await?;
-        self.parse_tokens_from_content(&content).await
+    async fn load_tokens(&self) -> StorageResult<Vec<TokenData>> {
+        Ok(self.read_document().await?.token_data())
     }
 
-    async fn update_token(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    async fn update_token(&self, token: &TokenData) -> StorageResult<()> {
         let mut updated_token = token.clone();
         updated_token.update_timestamp();
         self.save_token(&updated_token).await
     }
 
-    async fn delete_token(&self, token_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        let mut tokens = self.load_tokens().await?;
-        let initial_len = tokens.len();
-        
-        tokens.retain(|t| t.id != token_id);
-        
-        if tokens.len() < initial_len {
-            // 转换为旧格式并保存
-            let legacy_tokens: Vec<serde_json::Value> = tokens.iter()
-                .map(convert_to_legacy_format)
-                .collect();
-
-            let json_content = serde_json::to_string_pretty(&legacy_tokens)?;
-            self.write_file_content(&json_content).await?;
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+    async fn delete_token(&self, token_id: &str) -> StorageResult<bool> {
+        let deleted = self.delete_tokens(&[token_id.to_string()]).await?;
+        Ok(deleted > 0)
     }
 
-    async fn get_token(&self, token_id: &str) -> Result<Option<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
+    async fn get_token(&self, token_id: &str) -> StorageResult<Option<TokenData>> {
         let tokens = self.load_tokens().await?;
         Ok(tokens.into_iter().find(|t| t.id == token_id))
     }
 
-    async fn clear_all_tokens(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.write_file_content("[]").await
+    async fn clear_all_tokens(&self) -> StorageResult<()> {
+        self.update_document(|document| document.tokens.clear()).await
+    }
+
+    async fn save_tokens(&self, new_tokens: &[TokenData]) -> StorageResult<()> {
+        let new_tokens = new_tokens.to_vec();
+        self.update_document(move |document| {
+            for token in &new_tokens {
+                document.upsert(token);
+            }
+        }).await
+    }
+
+    async fn delete_tokens(&self, token_ids: &[String]) -> StorageResult<usize> {
+        let token_ids = token_ids.to_vec();
+        self.update_document(move |document| document.remove(&token_ids)).await
+    }
+
+    async fn replace_all(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        let tokens: Vec<serde_json::Value> = tokens.iter().map(convert_to_legacy_format).collect();
+        self.update_document(move |document| document.replace_tokens(tokens)).await
+    }
+
+    async fn load_tombstones(&self) -> StorageResult<Vec<Tombstone>> {
+        Ok(self.read_document().await?.tombstones)
+    }
+
+    async fn save_tombstones(&self, tombstones: &[Tombstone]) -> StorageResult<()> {
+        if tombstones.is_empty() {
+            return Ok(());
+        }
+
+        let tombstones = tombstones.to_vec();
+        self.update_document(move |document| {
+            let existing = std::mem::take(&mut document.tombstones);
+            document.tombstones = merge_tombstones(existing, tombstones);
+        }).await
+    }
+
+    async fn purge_tombstones(&self, older_than: DateTime<Utc>) -> StorageResult<usize> {
+        self.update_document(move |document| {
+            let initial_len = document.tombstones.len();
+            document.tombstones.retain(|t| t.deleted_at >= older_than);
+            initial_len - document.tombstones.len()
+        }).await
+    }
+
+    async fn load_sync_base(&self) -> StorageResult<Vec<TokenData>> {
+        Ok(self.read_document().await?.sync_base)
+    }
+
+    async fn save_sync_base(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        let tokens = tokens.to_vec();
+        self.update_document(move |document| document.sync_base = tokens).await
+    }
+
+    async fn load_sync_watermark(&self) -> StorageResult<Option<SyncWatermark>> {
+        Ok(self.read_document().await?.sync_watermark)
+    }
+
+    async fn save_sync_watermark(&self, watermark: &SyncWatermark) -> StorageResult<()> {
+        let watermark = watermark.clone();
+        self.update_document(move |document| document.sync_watermark = Some(watermark)).await
+    }
+
+    async fn apply_changes(&self, tokens: &[TokenData], tombstones: &[Tombstone]) -> StorageResult<usize> {
+        let tokens = tokens.to_vec();
+        let tombstones = tombstones.to_vec();
+        self.update_document(move |document| document.apply_changes(&tokens, tombstones)).await
+    }
+
+    /// tokens、墓碑、同步基准和水位线在一次文件替换中写入
+    async fn commit_sync(&self, tokens: &[TokenData], tombstones: &[Tombstone], watermark: Option<&SyncWatermark>) -> StorageResult<usize> {
+        let tokens = tokens.to_vec();
+        let tombstones = tombstones.to_vec();
+        let watermark = watermark.cloned();
+        self.update_document(move |document| {
+            let deleted = document.apply_changes(&tokens, tombstones);
+            if let Some(watermark) = watermark {
+                document.sync_base = document.token_data();
+                document.sync_watermark = Some(watermark);
+            }
+            deleted
+        }).await
     }
 
     fn storage_type(&self) -> &'static str {
@@ -182,7 +587,7 @@ impl TokenStorage for LocalFileStorage {
     async fn is_available(&self) -> bool {
         // 检查文件是否可写
         if let Some(parent) = self.storage_path.parent() {
-            parent.exists() || fs::create_dir_all(parent).is_ok()
+            tokio::fs::try_exists(parent).await.unwrap_or(false) || tokio::fs::create_dir_all(parent).await.is_ok()
         } else {
             false
         }
@@ -229,6 +634,29 @@ mod tests {
         assert_eq!(tokens_after_delete.len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_local_storage_batch_operations() {
+        let temp_dir = tempdir().unwrap();
+        let storage_path = temp_dir.path().join("test_tokens.json");
+        let storage = LocalFileStorage::new_with_path(storage_path);
+
+        let tokens: Vec<TokenData> = (0..5)
+            .map(|i| TokenData::new(format!("id_{}", i), "https://example.com".to_string(), format!("token_{}", i), None, None))
+            .collect();
+
+        storage.save_tokens(&tokens).await.unwrap();
+        assert_eq!(storage.load_tokens().await.unwrap().len(), 5);
+
+        let deleted = storage.delete_tokens(&["id_0".to_string(), "id_1".to_string(), "missing".to_string()]).await.unwrap();
+        assert_eq!(deleted, 2);
+        assert_eq!(storage.load_tokens().await.unwrap().len(), 3);
+
+        storage.replace_all(&tokens[..1]).await.unwrap();
+        let loaded_tokens = storage.load_tokens().await.unwrap();
+        assert_eq!(loaded_tokens.len(), 1);
+        assert_eq!(loaded_tokens[0].id, "id_0");
+    }
+
     #[tokio::test]
     async fn test_storage_availability() {
         let temp_dir = tempdir().unwrap();
@@ -238,4 +666,231 @@ mod tests {
         assert!(storage.is_available().await);
         assert_eq!(storage.storage_type(), "local_file");
     }
+
+    #[tokio::test]
+    async fn test_encrypted_storage_migrates_plaintext() {
+        let temp_dir = tempdir().unwrap();
+        let storage_path = temp_dir.path().join("test_tokens.json");
+
+        // 先以明文格式写入
+        let plain_storage = LocalFileStorage::new_with_path(storage_path.clone());
+        let token = TokenData::new(
+            "test_id".to_string(),
+            "https://example.com".to_string(),
+            "secret_token".to_string(),
+            None,
+            None,
+        );
+        plain_storage.save_token(&token).await.unwrap();
+        assert!(fs::read_to_string(&storage_path).unwrap().contains("secret_token"));
+
+        // 启用加密后读取会迁移文件
+        let cipher = Arc::new(TokenCipher::new("passphrase".to_string()).unwrap());
+        let storage = LocalFileStorage::new_with_path(storage_path.clone()).with_cipher(cipher);
+        let loaded_tokens = storage.load_tokens().await.unwrap();
+        assert_eq!(loaded_tokens.len(), 1);
+        assert_eq!(loaded_tokens[0].access_token, "secret_token");
+
+        let raw = fs::read_to_string(&storage_path).unwrap();
+        assert!(is_encrypted_content(&raw));
+        assert!(!raw.contains("secret_token"));
+
+        // 没有口令时无法读取，也不能覆盖
+        assert!(plain_storage.load_tokens().await.is_err());
+        assert_eq!(plain_storage.clear_all_tokens().await.unwrap_err().code(), "locked");
+        assert!(is_encrypted_content(&fs::read_to_string(&storage_path).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_entries_preserved_across_writes() {
+        let temp_dir = tempdir().unwrap();
+        let storage_path = temp_dir.path().join("test_tokens.json");
+        fs::write(&storage_path, r#"[
+            {"id": "good", "tenant_url": "https://example.com", "access_token": "a"},
+            {"id": "broken", "access_token": "b", "custom": 1},
+            {"note": "no id"}
+        ]"#).unwrap();
+        let storage = LocalFileStorage::new_with_path(storage_path.clone());
+
+        let loaded = storage.load_tokens().await.unwrap();
+        assert_eq!(loaded.len(), 1);
+
+        // 保存和整体替换后，无法解析的条目原样留在文件中
+        let other = TokenData::new("other".to_string(), "https://example.com".to_string(), "c".to_string(), None, None);
+        storage.save_token(&other).await.unwrap();
+        storage.replace_all(std::slice::from_ref(&other)).await.unwrap();
+        let raw: Vec<serde_json::Value> = serde_json::from_str(&fs::read_to_string(&storage_path).unwrap()).unwrap();
+        assert_eq!(raw.len(), 3);
+        assert!(raw.contains(&serde_json::json!({"id": "broken", "access_token": "b", "custom": 1})));
+        assert!(raw.contains(&serde_json::json!({"note": "no id"})));
+
+        // 新的tokens中有相同ID时以新条目为准
+        let fixed = TokenData::new("broken".to_string(), "https://example.com".to_string(), "b".to_string(), None, None);
+        storage.replace_all(&[other, fixed]).await.unwrap();
+        assert_eq!(storage.load_tokens().await.unwrap().len(), 2);
+        let raw: Vec<serde_json::Value> = serde_json::from_str(&fs::read_to_string(&storage_path).unwrap()).unwrap();
+        assert_eq!(raw.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_tombstones_preserved_across_writes() {
+        let temp_dir = tempdir().unwrap();
+        let storage_path = temp_dir.path().join("test_tokens.json");
+        let storage = LocalFileStorage::new_with_path(storage_path.clone());
+
+        let token = TokenData::new("keep".to_string(), "https://example.com".to_string(), "a".to_string(), None, None);
+        storage.save_token(&token).await.unwrap();
+        // 没有墓碑时仍然保存为数组格式
+        assert!(fs::read_to_string(&storage_path).unwrap().trim_start().starts_with('['));
+
+        storage.save_tombstones(&[Tombstone::new("gone".to_string(), "device_1".to_string())]).await.unwrap();
+
+        // 替换全部tokens不会覆盖墓碑
+        let mut updated = token.clone();
+        updated.access_token = "b".to_string();
+        storage.replace_all(&[updated]).await.unwrap();
+
+        let tombstones = storage.load_tombstones().await.unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].token_id, "gone");
+        assert_eq!(storage.load_tokens().await.unwrap()[0].access_token, "b");
+
+        assert_eq!(storage.purge_tombstones(Utc::now() + chrono::Duration::seconds(1)).await.unwrap(), 1);
+        assert!(storage.load_tombstones().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sync_base_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let storage = LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json"));
+
+        let token = TokenData::new("a".to_string(), "https://example.com".to_string(), "a".to_string(), None, None);
+        storage.save_token(&token).await.unwrap();
+        storage.save_sync_base(std::slice::from_ref(&token)).await.unwrap();
+
+        // 替换全部tokens不会丢失同步基准
+        storage.replace_all(&[]).await.unwrap();
+        let base = storage.load_sync_base().await.unwrap();
+        assert_eq!(base.len(), 1);
+        assert_eq!(base[0].id, "a");
+        assert!(storage.load_tokens().await.unwrap().is_empty());
+
+        let watermark = SyncWatermark {
+            database_id: "db".to_string(),
+            remote_change_seq: 42,
+            remote_xmin: Some(40),
+        };
+        storage.save_sync_watermark(&watermark).await.unwrap();
+        assert_eq!(storage.load_sync_watermark().await.unwrap(), Some(watermark));
+    }
+
+    #[tokio::test]
+    async fn test_external_change_detection() {
+        let temp_dir = tempdir().unwrap();
+        let storage_path = temp_dir.path().join("test_tokens.json");
+        let storage = LocalFileStorage::new_with_path(storage_path.clone());
+        let a = TokenData::new("a".to_string(), "https://example.com".to_string(), "a".to_string(), None, None);
+        storage.save_token(&a).await.unwrap();
+
+        // 其他实例的写入同样是本应用的写入
+        let other = LocalFileStorage::new_with_path(storage_path.clone());
+        other.save_token(&TokenData::new("b".to_string(), "https://example.com".to_string(), "b".to_string(), None, None)).await.unwrap();
+        assert_eq!(storage.check_external_change().await.unwrap(), None);
+
+        // 外部工具修改了一个token并删除了另一个
+        let mut edited = convert_to_legacy_format(&a);
+        edited["email_note"] = serde_json::json!("edited by hand");
+        fs::write(&storage_path, serde_json::to_string(&vec![edited]).unwrap()).unwrap();
+        let change = storage.check_external_change().await.unwrap().unwrap();
+        assert_eq!(change, TokenFileChange { added: vec![], removed: vec!["b".to_string()], modified: vec!["a".to_string()] });
+        assert_eq!(storage.check_external_change().await.unwrap(), None);
+
+        // 损坏的文件被拒绝，读取继续使用上一次正常的内容
+        fs::write(&storage_path, r#"[{"id": "broken"}]"#).unwrap();
+        assert_eq!(storage.check_external_change().await.unwrap_err().code(), "corrupt");
+        assert!(storage_path.with_extension("json.rejected").exists());
+        let tokens = storage.load_tokens().await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].email_note, Some("edited by hand".to_string()));
+
+        // 下一次写入覆盖损坏的文件
+        storage.save_token(&a).await.unwrap();
+        assert!(!fs::read_to_string(&storage_path).unwrap().contains("broken"));
+    }
+
+    #[tokio::test]
+    async fn test_external_change_to_encrypted_file_without_passphrase_is_not_rejected() {
+        let temp_dir = tempdir().unwrap();
+        let storage_path = temp_dir.path().join("test_tokens.json");
+        let storage = LocalFileStorage::new_with_path(storage_path.clone());
+        storage.save_token(&TokenData::new("a".to_string(), "https://example.com".to_string(), "a".to_string(), None, None)).await.unwrap();
+
+        // 另一台机器同步过来一个加密的文件，本实例还没有口令
+        let other_path = temp_dir.path().join("other_tokens.json");
+        let cipher = Arc::new(TokenCipher::new("passphrase".to_string()).unwrap());
+        let encrypted = LocalFileStorage::new_with_path(other_path.clone()).with_cipher(cipher);
+        encrypted.save_token(&TokenData::new("b".to_string(), "https://example.com".to_string(), "b".to_string(), None, None)).await.unwrap();
+        fs::copy(&other_path, &storage_path).unwrap();
+        assert!(storage.is_file_encrypted());
+
+        assert_eq!(storage.check_external_change().await.unwrap_err().code(), "locked");
+        assert!(!storage_path.with_extension("json.rejected").exists());
+        // 没有记为已拒绝，再次检查仍然报告未解锁
+        assert_eq!(storage.check_external_change().await.unwrap_err().code(), "locked");
+    }
+
+    const WRITER_PATH_ENV: &str = "ATM_TEST_WRITER_PATH";
+    const WRITER_INDEX_ENV: &str = "ATM_TEST_WRITER_INDEX";
+    const WRITES_PER_PROCESS: usize = 20;
+
+    #[test]
+    fn test_concurrent_writers_do_not_lose_updates() {
+        let temp_dir = tempdir().unwrap();
+        let storage_path = temp_dir.path().join("test_tokens.json");
+
+        // 在子进程中运行 concurrent_writer_process，进程之间只有文件锁能让读取-修改-写入互斥
+        let test_name = concat!(module_path!(), "::concurrent_writer_process");
+        let test_name = test_name.split_once("::").map_or(test_name, |(_, name)| name);
+        let children: Vec<_> = (0..3)
+            .map(|writer_index| {
+                std::process::Command::new(std::env::current_exe().unwrap())
+                    .args([test_name, "--exact", "--ignored", "--test-threads=1"])
+                    .env(WRITER_PATH_ENV, &storage_path)
+                    .env(WRITER_INDEX_ENV, writer_index.to_string())
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let tokens = runtime.block_on(LocalFileStorage::new_with_path(storage_path).load_tokens()).unwrap();
+        assert_eq!(tokens.len(), 3 * WRITES_PER_PROCESS);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore] // 只由 test_concurrent_writers_do_not_lose_updates 在子进程中运行
+    async fn concurrent_writer_process() {
+        let (Ok(storage_path), Ok(writer_index)) = (std::env::var(WRITER_PATH_ENV), std::env::var(WRITER_INDEX_ENV)) else {
+            return;
+        };
+
+        let storage = LocalFileStorage::new_with_path(PathBuf::from(storage_path));
+        let handles: Vec<_> = (0..WRITES_PER_PROCESS)
+            .map(|i| {
+                let storage = storage.clone();
+                let id = format!("writer_{}_{}", writer_index, i);
+                tokio::spawn(async move {
+                    let token = TokenData::new(id.clone(), "https://example.com".to_string(), id, None, None);
+                    storage.save_token(&token).await.unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
 }
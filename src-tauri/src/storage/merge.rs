@@ -0,0 +1,202 @@
+use super::traits::{TokenData, SyncConflict, normalize_tags};
+use std::collections::HashMap;
+
+/// 以上次同步的基准快照为参照，对本地和远程的同一个token做逐字段三方合并
+///
+/// 只有一端修改过的字段直接采用修改后的值；两端都修改且值不同的字段
+/// 采用更新时间较新的一端，并记录为冲突，不会静默丢弃。
+pub fn three_way_merge(
+    base: Option<&TokenData>,
+    local: &TokenData,
+    remote: &TokenData,
+) -> (TokenData, Vec<SyncConflict>) {
+    let mut merged = local.clone();
+    let mut conflicts = Vec::new();
+    let prefer_local = local.updated_at > remote.updated_at;
+
+    macro_rules! merge_field {
+        ($field:ident) => {
+            if local.$field != remote.$field {
+                let base_value = base.map(|b| &b.$field);
+                if base_value == Some(&local.$field) {
+                    // 只有远程修改过
+                    merged.$field = remote.$field.clone();
+                } else if base_value == Some(&remote.$field) {
+                    // 只有本地修改过
+                    merged.$field = local.$field.clone();
+                } else {
+                    merged.$field = if prefer_local { local.$field.clone() } else { remote.$field.clone() };
+                    conflicts.push(SyncConflict {
+                        token_id: local.id.clone(),
+                        field: stringify!($field).to_string(),
+                        local_value: serde_json::to_value(&local.$field).unwrap_or_default(),
+                        remote_value: serde_json::to_value(&remote.$field).unwrap_or_default(),
+                        resolved_with: if prefer_local { "local" } else { "remote" }.to_string(),
+                    });
+                }
+            }
+        };
+    }
+
+    merge_field!(tenant_url);
+    merge_field!(access_token);
+    merge_field!(portal_url);
+    merge_field!(email_note);
+    merge_field!(ban_status);
+    merge_field!(portal_info);
+    merge_field!(auth_session);
+    merge_field!(suspensions);
+    merge_field!(balance_color_mode);
+    merge_field!(skip_check);
+    merge_field!(group);
+
+    merged.tags = merge_tags(base.map(|b| b.tags.as_slice()), &local.tags, &remote.tags);
+
+    merged.created_at = local.created_at.min(remote.created_at);
+    // 版本号以数据库为准
+    merged.version = remote.version;
+    merged.updated_at = local.updated_at.max(remote.updated_at);
+
+    (merged, conflicts)
+}
+
+/// 按集合合并标签：任一端新增的标签保留，任一端删除的标签移除，不产生冲突
+fn merge_tags(base: Option<&[String]>, local: &[String], remote: &[String]) -> Vec<String> {
+    let removed = |side: &[String]| -> Vec<String> {
+        base.unwrap_or_default().iter()
+            .filter(|tag| !side.contains(tag))
+            .cloned()
+            .collect()
+    };
+    let removed_locally = removed(local);
+    let removed_remotely = removed(remote);
+
+    normalize_tags(
+        local.iter()
+            .chain(remote.iter())
+            .filter(|tag| !removed_locally.contains(tag) && !removed_remotely.contains(tag)),
+    )
+}
+
+/// 判断两个token的数据字段是否相同（不比较时间戳）
+pub fn same_content(a: &TokenData, b: &TokenData) -> bool {
+    a.id == b.id
+        && a.tenant_url == b.tenant_url
+        && a.access_token == b.access_token
+        && a.portal_url == b.portal_url
+        && a.email_note == b.email_note
+        && a.ban_status == b.ban_status
+        && a.portal_info == b.portal_info
+        && a.auth_session == b.auth_session
+        && a.suspensions == b.suspensions
+        && a.balance_color_mode == b.balance_color_mode
+        && a.skip_check == b.skip_check
+        && a.tags == b.tags
+        && a.group == b.group
+}
+
+/// 合并本地和远程的token集合：只存在于一端的token直接保留，两端都有的逐字段三方合并
+pub fn merge_token_sets(
+    local_tokens: Vec<TokenData>,
+    remote_tokens: Vec<TokenData>,
+    base_tokens: &[TokenData],
+) -> (Vec<TokenData>, Vec<SyncConflict>) {
+    let base: HashMap<&str, &TokenData> = base_tokens.iter()
+        .map(|token| (token.id.as_str(), token))
+        .collect();
+
+    let mut resolved: HashMap<String, TokenData> = remote_tokens.into_iter()
+        .map(|token| (token.id.clone(), token))
+        .collect();
+    let mut conflicts = Vec::new();
+
+    for local_token in local_tokens {
+        let merged = match resolved.get(&local_token.id) {
+            Some(remote_token) => {
+                let (merged, token_conflicts) = three_way_merge(
+                    base.get(local_token.id.as_str()).copied(),
+                    &local_token,
+                    remote_token,
+                );
+                conflicts.extend(token_conflicts);
+                merged
+            }
+            // 远程不存在此token，添加本地token（新增的token）
+            None => local_token,
+        };
+        resolved.insert(merged.id.clone(), merged);
+    }
+
+    (resolved.into_values().collect(), conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    fn token(id: &str) -> TokenData {
+        TokenData::new(id.to_string(), "https://example.com".to_string(), "token".to_string(), None, None)
+    }
+
+    #[test]
+    fn test_non_overlapping_edits_are_combined() {
+        let base = token("a");
+
+        let mut local = base.clone();
+        local.email_note = Some("local note".to_string());
+        local.updated_at = Utc::now() + Duration::seconds(10);
+
+        let mut remote = base.clone();
+        remote.ban_status = Some(serde_json::json!("SUSPENDED"));
+        remote.updated_at = Utc::now() + Duration::seconds(20);
+
+        let (merged, conflicts) = three_way_merge(Some(&base), &local, &remote);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.email_note, Some("local note".to_string()));
+        assert_eq!(merged.ban_status, Some(serde_json::json!("SUSPENDED")));
+        assert_eq!(merged.updated_at, remote.updated_at);
+    }
+
+    #[test]
+    fn test_conflicting_edits_are_reported() {
+        let base = token("a");
+
+        let mut local = base.clone();
+        local.email_note = Some("local note".to_string());
+        local.updated_at = Utc::now() + Duration::seconds(20);
+
+        let mut remote = base.clone();
+        remote.email_note = Some("remote note".to_string());
+        remote.updated_at = Utc::now() + Duration::seconds(10);
+
+        let (merged, conflicts) = merge_token_sets(vec![local], vec![remote, token("b")], &[base]);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].field, "email_note");
+        assert_eq!(conflicts[0].remote_value, serde_json::json!("remote note"));
+        assert_eq!(conflicts[0].resolved_with, "local");
+
+        let a = merged.iter().find(|t| t.id == "a").unwrap();
+        assert_eq!(a.email_note, Some("local note".to_string()));
+    }
+
+    #[test]
+    fn test_tags_are_merged_as_sets() {
+        let mut base = token("a");
+        base.tags = vec!["project-x".to_string(), "old".to_string()];
+
+        let mut local = base.clone();
+        local.tags = vec!["project-x".to_string(), "alice".to_string()];
+        local.group = Some("team-a".to_string());
+
+        let mut remote = base.clone();
+        remote.tags = vec!["project-x".to_string(), "old".to_string(), "bob".to_string()];
+        remote.updated_at = Utc::now() + Duration::seconds(10);
+
+        let (merged, conflicts) = three_way_merge(Some(&base), &local, &remote);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.tags, vec!["project-x", "alice", "bob"]);
+        assert_eq!(merged.group, Some("team-a".to_string()));
+    }
+}
@@ -1,9 +1,43 @@
+pub mod error;
 pub mod traits;
+pub mod query;
+pub mod config;
+pub mod encryption;
+pub mod secrets;
+pub mod merge;
+pub mod history;
+pub mod outbox;
 pub mod local_storage;
+pub mod sqlite_storage;
 pub mod postgres_storage;
 pub mod dual_storage;
+pub mod change_feed;
+pub mod file_watcher;
+pub mod snapshot;
+pub mod transfer;
+pub mod dedupe;
+pub mod sync_plan;
+pub mod sync_journal;
+pub mod sync_scheduler;
 
+pub use error::*;
 pub use traits::*;
+pub use query::*;
+pub use config::*;
+pub use encryption::*;
+pub use secrets::*;
+pub use merge::*;
+pub use history::*;
+pub use outbox::*;
 pub use local_storage::*;
+pub use sqlite_storage::*;
 pub use postgres_storage::*;
 pub use dual_storage::*;
+pub use change_feed::*;
+pub use file_watcher::*;
+pub use snapshot::*;
+pub use transfer::*;
+pub use dedupe::*;
+pub use sync_plan::*;
+pub use sync_journal::*;
+pub use sync_scheduler::*;
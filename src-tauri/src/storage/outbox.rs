@@ -0,0 +1,245 @@
+use super::traits::{TokenData, Tombstone};
+use super::history::TokenEvent;
+use super::encryption::{TokenCipher, is_encrypted_content};
+use super::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::fs;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use tauri::Manager;
+
+/// 等待写入数据库的修改
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum OutboxOp {
+    Upsert { token: Box<TokenData> },
+    Delete { tombstone: Tombstone },
+    /// 修改历史只追加，不会被同一token的后续修改替换
+    Event { event: Box<TokenEvent> },
+}
+
+impl OutboxOp {
+    pub fn token_id(&self) -> &str {
+        match self {
+            OutboxOp::Upsert { token } => &token.id,
+            OutboxOp::Delete { tombstone } => &tombstone.token_id,
+            OutboxOp::Event { event } => &event.token_id,
+        }
+    }
+
+    /// 是否替换另一个待写入的修改
+    fn supersedes(&self, other: &OutboxOp) -> bool {
+        !matches!(self, OutboxOp::Event { .. })
+            && !matches!(other, OutboxOp::Event { .. })
+            && self.token_id() == other.token_id()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub seq: u64,
+    pub queued_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub op: OutboxOp,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct OutboxDocument {
+    // 单调递增，发件箱清空后也不会重复使用已分配的序号
+    next_seq: u64,
+    entries: Vec<OutboxEntry>,
+}
+
+/// 数据库不可用时保存在本地的待写入修改，数据库恢复后按顺序重放
+///
+/// 每个token只保留最后一次修改，所以重放时可以在一个事务中写入全部条目；修改历史事件全部保留。
+#[derive(Clone)]
+pub struct Outbox {
+    path: PathBuf,
+    // 设置后文件以加密信封格式保存，与 tokens.json 使用同一口令
+    cipher: Option<Arc<TokenCipher>>,
+    // 克隆之间共享，保证读取-修改-写入互斥
+    lock: Arc<Mutex<()>>,
+}
+
+impl Outbox {
+    pub fn new(app_handle: &tauri::AppHandle) -> StorageResult<Self> {
+        let app_data_dir = app_handle.path().app_data_dir()?;
+        fs::create_dir_all(&app_data_dir)?;
+
+        Ok(Self::new_with_path(app_data_dir.join("outbox.json")))
+    }
+
+    pub fn new_with_path(path: PathBuf) -> Self {
+        Self {
+            path,
+            cipher: None,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn with_cipher(mut self, cipher: Arc<TokenCipher>) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// 追加修改；同一token已有待写入的修改时替换为新的修改，历史事件总是追加
+    pub async fn enqueue(&self, ops: Vec<OutboxOp>) -> StorageResult<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        self.with_lock(move |outbox| {
+            let mut document = outbox.read_locked()?;
+
+            for op in ops {
+                document.entries.retain(|entry| !op.supersedes(&entry.op));
+                document.next_seq += 1;
+                document.entries.push(OutboxEntry {
+                    seq: document.next_seq,
+                    queued_at: Utc::now(),
+                    op,
+                });
+            }
+
+            outbox.write_locked(&document)
+        }).await
+    }
+
+    /// 按入队顺序返回全部待写入的修改
+    pub async fn entries(&self) -> StorageResult<Vec<OutboxEntry>> {
+        self.with_lock(|outbox| Ok(outbox.read_locked()?.entries)).await
+    }
+
+    pub async fn len(&self) -> StorageResult<usize> {
+        Ok(self.entries().await?.len())
+    }
+
+    /// 移除已经写入数据库的条目；重放期间被新修改替换的条目会保留
+    pub async fn acknowledge(&self, entries: &[OutboxEntry]) -> StorageResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let done: Vec<u64> = entries.iter().map(|entry| entry.seq).collect();
+        self.with_lock(move |outbox| {
+            let mut document = outbox.read_locked()?;
+            document.entries.retain(|entry| !done.contains(&entry.seq));
+            outbox.write_locked(&document)
+        }).await
+    }
+
+    /// 丢弃全部待写入的修改
+    pub async fn clear(&self) -> StorageResult<()> {
+        self.with_lock(|outbox| {
+            let mut document = outbox.read_locked()?;
+            document.entries.clear();
+            outbox.write_locked(&document)
+        }).await
+    }
+
+    /// 持有锁在阻塞线程池中执行文件读写，不阻塞异步运行时
+    async fn with_lock<F, T>(&self, f: F) -> StorageResult<T>
+    where
+        F: FnOnce(&Outbox) -> StorageResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let outbox = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = outbox.lock.lock().unwrap();
+            f(&outbox)
+        }).await?
+    }
+
+    // 调用方需持有 lock
+    fn read_locked(&self) -> StorageResult<OutboxDocument> {
+        if !self.path.exists() {
+            return Ok(OutboxDocument::default());
+        }
+
+        let content = fs::read_to_string(&self.path)?;
+        if content.trim().is_empty() {
+            return Ok(OutboxDocument::default());
+        }
+
+        let content = if is_encrypted_content(&content) {
+            let cipher = self.cipher.as_ref()
+                .ok_or_else(|| StorageError::Locked("Outbox file is encrypted, passphrase required".to_string()))?;
+            cipher.decrypt(&content)?
+        } else {
+            content
+        };
+
+        serde_json::from_str(&content)
+            .map_err(|e| StorageError::Corrupt(format!("Invalid outbox file: {}", e)))
+    }
+
+    // 调用方需持有 lock
+    fn write_locked(&self, document: &OutboxDocument) -> StorageResult<()> {
+        let json = serde_json::to_string_pretty(document)?;
+        let content = match &self.cipher {
+            Some(cipher) => cipher.encrypt(&json)?,
+            None => json,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = self.path.with_extension("tmp");
+        {
+            let mut temp_file = fs::File::create(&temp_path)?;
+            temp_file.write_all(content.as_bytes())?;
+            temp_file.sync_all()?;
+        }
+        fs::rename(&temp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn upsert(id: &str) -> OutboxOp {
+        OutboxOp::Upsert {
+            token: Box::new(TokenData::new(id.to_string(), "https://example.com".to_string(), "token".to_string(), None, None)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outbox_deduplicates_by_token_id() {
+        let dir = tempdir().unwrap();
+        let outbox = Outbox::new_with_path(dir.path().join("outbox.json"));
+
+        outbox.enqueue(vec![upsert("a"), upsert("b")]).await.unwrap();
+        outbox.enqueue(vec![OutboxOp::Delete { tombstone: Tombstone::new("a".to_string(), "device".to_string()) }]).await.unwrap();
+
+        let entries = outbox.entries().await.unwrap();
+        let ids: Vec<&str> = entries.iter().map(|entry| entry.op.token_id()).collect();
+        assert_eq!(ids, vec!["b", "a"]);
+        assert!(matches!(entries[1].op, OutboxOp::Delete { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_acknowledge_keeps_newer_entries() {
+        let dir = tempdir().unwrap();
+        let outbox = Outbox::new_with_path(dir.path().join("outbox.json"));
+
+        outbox.enqueue(vec![upsert("a")]).await.unwrap();
+        let replayed = outbox.entries().await.unwrap();
+
+        // 重放期间同一token又被修改
+        outbox.enqueue(vec![upsert("a")]).await.unwrap();
+        outbox.acknowledge(&replayed).await.unwrap();
+        assert_eq!(outbox.len().await.unwrap(), 1);
+
+        let pending = outbox.entries().await.unwrap();
+        outbox.acknowledge(&pending).await.unwrap();
+        assert_eq!(outbox.len().await.unwrap(), 0);
+    }
+}
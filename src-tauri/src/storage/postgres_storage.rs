@@ -1,35 +1,280 @@
-use super::traits::{TokenStorage, TokenData};
+use super::traits::{TokenStorage, TokenData, Tombstone, SyncStatus, SyncError, SyncHistoryPage, SyncDirectionStats, SyncWatermark};
+use super::history::{TokenEvent, TokenEventKind};
+use super::query::{TokenFilter, TokenSort, TokenSortField, PageRequest, TokenPage};
+use super::error::{StorageError, StorageResult};
+use super::secrets::{SecretCipher, SecretKeyInfo, is_sealed_secret};
 use crate::database::{DatabaseManager, DbPool};
 use std::sync::Arc;
-use chrono::Utc;
+use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use tokio_postgres::types::ToSql;
+
+// 每条多行UPSERT语句包含的行数，17列 * 500行远低于PostgreSQL的参数上限(65535)
+const UPSERT_BATCH_SIZE: usize = 500;
+const TOKEN_COLUMN_COUNT: usize = 17;
+
+/// 增量读取的结果
+#[derive(Debug, Clone)]
+pub struct RemoteChanges {
+    pub tokens: Vec<TokenData>,
+    /// 已读到的最大 change_seq
+    pub change_seq: i64,
+    /// 读取时仍未提交的最早事务，下次从这里开始读取
+    pub xmin: i64,
+    /// 无法解密的行
+    pub errors: Vec<SyncError>,
+}
+
+// 与 query::tenant_host 和 query::credits_balance 保持一致
+const TENANT_HOST_SQL: &str = "lower(substring(tenant_url from '://([^/:?#]+)'))";
+const CREDITS_BALANCE_SQL: &str = "(CASE WHEN jsonb_typeof(portal_info->'credits_balance') = 'number' THEN (portal_info->>'credits_balance')::float8 END)";
 
 pub struct PostgreSQLStorage {
     pub db_manager: Arc<DatabaseManager>,
+    // 设置后 access_token 和 auth_session 以团队密钥加密后写入
+    secrets: Option<Arc<SecretCipher>>,
 }
 
 impl PostgreSQLStorage {
     pub fn new(db_manager: Arc<DatabaseManager>) -> Self {
-        Self { db_manager }
+        Self { db_manager, secrets: None }
+    }
+
+    /// 用已解锁的团队密钥加解密数据库中的凭据
+    pub fn with_secret_cipher(mut self, secrets: Arc<SecretCipher>) -> Self {
+        self.secrets = Some(secrets);
+        self
+    }
+
+    /// 启用团队密钥时加密凭据，返回待写入的token和 access_token 的哈希
+    fn seal_token(&self, token: &TokenData) -> StorageResult<(TokenData, Option<String>)> {
+        let Some(secrets) = &self.secrets else {
+            return Ok((token.clone(), None));
+        };
+
+        let mut sealed = token.clone();
+        sealed.access_token = secrets.encrypt(&token.access_token, &token.id, "access_token")?;
+        sealed.auth_session = token.auth_session.as_deref()
+            .map(|session| secrets.encrypt(session, &token.id, "auth_session"))
+            .transpose()?;
+        Ok((sealed, Some(secrets.keyed_hash(&token.access_token))))
+    }
+
+    /// 解密从数据库读出的凭据，未解锁时遇到加密的值返回 Locked
+    fn open_token(&self, mut token: TokenData) -> StorageResult<TokenData> {
+        match &self.secrets {
+            Some(secrets) => {
+                token.access_token = secrets.decrypt(&token.access_token, &token.id, "access_token")?;
+                token.auth_session = token.auth_session
+                    .map(|session| secrets.decrypt(&session, &token.id, "auth_session"))
+                    .transpose()?;
+            }
+            None if is_sealed_secret(&token.access_token) || token.auth_session.as_deref().is_some_and(is_sealed_secret) => {
+                return Err(StorageError::Locked("Database secrets are encrypted, team key required".to_string()));
+            }
+            None => {}
+        }
+        Ok(token)
     }
 
-    async fn get_pool(&self) -> Result<Arc<DbPool>, Box<dyn std::error::Error + Send + Sync>> {
+    fn tokens_from_rows(&self, rows: &[tokio_postgres::Row]) -> StorageResult<Vec<TokenData>> {
+        rows.iter().map(|row| self.open_token(token_from_row(row))).collect()
+    }
+
+    /// 数据库已启用团队密钥加密而本客户端未解锁时拒绝写入，避免把明文凭据写回数据库；
+    /// 持有的密钥已被轮换作废时同样拒绝，避免写入其他客户端无法解密的凭据
+    async fn ensure_can_write(&self, client: &tokio_postgres::Client) -> StorageResult<()> {
+        match (&self.secrets, self.active_secret_key_in(client).await?) {
+            (None, Some(_)) => Err(StorageError::Locked("Database secrets are encrypted, team key required".to_string())),
+            (Some(secrets), Some(active)) if active.key_id != secrets.key_id() => {
+                Err(StorageError::Locked("Team key has been rotated, unlock the current team key".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 数据库已启用团队密钥加密而本客户端未解锁时拒绝读取
+    ///
+    /// 持有已作废密钥的客户端仍可读取，无法解密的行由 `open_token` 返回 Locked。
+    async fn ensure_can_read(&self, client: &tokio_postgres::Client) -> StorageResult<()> {
+        if self.secrets.is_none() && self.active_secret_key_in(client).await?.is_some() {
+            return Err(StorageError::Locked("Database secrets are encrypted, team key required".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 所有读写都限定在这个工作区内
+    pub fn workspace(&self) -> &str {
+        self.db_manager.workspace()
+    }
+
+    async fn get_pool(&self) -> StorageResult<Arc<DbPool>> {
         self.db_manager.get_pool()
-            .ok_or_else(|| "Database not connected".into())
+            .ok_or_else(|| StorageError::Unavailable("Database not connected".to_string()))
+    }
+
+    /// 在事务内以多行UPSERT批量写入tokens
+    ///
+    /// 给出 `expected_versions` 时只更新版本仍为预期值的行，不在其中的token只能新建；
+    /// 有行因此没有写入时返回 Conflict，调用方应回滚事务。
+    async fn upsert_tokens_in(
+        &self,
+        tx: &tokio_postgres::Transaction<'_>,
+        tokens: &[TokenData],
+        expected_versions: Option<&HashMap<String, i64>>,
+    ) -> StorageResult<()> {
+        // 同一条语句中不能两次更新同一行，相同ID只保留最后一个
+        let mut positions = HashMap::new();
+        for (index, token) in tokens.iter().enumerate() {
+            positions.insert(token.id.as_str(), index);
+        }
+        let unique_tokens: Vec<(TokenData, Option<String>)> = tokens.iter()
+            .enumerate()
+            .filter(|(index, token)| positions.get(token.id.as_str()) == Some(index))
+            .map(|(_, token)| self.seal_token(token))
+            .collect::<StorageResult<_>>()?;
+        let workspace = self.workspace();
+        let expected_versions = expected_versions.map(serde_json::to_value).transpose()?;
+
+        for chunk in unique_tokens.chunks(UPSERT_BATCH_SIZE) {
+            let mut values = Vec::with_capacity(chunk.len());
+            let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(chunk.len() * TOKEN_COLUMN_COUNT);
+
+            for (row, (token, access_token_hash)) in chunk.iter().enumerate() {
+                let placeholders: Vec<String> = (1..=TOKEN_COLUMN_COUNT)
+                    .map(|column| format!("${}", row * TOKEN_COLUMN_COUNT + column))
+                    .collect();
+                values.push(format!("({})", placeholders.join(", ")));
+
+                params.push(&token.id);
+                params.push(&token.tenant_url);
+                params.push(&token.access_token);
+                params.push(&token.created_at);
+                params.push(&token.updated_at);
+                params.push(&token.portal_url);
+                params.push(&token.email_note);
+                params.push(&token.ban_status);
+                params.push(&token.portal_info);
+                params.push(&token.auth_session);
+                params.push(&token.suspensions);
+                params.push(&token.balance_color_mode);
+                params.push(&token.skip_check);
+                params.push(&token.tags);
+                params.push(&token.group);
+                params.push(access_token_hash);
+                params.push(&workspace);
+            }
+
+            // 版本条件放在 DO UPDATE 的 WHERE 中，条件不成立的行既不更新也不返回
+            let version_check = match &expected_versions {
+                Some(expected_versions) => {
+                    params.push(expected_versions);
+                    format!(
+                        "WHERE tokens.version = (${}::jsonb ->> EXCLUDED.id)::bigint RETURNING id",
+                        chunk.len() * TOKEN_COLUMN_COUNT + 1
+                    )
+                }
+                None => String::new(),
+            };
+
+            let statement = format!(
+                r#"
+                INSERT INTO tokens (id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, access_token_hash, workspace_id)
+                VALUES {}
+                ON CONFLICT (workspace_id, id) DO UPDATE SET
+                    tenant_url = EXCLUDED.tenant_url,
+                    access_token = EXCLUDED.access_token,
+                    updated_at = EXCLUDED.updated_at,
+                    portal_url = EXCLUDED.portal_url,
+                    email_note = EXCLUDED.email_note,
+                    ban_status = EXCLUDED.ban_status,
+                    portal_info = EXCLUDED.portal_info,
+                    auth_session = EXCLUDED.auth_session,
+                    suspensions = EXCLUDED.suspensions,
+                    balance_color_mode = EXCLUDED.balance_color_mode,
+                    skip_check = EXCLUDED.skip_check,
+                    tags = EXCLUDED.tags,
+                    group_name = EXCLUDED.group_name,
+                    access_token_hash = EXCLUDED.access_token_hash
+                {}
+                "#,
+                values.join(", "),
+                version_check
+            );
+
+            if expected_versions.is_none() {
+                tx.execute(statement.as_str(), &params).await?;
+                continue;
+            }
+
+            let written: std::collections::HashSet<String> = tx.query(statement.as_str(), &params).await?
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+            let conflicting: Vec<&str> = chunk.iter()
+                .map(|(token, _)| token.id.as_str())
+                .filter(|id| !written.contains(*id))
+                .collect();
+            if !conflicting.is_empty() {
+                return Err(StorageError::Conflict(format!(
+                    "Tokens modified by another client since the sync was planned: {}", conflicting.join(", ")
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 在事务内写入墓碑并删除对应的行（同时写入的tokens除外），返回删除的行数
+    async fn delete_tombstoned_in(&self, tx: &tokio_postgres::Transaction<'_>, tokens: &[TokenData], tombstones: &[Tombstone]) -> StorageResult<usize> {
+        self.upsert_tombstones_in(tx, tombstones).await?;
+
+        let deleted_ids: Vec<&str> = tombstones.iter()
+            .map(|t| t.token_id.as_str())
+            .filter(|id| !tokens.iter().any(|token| token.id == *id))
+            .collect();
+        if deleted_ids.is_empty() {
+            return Ok(0);
+        }
+        let deleted = tx.execute("DELETE FROM tokens WHERE workspace_id = $1 AND id = ANY($2)", &[&self.workspace(), &deleted_ids]).await?;
+        Ok(deleted as usize)
+    }
+
+    /// 在事务内写入墓碑，已有记录只会被更晚的删除时间覆盖
+    async fn upsert_tombstones_in(&self, tx: &tokio_postgres::Transaction<'_>, tombstones: &[Tombstone]) -> StorageResult<()> {
+        let statement = tx.prepare(
+            r#"
+            INSERT INTO token_tombstones (token_id, deleted_at, origin, workspace_id)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (workspace_id, token_id) DO UPDATE SET
+                deleted_at = EXCLUDED.deleted_at,
+                origin = EXCLUDED.origin
+            WHERE token_tombstones.deleted_at < EXCLUDED.deleted_at
+            "#,
+        ).await?;
+
+        for tombstone in tombstones {
+            tx.execute(&statement, &[&tombstone.token_id, &tombstone.deleted_at, &tombstone.origin, &self.workspace()]).await?;
+        }
+
+        Ok(())
     }
 }
 
 #[async_trait::async_trait]
 impl TokenStorage for PostgreSQLStorage {
-    async fn save_token(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    async fn save_token(&self, token: &TokenData) -> StorageResult<()> {
         let pool = self.get_pool().await?;
         let client = pool.get().await?;
+        self.ensure_can_write(&client).await?;
+        let (token, access_token_hash) = self.seal_token(token)?;
 
         // 使用UPSERT (INSERT ... ON CONFLICT)
         client.execute(
             r#"
-            INSERT INTO tokens (id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
-            ON CONFLICT (id) DO UPDATE SET
+            INSERT INTO tokens (id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, access_token_hash, workspace_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+            ON CONFLICT (workspace_id, id) DO UPDATE SET
                 tenant_url = EXCLUDED.tenant_url,
                 access_token = EXCLUDED.access_token,
                 updated_at = EXCLUDED.updated_at,
@@ -40,7 +285,10 @@ impl TokenStorage for PostgreSQLStorage {
                 auth_session = EXCLUDED.auth_session,
                 suspensions = EXCLUDED.suspensions,
                 balance_color_mode = EXCLUDED.balance_color_mode,
-                skip_check = EXCLUDED.skip_check
+                skip_check = EXCLUDED.skip_check,
+                tags = EXCLUDED.tags,
+                group_name = EXCLUDED.group_name,
+                access_token_hash = EXCLUDED.access_token_hash
             "#,
             &[
                 &token.id,
@@ -56,50 +304,402 @@ impl TokenStorage for PostgreSQLStorage {
                 &token.suspensions,
                 &token.balance_color_mode,
                 &token.skip_check,
+                &token.tags,
+                &token.group,
+                &access_token_hash,
+                &self.workspace(),
             ],
         ).await?;
 
         Ok(())
     }
 
-    async fn load_tokens(&self) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
+    async fn load_tokens(&self) -> StorageResult<Vec<TokenData>> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        let rows = client.query(
+            "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version FROM tokens WHERE workspace_id = $1 ORDER BY created_at DESC",
+            &[&self.workspace()],
+        ).await?;
+
+        self.tokens_from_rows(&rows)
+    }
+
+    /// 按版本号更新，数据库中的行已被其他客户端修改时返回 Conflict
+    async fn update_token(&self, token: &TokenData) -> StorageResult<()> {
+        self.compare_and_swap(token).await.map(|_| ())
+    }
+
+    async fn delete_token(&self, token_id: &str) -> StorageResult<bool> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        let rows_affected = client.execute(
+            "DELETE FROM tokens WHERE workspace_id = $1 AND id = $2",
+            &[&self.workspace(), &token_id],
+        ).await?;
+
+        Ok(rows_affected > 0)
+    }
+
+    async fn get_token(&self, token_id: &str) -> StorageResult<Option<TokenData>> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        let rows = client.query(
+            "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version FROM tokens WHERE workspace_id = $1 AND id = $2",
+            &[&self.workspace(), &token_id],
+        ).await?;
+
+        if let Some(row) = rows.first() {
+            self.open_token(token_from_row(row)).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn clear_all_tokens(&self) -> StorageResult<()> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        client.execute("DELETE FROM tokens WHERE workspace_id = $1", &[&self.workspace()]).await?;
+        Ok(())
+    }
+
+    async fn save_tokens(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        let pool = self.get_pool().await?;
+        let mut client = pool.get().await?;
+        self.ensure_can_write(&client).await?;
+
+        let tx = client.transaction().await?;
+        self.upsert_tokens_in(&tx, tokens, None).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn delete_tokens(&self, token_ids: &[String]) -> StorageResult<usize> {
+        if token_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        let rows_affected = client.execute(
+            "DELETE FROM tokens WHERE workspace_id = $1 AND id = ANY($2)",
+            &[&self.workspace(), &token_ids],
+        ).await?;
+
+        Ok(rows_affected as usize)
+    }
+
+    async fn query_tokens(&self, filter: &TokenFilter, sort: &TokenSort, page: &PageRequest) -> StorageResult<TokenPage> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        let mut conditions: Vec<String> = Vec::new();
+        let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::new();
+        // 条件中的 $? 替换为参数序号
+        let mut push = |condition: &str, param: Box<dyn ToSql + Sync + Send>| {
+            params.push(param);
+            conditions.push(condition.replace("$?", &format!("${}", params.len())));
+        };
+
+        push("workspace_id = $?", Box::new(self.workspace().to_string()));
+        if let Some(host) = &filter.tenant_host {
+            let host = host.to_lowercase();
+            push(&format!("({0} = $? OR starts_with({0}, $? || '.'))", TENANT_HOST_SQL), Box::new(host));
+        }
+        if let Some(status) = &filter.ban_status {
+            push("(jsonb_typeof(ban_status) = 'string' AND ban_status #>> '{}' = $?)", Box::new(status.clone()));
+        }
+        if let Some(text) = &filter.email_contains {
+            push("strpos(lower(email_note), lower($?)) > 0", Box::new(text.clone()));
+        }
+        if let Some(skip_check) = filter.skip_check {
+            push("COALESCE(skip_check, false) = $?", Box::new(skip_check));
+        }
+        if let Some(after) = filter.created_after {
+            push("created_at >= $?", Box::new(after));
+        }
+        if let Some(before) = filter.created_before {
+            push("created_at < $?", Box::new(before));
+        }
+        if let Some(after) = filter.updated_after {
+            push("updated_at >= $?", Box::new(after));
+        }
+        if let Some(before) = filter.updated_before {
+            push("updated_at < $?", Box::new(before));
+        }
+        if let Some(min) = filter.min_balance {
+            push(&format!("{} >= $?", CREDITS_BALANCE_SQL), Box::new(min));
+        }
+        if let Some(max) = filter.max_balance {
+            push(&format!("{} <= $?", CREDITS_BALANCE_SQL), Box::new(max));
+        }
+        if !filter.tags.is_empty() {
+            push("tags @> $?", Box::new(filter.tags.clone()));
+        }
+        if let Some(group) = &filter.group {
+            push("group_name = $?", Box::new(group.clone()));
+        }
+
+        let where_clause = format!("WHERE {}", conditions.join(" AND "));
+        let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|param| param.as_ref() as &(dyn ToSql + Sync)).collect();
+
+        let total: i64 = client.query_one(
+            &format!("SELECT COUNT(*) FROM tokens {}", where_clause),
+            &params,
+        ).await?.get(0);
+
+        let sort_expr = match sort.field {
+            TokenSortField::CreatedAt => "created_at".to_string(),
+            TokenSortField::UpdatedAt => "updated_at".to_string(),
+            TokenSortField::EmailNote => "lower(email_note) COLLATE \"C\"".to_string(),
+            TokenSortField::Balance => CREDITS_BALANCE_SQL.to_string(),
+        };
+        let direction = if sort.descending { "DESC" } else { "ASC" };
+        let limit = page.limit.map(|limit| format!("LIMIT {}", limit)).unwrap_or_default();
+
+        let rows = client.query(
+            &format!(
+                "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version FROM tokens {} ORDER BY {} {} NULLS LAST, id COLLATE \"C\" {} OFFSET {}",
+                where_clause, sort_expr, direction, limit, page.offset
+            ),
+            &params,
+        ).await?;
+
+        Ok(TokenPage {
+            tokens: self.tokens_from_rows(&rows)?,
+            total: total as usize,
+        })
+    }
+
+    async fn replace_all(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        let pool = self.get_pool().await?;
+        let mut client = pool.get().await?;
+        self.ensure_can_write(&client).await?;
+
+        // 清空和写入在同一事务中完成，失败时整张表保持原样
+        let tx = client.transaction().await?;
+        tx.execute("DELETE FROM tokens WHERE workspace_id = $1", &[&self.workspace()]).await?;
+        self.upsert_tokens_in(&tx, tokens, None).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn load_tombstones(&self) -> StorageResult<Vec<Tombstone>> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        let rows = client.query(
+            "SELECT token_id, deleted_at, origin FROM token_tombstones WHERE workspace_id = $1",
+            &[&self.workspace()],
+        ).await?;
+
+        Ok(rows.iter()
+            .map(|row| Tombstone {
+                token_id: row.get(0),
+                deleted_at: row.get(1),
+                origin: row.get(2),
+            })
+            .collect())
+    }
+
+    async fn save_tombstones(&self, tombstones: &[Tombstone]) -> StorageResult<()> {
+        if tombstones.is_empty() {
+            return Ok(());
+        }
+
+        let pool = self.get_pool().await?;
+        let mut client = pool.get().await?;
+
+        let tx = client.transaction().await?;
+        self.upsert_tombstones_in(&tx, tombstones).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn purge_tombstones(&self, older_than: DateTime<Utc>) -> StorageResult<usize> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        let rows_affected = client.execute(
+            "DELETE FROM token_tombstones WHERE workspace_id = $1 AND deleted_at < $2",
+            &[&self.workspace(), &older_than],
+        ).await?;
+
+        Ok(rows_affected as usize)
+    }
+
+    /// 在一个事务中完成墓碑写入、删除和tokens写入
+    async fn apply_changes(&self, tokens: &[TokenData], tombstones: &[Tombstone]) -> StorageResult<usize> {
+        let pool = self.get_pool().await?;
+        let mut client = pool.get().await?;
+        if !tokens.is_empty() {
+            self.ensure_can_write(&client).await?;
+        }
+
+        let tx = client.transaction().await?;
+        let deleted = self.delete_tombstoned_in(&tx, tokens, tombstones).await?;
+        self.upsert_tokens_in(&tx, tokens, None).await?;
+        tx.commit().await?;
+
+        Ok(deleted)
+    }
+
+    fn storage_type(&self) -> &'static str {
+        "postgresql"
+    }
+
+    async fn is_available(&self) -> bool {
+        self.db_manager.is_connected() && self.db_manager.test_connection().await.is_ok()
+    }
+}
+
+impl PostgreSQLStorage {
+    /// 同步写入：与 `apply_changes` 相同，但tokens只在版本仍为 `expected_versions` 中的值时更新
+    ///
+    /// 计划之后有其他客户端修改了其中的行时整个事务回滚并返回 Conflict，由调用方重新计划。
+    pub async fn apply_sync_changes(
+        &self,
+        tokens: &[TokenData],
+        expected_versions: &HashMap<String, i64>,
+        tombstones: &[Tombstone],
+    ) -> StorageResult<usize> {
+        let pool = self.get_pool().await?;
+        let mut client = pool.get().await?;
+        if !tokens.is_empty() {
+            self.ensure_can_write(&client).await?;
+        }
+
+        let tx = client.transaction().await?;
+        let deleted = self.delete_tombstoned_in(&tx, tokens, tombstones).await?;
+        self.upsert_tokens_in(&tx, tokens, Some(expected_versions)).await?;
+        tx.commit().await?;
+
+        Ok(deleted)
+    }
+
+    /// 数据库中指定tokens的当前版本，不存在的ID不会出现在结果中
+    pub async fn load_token_versions(&self, token_ids: &[String]) -> StorageResult<HashMap<String, i64>> {
+        if token_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+        let rows = client.query(
+            "SELECT id, version FROM tokens WHERE workspace_id = $1 AND id = ANY($2)",
+            &[&self.workspace(), &token_ids],
+        ).await?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    /// 读取水位线之后写入的tokens，没有水位线（或水位线来自旧版本）时读取全部
+    ///
+    /// change_seq 在写入时分配，晚提交的事务可能持有更小的序号，因此按写入事务ID过滤：
+    /// 从上次读取时仍未提交的最早事务开始重新读取，已读过的行可能再次返回。
+    /// 单行解密失败不影响其他行，由调用方记为该token的同步错误；未解锁团队密钥时直接返回 Locked。
+    pub async fn load_tokens_changed_since(&self, since: Option<&SyncWatermark>) -> StorageResult<RemoteChanges> {
         let pool = self.get_pool().await?;
         let client = pool.get().await?;
+        self.ensure_can_read(&client).await?;
 
+        // 先取快照的 xmin 再读取：读取时看不到的事务ID都不小于它
+        let xmin: i64 = client.query_one("SELECT pg_snapshot_xmin(pg_current_snapshot())::text::bigint", &[]).await?.get(0);
+        let since_xmin = since.and_then(|watermark| watermark.remote_xmin).unwrap_or(0);
         let rows = client.query(
-            "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check FROM tokens ORDER BY created_at DESC",
-            &[],
+            "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version, change_seq FROM tokens WHERE workspace_id = $1 AND change_xid >= $2::text::xid8 ORDER BY change_seq",
+            &[&self.workspace(), &since_xmin.to_string()],
         ).await?;
 
-        let mut tokens = Vec::new();
+        let mut max_change_seq = since.map_or(0, |watermark| watermark.remote_change_seq);
+        let mut tokens = Vec::with_capacity(rows.len());
+        let mut errors = Vec::new();
         for row in rows {
-            let token = TokenData {
-                id: row.get(0),
-                tenant_url: row.get(1),
-                access_token: row.get(2),
-                created_at: row.get(3),
-                updated_at: row.get(4),
-                portal_url: row.get(5),
-                email_note: row.get(6),
-                ban_status: row.get(7),
-                portal_info: row.get(8),
-                auth_session: row.get(9),
-                suspensions: row.get(10),
-                balance_color_mode: row.get(11),
-                skip_check: row.get(12),
-            };
-            tokens.push(token);
+            max_change_seq = max_change_seq.max(row.get(16));
+            let token = token_from_row(&row);
+            let token_id = token.id.clone();
+            match self.open_token(token) {
+                Ok(token) => tokens.push(token),
+                Err(e) => errors.push(SyncError::new(Some(token_id), &e)),
+            }
         }
 
-        Ok(tokens)
+        Ok(RemoteChanges { tokens, change_seq: max_change_seq, xmin, errors })
     }
 
-    async fn update_token(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// 按ID批量读取tokens，不存在的ID会被忽略
+    pub async fn load_tokens_by_ids(&self, token_ids: &[String]) -> StorageResult<Vec<TokenData>> {
+        if token_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
         let pool = self.get_pool().await?;
         let client = pool.get().await?;
 
+        let rows = client.query(
+            "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version FROM tokens WHERE workspace_id = $1 AND id = ANY($2)",
+            &[&self.workspace(), &token_ids],
+        ).await?;
+
+        self.tokens_from_rows(&rows)
+    }
+
+    /// 返回数据库和工作区的唯一标识，首次调用时生成；切换工作区后标识随之改变
+    pub async fn database_id(&self) -> StorageResult<String> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        client.execute(
+            "INSERT INTO sync_metadata (key, value) VALUES ('database_id', $1) ON CONFLICT (key) DO NOTHING",
+            &[&uuid::Uuid::new_v4().to_string()],
+        ).await?;
+
+        let row = client.query_one("SELECT value FROM sync_metadata WHERE key = 'database_id'", &[]).await?;
+        Ok(format!("{}/{}", row.get::<_, String>(0), self.workspace()))
+    }
+
+    /// 查找具有相同tenant_url和access_token但不同ID的token
+    pub async fn find_duplicate_tokens(&self, tenant_url: &str, access_token: &str, exclude_token_id: &str) -> StorageResult<Vec<TokenData>> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+
+        // 加密后密文每次都不同，按带密钥的哈希比较
+        let rows = match &self.secrets {
+            Some(secrets) => client.query(
+                "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version FROM tokens WHERE workspace_id = $1 AND tenant_url = $2 AND access_token_hash = $3 AND id != $4",
+                &[&self.workspace(), &tenant_url, &secrets.keyed_hash(access_token), &exclude_token_id],
+            ).await?,
+            None => client.query(
+                "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version FROM tokens WHERE workspace_id = $1 AND tenant_url = $2 AND access_token = $3 AND id != $4",
+                &[&self.workspace(), &tenant_url, &access_token, &exclude_token_id],
+            ).await?,
+        };
+
+        self.tokens_from_rows(&rows)
+    }
+
+    /// 仅当数据库中的版本仍为 `token.version` 时更新，返回更新后的token（含新的版本号）
+    ///
+    /// 版本不一致说明读取之后其他客户端已经修改过这一行，返回 Conflict，调用方应重新读取后再修改。
+    pub async fn compare_and_swap(&self, token: &TokenData) -> StorageResult<TokenData> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+        self.ensure_can_write(&client).await?;
+        let (sealed, access_token_hash) = self.seal_token(token)?;
+
         let updated_at = Utc::now();
-        let rows_affected = client.execute(
+        let row = client.query_opt(
             r#"
             UPDATE tokens SET
                 tenant_url = $2,
@@ -112,170 +712,431 @@ impl TokenStorage for PostgreSQLStorage {
                 auth_session = $9,
                 suspensions = $10,
                 balance_color_mode = $11,
-                skip_check = $12
-            WHERE id = $1
+                skip_check = $12,
+                tags = $13,
+                group_name = $14,
+                access_token_hash = $16
+            WHERE id = $1 AND version = $15 AND workspace_id = $17
+            RETURNING id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version
             "#,
             &[
-                &token.id,
-                &token.tenant_url,
-                &token.access_token,
+                &sealed.id,
+                &sealed.tenant_url,
+                &sealed.access_token,
                 &updated_at,
-                &token.portal_url,
-                &token.email_note,
-                &token.ban_status,
-                &token.portal_info,
-                &token.auth_session,
-                &token.suspensions,
-                &token.balance_color_mode,
-                &token.skip_check,
+                &sealed.portal_url,
+                &sealed.email_note,
+                &sealed.ban_status,
+                &sealed.portal_info,
+                &sealed.auth_session,
+                &sealed.suspensions,
+                &sealed.balance_color_mode,
+                &sealed.skip_check,
+                &sealed.tags,
+                &sealed.group,
+                &sealed.version,
+                &access_token_hash,
+                &self.workspace(),
             ],
         ).await?;
 
-        if rows_affected == 0 {
-            return Err("Token not found for update".into());
+        if let Some(row) = row {
+            return self.open_token(token_from_row(&row));
         }
 
-        Ok(())
+        let current = client.query_opt(
+            "SELECT version FROM tokens WHERE workspace_id = $1 AND id = $2",
+            &[&self.workspace(), &token.id],
+        ).await?;
+        match current {
+            Some(row) => Err(StorageError::Conflict(format!(
+                "Token {} was modified by another client (expected version {}, current version {})",
+                token.id, token.version, row.get::<_, i64>(0)
+            ))),
+            None => Err(StorageError::NotFound(format!("Token {} not found for update", token.id))),
+        }
     }
 
-    async fn delete_token(&self, token_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    /// 在一个事务中按各token自身的版本号批量更新，返回更新后的tokens（含新的版本号）
+    ///
+    /// 数据库中还没有的token直接新建；任一行版本不一致时整批回滚并返回 Conflict。
+    pub async fn compare_and_swap_many(&self, tokens: &[TokenData]) -> StorageResult<Vec<TokenData>> {
+        if tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+
         let pool = self.get_pool().await?;
-        let client = pool.get().await?;
+        let mut client = pool.get().await?;
+        self.ensure_can_write(&client).await?;
 
-        let rows_affected = client.execute(
-            "DELETE FROM tokens WHERE id = $1",
-            &[&token_id],
+        let expected_versions: HashMap<String, i64> = tokens.iter()
+            .map(|token| (token.id.clone(), token.version))
+            .collect();
+        let token_ids: Vec<String> = expected_versions.keys().cloned().collect();
+
+        let tx = client.transaction().await?;
+        self.upsert_tokens_in(&tx, tokens, Some(&expected_versions)).await?;
+        let rows = tx.query(
+            "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version FROM tokens WHERE workspace_id = $1 AND id = ANY($2)",
+            &[&self.workspace(), &token_ids],
         ).await?;
+        tx.commit().await?;
 
-        Ok(rows_affected > 0)
+        self.tokens_from_rows(&rows)
     }
 
-    async fn get_token(&self, token_id: &str) -> Result<Option<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
+    /// 数据库是否已启用团队密钥加密
+    pub async fn secret_encryption_enabled(&self) -> StorageResult<bool> {
+        Ok(self.active_secret_key().await?.is_some())
+    }
+
+    /// 数据库当前使用的团队密钥信息，未启用加密时返回 None
+    pub async fn active_secret_key(&self) -> StorageResult<Option<SecretKeyInfo>> {
         let pool = self.get_pool().await?;
         let client = pool.get().await?;
+        self.active_secret_key_in(&client).await
+    }
 
-        let rows = client.query(
-            "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check FROM tokens WHERE id = $1",
-            &[&token_id],
+    async fn active_secret_key_in(&self, client: &tokio_postgres::Client) -> StorageResult<Option<SecretKeyInfo>> {
+        let row = client.query_opt(
+            "SELECT key_id, kdf, check_value FROM secret_keys WHERE workspace_id = $1 AND retired_at IS NULL ORDER BY created_at DESC LIMIT 1",
+            &[&self.workspace()],
         ).await?;
 
-        if let Some(row) = rows.first() {
-            let token = TokenData {
-                id: row.get(0),
-                tenant_url: row.get(1),
-                access_token: row.get(2),
-                created_at: row.get(3),
-                updated_at: row.get(4),
-                portal_url: row.get(5),
-                email_note: row.get(6),
-                ban_status: row.get(7),
-                portal_info: row.get(8),
-                auth_session: row.get(9),
-                suspensions: row.get(10),
-                balance_color_mode: row.get(11),
-                skip_check: row.get(12),
-            };
-            Ok(Some(token))
-        } else {
-            Ok(None)
-        }
+        row.map(|row| Ok(SecretKeyInfo {
+            key_id: row.get(0),
+            kdf: serde_json::from_value(row.get(1))?,
+            check_value: row.get(2),
+        })).transpose()
     }
 
-    async fn clear_all_tokens(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// 用口令解锁数据库当前的团队密钥
+    pub async fn unlock_secrets(&self, passphrase: &str) -> StorageResult<SecretCipher> {
         let pool = self.get_pool().await?;
         let client = pool.get().await?;
 
-        client.execute("DELETE FROM tokens", &[]).await?;
-        Ok(())
+        let info = self.active_secret_key_in(&client).await?
+            .ok_or_else(|| StorageError::NotFound("Database secret encryption is not enabled".to_string()))?;
+        SecretCipher::unlock(&info, passphrase)
     }
 
-    fn storage_type(&self) -> &'static str {
-        "postgresql"
+    /// 启用团队密钥加密并加密已有的全部凭据，返回新的密钥
+    pub async fn enable_secret_encryption(&self, passphrase: &str) -> StorageResult<SecretCipher> {
+        let pool = self.get_pool().await?;
+        let client = pool.get().await?;
+        if self.active_secret_key_in(&client).await?.is_some() {
+            return Err(StorageError::Conflict("Database secret encryption is already enabled".to_string()));
+        }
+        drop(client);
+
+        self.switch_secret_key(None, passphrase).await
     }
 
-    async fn is_available(&self) -> bool {
-        self.db_manager.is_connected() && self.db_manager.test_connection().await.is_ok()
+    /// 生成新的团队密钥并用它重新加密全部凭据，旧密钥随之作废；返回新的密钥
+    ///
+    /// 其他客户端需要用新口令重新解锁。
+    pub async fn rotate_secret_key(&self, new_passphrase: &str) -> StorageResult<SecretCipher> {
+        let current = self.secrets.as_deref()
+            .ok_or_else(|| StorageError::Locked("Unlock the current team key before rotating it".to_string()))?;
+        self.switch_secret_key(Some(current), new_passphrase).await
     }
-}
 
-impl PostgreSQLStorage {
-    /// 查找具有相同tenant_url和access_token但不同ID的token
-    pub async fn find_duplicate_tokens(&self, tenant_url: &str, access_token: &str, exclude_token_id: &str) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>> {
+    // 在一个事务中登记新密钥、重新加密每一行并作废旧密钥
+    async fn switch_secret_key(&self, current: Option<&SecretCipher>, passphrase: &str) -> StorageResult<SecretCipher> {
+        let (next, info) = SecretCipher::generate(passphrase)?;
+
+        let pool = self.get_pool().await?;
+        let mut client = pool.get().await?;
+        let tx = client.transaction().await?;
+
+        tx.execute(
+            "INSERT INTO secret_keys (key_id, kdf, check_value, workspace_id) VALUES ($1, $2, $3, $4)",
+            &[&info.key_id, &serde_json::to_value(&info.kdf)?, &info.check_value, &self.workspace()],
+        ).await?;
+
+        let rows = tx.query(
+            "SELECT id, access_token, auth_session FROM tokens WHERE workspace_id = $1 FOR UPDATE",
+            &[&self.workspace()],
+        ).await?;
+        let statement = tx.prepare(
+            "UPDATE tokens SET access_token = $2, auth_session = $3, access_token_hash = $4 WHERE id = $1 AND workspace_id = $5"
+        ).await?;
+        for row in rows {
+            let id: String = row.get(0);
+            let access_token: String = row.get(1);
+            let auth_session: Option<String> = row.get(2);
+
+            // 未启用加密时只有明文；用未知密钥加密的值会返回 Locked，整个事务回滚
+            let open = |value: &str, column: &str| match current {
+                Some(current) => current.decrypt(value, &id, column),
+                None if is_sealed_secret(value) => Err(StorageError::Locked(format!("Token {} is encrypted with an unknown team key", id))),
+                None => Ok(value.to_string()),
+            };
+            let access_token = open(&access_token, "access_token")?;
+            let auth_session = auth_session.as_deref().map(|session| open(session, "auth_session")).transpose()?;
+
+            tx.execute(&statement, &[
+                &id,
+                &next.encrypt(&access_token, &id, "access_token")?,
+                &auth_session.as_deref().map(|session| next.encrypt(session, &id, "auth_session")).transpose()?,
+                &next.keyed_hash(&access_token),
+                &self.workspace(),
+            ]).await?;
+        }
+
+        tx.execute(
+            "UPDATE secret_keys SET retired_at = NOW() WHERE workspace_id = $1 AND key_id != $2 AND retired_at IS NULL",
+            &[&self.workspace(), &info.key_id],
+        ).await?;
+        tx.commit().await?;
+
+        Ok(next)
+    }
+
+    /// 写入token修改历史，已存在的事件会被忽略，重放时可以重复写入
+    pub async fn append_events(&self, events: &[TokenEvent]) -> StorageResult<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let pool = self.get_pool().await?;
+        let mut client = pool.get().await?;
+
+        let tx = client.transaction().await?;
+        let statement = tx.prepare(
+            "INSERT INTO token_events (id, token_id, kind, changes, device_id, occurred_at, workspace_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (id) DO NOTHING"
+        ).await?;
+        for event in events {
+            tx.execute(&statement, &[
+                &event.id,
+                &event.token_id,
+                &event.kind.as_str(),
+                &serde_json::to_value(&event.changes)?,
+                &event.device_id,
+                &event.occurred_at,
+                &self.workspace(),
+            ]).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// 按发生时间读取一个token的修改历史
+    pub async fn load_events(&self, token_id: &str) -> StorageResult<Vec<TokenEvent>> {
         let pool = self.get_pool().await?;
         let client = pool.get().await?;
 
         let rows = client.query(
-            "SELECT id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check FROM tokens WHERE tenant_url = $1 AND access_token = $2 AND id != $3",
-            &[&tenant_url, &access_token, &exclude_token_id],
+            "SELECT id, token_id, kind, changes, device_id, occurred_at FROM token_events WHERE workspace_id = $1 AND token_id = $2 ORDER BY occurred_at, id",
+            &[&self.workspace(), &token_id],
         ).await?;
 
-        let mut tokens = Vec::new();
+        let mut events = Vec::with_capacity(rows.len());
         for row in rows {
-            let token = TokenData {
+            let kind: String = row.get(2);
+            events.push(TokenEvent {
                 id: row.get(0),
-                tenant_url: row.get(1),
-                access_token: row.get(2),
-                created_at: row.get(3),
-                updated_at: row.get(4),
-                portal_url: row.get(5),
-                email_note: row.get(6),
-                ban_status: row.get(7),
-                portal_info: row.get(8),
-                auth_session: row.get(9),
-                suspensions: row.get(10),
-                balance_color_mode: row.get(11),
-                skip_check: row.get(12),
-            };
-            tokens.push(token);
+                token_id: row.get(1),
+                kind: TokenEventKind::parse(&kind)
+                    .ok_or_else(|| StorageError::Corrupt(format!("Unknown token event kind: {}", kind)))?,
+                changes: serde_json::from_value(row.get(3))?,
+                device_id: row.get(4),
+                occurred_at: row.get(5),
+            });
         }
 
-        Ok(tokens)
+        Ok(events)
     }
 }
 
-// 辅助函数：记录同步状态
+// 辅助函数：记录同步状态，每条错误单独一行，返回记录的ID
 pub async fn record_sync_status(
     pool: &DbPool,
-    sync_direction: &str,
-    status: &str,
-    error_message: Option<&str>,
-    tokens_synced: i32,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let client = pool.get().await?;
-    
-    client.execute(
+    workspace: &str,
+    sync_status: &SyncStatus,
+) -> StorageResult<i32> {
+    let mut client = pool.get().await?;
+    let conflicts = serde_json::to_value(&sync_status.conflicts)?;
+
+    let tx = client.transaction().await?;
+    let sync_id: i32 = tx.query_one(
         r#"
-        INSERT INTO sync_status (last_sync_at, sync_direction, status, error_message, tokens_synced)
-        VALUES (NOW(), $1, $2, $3, $4)
+        INSERT INTO sync_status (last_sync_at, sync_direction, status, error_message, tokens_synced, conflicts, workspace_id)
+        VALUES (COALESCE($1, NOW()), $2, $3, $4, $5, $6, $7)
+        RETURNING id
         "#,
-        &[&sync_direction, &status, &error_message, &tokens_synced],
-    ).await?;
+        &[
+            &sync_status.last_sync_at,
+            &sync_status.sync_direction,
+            &sync_status.status,
+            &sync_status.error_message,
+            &sync_status.tokens_synced,
+            &conflicts,
+            &workspace,
+        ],
+    ).await?.get(0);
 
-    Ok(())
+    if !sync_status.errors.is_empty() {
+        let statement = tx.prepare(
+            "INSERT INTO sync_errors (sync_id, workspace_id, token_id, code, message) VALUES ($1, $2, $3, $4, $5)"
+        ).await?;
+        for error in &sync_status.errors {
+            tx.execute(&statement, &[&sync_id, &workspace, &error.token_id, &error.code, &error.message]).await?;
+        }
+    }
+    tx.commit().await?;
+
+    Ok(sync_id)
 }
 
 // 辅助函数：获取最新的同步状态
 pub async fn get_latest_sync_status(
     pool: &DbPool,
-) -> Result<Option<super::traits::SyncStatus>, Box<dyn std::error::Error + Send + Sync>> {
+    workspace: &str,
+) -> StorageResult<Option<SyncStatus>> {
+    let page = PageRequest { offset: 0, limit: Some(1) };
+    Ok(load_sync_history(pool, workspace, &page).await?.entries.into_iter().next())
+}
+
+// 辅助函数：按时间倒序分页读取同步历史及每次同步的错误
+pub async fn load_sync_history(
+    pool: &DbPool,
+    workspace: &str,
+    page: &PageRequest,
+) -> StorageResult<SyncHistoryPage> {
     let client = pool.get().await?;
-    
+
+    let total: i64 = client.query_one(
+        "SELECT COUNT(*) FROM sync_status WHERE workspace_id = $1",
+        &[&workspace],
+    ).await?.get(0);
+
+    // LIMIT NULL 表示不限制
+    let limit = page.limit.map(|limit| limit as i64);
     let rows = client.query(
-        "SELECT last_sync_at, sync_direction, status, error_message, tokens_synced FROM sync_status ORDER BY created_at DESC LIMIT 1",
-        &[],
+        "SELECT id, last_sync_at, sync_direction, status, error_message, tokens_synced, conflicts FROM sync_status WHERE workspace_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3",
+        &[&workspace, &limit, &(page.offset as i64)],
     ).await?;
 
-    if let Some(row) = rows.first() {
-        let sync_status = super::traits::SyncStatus {
-            last_sync_at: row.get(0),
-            sync_direction: row.get(1),
-            status: row.get(2),
-            error_message: row.get(3),
-            tokens_synced: row.get(4),
-        };
-        Ok(Some(sync_status))
-    } else {
-        Ok(None)
+    let mut entries: Vec<SyncStatus> = rows.iter()
+        .map(|row| SyncStatus {
+            id: Some(row.get(0)),
+            last_sync_at: row.get(1),
+            sync_direction: row.get::<_, Option<String>>(2).unwrap_or_default(),
+            status: row.get::<_, Option<String>>(3).unwrap_or_default(),
+            error_message: row.get(4),
+            tokens_synced: row.get::<_, Option<i32>>(5).unwrap_or_default(),
+            conflicts: row.get::<_, Option<serde_json::Value>>(6)
+                .and_then(|value| serde_json::from_value(value).ok())
+                .unwrap_or_default(),
+            errors: Vec::new(),
+        })
+        .collect();
+
+    let sync_ids: Vec<i32> = entries.iter().filter_map(|entry| entry.id).collect();
+    if !sync_ids.is_empty() {
+        let error_rows = client.query(
+            "SELECT sync_id, token_id, code, message FROM sync_errors WHERE sync_id = ANY($1) ORDER BY id",
+            &[&sync_ids],
+        ).await?;
+
+        let mut errors: HashMap<i32, Vec<SyncError>> = HashMap::new();
+        for row in error_rows {
+            errors.entry(row.get(0)).or_default().push(SyncError {
+                token_id: row.get(1),
+                code: row.get(2),
+                message: row.get(3),
+            });
+        }
+        for entry in &mut entries {
+            if let Some(entry_errors) = entry.id.and_then(|id| errors.remove(&id)) {
+                entry.errors = entry_errors;
+            }
+        }
+    }
+
+    Ok(SyncHistoryPage {
+        entries,
+        total: total as usize,
+    })
+}
+
+// 辅助函数：按同步方向统计同步次数、失败率和平均写入的token数，`since` 为空时统计全部记录
+pub async fn load_sync_stats(
+    pool: &DbPool,
+    workspace: &str,
+    since: Option<DateTime<Utc>>,
+) -> StorageResult<Vec<SyncDirectionStats>> {
+    let client = pool.get().await?;
+
+    let rows = client.query(
+        r#"
+        SELECT
+            COALESCE(sync_direction, ''),
+            COUNT(*),
+            COUNT(*) FILTER (WHERE status = 'failed'),
+            COALESCE(AVG(tokens_synced) FILTER (WHERE status <> 'failed'), 0)::FLOAT8
+        FROM sync_status
+        WHERE workspace_id = $1 AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        &[&workspace, &since],
+    ).await?;
+
+    Ok(rows.iter()
+        .map(|row| {
+            let sync_count: i64 = row.get(1);
+            let failure_count: i64 = row.get(2);
+            SyncDirectionStats {
+                sync_direction: row.get(0),
+                sync_count,
+                failure_count,
+                failure_rate: if sync_count > 0 { failure_count as f64 / sync_count as f64 } else { 0.0 },
+                average_tokens_synced: row.get(3),
+            }
+        })
+        .collect())
+}
+
+// 辅助函数：删除早于给定时间的同步记录（错误记录随之删除），返回删除的数量
+pub async fn prune_sync_history(
+    pool: &DbPool,
+    workspace: &str,
+    older_than: DateTime<Utc>,
+) -> StorageResult<usize> {
+    let client = pool.get().await?;
+
+    let rows_affected = client.execute(
+        "DELETE FROM sync_status WHERE workspace_id = $1 AND created_at < $2",
+        &[&workspace, &older_than],
+    ).await?;
+
+    Ok(rows_affected as usize)
+}
+
+// 列顺序与查询中的 SELECT id, tenant_url, ... group_name, version 一致
+fn token_from_row(row: &tokio_postgres::Row) -> TokenData {
+    TokenData {
+        id: row.get(0),
+        tenant_url: row.get(1),
+        access_token: row.get(2),
+        created_at: row.get(3),
+        updated_at: row.get(4),
+        portal_url: row.get(5),
+        email_note: row.get(6),
+        ban_status: row.get(7),
+        portal_info: row.get(8),
+        auth_session: row.get(9),
+        suspensions: row.get(10),
+        balance_color_mode: row.get(11),
+        skip_check: row.get(12),
+        tags: row.get(13),
+        group: row.get(14),
+        version: row.get(15),
     }
 }
 
@@ -285,6 +1146,10 @@ mod tests {
     use crate::database::{DatabaseConfig, DatabaseManager};
 
     async fn create_test_storage() -> Option<PostgreSQLStorage> {
+        create_workspace_storage(crate::database::DEFAULT_WORKSPACE).await
+    }
+
+    async fn create_workspace_storage(workspace: &str) -> Option<PostgreSQLStorage> {
         // 这需要一个真实的测试数据库连接
         // 在实际测试中，你需要设置测试数据库
         let config = DatabaseConfig::new(
@@ -293,7 +1158,7 @@ mod tests {
             "test_augment_tokens".to_string(),
             "postgres".to_string(),
             "password".to_string(),
-        );
+        ).with_workspace(workspace.to_string());
 
         let mut db_manager = DatabaseManager::new(config);
         if db_manager.initialize().await.is_ok() {
@@ -331,4 +1196,183 @@ mod tests {
             assert!(deleted);
         }
     }
+
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_compare_and_swap_detects_conflict() {
+        if let Some(storage) = create_test_storage().await {
+            let token = TokenData::new("cas_id".to_string(), "https://example.com".to_string(), "token".to_string(), None, None);
+            storage.save_token(&token).await.unwrap();
+            let loaded = storage.get_token("cas_id").await.unwrap().unwrap();
+
+            // 两个客户端基于同一版本修改，后提交的一方得到 Conflict
+            let mut first = loaded.clone();
+            first.email_note = Some("first".to_string());
+            let stored = storage.compare_and_swap(&first).await.unwrap();
+            assert_eq!(stored.version, loaded.version + 1);
+
+            let mut second = loaded.clone();
+            second.email_note = Some("second".to_string());
+            let error = storage.compare_and_swap(&second).await.unwrap_err();
+            assert_eq!(error.code(), "conflict");
+
+            storage.delete_token("cas_id").await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_secret_encryption_and_rotation() {
+        if let Some(storage) = create_test_storage().await {
+            let token = TokenData::new("secret_id".to_string(), "https://example.com".to_string(), "secret-token".to_string(), None, None);
+            storage.save_token(&token).await.unwrap();
+
+            let secrets = Arc::new(storage.enable_secret_encryption("team passphrase").await.unwrap());
+            let locked = PostgreSQLStorage::new(storage.db_manager.clone());
+            assert_eq!(locked.get_token("secret_id").await.unwrap_err().code(), "locked");
+            assert_eq!(locked.save_token(&token).await.unwrap_err().code(), "locked");
+
+            // 解锁后读到明文，按哈希仍能找到重复
+            let unlocked = PostgreSQLStorage::new(storage.db_manager.clone()).with_secret_cipher(secrets);
+            assert_eq!(unlocked.get_token("secret_id").await.unwrap().unwrap().access_token, "secret-token");
+            assert_eq!(unlocked.find_duplicate_tokens("https://example.com", "secret-token", "other").await.unwrap().len(), 1);
+
+            // 轮换后旧口令失效
+            let rotated = Arc::new(unlocked.rotate_secret_key("new passphrase").await.unwrap());
+            assert_eq!(storage.unlock_secrets("team passphrase").await.err().unwrap().code(), "locked");
+            let rotated = PostgreSQLStorage::new(storage.db_manager.clone()).with_secret_cipher(rotated);
+            assert_eq!(rotated.get_token("secret_id").await.unwrap().unwrap().access_token, "secret-token");
+
+            rotated.delete_token("secret_id").await.unwrap();
+            let pool = storage.get_pool().await.unwrap();
+            pool.get().await.unwrap().execute("DELETE FROM secret_keys WHERE workspace_id = $1", &[&storage.workspace()]).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_retired_secret_key_cannot_write() {
+        if let Some(storage) = create_workspace_storage("secret-rotation-test").await {
+            let token = TokenData::new("rotated_id".to_string(), "https://example.com".to_string(), "secret-token".to_string(), None, None);
+            storage.save_token(&token).await.unwrap();
+
+            let old_key = Arc::new(storage.enable_secret_encryption("team passphrase").await.unwrap());
+            let stale = PostgreSQLStorage::new(storage.db_manager.clone()).with_secret_cipher(old_key.clone());
+            stale.save_token(&token).await.unwrap();
+
+            // 另一个客户端轮换密钥后，仍持有旧密钥的客户端不能再写入
+            let new_key = Arc::new(storage.switch_secret_key(Some(&old_key), "new passphrase").await.unwrap());
+            assert_eq!(stale.save_token(&token).await.unwrap_err().code(), "locked");
+
+            // 但仍能拉取，无法解密的行记为该token的错误
+            let changes = stale.load_tokens_changed_since(None).await.unwrap();
+            assert!(changes.tokens.is_empty());
+            assert_eq!(changes.errors.len(), 1);
+
+            let current = PostgreSQLStorage::new(storage.db_manager.clone()).with_secret_cipher(new_key);
+            assert_eq!(current.get_token("rotated_id").await.unwrap().unwrap().access_token, "secret-token");
+
+            current.delete_token("rotated_id").await.unwrap();
+            let pool = storage.get_pool().await.unwrap();
+            pool.get().await.unwrap().execute("DELETE FROM secret_keys WHERE workspace_id = $1", &[&storage.workspace()]).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_incremental_pull_sees_late_commits() {
+        if let Some(storage) = create_workspace_storage("change-xid-test").await {
+            storage.clear_all_tokens().await.unwrap();
+            for id in ["early", "late"] {
+                storage.save_token(&TokenData::new(id.to_string(), "https://example.com".to_string(), id.to_string(), None, None)).await.unwrap();
+            }
+            let first = storage.load_tokens_changed_since(None).await.unwrap();
+            let watermark = SyncWatermark { database_id: "db".to_string(), remote_change_seq: first.change_seq, remote_xmin: Some(first.xmin) };
+
+            // 事务A先分配到 change_seq，但在事务B之后才提交
+            let pool = storage.get_pool().await.unwrap();
+            let mut client = pool.get().await.unwrap();
+            let tx = client.transaction().await.unwrap();
+            tx.execute("UPDATE tokens SET email_note = 'from A' WHERE id = 'early' AND workspace_id = $1", &[&storage.workspace()]).await.unwrap();
+            let mut late = storage.get_token("late").await.unwrap().unwrap();
+            late.email_note = Some("from B".to_string());
+            storage.save_token(&late).await.unwrap();
+
+            let between = storage.load_tokens_changed_since(Some(&watermark)).await.unwrap();
+            assert_eq!(between.tokens.iter().map(|token| token.id.as_str()).collect::<Vec<_>>(), vec!["late"]);
+            tx.commit().await.unwrap();
+
+            // 用两次提交之间得到的水位线仍能读到事务A的修改
+            let watermark = SyncWatermark { database_id: "db".to_string(), remote_change_seq: between.change_seq, remote_xmin: Some(between.xmin) };
+            let after = storage.load_tokens_changed_since(Some(&watermark)).await.unwrap();
+            let early = after.tokens.iter().find(|token| token.id == "early").expect("late commit must be pulled");
+            assert_eq!(early.email_note.as_deref(), Some("from A"));
+
+            storage.clear_all_tokens().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_workspaces_are_isolated() {
+        if let (Some(team_a), Some(team_b)) = (create_workspace_storage("team-a").await, create_workspace_storage("team-b").await) {
+            let token = TokenData::new("shared_id".to_string(), "https://example.com".to_string(), "token-a".to_string(), None, None);
+            team_a.save_token(&token).await.unwrap();
+
+            // 另一个工作区可以使用相同的ID，互不覆盖
+            let mut other = token.clone();
+            other.access_token = "token-b".to_string();
+            team_b.save_token(&other).await.unwrap();
+
+            assert_eq!(team_a.get_token("shared_id").await.unwrap().unwrap().access_token, "token-a");
+            assert_eq!(team_b.get_token("shared_id").await.unwrap().unwrap().access_token, "token-b");
+            assert_ne!(team_a.database_id().await.unwrap(), team_b.database_id().await.unwrap());
+
+            team_b.clear_all_tokens().await.unwrap();
+            assert!(team_a.get_token("shared_id").await.unwrap().is_some());
+            team_a.delete_token("shared_id").await.unwrap();
+        }
+    }
+    #[tokio::test]
+    #[ignore] // 忽略这个测试，因为它需要真实的数据库连接
+    async fn test_sync_history_and_stats() {
+        if let Some(storage) = create_workspace_storage("sync-history-test").await {
+            let pool = storage.db_manager.get_pool().unwrap();
+            prune_sync_history(&pool, storage.workspace(), Utc::now() + chrono::Duration::days(1)).await.unwrap();
+
+            let error = SyncError::new(Some("bad_token".to_string()), &StorageError::Corrupt("cannot decrypt".to_string()));
+            for (status, tokens_synced, errors) in [("success", 4, vec![]), ("partial", 2, vec![error]), ("failed", 0, vec![])] {
+                let sync_status = SyncStatus {
+                    id: None,
+                    last_sync_at: Some(Utc::now()),
+                    sync_direction: "bidirectional".to_string(),
+                    status: status.to_string(),
+                    error_message: None,
+                    tokens_synced,
+                    conflicts: Vec::new(),
+                    errors,
+                };
+                record_sync_status(&pool, storage.workspace(), &sync_status).await.unwrap();
+            }
+
+            let page = load_sync_history(&pool, storage.workspace(), &PageRequest { offset: 0, limit: Some(2) }).await.unwrap();
+            assert_eq!(page.total, 3);
+            assert_eq!(page.entries.len(), 2);
+            assert_eq!(page.entries[0].status, "failed");
+            assert_eq!(page.entries[1].errors.len(), 1);
+            assert_eq!(page.entries[1].errors[0].token_id.as_deref(), Some("bad_token"));
+            assert_eq!(page.entries[1].errors[0].code, "corrupt");
+
+            let stats = load_sync_stats(&pool, storage.workspace(), None).await.unwrap();
+            assert_eq!(stats.len(), 1);
+            assert_eq!(stats[0].sync_count, 3);
+            assert_eq!(stats[0].failure_count, 1);
+            assert!((stats[0].average_tokens_synced - 3.0).abs() < f64::EPSILON);
+
+            // 错误记录随同步记录一起删除
+            let removed = prune_sync_history(&pool, storage.workspace(), Utc::now() + chrono::Duration::days(1)).await.unwrap();
+            assert_eq!(removed, 3);
+            assert!(get_latest_sync_status(&pool, storage.workspace()).await.unwrap().is_none());
+        }
+    }
 }
@@ -0,0 +1,315 @@
+use super::traits::{TokenData, normalize_tags};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// token查询条件，未设置的条件不参与过滤
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TokenFilter {
+    /// tenant_url 的主机名，也可以只写第一段（如 `d15` 匹配 `d15.api.augmentcode.com`），不区分大小写
+    pub tenant_host: Option<String>,
+    /// 封禁状态，如 `SUSPENDED`、`EXPIRED`
+    pub ban_status: Option<String>,
+    /// 邮箱备注包含的文本，不区分大小写
+    pub email_contains: Option<String>,
+    /// 未设置 skip_check 的token视为 false
+    pub skip_check: Option<bool>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    /// 额度范围（含边界），没有额度信息的token不匹配
+    pub min_balance: Option<f64>,
+    pub max_balance: Option<f64>,
+    /// 必须同时带有的全部标签
+    pub tags: Vec<String>,
+    /// 所属分组，区分大小写
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenSortField {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    EmailNote,
+    Balance,
+}
+
+/// 排序方式，值为空的token总是排在最后，相同值按ID排序
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TokenSort {
+    pub field: TokenSortField,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PageRequest {
+    pub offset: usize,
+    /// 为空时返回全部结果
+    pub limit: Option<usize>,
+}
+
+/// 查询结果：当前页的tokens和满足条件的总数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenPage {
+    pub tokens: Vec<TokenData>,
+    pub total: usize,
+}
+
+impl TokenFilter {
+    pub fn matches(&self, token: &TokenData) -> bool {
+        if let Some(host) = &self.tenant_host
+            && !tenant_host(&token.tenant_url).is_some_and(|token_host| host_matches(&token_host, host))
+        {
+            return false;
+        }
+
+        if let Some(status) = &self.ban_status
+            && token.ban_status.as_ref().and_then(|value| value.as_str()) != Some(status.as_str())
+        {
+            return false;
+        }
+
+        if let Some(text) = &self.email_contains {
+            let text = text.to_lowercase();
+            if !token.email_note.as_ref().is_some_and(|email| email.to_lowercase().contains(&text)) {
+                return false;
+            }
+        }
+
+        if let Some(skip_check) = self.skip_check
+            && token.skip_check.unwrap_or(false) != skip_check
+        {
+            return false;
+        }
+
+        let in_range = |value: DateTime<Utc>, after: Option<DateTime<Utc>>, before: Option<DateTime<Utc>>| {
+            after.is_none_or(|after| value >= after) && before.is_none_or(|before| value < before)
+        };
+        if !in_range(token.created_at, self.created_after, self.created_before)
+            || !in_range(token.updated_at, self.updated_after, self.updated_before)
+        {
+            return false;
+        }
+
+        if self.min_balance.is_some() || self.max_balance.is_some() {
+            let Some(balance) = credits_balance(token) else {
+                return false;
+            };
+            if self.min_balance.is_some_and(|min| balance < min) || self.max_balance.is_some_and(|max| balance > max) {
+                return false;
+            }
+        }
+
+        if !self.tags.iter().all(|tag| token.has_tag(tag)) {
+            return false;
+        }
+
+        if let Some(group) = &self.group
+            && token.group.as_ref() != Some(group)
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// 批量修改标签和分组，对所有满足 [`TokenFilter`] 的token生效
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TokenBulkUpdate {
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    /// 设置分组；与 clear_group 同时设置时以 set_group 为准
+    pub set_group: Option<String>,
+    pub clear_group: bool,
+}
+
+impl TokenBulkUpdate {
+    /// 修改token，返回是否有变化（不更新时间戳）
+    pub fn apply(&self, token: &mut TokenData) -> bool {
+        let tags = normalize_tags(
+            token.tags.iter()
+                .chain(self.add_tags.iter())
+                .filter(|tag| !self.remove_tags.iter().any(|removed| removed.trim() == tag.trim())),
+        );
+        let group = match &self.set_group {
+            Some(group) => Some(group.trim().to_string()).filter(|group| !group.is_empty()),
+            None if self.clear_group => None,
+            None => token.group.clone(),
+        };
+
+        let changed = tags != token.tags || group != token.group;
+        token.tags = tags;
+        token.group = group;
+        changed
+    }
+}
+
+/// 从 tenant_url 中取出小写的主机名
+pub fn tenant_host(tenant_url: &str) -> Option<String> {
+    let (_, rest) = tenant_url.split_once("://")?;
+    let host = rest.split(['/', ':', '?', '#']).next()?;
+    if host.is_empty() {
+        return None;
+    }
+    Some(host.to_lowercase())
+}
+
+fn host_matches(token_host: &str, wanted: &str) -> bool {
+    let wanted = wanted.to_lowercase();
+    token_host == wanted || token_host.starts_with(&format!("{}.", wanted))
+}
+
+/// portal_info 中的额度，只接受数字
+pub fn credits_balance(token: &TokenData) -> Option<f64> {
+    token.portal_info.as_ref()?.get("credits_balance")?.as_f64()
+}
+
+fn compare(a: &TokenData, b: &TokenData, field: TokenSortField) -> Option<Ordering> {
+    match field {
+        TokenSortField::CreatedAt => Some(a.created_at.cmp(&b.created_at)),
+        TokenSortField::UpdatedAt => Some(a.updated_at.cmp(&b.updated_at)),
+        TokenSortField::EmailNote => {
+            let a = a.email_note.as_ref()?.to_lowercase();
+            let b = b.email_note.as_ref()?.to_lowercase();
+            Some(a.cmp(&b))
+        }
+        TokenSortField::Balance => credits_balance(a)?.partial_cmp(&credits_balance(b)?),
+    }
+}
+
+fn has_sort_value(token: &TokenData, field: TokenSortField) -> bool {
+    match field {
+        TokenSortField::CreatedAt | TokenSortField::UpdatedAt => true,
+        TokenSortField::EmailNote => token.email_note.is_some(),
+        TokenSortField::Balance => credits_balance(token).is_some(),
+    }
+}
+
+/// 在内存中过滤、排序和分页，供没有查询能力的存储后端使用
+pub fn query_in_memory(tokens: Vec<TokenData>, filter: &TokenFilter, sort: &TokenSort, page: &PageRequest) -> TokenPage {
+    let mut matched: Vec<TokenData> = tokens.into_iter().filter(|token| filter.matches(token)).collect();
+    let total = matched.len();
+
+    matched.sort_by(|a, b| {
+        // 空值排在最后，不受排序方向影响
+        let by_value = match (has_sort_value(a, sort.field), has_sort_value(b, sort.field)) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => Ordering::Equal,
+            (true, true) => {
+                let ordering = compare(a, b, sort.field).unwrap_or(Ordering::Equal);
+                if sort.descending { ordering.reverse() } else { ordering }
+            }
+        };
+        by_value.then_with(|| a.id.cmp(&b.id))
+    });
+
+    let tokens = matched.into_iter()
+        .skip(page.offset)
+        .take(page.limit.unwrap_or(usize::MAX))
+        .collect();
+
+    TokenPage { tokens, total }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(id: &str, tenant_url: &str, status: Option<&str>, balance: Option<i32>) -> TokenData {
+        let mut token = TokenData::new(id.to_string(), tenant_url.to_string(), "token".to_string(), None, Some(format!("{}@Example.com", id)));
+        token.ban_status = status.map(|status| serde_json::json!(status));
+        token.portal_info = balance.map(|balance| serde_json::json!({ "credits_balance": balance }));
+        token
+    }
+
+    #[test]
+    fn test_tenant_host() {
+        assert_eq!(tenant_host("https://D15.api.augmentcode.com/"), Some("d15.api.augmentcode.com".to_string()));
+        assert_eq!(tenant_host("http://localhost:8080/path"), Some("localhost".to_string()));
+        assert_eq!(tenant_host("not a url"), None);
+    }
+
+    #[test]
+    fn test_filter_sort_and_page() {
+        let tokens = vec![
+            token("a", "https://d15.api.augmentcode.com/", Some("SUSPENDED"), Some(10)),
+            token("b", "https://d15.api.augmentcode.com/", Some("SUSPENDED"), None),
+            token("c", "https://d16.api.augmentcode.com/", Some("SUSPENDED"), Some(30)),
+            token("d", "https://d15.api.augmentcode.com/", Some("ACTIVE"), Some(50)),
+            token("e", "https://d15.api.augmentcode.com/", Some("SUSPENDED"), Some(20)),
+        ];
+
+        let filter = TokenFilter {
+            tenant_host: Some("d15".to_string()),
+            ban_status: Some("SUSPENDED".to_string()),
+            ..Default::default()
+        };
+        let sort = TokenSort { field: TokenSortField::Balance, descending: true };
+
+        let page = query_in_memory(tokens.clone(), &filter, &sort, &PageRequest { offset: 0, limit: Some(2) });
+        assert_eq!(page.total, 3);
+        let ids: Vec<&str> = page.tokens.iter().map(|token| token.id.as_str()).collect();
+        assert_eq!(ids, vec!["e", "a"]);
+
+        // 没有额度的token排在最后
+        let page = query_in_memory(tokens.clone(), &filter, &sort, &PageRequest { offset: 2, limit: Some(2) });
+        assert_eq!(page.tokens.len(), 1);
+        assert_eq!(page.tokens[0].id, "b");
+
+        let filter = TokenFilter {
+            email_contains: Some("C@EXAMPLE".to_string()),
+            min_balance: Some(25.0),
+            ..Default::default()
+        };
+        let page = query_in_memory(tokens, &filter, &TokenSort::default(), &PageRequest::default());
+        assert_eq!(page.total, 1);
+        assert_eq!(page.tokens[0].id, "c");
+    }
+
+    #[test]
+    fn test_filter_by_tags_and_group() {
+        let mut a = token("a", "https://example.com/", None, None);
+        a.tags = vec!["project-x".to_string(), "alice".to_string()];
+        a.group = Some("team-a".to_string());
+        let mut b = token("b", "https://example.com/", None, None);
+        b.tags = vec!["project-x".to_string()];
+        b.group = Some("team-a".to_string());
+        let mut c = token("c", "https://example.com/", None, None);
+        c.tags = vec!["project-x".to_string(), "alice".to_string()];
+
+        let filter = TokenFilter {
+            tags: vec!["alice".to_string(), "project-x".to_string()],
+            group: Some("team-a".to_string()),
+            ..Default::default()
+        };
+        let page = query_in_memory(vec![a, b, c], &filter, &TokenSort::default(), &PageRequest::default());
+        assert_eq!(page.total, 1);
+        assert_eq!(page.tokens[0].id, "a");
+    }
+
+    #[test]
+    fn test_bulk_update() {
+        let mut token = token("a", "https://example.com/", None, None);
+        token.tags = vec!["old".to_string(), "keep".to_string()];
+
+        let update = TokenBulkUpdate {
+            add_tags: vec![" new ".to_string(), "keep".to_string()],
+            remove_tags: vec!["old".to_string()],
+            set_group: Some("team-a".to_string()),
+            ..Default::default()
+        };
+        assert!(update.apply(&mut token));
+        assert_eq!(token.tags, vec!["keep", "new"]);
+        assert_eq!(token.group, Some("team-a".to_string()));
+        assert!(!update.apply(&mut token));
+    }
+}
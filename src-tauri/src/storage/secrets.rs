@@ -0,0 +1,200 @@
+use super::encryption::KdfParams;
+use super::error::{StorageError, StorageResult};
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// 加密后的列值格式：enc:v2:<key_id>:<nonce>:<ciphertext>
+// v2 把token ID和列名作为附加认证数据，密文不能在行或列之间互换；v1 没有附加认证数据，仅用于读取旧数据
+const SECRET_PREFIX: &str = "enc:v2:";
+const LEGACY_SECRET_PREFIX: &str = "enc:v1:";
+// 用于校验口令是否正确的固定明文
+const CHECK_PLAINTEXT: &str = "atm-secret-key-check";
+const CHECK_COLUMN: &str = "check_value";
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// 数据库中保存的团队密钥信息（派生参数和校验值），不含密钥本身
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretKeyInfo {
+    pub key_id: String,
+    pub kdf: KdfParams,
+    pub check_value: String,
+}
+
+/// 数据库中 access_token 和 auth_session 列的加解密器
+///
+/// 团队成员用同一口令和数据库中保存的派生参数得到相同的密钥，加密和哈希使用从中派生的两个子密钥。
+pub struct SecretCipher {
+    key_id: String,
+    cipher_key: [u8; KEY_LEN],
+    hash_key: [u8; KEY_LEN],
+}
+
+impl SecretCipher {
+    /// 用口令生成新的团队密钥，返回密钥和需要写入数据库的信息
+    pub fn generate(passphrase: &str) -> StorageResult<(Self, SecretKeyInfo)> {
+        if passphrase.is_empty() {
+            return Err(StorageError::InvalidInput("Team key passphrase must not be empty".to_string()));
+        }
+
+        let kdf = KdfParams::generate();
+        let cipher = Self::derive(uuid::Uuid::new_v4().simple().to_string(), &kdf, passphrase)?;
+        let info = SecretKeyInfo {
+            key_id: cipher.key_id.clone(),
+            kdf,
+            check_value: cipher.encrypt(CHECK_PLAINTEXT, &cipher.key_id, CHECK_COLUMN)?,
+        };
+        Ok((cipher, info))
+    }
+
+    /// 用口令解锁数据库中的团队密钥，口令错误时返回 Locked
+    pub fn unlock(info: &SecretKeyInfo, passphrase: &str) -> StorageResult<Self> {
+        let cipher = Self::derive(info.key_id.clone(), &info.kdf, passphrase)?;
+        match cipher.decrypt(&info.check_value, &info.key_id, CHECK_COLUMN) {
+            Ok(check) if check == CHECK_PLAINTEXT => Ok(cipher),
+            _ => Err(StorageError::Locked("Wrong team key passphrase".to_string())),
+        }
+    }
+
+    fn derive(key_id: String, kdf: &KdfParams, passphrase: &str) -> StorageResult<Self> {
+        let master_key = kdf.derive_key(passphrase)?;
+        Ok(Self {
+            key_id,
+            cipher_key: hmac_sha256(&master_key, b"atm-secret-encryption"),
+            hash_key: hmac_sha256(&master_key, b"atm-secret-hash"),
+        })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// 加密 token_id 行 column 列的值
+    pub fn encrypt(&self, value: &str, token_id: &str, column: &str) -> StorageResult<String> {
+        let cipher = Aes256Gcm::new_from_slice(&self.cipher_key)
+            .map_err(|e| StorageError::backend(format!("Failed to create encryption key: {}", e)))?;
+
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let aad = secret_aad(token_id, column);
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce_bytes), Payload { msg: value.as_bytes(), aad: &aad })
+            .map_err(|e| StorageError::backend(format!("Failed to encrypt secret: {}", e)))?;
+
+        Ok(format!("{}{}:{}:{}", SECRET_PREFIX, self.key_id, hex::encode(nonce_bytes), hex::encode(ciphertext)))
+    }
+
+    /// 解密 token_id 行 column 列的值；未加密的值原样返回，用其他密钥加密的值返回 Locked
+    pub fn decrypt(&self, value: &str, token_id: &str, column: &str) -> StorageResult<String> {
+        let (sealed, aad) = if let Some(sealed) = value.strip_prefix(SECRET_PREFIX) {
+            (sealed, secret_aad(token_id, column))
+        } else if let Some(sealed) = value.strip_prefix(LEGACY_SECRET_PREFIX) {
+            (sealed, Vec::new())
+        } else {
+            return Ok(value.to_string());
+        };
+
+        let mut parts = sealed.splitn(3, ':');
+        let (Some(key_id), Some(nonce), Some(ciphertext)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(StorageError::Corrupt("Malformed encrypted secret".to_string()));
+        };
+        if key_id != self.key_id {
+            return Err(StorageError::Locked(format!("Secret is encrypted with team key {}, unlock it first", key_id)));
+        }
+
+        let nonce_bytes = hex::decode(nonce)?;
+        if nonce_bytes.len() != NONCE_LEN {
+            return Err(StorageError::Corrupt("Invalid nonce length".to_string()));
+        }
+        let cipher = Aes256Gcm::new_from_slice(&self.cipher_key)
+            .map_err(|e| StorageError::backend(format!("Failed to create decryption key: {}", e)))?;
+        let ciphertext = hex::decode(ciphertext)?;
+        let plaintext = cipher.decrypt(Nonce::from_slice(&nonce_bytes), Payload { msg: ciphertext.as_ref(), aad: &aad })
+            .map_err(|_| StorageError::Locked("Failed to decrypt secret: wrong team key or corrupted value".to_string()))?;
+
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    /// 带密钥的哈希，相同的值得到相同的结果，没有密钥无法据此猜测原值
+    pub fn keyed_hash(&self, value: &str) -> String {
+        hex::encode(hmac_sha256(&self.hash_key, value.as_bytes()))
+    }
+}
+
+/// 判断列值是否已加密
+pub fn is_sealed_secret(value: &str) -> bool {
+    value.starts_with(SECRET_PREFIX) || value.starts_with(LEGACY_SECRET_PREFIX)
+}
+
+// 长度前缀避免不同的ID和列名拼接出相同的字节
+fn secret_aad(token_id: &str, column: &str) -> Vec<u8> {
+    format!("{}:{}:{}", token_id.len(), token_id, column).into_bytes()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; KEY_LEN] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256_matches_rfc4231() {
+        // RFC 4231 测试用例 2
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(hex::encode(mac), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn test_secret_round_trip_and_unlock() {
+        let (cipher, info) = SecretCipher::generate("team passphrase").unwrap();
+        let sealed = cipher.encrypt("access-token", "token-1", "access_token").unwrap();
+        assert!(is_sealed_secret(&sealed));
+        assert!(!sealed.contains("access-token"));
+        assert_ne!(sealed, cipher.encrypt("access-token", "token-1", "access_token").unwrap());
+        assert_eq!(cipher.decrypt(&sealed, "token-1", "access_token").unwrap(), "access-token");
+        assert_eq!(cipher.decrypt("legacy-plaintext", "token-1", "access_token").unwrap(), "legacy-plaintext");
+
+        // 团队其他成员用同一口令解锁，得到相同的密钥和哈希
+        let member = SecretCipher::unlock(&info, "team passphrase").unwrap();
+        assert_eq!(member.decrypt(&sealed, "token-1", "access_token").unwrap(), "access-token");
+        assert_eq!(member.keyed_hash("access-token"), cipher.keyed_hash("access-token"));
+        assert_eq!(SecretCipher::unlock(&info, "wrong").err().unwrap().code(), "locked");
+
+        // 轮换后的新密钥不能解密旧密钥加密的值
+        let (rotated, _) = SecretCipher::generate("new passphrase").unwrap();
+        assert_eq!(rotated.decrypt(&sealed, "token-1", "access_token").unwrap_err().code(), "locked");
+        assert_ne!(rotated.keyed_hash("access-token"), cipher.keyed_hash("access-token"));
+    }
+
+    #[test]
+    fn test_secret_bound_to_row_and_column() {
+        let (cipher, _) = SecretCipher::generate("team passphrase").unwrap();
+        let sealed = cipher.encrypt("access-token", "token-1", "access_token").unwrap();
+
+        // 复制到其他行或其他列的密文无法解密
+        assert_eq!(cipher.decrypt(&sealed, "token-2", "access_token").unwrap_err().code(), "locked");
+        assert_eq!(cipher.decrypt(&sealed, "token-1", "auth_session").unwrap_err().code(), "locked");
+    }
+
+    #[test]
+    fn test_legacy_secret_still_readable() {
+        let (cipher, _) = SecretCipher::generate("team passphrase").unwrap();
+        let nonce_bytes = [7u8; NONCE_LEN];
+        let ciphertext = Aes256Gcm::new_from_slice(&cipher.cipher_key).unwrap()
+            .encrypt(Nonce::from_slice(&nonce_bytes), b"access-token".as_ref())
+            .unwrap();
+        let legacy = format!("{}{}:{}:{}", LEGACY_SECRET_PREFIX, cipher.key_id, hex::encode(nonce_bytes), hex::encode(ciphertext));
+
+        assert!(is_sealed_secret(&legacy));
+        assert_eq!(cipher.decrypt(&legacy, "token-1", "access_token").unwrap(), "access-token");
+    }
+}
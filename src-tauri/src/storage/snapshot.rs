@@ -0,0 +1,474 @@
+use super::traits::{TokenData, convert_legacy_token, convert_to_legacy_format};
+use super::encryption::{TokenCipher, is_encrypted_content};
+use super::error::{StorageError, StorageResult};
+use super::history::{FieldChange, diff_tokens};
+use super::dual_storage::DualStorage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::fs;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tauri::Manager;
+
+const SNAPSHOT_FORMAT: &str = "atm-token-snapshot";
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+
+/// 快照保留策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotRetention {
+    /// 最多保留的快照数量
+    pub keep_last: usize,
+    /// 超过天数的快照会被删除，0 表示不按时间清理
+    pub max_age_days: u32,
+}
+
+impl Default for SnapshotRetention {
+    fn default() -> Self {
+        Self {
+            keep_last: 20,
+            max_age_days: 30,
+        }
+    }
+}
+
+/// 创建快照的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotReason {
+    Manual,
+    Scheduled,
+    BeforeClear,
+    BeforeSync,
+    BeforeBulkDelete,
+    BeforeRestore,
+    BeforeMerge,
+    BeforeWorkspaceSwitch,
+    BeforeReplace,
+}
+
+impl SnapshotReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotReason::Manual => "manual",
+            SnapshotReason::Scheduled => "scheduled",
+            SnapshotReason::BeforeClear => "before_clear",
+            SnapshotReason::BeforeSync => "before_sync",
+            SnapshotReason::BeforeBulkDelete => "before_bulk_delete",
+            SnapshotReason::BeforeRestore => "before_restore",
+            SnapshotReason::BeforeMerge => "before_merge",
+            SnapshotReason::BeforeWorkspaceSwitch => "before_workspace_switch",
+            SnapshotReason::BeforeReplace => "before_replace",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub reason: SnapshotReason,
+    pub token_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotDocument {
+    format: String,
+    created_at: DateTime<Utc>,
+    reason: SnapshotReason,
+    // 内容相同的快照不重复保存
+    checksum: String,
+    // 与 tokens.json 相同的格式
+    tokens: Vec<serde_json::Value>,
+}
+
+impl SnapshotDocument {
+    fn info(&self, id: &str) -> SnapshotInfo {
+        SnapshotInfo {
+            id: id.to_string(),
+            created_at: self.created_at,
+            reason: self.reason,
+            token_count: self.tokens.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenSummary {
+    pub id: String,
+    pub email_note: Option<String>,
+}
+
+impl From<&TokenData> for TokenSummary {
+    fn from(token: &TokenData) -> Self {
+        Self {
+            id: token.id.clone(),
+            email_note: token.email_note.clone(),
+        }
+    }
+}
+
+/// 快照中的token与当前版本的字段差异，old 为快照中的值
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangedToken {
+    pub id: String,
+    pub email_note: Option<String>,
+    pub changes: Vec<FieldChange>,
+}
+
+/// 快照与当前tokens的差异
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    /// 快照之后新增的tokens，恢复时会被删除
+    pub added: Vec<TokenSummary>,
+    /// 快照之后删除的tokens，恢复时会被找回
+    pub removed: Vec<TokenSummary>,
+    pub changed: Vec<ChangedToken>,
+}
+
+impl SnapshotDiff {
+    pub fn between(snapshot: &[TokenData], current: &[TokenData]) -> Self {
+        let snapshot_by_id: HashMap<&str, &TokenData> = snapshot.iter().map(|token| (token.id.as_str(), token)).collect();
+        let current_by_id: HashMap<&str, &TokenData> = current.iter().map(|token| (token.id.as_str(), token)).collect();
+
+        let mut diff = SnapshotDiff::default();
+        for token in current {
+            match snapshot_by_id.get(token.id.as_str()) {
+                None => diff.added.push(token.into()),
+                Some(old) => {
+                    let changes = diff_tokens(Some(old), Some(token));
+                    if !changes.is_empty() {
+                        diff.changed.push(ChangedToken {
+                            id: token.id.clone(),
+                            email_note: token.email_note.clone(),
+                            changes,
+                        });
+                    }
+                }
+            }
+        }
+        diff.removed = snapshot.iter()
+            .filter(|token| !current_by_id.contains_key(token.id.as_str()))
+            .map(TokenSummary::from)
+            .collect();
+
+        diff
+    }
+}
+
+/// 恢复快照的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotRestore {
+    /// 写回的tokens数量（与当前内容相同的不计入）
+    pub restored: usize,
+    /// 快照之后新增、恢复时删除的tokens数量
+    pub removed: usize,
+    /// 恢复前自动创建的快照，可以用来撤销这次恢复
+    pub backup_id: Option<String>,
+}
+
+/// token存储的定时快照，保存在应用数据目录的 snapshots 子目录中
+#[derive(Clone)]
+pub struct SnapshotManager {
+    dir: PathBuf,
+    // 设置后快照以加密信封格式保存，与 tokens.json 使用同一口令
+    cipher: Option<Arc<TokenCipher>>,
+    retention: SnapshotRetention,
+    // 克隆之间共享，保证创建、读取和清理互斥
+    lock: Arc<Mutex<()>>,
+}
+
+impl SnapshotManager {
+    pub fn new(app_handle: &tauri::AppHandle) -> StorageResult<Self> {
+        let app_data_dir = app_handle.path().app_data_dir()?;
+        Ok(Self::new_with_dir(app_data_dir.join("snapshots")))
+    }
+
+    pub fn new_with_dir(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            cipher: None,
+            retention: SnapshotRetention::default(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn with_cipher(mut self, cipher: Arc<TokenCipher>) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    pub fn with_retention(mut self, retention: SnapshotRetention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// 保存快照并按保留策略清理旧快照
+    ///
+    /// 没有tokens时不创建；内容与最新快照相同时直接返回最新快照。
+    pub async fn create(&self, tokens: &[TokenData], reason: SnapshotReason) -> StorageResult<Option<SnapshotInfo>> {
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+
+        let legacy_tokens: Vec<serde_json::Value> = tokens.iter().map(convert_to_legacy_format).collect();
+        let checksum = hex::encode(Sha256::digest(serde_json::to_string(&legacy_tokens)?.as_bytes()));
+
+        self.with_lock(move |manager| {
+            fs::create_dir_all(&manager.dir)?;
+
+            // 最新快照无法读取时照常创建新快照
+            let latest = manager.snapshot_ids()?.into_iter().next()
+                .and_then(|id| manager.read_document(&id).ok().map(|document| (id, document)));
+            if let Some((id, latest)) = &latest
+                && latest.checksum == checksum
+            {
+                return Ok(Some(latest.info(id)));
+            }
+
+            // 同一毫秒内连续创建时顺延，保证新快照按ID排在最前
+            let mut created_at = Utc::now();
+            if let Some((_, latest)) = &latest
+                && created_at.timestamp_millis() <= latest.created_at.timestamp_millis()
+            {
+                created_at = latest.created_at + chrono::Duration::milliseconds(1);
+            }
+
+            let document = SnapshotDocument {
+                format: SNAPSHOT_FORMAT.to_string(),
+                created_at,
+                reason,
+                checksum,
+                tokens: legacy_tokens,
+            };
+
+            // ID以时间开头，按字符串排序即按时间排序
+            let base_id = format!("{}-{}", document.created_at.format("%Y%m%dT%H%M%S%.3fZ"), reason.as_str());
+            let mut id = base_id.clone();
+            let mut suffix = 1;
+            while manager.path_for(&id).exists() {
+                suffix += 1;
+                id = format!("{}-{}", base_id, suffix);
+            }
+
+            manager.write_document(&id, &document)?;
+            manager.prune_locked()?;
+
+            Ok(Some(document.info(&id)))
+        }).await
+    }
+
+    /// 按时间从新到旧列出全部快照
+    pub async fn list(&self) -> StorageResult<Vec<SnapshotInfo>> {
+        self.with_lock(|manager| {
+            manager.snapshot_ids()?
+                .iter()
+                .map(|id| Ok(manager.read_document(id)?.info(id)))
+                .collect()
+        }).await
+    }
+
+    pub async fn load(&self, id: &str) -> StorageResult<Vec<TokenData>> {
+        let id = id.to_string();
+        self.with_lock(move |manager| {
+            manager.read_document(&id)?
+                .tokens
+                .iter()
+                .map(convert_legacy_token)
+                .collect()
+        }).await
+    }
+
+    /// 按保留策略删除旧快照，返回删除的数量；最新的快照总是保留
+    pub async fn prune(&self) -> StorageResult<usize> {
+        self.with_lock(|manager| manager.prune_locked()).await
+    }
+
+    /// 持有锁在阻塞线程池中执行文件读写，不阻塞异步运行时
+    async fn with_lock<F, T>(&self, f: F) -> StorageResult<T>
+    where
+        F: FnOnce(&SnapshotManager) -> StorageResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let manager = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = manager.lock.lock().unwrap();
+            f(&manager)
+        }).await?
+    }
+
+    // 调用方需持有 lock
+    fn prune_locked(&self) -> StorageResult<usize> {
+        let cutoff = (self.retention.max_age_days > 0)
+            .then(|| Utc::now() - chrono::Duration::days(self.retention.max_age_days as i64));
+
+        let mut removed = 0;
+        for (index, id) in self.snapshot_ids()?.iter().enumerate().skip(1) {
+            let expired = index >= self.retention.keep_last.max(1)
+                || cutoff.is_some_and(|cutoff| self.read_created_at(id).is_some_and(|created_at| created_at < cutoff));
+            if expired {
+                fs::remove_file(self.path_for(id))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    // 从新到旧排列的快照ID
+    fn snapshot_ids(&self) -> StorageResult<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut ids: Vec<String> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                Some(name.strip_prefix(SNAPSHOT_PREFIX)?.strip_suffix(".json")?.to_string())
+            })
+            .collect();
+        ids.sort_by(|a, b| b.cmp(a));
+        Ok(ids)
+    }
+
+    fn path_for(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}{}.json", SNAPSHOT_PREFIX, id))
+    }
+
+    // 清理时不需要解密内容，创建时间从ID中解析
+    fn read_created_at(&self, id: &str) -> Option<DateTime<Utc>> {
+        let timestamp = id.split('-').next()?;
+        chrono::NaiveDateTime::parse_from_str(timestamp, "%Y%m%dT%H%M%S%.3fZ")
+            .ok()
+            .map(|naive| naive.and_utc())
+    }
+
+    fn read_document(&self, id: &str) -> StorageResult<SnapshotDocument> {
+        // ID来自前端，拒绝路径分隔符
+        if id.is_empty() || id.contains(['/', '\\']) || id.contains("..") {
+            return Err(StorageError::InvalidInput(format!("Invalid snapshot id: {}", id)));
+        }
+
+        let path = self.path_for(id);
+        if !path.exists() {
+            return Err(StorageError::NotFound(format!("Snapshot {} not found", id)));
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let content = if is_encrypted_content(&content) {
+            let cipher = self.cipher.as_ref()
+                .ok_or_else(|| StorageError::Locked("Snapshot is encrypted, passphrase required".to_string()))?;
+            cipher.decrypt(&content)?
+        } else {
+            content
+        };
+
+        let document: SnapshotDocument = serde_json::from_str(&content)
+            .map_err(|e| StorageError::Corrupt(format!("Invalid snapshot {}: {}", id, e)))?;
+        if document.format != SNAPSHOT_FORMAT {
+            return Err(StorageError::Corrupt(format!("Unknown snapshot format: {}", document.format)));
+        }
+        Ok(document)
+    }
+
+    fn write_document(&self, id: &str, document: &SnapshotDocument) -> StorageResult<()> {
+        let json = serde_json::to_string_pretty(document)?;
+        let content = match &self.cipher {
+            Some(cipher) => cipher.encrypt(&json)?,
+            None => json,
+        };
+
+        let path = self.path_for(id);
+        let temp_path = path.with_extension("tmp");
+        {
+            let mut temp_file = fs::File::create(&temp_path)?;
+            temp_file.write_all(content.as_bytes())?;
+            temp_file.sync_all()?;
+        }
+        fs::rename(&temp_path, &path)?;
+
+        Ok(())
+    }
+}
+
+/// 按固定间隔为当前的存储管理器创建快照
+pub async fn run_snapshot_schedule(
+    storage_manager: Arc<Mutex<Option<Arc<DualStorage>>>>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // 第一次 tick 立即完成，启动时不需要马上创建快照
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let storage = storage_manager.lock().ok().and_then(|guard| guard.clone());
+        let Some(storage) = storage else {
+            continue;
+        };
+        match storage.create_snapshot(SnapshotReason::Scheduled).await {
+            Ok(Some(info)) => println!("Created scheduled snapshot {}", info.id),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to create scheduled snapshot [{}]: {}", e.code(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn token(id: &str, note: &str) -> TokenData {
+        TokenData::new(id.to_string(), "https://example.com".to_string(), "token".to_string(), None, Some(note.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_retention() {
+        let dir = tempdir().unwrap();
+        let manager = SnapshotManager::new_with_dir(dir.path().to_path_buf())
+            .with_retention(SnapshotRetention { keep_last: 2, max_age_days: 30 });
+
+        assert!(manager.create(&[], SnapshotReason::Manual).await.unwrap().is_none());
+
+        let original = token("a", "1");
+        let first = manager.create(std::slice::from_ref(&original), SnapshotReason::Manual).await.unwrap().unwrap();
+        // 内容没变时不重复保存
+        let same = manager.create(&[original], SnapshotReason::Scheduled).await.unwrap().unwrap();
+        assert_eq!(first.id, same.id);
+
+        manager.create(&[token("a", "2")], SnapshotReason::Scheduled).await.unwrap();
+        let latest = manager.create(&[token("a", "3"), token("b", "3")], SnapshotReason::BeforeClear).await.unwrap().unwrap();
+
+        let snapshots = manager.list().await.unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].id, latest.id);
+        assert_eq!(snapshots[0].reason, SnapshotReason::BeforeClear);
+        assert_eq!(snapshots[0].token_count, 2);
+        assert!(snapshots.iter().all(|snapshot| snapshot.id != first.id));
+
+        assert_eq!(manager.load(&latest.id).await.unwrap().len(), 2);
+        assert_eq!(manager.load("../tokens").await.unwrap_err().code(), "invalid_input");
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let snapshot = vec![token("a", "old"), token("b", "kept"), token("c", "deleted")];
+        let mut changed = snapshot[0].clone();
+        changed.email_note = Some("new".to_string());
+        let current = vec![changed, snapshot[1].clone(), token("d", "added")];
+
+        let diff = SnapshotDiff::between(&snapshot, &current);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].id, "d");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].id, "c");
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].changes[0].old, serde_json::json!("old"));
+    }
+}
@@ -0,0 +1,611 @@
+use super::traits::{TokenStorage, TokenData, Tombstone, SyncWatermark};
+use super::error::{StorageError, StorageResult};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::PathBuf;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use tauri::Manager;
+
+const TOKEN_COLUMNS: &str = "id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version";
+
+// 已有记录只会被更晚的删除时间覆盖
+const UPSERT_TOMBSTONE: &str = r#"
+    INSERT INTO token_tombstones (token_id, deleted_at, origin)
+    VALUES (?1, ?2, ?3)
+    ON CONFLICT (token_id) DO UPDATE SET
+        deleted_at = excluded.deleted_at,
+        origin = excluded.origin
+    WHERE token_tombstones.deleted_at < excluded.deleted_at
+"#;
+
+/// 基于嵌入式SQLite文件的本地存储，列与PostgreSQL的tokens表保持一致
+pub struct SqliteStorage {
+    db_path: PathBuf,
+    // rusqlite::Connection 不是 Sync，所有访问都通过 spawn_blocking 串行化
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    pub fn new(app_handle: &tauri::AppHandle) -> StorageResult<Self> {
+        let app_data_dir = app_handle.path().app_data_dir()?;
+        fs::create_dir_all(&app_data_dir)?;
+
+        Self::new_with_path(app_data_dir.join("tokens.db"))
+    }
+
+    pub fn new_with_path(db_path: PathBuf) -> StorageResult<Self> {
+        if let Some(parent) = db_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(&db_path)?;
+        Self::initialize_schema(&conn)?;
+
+        Ok(Self {
+            db_path,
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn db_path(&self) -> &PathBuf {
+        &self.db_path
+    }
+
+    fn initialize_schema(conn: &Connection) -> rusqlite::Result<()> {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS tokens (
+                id TEXT PRIMARY KEY,
+                tenant_url TEXT NOT NULL,
+                access_token TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                portal_url TEXT,
+                email_note TEXT,
+                ban_status TEXT,
+                portal_info TEXT,
+                auth_session TEXT,
+                suspensions TEXT,
+                balance_color_mode TEXT,
+                skip_check INTEGER,
+                tags TEXT NOT NULL DEFAULT '[]',
+                group_name TEXT,
+                version INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE INDEX IF NOT EXISTS idx_tokens_created_at ON tokens(created_at);
+            CREATE INDEX IF NOT EXISTS idx_tokens_updated_at ON tokens(updated_at);
+            CREATE INDEX IF NOT EXISTS idx_tokens_tenant_access ON tokens(tenant_url, access_token);
+            CREATE TABLE IF NOT EXISTS token_tombstones (
+                token_id TEXT PRIMARY KEY,
+                deleted_at TEXT NOT NULL,
+                origin TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_token_tombstones_deleted_at ON token_tombstones(deleted_at);
+            CREATE TABLE IF NOT EXISTS sync_state (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            "#,
+        )?;
+
+        // 旧版本创建的表缺少后来加入的列
+        Self::add_column_if_missing(conn, "tokens", "tags", "TEXT NOT NULL DEFAULT '[]'")?;
+        Self::add_column_if_missing(conn, "tokens", "group_name", "TEXT")?;
+        Self::add_column_if_missing(conn, "tokens", "version", "INTEGER NOT NULL DEFAULT 0")?;
+
+        Ok(())
+    }
+
+    fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+        let exists = conn
+            .prepare(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1", table))?
+            .exists(params![column])?;
+        if !exists {
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+        }
+        Ok(())
+    }
+
+    fn row_to_token(row: &Row) -> rusqlite::Result<TokenData> {
+        Ok(TokenData {
+            id: row.get(0)?,
+            tenant_url: row.get(1)?,
+            access_token: row.get(2)?,
+            created_at: row.get(3)?,
+            updated_at: row.get(4)?,
+            portal_url: row.get(5)?,
+            email_note: row.get(6)?,
+            ban_status: row.get(7)?,
+            portal_info: row.get(8)?,
+            auth_session: row.get(9)?,
+            suspensions: row.get(10)?,
+            balance_color_mode: row.get(11)?,
+            skip_check: row.get(12)?,
+            // 标签以JSON数组保存
+            tags: serde_json::from_value(row.get::<_, serde_json::Value>(13)?).unwrap_or_default(),
+            group: row.get(14)?,
+            version: row.get(15)?,
+        })
+    }
+
+    fn upsert_token(conn: &Connection, token: &TokenData) -> rusqlite::Result<usize> {
+        conn.execute(
+            r#"
+            INSERT INTO tokens (id, tenant_url, access_token, created_at, updated_at, portal_url, email_note, ban_status, portal_info, auth_session, suspensions, balance_color_mode, skip_check, tags, group_name, version)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+            ON CONFLICT (id) DO UPDATE SET
+                tenant_url = excluded.tenant_url,
+                access_token = excluded.access_token,
+                updated_at = excluded.updated_at,
+                portal_url = excluded.portal_url,
+                email_note = excluded.email_note,
+                ban_status = excluded.ban_status,
+                portal_info = excluded.portal_info,
+                auth_session = excluded.auth_session,
+                suspensions = excluded.suspensions,
+                balance_color_mode = excluded.balance_color_mode,
+                skip_check = excluded.skip_check,
+                tags = excluded.tags,
+                group_name = excluded.group_name,
+                version = excluded.version
+            "#,
+            params![
+                token.id,
+                token.tenant_url,
+                token.access_token,
+                token.created_at,
+                token.updated_at,
+                token.portal_url,
+                token.email_note,
+                token.ban_status,
+                token.portal_info,
+                token.auth_session,
+                token.suspensions,
+                token.balance_color_mode,
+                token.skip_check,
+                serde_json::json!(token.tags),
+                token.group,
+                token.version,
+            ],
+        )
+    }
+
+    /// 在阻塞线程池中执行数据库操作，避免阻塞异步运行时
+    async fn with_connection<F, T>(&self, f: F) -> StorageResult<T>
+    where
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut guard = conn.lock().unwrap();
+            f(&mut guard)
+        }).await?;
+
+        Ok(result?)
+    }
+
+    async fn load_sync_state<T>(&self, key: &'static str) -> StorageResult<Option<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let value: Option<String> = self.with_connection(move |conn| {
+            conn.query_row("SELECT value FROM sync_state WHERE key = ?1", params![key], |row| row.get(0))
+                .optional()
+        }).await?;
+
+        match value {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save_sync_state<T>(&self, key: &'static str, value: &T) -> StorageResult<()>
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        let value = serde_json::to_string(value)?;
+        self.with_connection(move |conn| {
+            conn.execute(
+                "INSERT INTO sync_state (key, value) VALUES (?1, ?2) ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            ).map(|_| ())
+        }).await
+    }
+
+    /// 查找具有相同tenant_url和access_token但不同ID的token
+    pub async fn find_duplicate_tokens(&self, tenant_url: &str, access_token: &str, exclude_token_id: &str) -> StorageResult<Vec<TokenData>> {
+        let tenant_url = tenant_url.to_string();
+        let access_token = access_token.to_string();
+        let exclude_token_id = exclude_token_id.to_string();
+
+        self.with_connection(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM tokens WHERE tenant_url = ?1 AND access_token = ?2 AND id != ?3",
+                TOKEN_COLUMNS
+            ))?;
+            let rows = stmt.query_map(params![tenant_url, access_token, exclude_token_id], Self::row_to_token)?;
+            rows.collect()
+        }).await
+    }
+}
+
+#[async_trait::async_trait]
+impl TokenStorage for SqliteStorage {
+    async fn save_token(&self, token: &TokenData) -> StorageResult<()> {
+        let token = token.clone();
+        self.with_connection(move |conn| Self::upsert_token(conn, &token).map(|_| ())).await
+    }
+
+    async fn load_tokens(&self) -> StorageResult<Vec<TokenData>> {
+        self.with_connection(|conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM tokens ORDER BY created_at DESC",
+                TOKEN_COLUMNS
+            ))?;
+            let rows = stmt.query_map([], Self::row_to_token)?;
+            rows.collect()
+        }).await
+    }
+
+    async fn update_token(&self, token: &TokenData) -> StorageResult<()> {
+        let mut updated_token = token.clone();
+        updated_token.updated_at = Utc::now();
+
+        let rows_affected = self.with_connection(move |conn| {
+            conn.execute(
+                r#"
+                UPDATE tokens SET
+                    tenant_url = ?2,
+                    access_token = ?3,
+                    updated_at = ?4,
+                    portal_url = ?5,
+                    email_note = ?6,
+                    ban_status = ?7,
+                    portal_info = ?8,
+                    auth_session = ?9,
+                    suspensions = ?10,
+                    balance_color_mode = ?11,
+                    skip_check = ?12,
+                    tags = ?13,
+                    group_name = ?14,
+                    version = ?15
+                WHERE id = ?1
+                "#,
+                params![
+                    updated_token.id,
+                    updated_token.tenant_url,
+                    updated_token.access_token,
+                    updated_token.updated_at,
+                    updated_token.portal_url,
+                    updated_token.email_note,
+                    updated_token.ban_status,
+                    updated_token.portal_info,
+                    updated_token.auth_session,
+                    updated_token.suspensions,
+                    updated_token.balance_color_mode,
+                    updated_token.skip_check,
+                    serde_json::json!(updated_token.tags),
+                    updated_token.group,
+                    updated_token.version,
+                ],
+            )
+        }).await?;
+
+        if rows_affected == 0 {
+            return Err(StorageError::NotFound(format!("Token {} not found for update", token.id)));
+        }
+
+        Ok(())
+    }
+
+    async fn delete_token(&self, token_id: &str) -> StorageResult<bool> {
+        let token_id = token_id.to_string();
+        let rows_affected = self.with_connection(move |conn| {
+            conn.execute("DELETE FROM tokens WHERE id = ?1", params![token_id])
+        }).await?;
+
+        Ok(rows_affected > 0)
+    }
+
+    async fn get_token(&self, token_id: &str) -> StorageResult<Option<TokenData>> {
+        let token_id = token_id.to_string();
+        self.with_connection(move |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM tokens WHERE id = ?1", TOKEN_COLUMNS),
+                params![token_id],
+                Self::row_to_token,
+            ).optional()
+        }).await
+    }
+
+    async fn clear_all_tokens(&self) -> StorageResult<()> {
+        self.with_connection(|conn| conn.execute("DELETE FROM tokens", []).map(|_| ())).await
+    }
+
+    async fn save_tokens(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        let tokens = tokens.to_vec();
+        self.with_connection(move |conn| {
+            let tx = conn.transaction()?;
+            for token in &tokens {
+                Self::upsert_token(&tx, token)?;
+            }
+            tx.commit()
+        }).await
+    }
+
+    async fn delete_tokens(&self, token_ids: &[String]) -> StorageResult<usize> {
+        let token_ids = token_ids.to_vec();
+        self.with_connection(move |conn| {
+            let tx = conn.transaction()?;
+            let mut deleted = 0;
+            {
+                let mut stmt = tx.prepare("DELETE FROM tokens WHERE id = ?1")?;
+                for token_id in &token_ids {
+                    deleted += stmt.execute(params![token_id])?;
+                }
+            }
+            tx.commit()?;
+            Ok(deleted)
+        }).await
+    }
+
+    /// 在单个事务中用给定的tokens替换全部数据
+    async fn replace_all(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        let tokens = tokens.to_vec();
+        self.with_connection(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM tokens", [])?;
+            for token in &tokens {
+                Self::upsert_token(&tx, token)?;
+            }
+            tx.commit()
+        }).await
+    }
+
+    async fn load_tombstones(&self) -> StorageResult<Vec<Tombstone>> {
+        self.with_connection(|conn| {
+            let mut stmt = conn.prepare("SELECT token_id, deleted_at, origin FROM token_tombstones")?;
+            let rows = stmt.query_map([], |row| {
+                Ok(Tombstone {
+                    token_id: row.get(0)?,
+                    deleted_at: row.get(1)?,
+                    origin: row.get(2)?,
+                })
+            })?;
+            rows.collect()
+        }).await
+    }
+
+    async fn save_tombstones(&self, tombstones: &[Tombstone]) -> StorageResult<()> {
+        let tombstones = tombstones.to_vec();
+        self.with_connection(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare(UPSERT_TOMBSTONE)?;
+                for tombstone in &tombstones {
+                    stmt.execute(params![tombstone.token_id, tombstone.deleted_at, tombstone.origin])?;
+                }
+            }
+            tx.commit()
+        }).await
+    }
+
+    async fn purge_tombstones(&self, older_than: DateTime<Utc>) -> StorageResult<usize> {
+        self.with_connection(move |conn| {
+            conn.execute("DELETE FROM token_tombstones WHERE deleted_at < ?1", params![older_than])
+        }).await
+    }
+
+    async fn apply_changes(&self, tokens: &[TokenData], tombstones: &[Tombstone]) -> StorageResult<usize> {
+        self.commit_sync(tokens, tombstones, None).await
+    }
+
+    /// tokens、墓碑、同步基准和水位线在同一个事务中写入
+    async fn commit_sync(&self, tokens: &[TokenData], tombstones: &[Tombstone], watermark: Option<&SyncWatermark>) -> StorageResult<usize> {
+        let tokens = tokens.to_vec();
+        let tombstones = tombstones.to_vec();
+        let watermark = watermark.map(serde_json::to_string).transpose()?;
+
+        self.with_connection(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare(UPSERT_TOMBSTONE)?;
+                for tombstone in &tombstones {
+                    stmt.execute(params![tombstone.token_id, tombstone.deleted_at, tombstone.origin])?;
+                }
+            }
+
+            let mut deleted = 0;
+            {
+                let mut stmt = tx.prepare("DELETE FROM tokens WHERE id = ?1")?;
+                for tombstone in &tombstones {
+                    if !tokens.iter().any(|token| token.id == tombstone.token_id) {
+                        deleted += stmt.execute(params![tombstone.token_id])?;
+                    }
+                }
+            }
+
+            for token in &tokens {
+                Self::upsert_token(&tx, token)?;
+            }
+
+            if let Some(watermark) = watermark {
+                let synced_tokens: Vec<TokenData> = {
+                    let mut stmt = tx.prepare(&format!("SELECT {} FROM tokens ORDER BY created_at DESC", TOKEN_COLUMNS))?;
+                    let rows = stmt.query_map([], Self::row_to_token)?;
+                    rows.collect::<rusqlite::Result<_>>()?
+                };
+                let sync_base = serde_json::to_string(&synced_tokens)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+
+                let mut stmt = tx.prepare(
+                    "INSERT INTO sync_state (key, value) VALUES (?1, ?2) ON CONFLICT (key) DO UPDATE SET value = excluded.value"
+                )?;
+                stmt.execute(params!["sync_base", sync_base])?;
+                stmt.execute(params!["sync_watermark", watermark])?;
+            }
+
+            tx.commit()?;
+            Ok(deleted)
+        }).await
+    }
+
+    async fn load_sync_base(&self) -> StorageResult<Vec<TokenData>> {
+        Ok(self.load_sync_state("sync_base").await?.unwrap_or_default())
+    }
+
+    async fn save_sync_base(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        self.save_sync_state("sync_base", tokens).await
+    }
+
+    async fn load_sync_watermark(&self) -> StorageResult<Option<SyncWatermark>> {
+        self.load_sync_state("sync_watermark").await
+    }
+
+    async fn save_sync_watermark(&self, watermark: &SyncWatermark) -> StorageResult<()> {
+        self.save_sync_state("sync_watermark", watermark).await
+    }
+
+    fn storage_type(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn is_available(&self) -> bool {
+        self.with_connection(|conn| conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)))
+            .await
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_sqlite_storage_operations() {
+        let temp_dir = tempdir().unwrap();
+        let storage = SqliteStorage::new_with_path(temp_dir.path().join("tokens.db")).unwrap();
+
+        let mut token = TokenData::new(
+            "test_id".to_string(),
+            "https://example.com".to_string(),
+            "test_token".to_string(),
+            Some("https://portal.example.com".to_string()),
+            Some("test note".to_string()),
+        );
+        token.ban_status = Some(serde_json::json!({"status": "ACTIVE"}));
+        token.skip_check = Some(true);
+        token.tags = vec!["project-x".to_string()];
+        token.group = Some("team-a".to_string());
+
+        assert!(storage.save_token(&token).await.is_ok());
+
+        let loaded_tokens = storage.load_tokens().await.unwrap();
+        assert_eq!(loaded_tokens.len(), 1);
+        assert_eq!(loaded_tokens[0].ban_status, token.ban_status);
+        assert_eq!(loaded_tokens[0].skip_check, Some(true));
+        assert_eq!(loaded_tokens[0].tags, token.tags);
+        assert_eq!(loaded_tokens[0].group, token.group);
+        assert_eq!(loaded_tokens[0].created_at, token.created_at);
+
+        token.email_note = Some("updated note".to_string());
+        assert!(storage.update_token(&token).await.is_ok());
+        let retrieved_token = storage.get_token("test_id").await.unwrap().unwrap();
+        assert_eq!(retrieved_token.email_note, Some("updated note".to_string()));
+
+        assert!(storage.delete_token("test_id").await.unwrap());
+        assert!(!storage.delete_token("test_id").await.unwrap());
+        assert!(storage.get_token("test_id").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_batch_operations() {
+        let temp_dir = tempdir().unwrap();
+        let storage = SqliteStorage::new_with_path(temp_dir.path().join("tokens.db")).unwrap();
+
+        let old_token = TokenData::new("old".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        storage.save_token(&old_token).await.unwrap();
+
+        let new_tokens = vec![
+            TokenData::new("new_1".to_string(), "https://b.com".to_string(), "b".to_string(), None, None),
+            TokenData::new("new_2".to_string(), "https://b.com".to_string(), "b".to_string(), None, None),
+        ];
+        storage.replace_all(&new_tokens).await.unwrap();
+
+        let loaded_tokens = storage.load_tokens().await.unwrap();
+        assert_eq!(loaded_tokens.len(), 2);
+        assert!(storage.get_token("old").await.unwrap().is_none());
+
+        storage.save_tokens(&[old_token]).await.unwrap();
+        assert_eq!(storage.load_tokens().await.unwrap().len(), 3);
+        assert_eq!(storage.delete_tokens(&["old".to_string(), "missing".to_string()]).await.unwrap(), 1);
+
+        let duplicates = storage.find_duplicate_tokens("https://b.com", "b", "new_1").await.unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].id, "new_2");
+
+        assert!(storage.is_available().await);
+        assert_eq!(storage.storage_type(), "sqlite");
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_tombstones() {
+        let temp_dir = tempdir().unwrap();
+        let storage = SqliteStorage::new_with_path(temp_dir.path().join("tokens.db")).unwrap();
+
+        let old = Tombstone {
+            token_id: "old".to_string(),
+            deleted_at: Utc::now() - chrono::Duration::days(200),
+            origin: "device_1".to_string(),
+        };
+        let recent = Tombstone::new("recent".to_string(), "device_1".to_string());
+        storage.save_tombstones(&[old.clone(), recent]).await.unwrap();
+
+        // 较早的删除时间不会覆盖已有记录
+        let mut older = old.clone();
+        older.deleted_at = old.deleted_at - chrono::Duration::days(1);
+        older.origin = "device_2".to_string();
+        storage.save_tombstones(&[older]).await.unwrap();
+
+        let tombstones = storage.load_tombstones().await.unwrap();
+        assert_eq!(tombstones.len(), 2);
+        assert!(tombstones.contains(&old));
+
+        let purged = storage.purge_tombstones(Utc::now() - chrono::Duration::days(90)).await.unwrap();
+        assert_eq!(purged, 1);
+        assert_eq!(storage.load_tombstones().await.unwrap()[0].token_id, "recent");
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_commit_sync() {
+        let temp_dir = tempdir().unwrap();
+        let storage = SqliteStorage::new_with_path(temp_dir.path().join("tokens.db")).unwrap();
+
+        let kept = TokenData::new("kept".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        let removed = TokenData::new("removed".to_string(), "https://a.com".to_string(), "b".to_string(), None, None);
+        storage.save_tokens(&[kept.clone(), removed]).await.unwrap();
+
+        let added = TokenData::new("added".to_string(), "https://a.com".to_string(), "c".to_string(), None, None);
+        let watermark = SyncWatermark { database_id: "db".to_string(), remote_change_seq: 7, remote_xmin: Some(5) };
+        let deleted = storage.commit_sync(
+            &[added],
+            &[Tombstone::new("removed".to_string(), "device_1".to_string())],
+            Some(&watermark),
+        ).await.unwrap();
+        assert_eq!(deleted, 1);
+
+        let mut ids: Vec<String> = storage.load_tokens().await.unwrap().into_iter().map(|token| token.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["added", "kept"]);
+        // 同步基准是写入后的全部tokens
+        assert_eq!(storage.load_sync_base().await.unwrap().len(), 2);
+        assert_eq!(storage.load_sync_watermark().await.unwrap(), Some(watermark));
+        assert_eq!(storage.load_tombstones().await.unwrap().len(), 1);
+    }
+}
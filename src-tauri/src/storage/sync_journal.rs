@@ -0,0 +1,224 @@
+use super::traits::{TokenData, Tombstone, SyncWatermark};
+use super::encryption::{TokenCipher, is_encrypted_content};
+use super::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::fs;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use tauri::Manager;
+
+/// 同步进行到的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncJournalPhase {
+    /// 合并结果已暂存，数据库还没有确认提交
+    Staged,
+    /// 数据库已提交，本地存储还没有写入
+    RemoteCommitted,
+}
+
+/// 一次同步暂存的本地写入，本地写入完成后删除
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncJournalEntry {
+    pub id: String,
+    pub direction: String,
+    pub started_at: DateTime<Utc>,
+    pub phase: SyncJournalPhase,
+    /// 需要写入本地存储的tokens
+    pub tokens: Vec<TokenData>,
+    pub tombstones: Vec<Tombstone>,
+    /// 双向同步完成后保存的水位线
+    pub watermark: Option<SyncWatermark>,
+}
+
+impl SyncJournalEntry {
+    pub fn new(direction: &str, tokens: Vec<TokenData>, tombstones: Vec<Tombstone>, watermark: Option<SyncWatermark>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            direction: direction.to_string(),
+            started_at: Utc::now(),
+            phase: SyncJournalPhase::Staged,
+            tokens,
+            tombstones,
+            watermark,
+        }
+    }
+}
+
+/// 恢复中断的同步的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRecovery {
+    pub direction: String,
+    pub started_at: DateTime<Utc>,
+    /// true 表示补完了本地写入，false 表示放弃了这次同步
+    pub completed: bool,
+}
+
+/// 同步日志：写入任何一端之前先记下本地要写入的内容
+///
+/// 同步被中断时，数据库已提交的由恢复流程补完本地写入，否则放弃这次同步。
+/// 日志中包含完整的token，设置口令后与 tokens.json 一样加密保存。
+#[derive(Clone)]
+pub struct SyncJournal {
+    path: PathBuf,
+    cipher: Option<Arc<TokenCipher>>,
+    // 克隆之间共享，保证读取-修改-写入互斥
+    lock: Arc<Mutex<()>>,
+}
+
+impl SyncJournal {
+    pub fn new(app_handle: &tauri::AppHandle) -> StorageResult<Self> {
+        let app_data_dir = app_handle.path().app_data_dir()?;
+        fs::create_dir_all(&app_data_dir)?;
+
+        Ok(Self::new_with_path(app_data_dir.join("sync_journal.json")))
+    }
+
+    pub fn new_with_path(path: PathBuf) -> Self {
+        Self {
+            path,
+            cipher: None,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn with_cipher(mut self, cipher: Arc<TokenCipher>) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// 记录一次新的同步，还有未完成的同步时返回 Conflict
+    pub async fn begin(&self, entry: &SyncJournalEntry) -> StorageResult<()> {
+        let entry = entry.clone();
+        self.with_lock(move |journal| {
+            if let Some(pending) = journal.read_locked()? {
+                return Err(StorageError::Conflict(format!(
+                    "Sync started at {} has not been recovered yet", pending.started_at
+                )));
+            }
+            journal.write_locked(&entry)
+        }).await
+    }
+
+    /// 记录数据库已经提交
+    pub async fn mark_remote_committed(&self, id: &str) -> StorageResult<()> {
+        let id = id.to_string();
+        self.with_lock(move |journal| {
+            let mut entry = journal.read_locked()?
+                .filter(|entry| entry.id == id)
+                .ok_or_else(|| StorageError::NotFound(format!("Sync journal entry {} not found", id)))?;
+            entry.phase = SyncJournalPhase::RemoteCommitted;
+            journal.write_locked(&entry)
+        }).await
+    }
+
+    /// 未完成的同步
+    pub async fn pending(&self) -> StorageResult<Option<SyncJournalEntry>> {
+        self.with_lock(|journal| journal.read_locked()).await
+    }
+
+    /// 同步完成或被放弃后删除日志
+    pub async fn finish(&self, id: &str) -> StorageResult<()> {
+        let id = id.to_string();
+        self.with_lock(move |journal| {
+            if journal.read_locked()?.is_some_and(|entry| entry.id == id) {
+                fs::remove_file(&journal.path)?;
+            }
+            Ok(())
+        }).await
+    }
+
+    /// 持有锁在阻塞线程池中执行文件读写，不阻塞异步运行时
+    async fn with_lock<F, T>(&self, f: F) -> StorageResult<T>
+    where
+        F: FnOnce(&SyncJournal) -> StorageResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let journal = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = journal.lock.lock().unwrap();
+            f(&journal)
+        }).await?
+    }
+
+    // 调用方需持有 lock
+    fn read_locked(&self) -> StorageResult<Option<SyncJournalEntry>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&self.path)?;
+        if content.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let content = if is_encrypted_content(&content) {
+            let cipher = self.cipher.as_ref()
+                .ok_or_else(|| StorageError::Locked("Sync journal is encrypted, passphrase required".to_string()))?;
+            cipher.decrypt(&content)?
+        } else {
+            content
+        };
+
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| StorageError::Corrupt(format!("Invalid sync journal: {}", e)))
+    }
+
+    // 调用方需持有 lock
+    fn write_locked(&self, entry: &SyncJournalEntry) -> StorageResult<()> {
+        let json = serde_json::to_string(entry)?;
+        let content = match &self.cipher {
+            Some(cipher) => cipher.encrypt(&json)?,
+            None => json,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = self.path.with_extension("tmp");
+        {
+            let mut temp_file = fs::File::create(&temp_path)?;
+            temp_file.write_all(content.as_bytes())?;
+            temp_file.sync_all()?;
+        }
+        fs::rename(&temp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_sync_journal_lifecycle() {
+        let dir = tempdir().unwrap();
+        let journal = SyncJournal::new_with_path(dir.path().join("sync_journal.json"));
+        assert!(journal.pending().await.unwrap().is_none());
+
+        let token = TokenData::new("a".to_string(), "https://example.com".to_string(), "token".to_string(), None, None);
+        let entry = SyncJournalEntry::new("bidirectional", vec![token], Vec::new(), None);
+        journal.begin(&entry).await.unwrap();
+
+        // 同一时间只能有一次未完成的同步
+        let other = SyncJournalEntry::new("bidirectional", Vec::new(), Vec::new(), None);
+        assert_eq!(journal.begin(&other).await.unwrap_err().code(), "conflict");
+
+        journal.mark_remote_committed(&entry.id).await.unwrap();
+        let pending = SyncJournal::new_with_path(dir.path().join("sync_journal.json")).pending().await.unwrap().unwrap();
+        assert_eq!(pending.phase, SyncJournalPhase::RemoteCommitted);
+        assert_eq!(pending.tokens.len(), 1);
+
+        // 其他同步的ID不会删除当前日志
+        journal.finish(&other.id).await.unwrap();
+        assert!(journal.pending().await.unwrap().is_some());
+        journal.finish(&entry.id).await.unwrap();
+        assert!(journal.pending().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,160 @@
+use super::traits::{TokenData, Tombstone, SyncConflict, SyncError};
+use super::history::diff_tokens;
+use super::snapshot::{TokenSummary, ChangedToken};
+use super::error::StorageResult;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// 同步方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncDirection {
+    LocalToRemote,
+    RemoteToLocal,
+    Bidirectional,
+}
+
+impl SyncDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncDirection::LocalToRemote => "local_to_remote",
+            SyncDirection::RemoteToLocal => "remote_to_local",
+            SyncDirection::Bidirectional => "bidirectional",
+        }
+    }
+}
+
+/// 同步会对一端做出的修改
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoreChanges {
+    pub to_add: Vec<TokenSummary>,
+    /// 字段差异中 old 为该端当前的值
+    pub to_update: Vec<ChangedToken>,
+    pub to_delete: Vec<TokenSummary>,
+}
+
+impl StoreChanges {
+    /// 计算写入 `writes` 并应用 `tombstones` 后该端的变化，`before` 需包含所有受影响的tokens
+    ///
+    /// 与现有内容相同的写入不计入；墓碑与写入的token相同时以写入为准，与 `apply_changes` 一致。
+    pub fn between(before: &[TokenData], writes: &[TokenData], tombstones: &[Tombstone]) -> Self {
+        let before_by_id: HashMap<&str, &TokenData> = before.iter().map(|token| (token.id.as_str(), token)).collect();
+
+        let mut changes = StoreChanges::default();
+        for token in writes {
+            match before_by_id.get(token.id.as_str()) {
+                None => changes.to_add.push(token.into()),
+                Some(old) => {
+                    let field_changes = diff_tokens(Some(old), Some(token));
+                    if !field_changes.is_empty() {
+                        changes.to_update.push(ChangedToken {
+                            id: token.id.clone(),
+                            email_note: token.email_note.clone(),
+                            changes: field_changes,
+                        });
+                    }
+                }
+            }
+        }
+
+        let written_ids: HashSet<&str> = writes.iter().map(|token| token.id.as_str()).collect();
+        let mut deleted_ids = HashSet::new();
+        for tombstone in tombstones {
+            let token_id = tombstone.token_id.as_str();
+            if written_ids.contains(token_id) || !deleted_ids.insert(token_id) {
+                continue;
+            }
+            if let Some(token) = before_by_id.get(token_id) {
+                changes.to_delete.push((*token).into());
+            }
+        }
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.to_add.is_empty() && self.to_update.is_empty() && self.to_delete.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.to_add.len() + self.to_update.len() + self.to_delete.len()
+    }
+}
+
+/// 一次同步的预演结果，不修改任何一端
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncPlan {
+    pub direction: SyncDirection,
+    /// 本地存储将发生的变化
+    pub local: StoreChanges,
+    /// 数据库将发生的变化
+    pub remote: StoreChanges,
+    pub conflicts: Vec<SyncConflict>,
+    /// 无法读取、本次不会同步的tokens
+    pub errors: Vec<SyncError>,
+    /// 计划内容的指纹，执行前重新计算，两端在此期间有变化时拒绝执行
+    pub fingerprint: String,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.local.is_empty() && self.remote.is_empty()
+    }
+}
+
+/// 计算计划的指纹，覆盖两端的实际写入、展示给用户的差异和远程水位线
+pub(crate) fn plan_fingerprint(
+    plan: &SyncPlan,
+    writes: &[(&[TokenData], &[Tombstone])],
+    remote_change_seq: Option<i64>,
+) -> StorageResult<String> {
+    let content = serde_json::to_string(&(
+        plan.direction,
+        &plan.local,
+        &plan.remote,
+        &plan.conflicts,
+        &plan.errors,
+        writes,
+        remote_change_seq,
+    ))?;
+    Ok(hex::encode(Sha256::digest(content.as_bytes())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(id: &str, email: &str) -> TokenData {
+        let mut token = TokenData::new(id.to_string(), "https://example.com".to_string(), format!("token-{}", id), None, None);
+        token.email_note = Some(email.to_string());
+        token
+    }
+
+    #[test]
+    fn test_store_changes_between() {
+        let before = vec![token("a", "a@example.com"), token("b", "b@example.com"), token("c", "c@example.com")];
+
+        let mut updated = before[1].clone();
+        updated.email_note = Some("new@example.com".to_string());
+        let writes = vec![token("d", "d@example.com"), updated, before[0].clone()];
+        let tombstones = vec![
+            Tombstone::new("c".to_string(), "device".to_string()),
+            Tombstone::new("missing".to_string(), "device".to_string()),
+        ];
+
+        let changes = StoreChanges::between(&before, &writes, &tombstones);
+
+        assert_eq!(changes.to_add.len(), 1);
+        assert_eq!(changes.to_add[0].id, "d");
+        // 内容未变的写入不算更新
+        assert_eq!(changes.to_update.len(), 1);
+        assert_eq!(changes.to_update[0].id, "b");
+        assert_eq!(changes.to_update[0].changes[0].field, "email_note");
+        assert_eq!(changes.to_update[0].changes[0].old, serde_json::json!("b@example.com"));
+        // 该端不存在的token没有可删除的
+        assert_eq!(changes.to_delete.len(), 1);
+        assert_eq!(changes.to_delete[0].id, "c");
+        assert_eq!(changes.len(), 3);
+    }
+}
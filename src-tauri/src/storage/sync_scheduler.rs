@@ -0,0 +1,346 @@
+use super::traits::{SyncManager, SyncStatus, SyncError};
+use super::error::{StorageError, StorageResult};
+use super::dual_storage::DualStorage;
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, Weak};
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::Notify;
+
+// 数据库不可达时第一次重试前等待的秒数，之后每次翻倍
+const INITIAL_BACKOFF_SECONDS: i64 = 30;
+// 调度循环每次最多等待这么久，醒来后检查存储管理器是否已被替换或释放
+const STORAGE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// 自动同步设置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncSchedule {
+    /// 定时同步间隔（分钟），0 表示不定时同步
+    pub interval_minutes: u32,
+    /// 本地修改后等待的秒数，期间的修改合并为一次同步；0 表示修改后不自动同步
+    pub debounce_seconds: u32,
+    /// 数据库不可达时重试间隔的上限（分钟）
+    pub max_backoff_minutes: u32,
+    /// 暂停后不再自动同步，手动同步不受影响
+    pub paused: bool,
+}
+
+impl Default for SyncSchedule {
+    fn default() -> Self {
+        Self {
+            interval_minutes: 15,
+            debounce_seconds: 10,
+            max_backoff_minutes: 60,
+            paused: false,
+        }
+    }
+}
+
+/// 自动同步的当前状态
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncSchedulerState {
+    pub paused: bool,
+    pub running: bool,
+    /// 下次自动同步的时间，暂停或没有待执行的同步时为空
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    /// 最近一次自动同步的结果，同步失败时为空
+    pub last_result: Option<SyncStatus>,
+    /// 最近一次自动同步的错误，成功后清除
+    pub last_error: Option<SyncError>,
+    /// 数据库连续不可达的次数
+    pub consecutive_failures: u32,
+}
+
+/// `get_sync_status` 的返回内容
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncOverview {
+    /// 数据库中记录的最近一次同步
+    pub last_sync: Option<SyncStatus>,
+    /// 未配置数据库时为空
+    pub scheduler: Option<SyncSchedulerState>,
+}
+
+#[derive(Debug, Default)]
+struct SchedulerInner {
+    state: SyncSchedulerState,
+    // 下次定时同步的时间
+    scheduled_at: Option<DateTime<Utc>>,
+    // 本地修改触发的同步时间
+    change_due_at: Option<DateTime<Utc>>,
+    // 退避结束前不同步
+    backoff_until: Option<DateTime<Utc>>,
+}
+
+/// 存储管理器的自动同步计划：定时同步、本地修改后的延迟同步和数据库不可达时的指数退避
+pub struct SyncScheduler {
+    schedule: Mutex<SyncSchedule>,
+    inner: Mutex<SchedulerInner>,
+    // 本地修改、暂停、恢复或修改设置时唤醒调度循环重新计算等待时间
+    wake: Notify,
+}
+
+impl SyncScheduler {
+    pub fn new(schedule: SyncSchedule) -> Self {
+        let scheduler = Self {
+            schedule: Mutex::new(schedule),
+            inner: Mutex::new(SchedulerInner::default()),
+            wake: Notify::new(),
+        };
+        scheduler.reschedule(Utc::now());
+        scheduler
+    }
+
+    pub fn schedule(&self) -> SyncSchedule {
+        *self.schedule.lock().unwrap()
+    }
+
+    /// 修改设置，下次定时同步从现在重新计时
+    pub fn set_schedule(&self, schedule: SyncSchedule) {
+        *self.schedule.lock().unwrap() = schedule;
+        self.reschedule(Utc::now());
+        self.wake.notify_one();
+    }
+
+    pub fn pause(&self) {
+        self.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.set_paused(false);
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.schedule.lock().unwrap().paused = paused;
+        let mut inner = self.inner.lock().unwrap();
+        inner.state.paused = paused;
+        // 恢复时立即重试，不再等待之前的退避
+        if !paused {
+            inner.backoff_until = None;
+        }
+        self.refresh_next_run(&mut inner);
+        drop(inner);
+        self.wake.notify_one();
+    }
+
+    pub fn state(&self) -> SyncSchedulerState {
+        self.inner.lock().unwrap().state.clone()
+    }
+
+    /// 记录一次本地修改，在延迟时间内没有新的修改时同步
+    pub fn local_changed(&self, now: DateTime<Utc>) {
+        let debounce_seconds = self.schedule().debounce_seconds;
+        if debounce_seconds == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.change_due_at = Some(now + Duration::seconds(debounce_seconds as i64));
+        self.refresh_next_run(&mut inner);
+        drop(inner);
+        self.wake.notify_one();
+    }
+
+    fn reschedule(&self, now: DateTime<Utc>) {
+        let schedule = self.schedule();
+        let mut inner = self.inner.lock().unwrap();
+        inner.state.paused = schedule.paused;
+        inner.scheduled_at = (schedule.interval_minutes > 0)
+            .then(|| now + Duration::minutes(schedule.interval_minutes as i64));
+        self.refresh_next_run(&mut inner);
+    }
+
+    // 下次同步取定时和本地修改中较早的一个，但不早于退避结束
+    fn refresh_next_run(&self, inner: &mut SchedulerInner) {
+        let next = match (inner.scheduled_at, inner.change_due_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        inner.state.next_run_at = if inner.state.paused {
+            None
+        } else {
+            next.map(|next| inner.backoff_until.map_or(next, |until| next.max(until)))
+        };
+    }
+
+    fn next_run_at(&self) -> Option<DateTime<Utc>> {
+        self.inner.lock().unwrap().state.next_run_at
+    }
+
+    fn begin_run(&self, now: DateTime<Utc>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state.running = true;
+        inner.state.last_run_at = Some(now);
+    }
+
+    /// 记录一次自动同步的结果并计算下次同步的时间
+    fn finish_run(&self, started_at: DateTime<Utc>, now: DateTime<Utc>, result: StorageResult<SyncStatus>) {
+        let schedule = self.schedule();
+        let mut inner = self.inner.lock().unwrap();
+        inner.state.running = false;
+        inner.scheduled_at = (schedule.interval_minutes > 0)
+            .then(|| now + Duration::minutes(schedule.interval_minutes as i64));
+
+        match result {
+            Ok(status) => {
+                inner.state.last_result = Some(status);
+                inner.state.last_error = None;
+                inner.state.consecutive_failures = 0;
+                inner.backoff_until = None;
+            }
+            // 数据库不可达时按指数退避重试，本地修改留到重试时一起同步
+            Err(e @ StorageError::Unavailable(_)) => {
+                inner.state.consecutive_failures += 1;
+                let backoff = backoff_delay(inner.state.consecutive_failures, schedule.max_backoff_minutes);
+                inner.backoff_until = Some(now + backoff);
+                inner.state.last_result = None;
+                inner.state.last_error = Some(SyncError::new(None, &e));
+                self.refresh_next_run(&mut inner);
+                return;
+            }
+            Err(e) => {
+                inner.state.last_result = None;
+                inner.state.last_error = Some(SyncError::new(None, &e));
+                inner.state.consecutive_failures = 0;
+                inner.backoff_until = None;
+            }
+        }
+
+        // 同步期间又有本地修改时保留，延迟结束后再同步一次
+        if inner.change_due_at.is_some_and(|due| due <= started_at) {
+            inner.change_due_at = None;
+        }
+        self.refresh_next_run(&mut inner);
+    }
+}
+
+/// 第 n 次连续失败后的等待时间：30秒起每次翻倍，不超过上限
+fn backoff_delay(failures: u32, max_backoff_minutes: u32) -> Duration {
+    let max = Duration::minutes(max_backoff_minutes.max(1) as i64);
+    let delay = Duration::seconds(INITIAL_BACKOFF_SECONDS) * 2_i32.saturating_pow(failures.saturating_sub(1).min(16));
+    delay.min(max)
+}
+
+/// 按存储管理器的同步计划执行自动同步，存储管理器被替换或释放后退出
+pub async fn run_sync_scheduler(storage: Weak<DualStorage>) {
+    loop {
+        let Some(scheduler) = storage.upgrade().and_then(|storage| storage.sync_scheduler()) else {
+            return;
+        };
+
+        // 等待到下次同步，期间被唤醒或等待超过检查间隔时重新计算；
+        // 暂停或没有待执行的同步时也定期醒来，存储管理器被替换后不会一直挂起
+        let wait = scheduler.next_run_at()
+            .map_or(STORAGE_CHECK_INTERVAL, |next_run_at| {
+                (next_run_at - Utc::now()).to_std().unwrap_or_default().min(STORAGE_CHECK_INTERVAL)
+            });
+        if !wait.is_zero() {
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = scheduler.wake.notified() => {}
+            }
+            continue;
+        }
+
+        let Some(storage) = storage.upgrade() else {
+            return;
+        };
+
+        let started_at = Utc::now();
+        scheduler.begin_run(started_at);
+        let result = storage.bidirectional_sync().await;
+        match &result {
+            Ok(status) => println!("Automatic sync completed: {} tokens synced", status.tokens_synced),
+            Err(e) => eprintln!("Automatic sync failed [{}]: {}", e.code(), e),
+        }
+        scheduler.finish_run(started_at, Utc::now(), result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> SyncStatus {
+        SyncStatus {
+            id: None,
+            last_sync_at: Some(Utc::now()),
+            sync_direction: "bidirectional".to_string(),
+            status: "success".to_string(),
+            error_message: None,
+            tokens_synced: 1,
+            conflicts: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(1, 60), Duration::seconds(30));
+        assert_eq!(backoff_delay(2, 60), Duration::seconds(60));
+        assert_eq!(backoff_delay(4, 60), Duration::seconds(240));
+        assert_eq!(backoff_delay(20, 60), Duration::minutes(60));
+    }
+
+    #[test]
+    fn test_scheduler_debounce_and_backoff() {
+        let scheduler = SyncScheduler::new(SyncSchedule {
+            interval_minutes: 15,
+            debounce_seconds: 10,
+            max_backoff_minutes: 60,
+            paused: false,
+        });
+        let now = Utc::now();
+
+        // 本地修改后的同步早于定时同步，连续修改会推迟同步
+        scheduler.local_changed(now);
+        scheduler.local_changed(now + Duration::seconds(5));
+        assert_eq!(scheduler.next_run_at(), Some(now + Duration::seconds(15)));
+
+        // 数据库不可达时退避，本地修改保留到重试
+        let started_at = now + Duration::seconds(15);
+        scheduler.begin_run(started_at);
+        scheduler.finish_run(started_at, started_at, Err(StorageError::Unavailable("Database not available".to_string())));
+        let state = scheduler.state();
+        assert_eq!(state.consecutive_failures, 1);
+        assert_eq!(state.last_error.unwrap().code, "unavailable");
+        assert_eq!(state.next_run_at, Some(started_at + Duration::seconds(30)));
+
+        // 成功后清除本地修改和退避，回到定时同步
+        let started_at = started_at + Duration::seconds(30);
+        scheduler.finish_run(started_at, started_at, Ok(status()));
+        let state = scheduler.state();
+        assert_eq!(state.consecutive_failures, 0);
+        assert!(state.last_result.is_some());
+        assert_eq!(state.next_run_at, Some(started_at + Duration::minutes(15)));
+
+        scheduler.pause();
+        assert!(scheduler.state().next_run_at.is_none());
+        assert!(scheduler.schedule().paused);
+        scheduler.resume();
+        assert!(scheduler.state().next_run_at.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scheduler_exits_after_storage_dropped_while_paused() {
+        use crate::storage::{LocalFileStorage, PostgreSQLStorage};
+        use std::sync::Arc;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let local_storage = Arc::new(LocalFileStorage::new_with_path(temp_dir.path().join("test_tokens.json")));
+        let db_manager = Arc::new(crate::database::DatabaseManager::new(crate::database::DatabaseConfig::new(
+            "localhost".to_string(), 5432, "test".to_string(), "postgres".to_string(), "password".to_string(),
+        )));
+        let storage = Arc::new(DualStorage::new(local_storage, Some(Arc::new(PostgreSQLStorage::new(db_manager))))
+            .with_sync_schedule(SyncSchedule { paused: true, ..SyncSchedule::default() }));
+
+        let task = tokio::spawn(run_sync_scheduler(Arc::downgrade(&storage)));
+        tokio::task::yield_now().await;
+        assert!(!task.is_finished());
+
+        // 暂停时没有唤醒，存储管理器被替换后调度循环仍会在下次检查时退出
+        drop(storage);
+        tokio::time::advance(STORAGE_CHECK_INTERVAL).await;
+        tokio::time::timeout(std::time::Duration::from_secs(1), task).await.unwrap().unwrap();
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use super::error::{StorageError, StorageResult};
+use super::query::{TokenFilter, TokenSort, PageRequest, TokenPage, query_in_memory};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenData {
@@ -16,6 +18,15 @@ pub struct TokenData {
     pub suspensions: Option<serde_json::Value>,
     pub balance_color_mode: Option<String>,
     pub skip_check: Option<bool>,
+    /// 自定义标签，如项目、负责人
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 所属分组
+    #[serde(default)]
+    pub group: Option<String>,
+    /// 最近一次从数据库读到的行版本，0 表示尚未写入数据库；更新时用于检测并发修改
+    #[serde(default)]
+    pub version: i64,
 }
 
 impl TokenData {
@@ -41,36 +52,275 @@ impl TokenData {
             suspensions: None,
             balance_color_mode: None,
             skip_check: None,
+            tags: Vec::new(),
+            group: None,
+            version: 0,
         }
     }
 
     pub fn update_timestamp(&mut self) {
         self.updated_at = Utc::now();
     }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// 去掉标签首尾空白、空标签和重复标签，保留原有顺序
+pub fn normalize_tags<I, S>(tags: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.as_ref().trim();
+        if !tag.is_empty() && !normalized.iter().any(|existing| existing == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    normalized
+}
+
+/// 删除记录（墓碑），用于在双向同步时阻止已删除的token被重新合并回来
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub token_id: String,
+    pub deleted_at: DateTime<Utc>,
+    /// 执行删除的设备ID
+    pub origin: String,
+}
+
+impl Tombstone {
+    pub fn new(token_id: String, origin: String) -> Self {
+        Self {
+            token_id,
+            deleted_at: Utc::now(),
+            origin,
+        }
+    }
+}
+
+/// 合并两组墓碑，同一token保留最新的删除记录
+pub fn merge_tombstones(a: Vec<Tombstone>, b: Vec<Tombstone>) -> Vec<Tombstone> {
+    use std::collections::HashMap;
+
+    let mut merged: HashMap<String, Tombstone> = HashMap::new();
+    for tombstone in a.into_iter().chain(b) {
+        match merged.get(&tombstone.token_id) {
+            Some(existing) if existing.deleted_at >= tombstone.deleted_at => {}
+            _ => {
+                merged.insert(tombstone.token_id.clone(), tombstone);
+            }
+        }
+    }
+
+    merged.into_values().collect()
+}
+
+/// 过滤掉被墓碑覆盖的tokens，删除之后又更新过的token会保留
+pub fn apply_tombstones(tokens: Vec<TokenData>, tombstones: &[Tombstone]) -> Vec<TokenData> {
+    use std::collections::HashMap;
+
+    let deleted_at: HashMap<&str, DateTime<Utc>> = tombstones.iter()
+        .map(|t| (t.token_id.as_str(), t.deleted_at))
+        .collect();
+
+    tokens.into_iter()
+        .filter(|token| match deleted_at.get(token.id.as_str()) {
+            Some(deleted_at) => token.updated_at > *deleted_at,
+            None => true,
+        })
+        .collect()
+}
+
+/// 三方合并时两端都修改过、无法自动合并的字段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncConflict {
+    pub token_id: String,
+    pub field: String,
+    pub local_value: serde_json::Value,
+    pub remote_value: serde_json::Value,
+    /// 最终采用的一端："local" 或 "remote"
+    pub resolved_with: String,
+}
+
+/// 增量同步水位线：上次同步时看到的远程最大 change_seq 和读取时仍未提交的最早事务
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncWatermark {
+    /// 水位线所属的数据库，切换数据库后水位线失效
+    pub database_id: String,
+    pub remote_change_seq: i64,
+    /// 下次从这个事务ID开始读取；旧版本保存的水位线没有，下次同步读取全部
+    #[serde(default)]
+    pub remote_xmin: Option<i64>,
+}
+
+/// 同步中的一条错误，`token_id` 为空表示整次同步失败
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncError {
+    pub token_id: Option<String>,
+    /// 与 `StorageError::code` 相同的错误码
+    pub code: String,
+    pub message: String,
+}
+
+impl SyncError {
+    pub fn new(token_id: Option<String>, error: &StorageError) -> Self {
+        Self {
+            token_id,
+            code: error.code().to_string(),
+            message: error.to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncStatus {
+    /// 数据库中同步记录的ID，尚未记录时为空
+    #[serde(default)]
+    pub id: Option<i32>,
     pub last_sync_at: Option<DateTime<Utc>>,
     pub sync_direction: String,
+    /// "success"、"partial"（部分token出错）或 "failed"
     pub status: String,
+    /// 错误摘要，详细信息见 `errors`
     pub error_message: Option<String>,
     pub tokens_synced: i32,
+    #[serde(default)]
+    pub conflicts: Vec<SyncConflict>,
+    #[serde(default)]
+    pub errors: Vec<SyncError>,
+}
+
+/// 一页同步历史，按时间倒序
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncHistoryPage {
+    pub entries: Vec<SyncStatus>,
+    pub total: usize,
+}
+
+/// 一个同步方向的统计
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncDirectionStats {
+    pub sync_direction: String,
+    pub sync_count: i64,
+    pub failure_count: i64,
+    /// 失败次数占比，0 到 1
+    pub failure_rate: f64,
+    /// 成功（含部分成功）的同步平均写入的token数
+    pub average_tokens_synced: f64,
 }
 
 #[async_trait::async_trait]
 pub trait TokenStorage: Send + Sync {
-    async fn save_token(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    async fn save_token(&self, token: &TokenData) -> StorageResult<()>;
     
-    async fn load_tokens(&self) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn load_tokens(&self) -> StorageResult<Vec<TokenData>>;
     
-    async fn update_token(&self, token: &TokenData) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    async fn update_token(&self, token: &TokenData) -> StorageResult<()>;
     
-    async fn delete_token(&self, token_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+    async fn delete_token(&self, token_id: &str) -> StorageResult<bool>;
     
-    async fn get_token(&self, token_id: &str) -> Result<Option<TokenData>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn get_token(&self, token_id: &str) -> StorageResult<Option<TokenData>>;
     
-    async fn clear_all_tokens(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    async fn clear_all_tokens(&self) -> StorageResult<()>;
+
+    /// 批量保存（插入或更新）tokens，后端应尽量在一次事务或一次写入中完成
+    async fn save_tokens(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        for token in tokens {
+            self.save_token(token).await?;
+        }
+        Ok(())
+    }
+
+    /// 批量删除tokens，返回实际删除的数量
+    async fn delete_tokens(&self, token_ids: &[String]) -> StorageResult<usize> {
+        let mut deleted = 0;
+        for token_id in token_ids {
+            if self.delete_token(token_id).await? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// 按条件查询tokens，返回当前页和满足条件的总数；默认在内存中过滤
+    async fn query_tokens(&self, filter: &TokenFilter, sort: &TokenSort, page: &PageRequest) -> StorageResult<TokenPage> {
+        Ok(query_in_memory(self.load_tokens().await?, filter, sort, page))
+    }
+
+    /// 用给定的tokens替换全部数据
+    async fn replace_all(&self, tokens: &[TokenData]) -> StorageResult<()> {
+        self.clear_all_tokens().await?;
+        self.save_tokens(tokens).await
+    }
+
+    /// 读取所有删除墓碑，不支持墓碑的后端返回空列表
+    async fn load_tombstones(&self) -> StorageResult<Vec<Tombstone>> {
+        Ok(Vec::new())
+    }
+
+    /// 保存（插入或更新）删除墓碑
+    async fn save_tombstones(&self, _tombstones: &[Tombstone]) -> StorageResult<()> {
+        Ok(())
+    }
+
+    /// 清理早于指定时间的墓碑，返回清理的数量
+    async fn purge_tombstones(&self, _older_than: DateTime<Utc>) -> StorageResult<usize> {
+        Ok(0)
+    }
+
+    /// 读取上次双向同步后的基准快照，用于三方合并
+    async fn load_sync_base(&self) -> StorageResult<Vec<TokenData>> {
+        Ok(Vec::new())
+    }
+
+    /// 保存双向同步后的基准快照
+    async fn save_sync_base(&self, _tokens: &[TokenData]) -> StorageResult<()> {
+        Ok(())
+    }
+
+    /// 读取增量同步水位线
+    async fn load_sync_watermark(&self) -> StorageResult<Option<SyncWatermark>> {
+        Ok(None)
+    }
+
+    /// 保存增量同步水位线
+    async fn save_sync_watermark(&self, _watermark: &SyncWatermark) -> StorageResult<()> {
+        Ok(())
+    }
+
+    /// 写入墓碑、删除被墓碑覆盖的tokens并写入给定的tokens，返回删除的数量
+    ///
+    /// `tokens` 中出现的ID不会被删除，即墓碑之后又更新过的token会保留。
+    async fn apply_changes(&self, tokens: &[TokenData], tombstones: &[Tombstone]) -> StorageResult<usize> {
+        self.save_tombstones(tombstones).await?;
+
+        let deleted_ids: Vec<String> = tombstones.iter()
+            .filter(|t| !tokens.iter().any(|token| token.id == t.token_id))
+            .map(|t| t.token_id.clone())
+            .collect();
+        let deleted = self.delete_tokens(&deleted_ids).await?;
+
+        self.save_tokens(tokens).await?;
+        Ok(deleted)
+    }
+
+    /// 写入一次同步的结果，返回删除的数量
+    ///
+    /// 给出水位线时以写入后的全部tokens作为新的同步基准，并保存水位线。
+    /// 默认实现依次调用上面的方法，不保证原子性；本地后端应在一次写入中完成。
+    async fn commit_sync(&self, tokens: &[TokenData], tombstones: &[Tombstone], watermark: Option<&SyncWatermark>) -> StorageResult<usize> {
+        let deleted = self.apply_changes(tokens, tombstones).await?;
+        if let Some(watermark) = watermark {
+            let synced_tokens = self.load_tokens().await?;
+            self.save_sync_base(&synced_tokens).await?;
+            self.save_sync_watermark(watermark).await?;
+        }
+        Ok(deleted)
+    }
     
     fn storage_type(&self) -> &'static str;
     
@@ -79,34 +329,80 @@ pub trait TokenStorage: Send + Sync {
 
 #[async_trait::async_trait]
 pub trait SyncManager: Send + Sync {
-    async fn sync_local_to_remote(&self) -> Result<SyncStatus, Box<dyn std::error::Error + Send + Sync>>;
+    async fn sync_local_to_remote(&self) -> StorageResult<SyncStatus>;
 
-    async fn sync_remote_to_local(&self) -> Result<SyncStatus, Box<dyn std::error::Error + Send + Sync>>;
+    async fn sync_remote_to_local(&self) -> StorageResult<SyncStatus>;
 
-    async fn bidirectional_sync(&self) -> Result<SyncStatus, Box<dyn std::error::Error + Send + Sync>>;
+    async fn bidirectional_sync(&self) -> StorageResult<SyncStatus>;
 
-    async fn bidirectional_sync_with_tokens(&self, local_tokens: Vec<TokenData>) -> Result<SyncStatus, Box<dyn std::error::Error + Send + Sync>>;
+    async fn bidirectional_sync_with_tokens(&self, local_tokens: Vec<TokenData>) -> StorageResult<SyncStatus>;
 
-    async fn get_sync_status(&self) -> Result<Option<SyncStatus>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn get_sync_status(&self) -> StorageResult<Option<SyncStatus>>;
 
-    async fn resolve_conflicts(&self, local_tokens: Vec<TokenData>, remote_tokens: Vec<TokenData>) -> Result<Vec<TokenData>, Box<dyn std::error::Error + Send + Sync>>;
+    async fn resolve_conflicts(&self, local_tokens: Vec<TokenData>, remote_tokens: Vec<TokenData>) -> StorageResult<Vec<TokenData>>;
+}
+
+/// 旧格式token中可识别的字段，与 `convert_to_legacy_format` 输出的字段一致
+pub const LEGACY_TOKEN_FIELDS: &[&str] = &[
+    "id", "tenant_url", "access_token", "created_at", "updated_at", "portal_url", "email_note",
+    "ban_status", "portal_info", "auth_session", "suspensions", "balance_color_mode", "skip_check",
+    "tags", "group", "version",
+];
+
+/// 严格转换前端提交的整组token
+///
+/// 任一条目缺少必填字段、不是对象或带有未知字段时都不写入，
+/// 返回 InvalidInput 并逐条列出出错的条目序号、ID和原因，避免静默丢弃数据。
+pub fn convert_legacy_tokens_strict(values: &[serde_json::Value]) -> StorageResult<Vec<TokenData>> {
+    let mut tokens = Vec::with_capacity(values.len());
+    let mut problems = Vec::new();
+
+    for (index, value) in values.iter().enumerate() {
+        let label = match value.get("id").and_then(|v| v.as_str()) {
+            Some(id) => format!("entry {} (id {})", index, id),
+            None => format!("entry {}", index),
+        };
+        let Some(object) = value.as_object() else {
+            problems.push(format!("{}: not an object", label));
+            continue;
+        };
+
+        let unknown: Vec<&str> = object.keys()
+            .map(String::as_str)
+            .filter(|key| !LEGACY_TOKEN_FIELDS.contains(key))
+            .collect();
+        if !unknown.is_empty() {
+            problems.push(format!("{}: unknown fields {}", label, unknown.join(", ")));
+            continue;
+        }
+
+        match convert_legacy_token(value) {
+            Ok(token) => tokens.push(token),
+            Err(e) => problems.push(format!("{}: {}", label, e)),
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(StorageError::InvalidInput(format!("Invalid tokens: {}", problems.join("; "))));
+    }
+    Ok(tokens)
 }
 
 // 辅助函数：将旧格式的token转换为新格式
-pub fn convert_legacy_token(legacy: &serde_json::Value) -> Result<TokenData, Box<dyn std::error::Error + Send + Sync>> {
+pub fn convert_legacy_token(legacy: &serde_json::Value) -> StorageResult<TokenData> {
     let id = legacy.get("id")
         .and_then(|v| v.as_str())
-        .ok_or("Missing id field")?
+        .ok_or_else(|| StorageError::Corrupt("Missing id field".to_string()))?
         .to_string();
     
     let tenant_url = legacy.get("tenant_url")
         .and_then(|v| v.as_str())
-        .ok_or("Missing tenant_url field")?
+        .ok_or_else(|| StorageError::Corrupt("Missing tenant_url field".to_string()))?
         .to_string();
     
     let access_token = legacy.get("access_token")
         .and_then(|v| v.as_str())
-        .ok_or("Missing access_token field")?
+        .ok_or_else(|| StorageError::Corrupt("Missing access_token field".to_string()))?
         .to_string();
     
     let created_at = legacy.get("created_at")
@@ -140,6 +436,17 @@ pub fn convert_legacy_token(legacy: &serde_json::Value) -> Result<TokenData, Box
         .map(|s| s.to_string());
     let skip_check = legacy.get("skip_check")
         .and_then(|v| v.as_bool());
+    let tags = legacy.get("tags")
+        .and_then(|v| v.as_array())
+        .map(|values| normalize_tags(values.iter().filter_map(|v| v.as_str())))
+        .unwrap_or_default();
+    let group = legacy.get("group")
+        .and_then(|v| v.as_str())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    let version = legacy.get("version")
+        .and_then(|v| v.as_i64())
+        .unwrap_or(0);
 
     Ok(TokenData {
         id,
@@ -155,6 +462,9 @@ pub fn convert_legacy_token(legacy: &serde_json::Value) -> Result<TokenData, Box
         suspensions,
         balance_color_mode,
         skip_check,
+        tags,
+        group,
+        version,
     })
 }
 
@@ -200,6 +510,18 @@ pub fn convert_to_legacy_format(token: &TokenData) -> serde_json::Value {
         map.insert("skip_check".to_string(), serde_json::Value::Bool(skip_check));
     }
 
+    if !token.tags.is_empty() {
+        map.insert("tags".to_string(), serde_json::json!(token.tags));
+    }
+
+    if let Some(group) = &token.group {
+        map.insert("group".to_string(), serde_json::Value::String(group.clone()));
+    }
+
+    if token.version > 0 {
+        map.insert("version".to_string(), serde_json::json!(token.version));
+    }
+
     serde_json::Value::Object(map)
 }
 
@@ -226,6 +548,30 @@ mod tests {
         assert!(token.updated_at <= Utc::now());
     }
 
+    #[test]
+    fn test_strict_conversion_names_bad_entries() {
+        let values = vec![
+            serde_json::json!({"id": "ok", "tenant_url": "https://example.com", "access_token": "a"}),
+            serde_json::json!({"id": "missing", "access_token": "b"}),
+            serde_json::json!({"id": "extra", "tenant_url": "https://example.com", "access_token": "c", "nickname": "x"}),
+            serde_json::json!("not a token"),
+        ];
+
+        let error = convert_legacy_tokens_strict(&values).unwrap_err();
+        assert_eq!(error.code(), "invalid_input");
+        let message = error.to_string();
+        assert!(!message.contains("entry 0"));
+        assert!(message.contains("entry 1 (id missing): Missing tenant_url field"));
+        assert!(message.contains("entry 2 (id extra): unknown fields nickname"));
+        assert!(message.contains("entry 3: not an object"));
+
+        // 全部条目有效时按原顺序返回
+        let tokens = convert_legacy_tokens_strict(&values[..1]).unwrap();
+        assert_eq!(tokens[0].id, "ok");
+        let round_trip = convert_legacy_tokens_strict(&[convert_to_legacy_format(&tokens[0])]).unwrap();
+        assert_eq!(round_trip[0].id, "ok");
+    }
+
     #[test]
     fn test_legacy_conversion() {
         let legacy_json = serde_json::json!({
@@ -247,4 +593,62 @@ mod tests {
         assert_eq!(converted_back["tenant_url"], "https://example.com");
         assert_eq!(converted_back["access_token"], "test_token");
     }
+
+    #[test]
+    fn test_legacy_conversion_keeps_tags_and_group() {
+        let legacy_json = serde_json::json!({
+            "id": "test_id",
+            "tenant_url": "https://example.com",
+            "access_token": "test_token",
+            "tags": [" project-a ", "owner:li", "project-a", ""],
+            "group": "team-1",
+            "version": 3
+        });
+
+        let token = convert_legacy_token(&legacy_json).unwrap();
+        assert_eq!(token.tags, vec!["project-a".to_string(), "owner:li".to_string()]);
+        assert_eq!(token.group, Some("team-1".to_string()));
+
+        let converted_back = convert_to_legacy_format(&token);
+        assert_eq!(converted_back["tags"], serde_json::json!(["project-a", "owner:li"]));
+        assert_eq!(converted_back["group"], "team-1");
+        assert_eq!(converted_back["version"], 3);
+
+        // 没有标签时不输出字段，保持旧格式不变
+        let plain = convert_to_legacy_format(&TokenData::new("b".to_string(), "https://example.com".to_string(), "t".to_string(), None, None));
+        assert!(plain.get("tags").is_none());
+    }
+
+    #[test]
+    fn test_merge_tombstones_keeps_latest() {
+        let older = Tombstone {
+            token_id: "a".to_string(),
+            deleted_at: Utc::now() - chrono::Duration::days(1),
+            origin: "device_1".to_string(),
+        };
+        let newer = Tombstone::new("a".to_string(), "device_2".to_string());
+        let other = Tombstone::new("b".to_string(), "device_1".to_string());
+
+        let merged = merge_tombstones(vec![older, other], vec![newer]);
+        assert_eq!(merged.len(), 2);
+        let a = merged.iter().find(|t| t.token_id == "a").unwrap();
+        assert_eq!(a.origin, "device_2");
+    }
+
+    #[test]
+    fn test_apply_tombstones_keeps_newer_tokens() {
+        let deleted = TokenData::new("deleted".to_string(), "https://a.com".to_string(), "a".to_string(), None, None);
+        let mut revived = TokenData::new("revived".to_string(), "https://a.com".to_string(), "b".to_string(), None, None);
+        let untouched = TokenData::new("untouched".to_string(), "https://a.com".to_string(), "c".to_string(), None, None);
+
+        let tombstones = vec![
+            Tombstone::new("deleted".to_string(), "device_1".to_string()),
+            Tombstone::new("revived".to_string(), "device_1".to_string()),
+        ];
+        revived.updated_at = Utc::now() + chrono::Duration::seconds(10);
+
+        let remaining = apply_tombstones(vec![deleted, revived, untouched], &tombstones);
+        let ids: Vec<&str> = remaining.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["revived", "untouched"]);
+    }
 }
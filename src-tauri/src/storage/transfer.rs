@@ -0,0 +1,545 @@
+use super::traits::{TokenData, convert_legacy_token, convert_to_legacy_format};
+use super::encryption::TokenCipher;
+use super::error::{StorageError, StorageResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Utc};
+
+const BUNDLE_FORMAT: &str = "atm-token-bundle";
+const BUNDLE_VERSION: u32 = 1;
+
+// CSV 中 tags 列的分隔符；标签中的分隔符和反斜杠用反斜杠转义
+const TAG_SEPARATOR: char = ';';
+const TAG_ESCAPE: char = '\\';
+
+/// 可以出现在导入导出文件中的字段，与 tokens.json 中的字段名一致
+const TRANSFER_FIELDS: &[&str] = &[
+    "id", "tenant_url", "access_token", "created_at", "updated_at", "portal_url", "email_note",
+    "ban_status", "portal_info", "auth_session", "suspensions", "balance_color_mode", "skip_check",
+    "tags", "group",
+];
+
+// 以JSON文本保存在CSV单元格中的字段
+const JSON_FIELDS: &[&str] = &["ban_status", "portal_info", "suspensions"];
+
+const DEFAULT_CSV_FIELDS: &[&str] = &[
+    "id", "tenant_url", "access_token", "portal_url", "email_note", "auth_session",
+    "tags", "group", "ban_status", "skip_check", "created_at", "updated_at",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferFormat {
+    /// 与 tokens.json 相同的JSON数组
+    #[default]
+    Json,
+    /// 每行一个token
+    Jsonl,
+    Csv,
+    /// 用口令加密的JSON数组
+    EncryptedBundle,
+}
+
+/// CSV 列与token字段的对应关系
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvColumn {
+    /// token字段名，如 `email_note`
+    pub field: String,
+    /// CSV 表头
+    pub header: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub format: TransferFormat,
+    /// 为空时导出默认列，表头即字段名
+    pub csv_columns: Vec<CsvColumn>,
+    /// 导出加密包时必填
+    pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    pub format: TransferFormat,
+    /// 为空时按表头与字段名相同对应，不认识的列会被忽略
+    pub csv_columns: Vec<CsvColumn>,
+    /// 导入加密包时必填
+    pub passphrase: Option<String>,
+    /// 只生成报告，不写入存储
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportRowStatus {
+    Imported,
+    /// 与已有token或文件中前面的行的 (tenant_url, access_token) 相同
+    Duplicate,
+    Invalid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRowReport {
+    /// 从 1 开始的行号（CSV 和 JSON Lines 为文件行号，JSON 为数组下标加一）
+    pub row: usize,
+    pub status: ImportRowStatus,
+    pub token_id: Option<String>,
+    pub email_note: Option<String>,
+    /// 无效原因，或重复时已有token的ID
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+    pub rows: Vec<ImportRowReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenBundle {
+    format: String,
+    version: u32,
+    exported_at: DateTime<Utc>,
+    tokens: Vec<serde_json::Value>,
+}
+
+/// 按指定格式导出tokens
+pub fn export_tokens(tokens: &[TokenData], options: &ExportOptions) -> StorageResult<String> {
+    let values: Vec<serde_json::Value> = tokens.iter().map(export_value).collect();
+
+    match options.format {
+        TransferFormat::Json => Ok(serde_json::to_string_pretty(&values)?),
+        TransferFormat::Jsonl => {
+            let mut content = String::new();
+            for value in &values {
+                content.push_str(&serde_json::to_string(value)?);
+                content.push('\n');
+            }
+            Ok(content)
+        }
+        TransferFormat::Csv => {
+            let columns = csv_columns(&options.csv_columns)?;
+            let mut content = csv_line(columns.iter().map(|column| column.header.clone()));
+            for value in &values {
+                content.push_str(&csv_line(columns.iter().map(|column| csv_cell(value.get(&column.field)))));
+            }
+            Ok(content)
+        }
+        TransferFormat::EncryptedBundle => {
+            let bundle = TokenBundle {
+                format: BUNDLE_FORMAT.to_string(),
+                version: BUNDLE_VERSION,
+                exported_at: Utc::now(),
+                tokens: values,
+            };
+            bundle_cipher(options.passphrase.as_deref())?.encrypt(&serde_json::to_string(&bundle)?)
+        }
+    }
+}
+
+// 版本号只在导出它的数据库中有意义，不写入导出文件
+fn export_value(token: &TokenData) -> serde_json::Value {
+    let mut value = convert_to_legacy_format(token);
+    if let Some(map) = value.as_object_mut() {
+        map.remove("version");
+    }
+    value
+}
+
+/// 解析导入内容并与已有tokens比较，返回报告和需要写入的tokens
+///
+/// 整个文件无法解析（格式错误、口令错误、CSV 列配置错误）时返回错误；单行的问题记录在报告中，不影响其他行。
+pub fn plan_import(content: &str, options: &ImportOptions, existing: &[TokenData]) -> StorageResult<(ImportReport, Vec<TokenData>)> {
+    let rows = parse_rows(content, options)?;
+
+    let mut seen_credentials: HashMap<(String, String), String> = existing.iter()
+        .map(|token| ((token.tenant_url.clone(), token.access_token.clone()), token.id.clone()))
+        .collect();
+    let mut used_ids: HashSet<String> = existing.iter().map(|token| token.id.clone()).collect();
+
+    let mut report = ImportReport {
+        dry_run: options.dry_run,
+        imported: 0,
+        duplicates: 0,
+        invalid: 0,
+        rows: Vec::with_capacity(rows.len()),
+    };
+    let mut tokens = Vec::new();
+
+    for (row, parsed) in rows {
+        let token = match parsed.and_then(validate_row) {
+            Ok(token) => token,
+            Err(message) => {
+                report.invalid += 1;
+                report.rows.push(ImportRowReport { row, status: ImportRowStatus::Invalid, token_id: None, email_note: None, message: Some(message) });
+                continue;
+            }
+        };
+
+        let credentials = (token.tenant_url.clone(), token.access_token.clone());
+        if let Some(existing_id) = seen_credentials.get(&credentials) {
+            report.duplicates += 1;
+            report.rows.push(ImportRowReport {
+                row,
+                status: ImportRowStatus::Duplicate,
+                token_id: Some(token.id.clone()),
+                email_note: token.email_note.clone(),
+                message: Some(format!("Same tenant_url and access_token as token {}", existing_id)),
+            });
+            continue;
+        }
+
+        let mut token = token;
+        // ID已被其他token使用时分配新ID，避免覆盖无关的token
+        if !used_ids.insert(token.id.clone()) {
+            token.id = uuid::Uuid::new_v4().to_string();
+            used_ids.insert(token.id.clone());
+        }
+        seen_credentials.insert(credentials, token.id.clone());
+
+        report.imported += 1;
+        report.rows.push(ImportRowReport {
+            row,
+            status: ImportRowStatus::Imported,
+            token_id: Some(token.id.clone()),
+            email_note: token.email_note.clone(),
+            message: None,
+        });
+        tokens.push(token);
+    }
+
+    Ok((report, tokens))
+}
+
+fn validate_row(mut value: serde_json::Value) -> Result<TokenData, String> {
+    let Some(map) = value.as_object_mut() else {
+        return Err("Expected a JSON object".to_string());
+    };
+
+    // 缺少ID时生成新ID
+    if map.get("id").and_then(|id| id.as_str()).is_none_or(|id| id.trim().is_empty()) {
+        map.insert("id".to_string(), serde_json::Value::String(uuid::Uuid::new_v4().to_string()));
+    }
+
+    let mut token = convert_legacy_token(&value).map_err(|e| e.to_string())?;
+    if !token.tenant_url.starts_with("http://") && !token.tenant_url.starts_with("https://") {
+        return Err(format!("Invalid tenant_url: {}", token.tenant_url));
+    }
+    if token.access_token.trim().is_empty() {
+        return Err("Empty access_token".to_string());
+    }
+    token.version = 0;
+    Ok(token)
+}
+
+// 返回 (行号, 该行的JSON对象或解析错误)
+fn parse_rows(content: &str, options: &ImportOptions) -> StorageResult<Vec<(usize, Result<serde_json::Value, String>)>> {
+    match options.format {
+        TransferFormat::Json => {
+            let values: Vec<serde_json::Value> = serde_json::from_str(content)
+                .map_err(|e| StorageError::InvalidInput(format!("Expected a JSON array of tokens: {}", e)))?;
+            Ok(values.into_iter().enumerate().map(|(index, value)| (index + 1, Ok(value))).collect())
+        }
+        TransferFormat::Jsonl => Ok(content.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| (index + 1, serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {}", e))))
+            .collect()),
+        TransferFormat::Csv => parse_csv_rows(content, &options.csv_columns),
+        TransferFormat::EncryptedBundle => {
+            let json = bundle_cipher(options.passphrase.as_deref())?.decrypt(content.trim())?;
+            let bundle: TokenBundle = serde_json::from_str(&json)
+                .map_err(|e| StorageError::Corrupt(format!("Invalid token bundle: {}", e)))?;
+            if bundle.format != BUNDLE_FORMAT || bundle.version > BUNDLE_VERSION {
+                return Err(StorageError::Incompatible(format!("Unsupported token bundle {} v{}", bundle.format, bundle.version)));
+            }
+            Ok(bundle.tokens.into_iter().enumerate().map(|(index, value)| (index + 1, Ok(value))).collect())
+        }
+    }
+}
+
+fn bundle_cipher(passphrase: Option<&str>) -> StorageResult<TokenCipher> {
+    let passphrase = passphrase.filter(|passphrase| !passphrase.is_empty())
+        .ok_or_else(|| StorageError::InvalidInput("Passphrase required for encrypted bundle".to_string()))?;
+    TokenCipher::new(passphrase.to_string())
+}
+
+fn csv_columns(columns: &[CsvColumn]) -> StorageResult<Vec<CsvColumn>> {
+    if columns.is_empty() {
+        return Ok(DEFAULT_CSV_FIELDS.iter()
+            .map(|field| CsvColumn { field: field.to_string(), header: field.to_string() })
+            .collect());
+    }
+
+    for column in columns {
+        if !TRANSFER_FIELDS.contains(&column.field.as_str()) {
+            return Err(StorageError::InvalidInput(format!("Unknown token field in CSV mapping: {}", column.field)));
+        }
+    }
+    Ok(columns.to_vec())
+}
+
+fn parse_csv_rows(content: &str, mapping: &[CsvColumn]) -> StorageResult<Vec<(usize, Result<serde_json::Value, String>)>> {
+    let records = parse_csv(content).map_err(StorageError::InvalidInput)?;
+    let Some(((_, headers), records)) = records.split_first() else {
+        return Ok(Vec::new());
+    };
+
+    // 每一列对应的字段，没有映射的列忽略
+    let fields: Vec<Option<String>> = headers.iter()
+        .map(|header| {
+            let header = header.trim();
+            if mapping.is_empty() {
+                TRANSFER_FIELDS.contains(&header).then(|| header.to_string())
+            } else {
+                mapping.iter().find(|column| column.header == header).map(|column| column.field.clone())
+            }
+        })
+        .collect();
+    csv_columns(mapping)?;
+    if !fields.iter().flatten().any(|field| field == "tenant_url") || !fields.iter().flatten().any(|field| field == "access_token") {
+        return Err(StorageError::InvalidInput("CSV must have tenant_url and access_token columns".to_string()));
+    }
+
+    Ok(records.iter()
+        .map(|(line, cells)| {
+            if cells.len() != headers.len() {
+                return (*line, Err(format!("Expected {} columns, found {}", headers.len(), cells.len())));
+            }
+            let mut map = serde_json::Map::new();
+            for (field, cell) in fields.iter().zip(cells) {
+                if let Some(field) = field
+                    && !cell.is_empty()
+                {
+                    map.insert(field.clone(), csv_value(field, cell));
+                }
+            }
+            (*line, Ok(serde_json::Value::Object(map)))
+        })
+        .collect())
+}
+
+fn csv_value(field: &str, cell: &str) -> serde_json::Value {
+    match field {
+        "tags" => serde_json::json!(split_tags(cell)),
+        "skip_check" => match cell.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" => serde_json::Value::Bool(true),
+            "false" | "0" | "no" => serde_json::Value::Bool(false),
+            _ => serde_json::Value::String(cell.to_string()),
+        },
+        // JSON 字段也接受普通文本，如 ban_status 直接写 SUSPENDED
+        field if JSON_FIELDS.contains(&field) => serde_json::from_str(cell)
+            .unwrap_or_else(|_| serde_json::Value::String(cell.to_string())),
+        _ => serde_json::Value::String(cell.to_string()),
+    }
+}
+
+fn csv_cell(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(text)) => text.clone(),
+        Some(serde_json::Value::Array(items)) if items.iter().all(|item| item.is_string()) => items.iter()
+            .filter_map(|item| item.as_str())
+            .map(escape_tag)
+            .collect::<Vec<_>>()
+            .join(&TAG_SEPARATOR.to_string()),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn escape_tag(tag: &str) -> String {
+    let mut escaped = String::with_capacity(tag.len());
+    for c in tag.chars() {
+        if c == TAG_SEPARATOR || c == TAG_ESCAPE {
+            escaped.push(TAG_ESCAPE);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// 按未转义的分隔符拆分；转义符后的字符原样保留，末尾单独的转义符按普通字符处理
+fn split_tags(cell: &str) -> Vec<String> {
+    let mut tags = vec![String::new()];
+    let mut chars = cell.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            TAG_ESCAPE => tags.last_mut().unwrap().push(chars.next().unwrap_or(TAG_ESCAPE)),
+            TAG_SEPARATOR => tags.push(String::new()),
+            c => tags.last_mut().unwrap().push(c),
+        }
+    }
+    tags
+}
+
+fn csv_line(cells: impl Iterator<Item = String>) -> String {
+    let mut line = cells
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+// 按 RFC 4180 解析，返回 (起始行号, 单元格)；引号内可以包含逗号和换行
+fn parse_csv(content: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    cell.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    cell.push(c);
+                }
+                _ => cell.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if cell.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut cell)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                record.push(std::mem::take(&mut cell));
+                // 跳过空行
+                if !(record.len() == 1 && record[0].is_empty()) {
+                    records.push((record_line, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                record_line = line;
+            }
+            _ => cell.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("Unterminated quoted field starting on line {}", record_line));
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push((record_line, record));
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(id: &str, access_token: &str) -> TokenData {
+        let mut token = TokenData::new(id.to_string(), "https://d1.example.com/".to_string(), access_token.to_string(), None, Some(format!("{}@example.com", id)));
+        token.tags = vec!["project-x".to_string(), "owner, alice".to_string(), "a;b".to_string(), "c:\\d".to_string()];
+        token.ban_status = Some(serde_json::json!("SUSPENDED"));
+        token
+    }
+
+    #[test]
+    fn test_csv_round_trip_with_mapping() {
+        let columns = vec![
+            CsvColumn { field: "email_note".to_string(), header: "Email".to_string() },
+            CsvColumn { field: "tenant_url".to_string(), header: "Tenant".to_string() },
+            CsvColumn { field: "access_token".to_string(), header: "Token".to_string() },
+            CsvColumn { field: "tags".to_string(), header: "Tags".to_string() },
+            CsvColumn { field: "ban_status".to_string(), header: "Status".to_string() },
+        ];
+        let content = export_tokens(&[token("a", "t1")], &ExportOptions {
+            format: TransferFormat::Csv,
+            csv_columns: columns.clone(),
+            passphrase: None,
+        }).unwrap();
+        assert!(content.starts_with("Email,Tenant,Token,Tags,Status\r\n"));
+
+        let options = ImportOptions { format: TransferFormat::Csv, csv_columns: columns, ..Default::default() };
+        let (report, tokens) = plan_import(&content, &options, &[]).unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(tokens[0].email_note, Some("a@example.com".to_string()));
+        assert_eq!(tokens[0].tags, vec!["project-x", "owner, alice", "a;b", "c:\\d"]);
+        assert_eq!(tokens[0].ban_status, Some(serde_json::json!("SUSPENDED")));
+    }
+
+    #[test]
+    fn test_tag_cell_escaping() {
+        assert_eq!(split_tags("x;y"), vec!["x", "y"]);
+        assert_eq!(split_tags("a\\;b;c\\\\"), vec!["a;b", "c\\"]);
+        let tags = ["semi;colon", "back\\slash", "plain"];
+        let cell = tags.iter().map(|tag| escape_tag(tag)).collect::<Vec<_>>().join(";");
+        assert_eq!(split_tags(&cell), tags);
+    }
+
+    #[test]
+    fn test_import_reports_duplicates_and_bad_rows() {
+        let existing = vec![token("a", "t1")];
+        let content = [
+            r#"{"tenant_url":"https://d1.example.com/","access_token":"t1"}"#,
+            "not json",
+            r#"{"tenant_url":"ftp://bad","access_token":"t2"}"#,
+            "",
+            r#"{"id":"a","tenant_url":"https://d1.example.com/","access_token":"t3"}"#,
+            r#"{"tenant_url":"https://d1.example.com/","access_token":"t3"}"#,
+        ].join("\n");
+
+        let options = ImportOptions { format: TransferFormat::Jsonl, dry_run: true, ..Default::default() };
+        let (report, tokens) = plan_import(&content, &options, &existing).unwrap();
+        assert_eq!((report.imported, report.duplicates, report.invalid), (1, 2, 2));
+
+        let statuses: Vec<(usize, ImportRowStatus)> = report.rows.iter().map(|row| (row.row, row.status)).collect();
+        assert_eq!(statuses, vec![
+            (1, ImportRowStatus::Duplicate),
+            (2, ImportRowStatus::Invalid),
+            (3, ImportRowStatus::Invalid),
+            (5, ImportRowStatus::Imported),
+            (6, ImportRowStatus::Duplicate),
+        ]);
+        // ID与已有token冲突时分配新ID
+        assert_ne!(tokens[0].id, "a");
+    }
+
+    #[test]
+    fn test_encrypted_bundle_requires_passphrase() {
+        let options = ExportOptions {
+            format: TransferFormat::EncryptedBundle,
+            passphrase: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let content = export_tokens(&[token("a", "t1")], &options).unwrap();
+        assert!(!content.contains("t1"));
+
+        let import = ImportOptions { format: TransferFormat::EncryptedBundle, ..Default::default() };
+        assert_eq!(plan_import(&content, &import, &[]).unwrap_err().code(), "invalid_input");
+
+        let import = ImportOptions { passphrase: Some("wrong".to_string()), ..import };
+        assert_eq!(plan_import(&content, &import, &[]).unwrap_err().code(), "locked");
+
+        let import = ImportOptions { passphrase: Some("secret".to_string()), ..import };
+        let (report, tokens) = plan_import(&content, &import, &[]).unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(tokens[0].access_token, "t1");
+    }
+}
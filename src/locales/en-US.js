@@ -240,6 +240,7 @@ export default {
     username: 'Username',
     password: 'Password',
     sslMode: 'SSL Mode',
+    workspace: 'Workspace',
     enabled: 'Enable Database Storage',
     testConnection: 'Test Connection',
     saveConfig: 'Save Configuration',
@@ -268,7 +269,8 @@ export default {
       port: '5432',
       database: 'augment_tokens',
       username: 'postgres',
-      password: 'Enter password'
+      password: 'Enter password',
+      workspace: 'default (letters, digits, - and _)'
     }
   },
   emailViewer: {
@@ -344,6 +346,16 @@ export default {
     importing: 'Importing...',
     extractingFromSession: 'Extracting tokens from sessions ({current}/{total})...',
     sessionExtractionFailed: 'Session extraction failed',
+    enableEncryption: 'Enable Encryption',
+    encryptionEnabled: 'Encrypted',
+    unlockTokenStore: 'Unlock Tokens',
+    unlock: 'Unlock',
+    unlockTokenStoreMessage: 'The token file is encrypted. Enter the passphrase to unlock it.',
+    enableEncryptionMessage: 'tokens.json will be encrypted with this passphrase. You will need to enter it after every start.',
+    passphrase: 'Passphrase',
+    passphraseConfirm: 'Confirm passphrase',
+    passphraseMismatch: 'The passphrases do not match',
+    passphraseWarning: 'The passphrase is not stored anywhere. Encrypted tokens cannot be recovered if it is lost.',
     cancel: 'Cancel'
   },
   tokenCard: {
@@ -471,7 +483,10 @@ export default {
     status: 'Storage Status',
     syncData: 'Click to sync data',
     detectDatabase: 'Click to detect database',
+    pendingChanges: '{count} changes waiting for database',
     clickToSync: 'Click to perform bidirectional sync',
+    autoSyncPaused: 'Automatic sync paused',
+    nextAutoSync: 'Next automatic sync: {time}',
     clickToDetect: 'Click to detect database connection',
     local: 'Local Storage',
     dual: 'Dual Storage',
@@ -496,7 +511,13 @@ export default {
     tokenLoadSuccess: 'Tokens loaded successfully',
     tokenLoadFailed: 'Failed to load tokens',
     tokenSaveFailed: 'Failed to save token',
+    tokenStoreUnlocked: 'Tokens unlocked',
+    tokenStoreEncrypted: 'Token file is now encrypted',
+    tokenStoreUnlockFailed: 'Failed to unlock tokens',
     tokenUpdateFailed: 'Failed to update token',
+    tokenUpdateConflict: 'This token was changed on another device. The latest version has been loaded, please apply your edit again',
+    tokensFileChanged: 'Token file was changed outside the app and has been reloaded: {added} added, {removed} removed, {modified} modified',
+    tokensFileRejected: 'Token file was changed outside the app but is invalid, keeping the current tokens (a copy was saved as tokens.json.rejected): {error}',
     copySuccess: 'URL copied to clipboard!',
     copyFailed: 'Failed to copy URL',
     tenantUrlCopied: 'Tenant URL copied to clipboard!',
@@ -582,6 +603,7 @@ export default {
     getStorageStatusFailed: 'Failed to get storage status',
     syncFailed: 'Sync failed',
     bidirectionalSyncComplete: 'Bidirectional sync complete',
+    syncConflicts: 'Sync complete with {count} conflicting field(s); the newer values were kept',
     databaseDetected: 'Database connection detected successfully, switched to dual storage mode',
     databaseNotDetected: 'No database connection detected, still in local storage mode',
     tokenNotFound: 'Token not found',
@@ -239,6 +239,7 @@ export default {
     username: '用户名',
     password: '密码',
     sslMode: 'SSL模式',
+    workspace: '工作区',
     enabled: '启用数据库存储',
     testConnection: '测试连接',
     saveConfig: '保存配置',
@@ -267,7 +268,8 @@ export default {
       port: '5432',
       database: 'augment_tokens',
       username: 'postgres',
-      password: '输入密码'
+      password: '输入密码',
+      workspace: 'default（字母、数字、- 和 _）'
     }
   },
   emailViewer: {
@@ -343,6 +345,16 @@ export default {
     importing: '导入中...',
     extractingFromSession: '正在从 Session 提取 Token ({current}/{total})...',
     sessionExtractionFailed: 'Session 提取失败',
+    enableEncryption: '启用加密',
+    encryptionEnabled: '已加密',
+    unlockTokenStore: '解锁Token',
+    unlock: '解锁',
+    unlockTokenStoreMessage: 'Token文件已加密，请输入口令解锁。',
+    enableEncryptionMessage: '设置口令后 tokens.json 将加密保存，每次启动需要输入口令解锁。',
+    passphrase: '口令',
+    passphraseConfirm: '再次输入口令',
+    passphraseMismatch: '两次输入的口令不一致',
+    passphraseWarning: '口令不会被保存，忘记口令将无法恢复已加密的Token。',
     cancel: '取消'
   },
   tokenCard: {
@@ -470,7 +482,10 @@ export default {
     status: '存储状态',
     syncData: '点击同步数据',
     detectDatabase: '点击检测数据库',
+    pendingChanges: '{count} 项修改等待写入数据库',
     clickToSync: '点击执行双向同步',
+    autoSyncPaused: '自动同步已暂停',
+    nextAutoSync: '下次自动同步：{time}',
     clickToDetect: '点击检测数据库连接',
     local: '本地存储',
     dual: '双重存储',
@@ -497,7 +512,13 @@ export default {
     tokenLoadSuccess: 'Token加载成功',
     tokenLoadFailed: '加载Token失败',
     tokenSaveFailed: '保存Token失败',
+    tokenStoreUnlocked: 'Token已解锁',
+    tokenStoreEncrypted: 'Token文件已加密',
+    tokenStoreUnlockFailed: '解锁失败',
     tokenUpdateFailed: '更新Token失败',
+    tokenUpdateConflict: '该Token已在其他设备上被修改，已加载最新版本，请重新编辑',
+    tokensFileChanged: 'Token文件已被外部修改并重新加载：新增 {added} 个，删除 {removed} 个，修改 {modified} 个',
+    tokensFileRejected: 'Token文件被外部修改为无效内容，已保留当前数据（原文件已另存为 tokens.json.rejected）：{error}',
     copySuccess: 'URL已复制到剪贴板!',
     copyFailed: '复制URL失败',
     tenantUrlCopied: '租户URL已复制到剪贴板!',
@@ -583,6 +604,7 @@ export default {
     getStorageStatusFailed: '获取存储状态失败',
     syncFailed: '同步失败',
     bidirectionalSyncComplete: '双向同步完成',
+    syncConflicts: '同步完成，{count} 个字段两端都有修改，已保留较新的值',
     databaseDetected: '数据库连接检测成功，已切换到双重存储模式',
     databaseNotDetected: '未检测到数据库连接，仍为本地存储模式',
     tokenNotFound: 'Token不存在',